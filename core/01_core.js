@@ -162,13 +162,92 @@
     ArrayPrototypePush(nextTickCallbacks, cb);
   }
 
+  // The callbacks of the pending timers by id, with their arguments, whether
+  // they repeat and the timer nesting level of the task running them.
+  const timers = new SafeMap();
+  let timerNestingLevel = 0;
+
+  function queueTimer(repeat, callback, timeout, args) {
+    if (typeof callback !== "function") {
+      throw new TypeError("Timer callback must be a function");
+    }
+    // Converted like a WebIDL `long`, with negative timeouts treated as 0.
+    timeout = timeout | 0;
+    if (timeout < 0) {
+      timeout = 0;
+    }
+    const id = ops.op_timer_queue(timerNestingLevel, timeout, repeat);
+    MapPrototypeSet(timers, id, {
+      callback,
+      args,
+      repeat,
+      nestingLevel: timerNestingLevel + 1,
+    });
+    return id;
+  }
+
+  function setTimeout(callback, timeout = 0, ...args) {
+    return queueTimer(false, callback, timeout, args);
+  }
+
+  function setInterval(callback, timeout = 0, ...args) {
+    return queueTimer(true, callback, timeout, args);
+  }
+
+  function clearTimer(id) {
+    if (MapPrototypeDelete(timers, id)) {
+      ops.op_timer_cancel(id);
+    }
+  }
+
+  function refTimer(id) {
+    if (MapPrototypeHas(timers, id)) {
+      ops.op_timer_ref(id);
+    }
+  }
+
+  function unrefTimer(id) {
+    if (MapPrototypeHas(timers, id)) {
+      ops.op_timer_unref(id);
+    }
+  }
+
+  // Runs the callbacks of the expired timers, each followed by a microtask
+  // checkpoint, and returns the exceptions they threw.
+  function runTimers(ids) {
+    const errors = [];
+    for (let i = 0; i < ids.length; i++) {
+      const timer = MapPrototypeGet(timers, ids[i]);
+      // The timer was cleared by a callback that ran before.
+      if (timer === undefined) {
+        continue;
+      }
+      timerNestingLevel = timer.nestingLevel;
+      if (timer.repeat) {
+        timer.nestingLevel++;
+      } else {
+        MapPrototypeDelete(timers, ids[i]);
+      }
+      try {
+        ReflectApply(timer.callback, globalThis, timer.args);
+      } catch (error) {
+        ArrayPrototypePush(errors, error);
+      } finally {
+        timerNestingLevel = 0;
+        ops.op_run_microtasks();
+      }
+    }
+    return errors;
+  }
+
   // This function has variable number of arguments. The last argument describes
-  // if there's a "next tick" scheduled by the Node.js compat layer. Arguments
-  // before last are alternating integers and any values that describe the
+  // if there's a "next tick" scheduled by the Node.js compat layer. The one
+  // before it is an array of the ids of the expired timers, if any. Arguments
+  // before those are alternating integers and any values that describe the
   // responses of async ops.
   function eventLoopTick() {
     // First respond to all pending ops.
-    for (let i = 0; i < arguments.length - 1; i += 2) {
+    for (let i = 0; i < arguments.length - 2; i += 2) {
       const promiseId = arguments[i];
       const res = arguments[i + 1];
       const promise = getPromise(promiseId);
@@ -182,6 +261,11 @@
     } else {
       ops.op_run_microtasks();
     }
+    // Run the callbacks of the expired timers.
+    const expiredTimers = arguments[arguments.length - 2];
+    const timerErrors = expiredTimers !== undefined
+      ? runTimers(expiredTimers)
+      : [];
     // Finally drain macrotask queue.
     for (let i = 0; i < macrotaskCallbacks.length; i++) {
       const cb = macrotaskCallbacks[i];
@@ -202,6 +286,11 @@
         }
       }
     }
+    // The first exception thrown by a timer callback is rethrown once the rest
+    // of the tick ran.
+    if (timerErrors.length > 0) {
+      throw timerErrors[0];
+    }
   }
 
   function registerErrorClass(className, errorClass) {
@@ -695,6 +784,17 @@ for (let i = 0; i < 10; i++) {
   }
   const InterruptedPrototype = Interrupted.prototype;
 
+  // Thrown when importing a module is denied by the module loading policy of
+  // the embedder.
+  class ModuleLoadPolicyError extends TypeError {
+    constructor(msg) {
+      super(msg);
+      this.name = "ModuleLoadPolicyError";
+    }
+  }
+  const ModuleLoadPolicyErrorPrototype = ModuleLoadPolicyError.prototype;
+  registerErrorClass("ModuleLoadPolicyError", ModuleLoadPolicyError);
+
   const promiseHooks = [
     [], // init
     [], // before
@@ -814,11 +914,19 @@ for (let i = 0; i < 10; i++) {
     BadResourcePrototype,
     Interrupted,
     InterruptedPrototype,
+    ModuleLoadPolicyError,
+    ModuleLoadPolicyErrorPrototype,
     enableOpCallTracing,
     isOpCallTracingEnabled,
     opCallTraces,
     refOp,
     unrefOp,
+    setTimeout,
+    setInterval,
+    clearTimeout: clearTimer,
+    clearInterval: clearTimer,
+    refTimer,
+    unrefTimer,
     setReportExceptionCallback,
     setPromiseHooks,
     close,
@@ -6,9 +6,11 @@ use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::time::Duration;
 
 use anyhow::Error;
 
+use crate::modules::ModuleLoadPolicyError;
 use crate::runtime::JsRealm;
 use crate::runtime::JsRuntime;
 use crate::source_map::apply_source_map;
@@ -526,12 +528,146 @@ impl Display for JsError {
   }
 }
 
+/// Returned by the event loop when module evaluation can't make progress,
+/// because top-level awaits are waiting on promises that nothing is left to
+/// settle.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StalledTopLevelAwaitError {
+  /// Every stalled module, in all realms.
+  pub stalled: Vec<StalledTopLevelAwait>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StalledTopLevelAwait {
+  /// Index of the realm the module belongs to, in the order realms were
+  /// created. The main realm is 0.
+  pub realm: usize,
+  pub specifier: String,
+  /// The V8 message, eg. "Top-level await promise never resolved".
+  pub message: String,
+  /// The source line of the stalled `await`.
+  pub source_line: Option<String>,
+  /// The location of the stalled `await`.
+  pub call_site: Option<JsStackFrame>,
+  /// The specifiers of the modules importing each other from the main
+  /// module, or from the root of a dynamic import, to the stalled module.
+  /// It ends with `specifier`.
+  pub import_chain: Vec<String>,
+}
+
+impl std::error::Error for StalledTopLevelAwaitError {}
+
+impl Display for StalledTopLevelAwaitError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    for (i, stalled) in self.stalled.iter().enumerate() {
+      if i > 0 {
+        writeln!(f)?;
+      }
+      write!(f, "{}", stalled.message)?;
+      let location = stalled
+        .call_site
+        .as_ref()
+        .and_then(|frame| frame.maybe_format_location());
+      if let Some(location) = location {
+        write!(f, "\n    at {location}")?;
+      }
+      if stalled.import_chain.len() > 1 {
+        write!(
+          f,
+          "\n    imported through {}",
+          stalled.import_chain.join(" -> ")
+        )?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Returned when JavaScript runs past one of the limits set in
+/// [`RuntimeOptions`](crate::RuntimeOptions), after its execution was
+/// terminated. The runtime can still be used afterwards.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ExecutionTimeout {
+  pub kind: ExecutionTimeoutKind,
+  /// The limit that was exceeded.
+  pub limit: Duration,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExecutionTimeoutKind {
+  /// A single run exceeded `RuntimeOptions::max_run_time`.
+  RunTime,
+  /// The runtime spent more than `RuntimeOptions::max_cpu_time` running
+  /// JavaScript.
+  CpuTime,
+}
+
+impl std::error::Error for ExecutionTimeout {}
+
+impl Display for ExecutionTimeout {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self.kind {
+      ExecutionTimeoutKind::RunTime => write!(
+        f,
+        "Execution timed out: a run took longer than {:?}",
+        self.limit
+      ),
+      ExecutionTimeoutKind::CpuTime => write!(
+        f,
+        "Execution timed out: the CPU time budget of {:?} is spent",
+        self.limit
+      ),
+    }
+  }
+}
+
+/// Returned when the isolate reached its heap limit while running JavaScript,
+/// with the heap statistics at that point, if
+/// [`RuntimeOptions::heap_limit_policy`](crate::RuntimeOptions::heap_limit_policy)
+/// is set. The execution was terminated.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HeapLimitExceeded {
+  pub heap_size_limit: usize,
+  pub total_heap_size: usize,
+  pub used_heap_size: usize,
+  pub external_memory: usize,
+  /// Whether the runtime refuses to run any more JavaScript, as per
+  /// [`HeapLimitPolicy::Poison`](crate::HeapLimitPolicy::Poison).
+  pub poisoned: bool,
+}
+
+impl std::error::Error for HeapLimitExceeded {}
+
+impl Display for HeapLimitExceeded {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(
+      f,
+      "Heap limit of {} bytes exceeded ({} bytes used, {} bytes external)",
+      self.heap_size_limit, self.used_heap_size, self.external_memory
+    )?;
+    if self.poisoned {
+      write!(f, ", the runtime can't be used anymore")?;
+    }
+    Ok(())
+  }
+}
+
 // TODO(piscisaureus): rusty_v8 should implement the Error trait on
 // values of type v8::Global<T>.
 pub(crate) fn to_v8_type_error(
   scope: &mut v8::HandleScope,
   err: Error,
 ) -> v8::Global<v8::Value> {
+  // Denied imports throw their own `TypeError` subclass, so that they can be
+  // told apart.
+  if err.downcast_ref::<ModuleLoadPolicyError>().is_some() {
+    fn get_class(_: &Error) -> &'static str {
+      "ModuleLoadPolicyError"
+    }
+    let exception = to_v8_error(scope, &get_class, &err);
+    return v8::Global::new(scope, exception);
+  }
+
   let err_string = err.to_string();
   let error_chain = err
     .chain()
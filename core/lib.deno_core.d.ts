@@ -21,6 +21,37 @@ declare namespace Deno {
      * if there are only "unref" promises left. */
     function unrefOp(promiseId: number): void;
 
+    /** Calls `callback` with `args` after `timeout` milliseconds, like
+     * `setTimeout()` on the web. Timers nested more than 5 levels deep wait
+     * for at least 4 milliseconds. Returns the id of the timer. */
+    function setTimeout(
+      callback: (...args: any[]) => void,
+      timeout?: number,
+      ...args: any[]
+    ): number;
+
+    /** Calls `callback` with `args` every `timeout` milliseconds, like
+     * `setInterval()` on the web. Returns the id of the timer. */
+    function setInterval(
+      callback: (...args: any[]) => void,
+      timeout?: number,
+      ...args: any[]
+    ): number;
+
+    /** Cancels a timer set with `setTimeout()` or `setInterval()`. */
+    function clearTimeout(id: number): void;
+
+    /** Cancels a timer set with `setTimeout()` or `setInterval()`. */
+    function clearInterval(id: number): void;
+
+    /** Mark a timer as "ref", ie. event loop won't exit until it expires.
+     * All timers are "ref" by default. */
+    function refTimer(id: number): void;
+
+    /** Mark a timer as "unref", ie. event loop will exit if there are only
+     * "unref" timers and promises left. */
+    function unrefTimer(id: number): void;
+
     /**
      * List of all registered ops, in the form of a map that maps op
      * name to function.
@@ -57,8 +57,13 @@ pub use crate::async_cell::AsyncRefCell;
 pub use crate::async_cell::AsyncRefFuture;
 pub use crate::async_cell::RcLike;
 pub use crate::async_cell::RcRef;
+pub use crate::error::ExecutionTimeout;
+pub use crate::error::ExecutionTimeoutKind;
 pub use crate::error::GetErrorClassFn;
+pub use crate::error::HeapLimitExceeded;
 pub use crate::error::JsErrorCreateFn;
+pub use crate::error::StalledTopLevelAwait;
+pub use crate::error::StalledTopLevelAwaitError;
 pub use crate::extensions::Extension;
 pub use crate::extensions::ExtensionBuilder;
 pub use crate::extensions::ExtensionFileSource;
@@ -80,18 +85,59 @@ pub use crate::module_specifier::resolve_import;
 pub use crate::module_specifier::resolve_path;
 pub use crate::module_specifier::resolve_url;
 pub use crate::module_specifier::resolve_url_or_path;
+pub use crate::module_specifier::ImportMap;
+pub use crate::module_specifier::ImportMapError;
 pub use crate::module_specifier::ModuleResolutionError;
 pub use crate::module_specifier::ModuleSpecifier;
+pub use crate::modules::ArchiveModuleLoader;
+pub use crate::modules::AsyncFsModuleLoader;
+pub use crate::modules::AsyncFsModuleLoaderOptions;
+pub use crate::modules::ChromeTraceExporter;
+pub use crate::modules::CustomModuleEvaluationCb;
+pub use crate::modules::DataUrlModuleLoader;
 pub use crate::modules::ExtModuleLoaderCb;
 pub use crate::modules::FsModuleLoader;
+pub use crate::modules::ImportKind;
+pub use crate::modules::ImportMapLoader;
+pub use crate::modules::ImportMeta;
+pub use crate::modules::ImportMetaCallback;
+pub use crate::modules::ImportMetaModuleInfo;
+pub use crate::modules::IntegrityMode;
+pub use crate::modules::IntegrityModuleLoader;
+pub use crate::modules::LazyImportMetaCb;
+pub use crate::modules::ModuleArchiveEntry;
+pub use crate::modules::ModuleArchiveWriter;
 pub use crate::modules::ModuleCode;
+pub use crate::modules::ModuleCodeBytes;
+pub use crate::modules::ModuleCodeCache;
+pub use crate::modules::ModuleGraph;
+pub use crate::modules::ModuleGraphAlias;
+pub use crate::modules::ModuleGraphDependency;
+pub use crate::modules::ModuleGraphModule;
 pub use crate::modules::ModuleId;
+pub use crate::modules::ModuleIntegrityError;
+pub use crate::modules::ModuleLoadEvent;
+pub use crate::modules::ModuleLoadPhase;
+pub use crate::modules::ModuleLoadPolicy;
+pub use crate::modules::ModuleLoadPolicyError;
+pub use crate::modules::ModuleLoadRules;
+pub use crate::modules::ModuleLoadRulesOverride;
+pub use crate::modules::ModuleLoadTraceCallback;
 pub use crate::modules::ModuleLoader;
+pub use crate::modules::ModuleLockfile;
+pub use crate::modules::ModuleResolveFuture;
 pub use crate::modules::ModuleSource;
+pub use crate::modules::ModuleSourceCode;
 pub use crate::modules::ModuleSourceFuture;
+pub use crate::modules::ModuleStatus;
 pub use crate::modules::ModuleType;
 pub use crate::modules::NoopModuleLoader;
+pub use crate::modules::PolicyModuleLoader;
 pub use crate::modules::ResolutionKind;
+pub use crate::modules::RoutingModuleLoader;
+pub use crate::modules::StaticModuleLoader;
+pub use crate::modules::SyntheticModule;
+pub use crate::modules::SyntheticModuleEvaluationCb;
 pub use crate::normalize_path::normalize_path;
 pub use crate::ops::OpCall;
 pub use crate::ops::OpError;
@@ -113,13 +159,19 @@ pub use crate::resources::ResourceTable;
 pub use crate::runtime::CompiledWasmModuleStore;
 pub use crate::runtime::CreateRealmOptions;
 pub use crate::runtime::CrossIsolateStore;
+pub use crate::runtime::HeapLimitPolicy;
 pub use crate::runtime::JsRealm;
 pub use crate::runtime::JsRuntime;
 pub use crate::runtime::JsRuntimeForSnapshot;
+pub use crate::runtime::JsRuntimePool;
+pub use crate::runtime::JsRuntimePoolOptions;
+pub use crate::runtime::PooledJsRuntime;
+pub use crate::runtime::ResetOpStateFn;
 pub use crate::runtime::RuntimeOptions;
 pub use crate::runtime::RuntimeSnapshotOptions;
 pub use crate::runtime::SharedArrayBufferStore;
 pub use crate::runtime::Snapshot;
+pub use crate::runtime::VirtualClock;
 pub use crate::runtime::V8_WRAPPER_OBJECT_INDEX;
 pub use crate::runtime::V8_WRAPPER_TYPE_INDEX;
 pub use crate::source_map::SourceMapGetter;
@@ -1,6 +1,7 @@
 // Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
 
 use crate::normalize_path;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::path::Path;
@@ -162,6 +163,433 @@ fn specifier_has_uri_scheme(specifier: &str) -> bool {
   }
 }
 
+/// Error returned when parsing an import map or resolving a specifier with it
+/// fails.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ImportMapError {
+  /// The import map is not valid JSON or doesn't have the expected shape.
+  InvalidImportMap(String),
+  /// The specifier matched an entry that maps it to `null`.
+  BlockedByNullEntry(String),
+  /// The part of the specifier after a matched prefix is not a valid URL
+  /// relative to the address of that prefix.
+  InvalidAfterPrefix {
+    specifier: String,
+    after_prefix: String,
+    prefix: String,
+    address: String,
+  },
+  /// The specifier resolved to a URL outside of the address of the matched
+  /// prefix, eg. `"pkg/../x"` with a `"pkg/"` entry.
+  BacktracksAbovePrefix { specifier: String, prefix: String },
+  /// The specifier is a bare specifier that isn't mapped by the import map.
+  UnmappedBareSpecifier(String, Option<String>),
+}
+
+impl Error for ImportMapError {}
+
+impl fmt::Display for ImportMapError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ImportMapError::InvalidImportMap(ref msg) => {
+        write!(f, "Invalid import map: {msg}")
+      }
+      ImportMapError::BlockedByNullEntry(ref specifier) => write!(
+        f,
+        "Blocked by a null entry in the import map for \"{specifier}\""
+      ),
+      ImportMapError::InvalidAfterPrefix {
+        ref specifier,
+        ref after_prefix,
+        ref prefix,
+        ref address,
+      } => write!(
+        f,
+        "Failed to resolve the specifier \"{specifier}\" as its after-prefix portion \"{after_prefix}\" could not be URL-parsed relative to the URL prefix \"{address}\" mapped to by the prefix \"{prefix}\""
+      ),
+      ImportMapError::BacktracksAbovePrefix {
+        ref specifier,
+        ref prefix,
+      } => write!(
+        f,
+        "The specifier \"{specifier}\" backtracks above its prefix \"{prefix}\""
+      ),
+      ImportMapError::UnmappedBareSpecifier(
+        ref specifier,
+        ref maybe_referrer,
+      ) => write!(
+        f,
+        "Relative import path \"{}\" not prefixed with / or ./ or ../ and not in import map{}",
+        specifier,
+        match maybe_referrer {
+          Some(referrer) => format!(" from \"{referrer}\""),
+          None => String::new(),
+        }
+      ),
+    }
+  }
+}
+
+/// Normalized specifier keys with their addresses, sorted so that longer keys
+/// come first. A `None` address blocks the specifier.
+type SpecifierMap = Vec<(String, Option<Url>)>;
+
+/// An import map, as described in
+/// <https://html.spec.whatwg.org/multipage/webappapis.html#import-maps>.
+///
+/// ```
+/// # use deno_core::ImportMap;
+/// # use deno_core::resolve_url;
+/// let base_url = resolve_url("file:///app/import_map.json").unwrap();
+/// let import_map = ImportMap::parse(
+///   r#"{ "imports": { "lodash": "https://cdn.example/lodash.js" } }"#,
+///   &base_url,
+/// )
+/// .unwrap();
+/// let referrer = resolve_url("file:///app/main.js").unwrap();
+/// assert_eq!(
+///   import_map.resolve("lodash", &referrer).unwrap().as_str(),
+///   "https://cdn.example/lodash.js"
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct ImportMap {
+  base_url: Url,
+  imports: SpecifierMap,
+  scopes: Vec<(String, SpecifierMap)>,
+  integrity: HashMap<String, String>,
+  warnings: Vec<String>,
+}
+
+impl ImportMap {
+  /// Parses an import map from its JSON source. Relative addresses and scopes
+  /// are resolved against `base_url`, the URL of the import map itself.
+  ///
+  /// Invalid entries are ignored, as required by the spec. A description of
+  /// each of them is available from [`ImportMap::warnings`].
+  pub fn parse(json: &str, base_url: &Url) -> Result<Self, ImportMapError> {
+    let value: serde_json::Value = serde_json::from_str(json)
+      .map_err(|err| ImportMapError::InvalidImportMap(err.to_string()))?;
+    Self::from_json_value(value, base_url)
+  }
+
+  /// Like [`ImportMap::parse`], for an already parsed JSON value.
+  pub fn from_json_value(
+    value: serde_json::Value,
+    base_url: &Url,
+  ) -> Result<Self, ImportMapError> {
+    let serde_json::Value::Object(mut map) = value else {
+      return Err(ImportMapError::InvalidImportMap(
+        "the top-level value needs to be a JSON object".to_string(),
+      ));
+    };
+    let mut warnings = vec![];
+
+    let imports = match map.remove("imports") {
+      None => vec![],
+      Some(serde_json::Value::Object(imports)) => {
+        sort_and_normalize_specifier_map(imports, base_url, &mut warnings)
+      }
+      Some(_) => {
+        return Err(ImportMapError::InvalidImportMap(
+          "\"imports\" top-level key needs to be a JSON object".to_string(),
+        ))
+      }
+    };
+
+    let scopes = match map.remove("scopes") {
+      None => vec![],
+      Some(serde_json::Value::Object(scopes)) => {
+        sort_and_normalize_scopes(scopes, base_url, &mut warnings)?
+      }
+      Some(_) => {
+        return Err(ImportMapError::InvalidImportMap(
+          "\"scopes\" top-level key needs to be a JSON object".to_string(),
+        ))
+      }
+    };
+
+    let integrity = match map.remove("integrity") {
+      None => HashMap::new(),
+      Some(serde_json::Value::Object(integrity)) => {
+        normalize_integrity_map(integrity, base_url, &mut warnings)
+      }
+      Some(_) => {
+        return Err(ImportMapError::InvalidImportMap(
+          "\"integrity\" top-level key needs to be a JSON object".to_string(),
+        ))
+      }
+    };
+
+    for key in map.keys() {
+      warnings.push(format!("Invalid top-level key \"{key}\". Only \"imports\", \"scopes\" and \"integrity\" can be present."));
+    }
+
+    Ok(Self {
+      base_url: base_url.clone(),
+      imports,
+      scopes,
+      integrity,
+      warnings,
+    })
+  }
+
+  /// The URL the import map was parsed relative to.
+  pub fn base_url(&self) -> &Url {
+    &self.base_url
+  }
+
+  /// The integrity metadata given for a module URL, eg.
+  /// `"sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="`.
+  pub fn integrity(&self, url: &Url) -> Option<&str> {
+    self.integrity.get(url.as_str()).map(String::as_str)
+  }
+
+  /// Entries of the import map that were ignored while parsing it.
+  pub fn warnings(&self) -> &[String] {
+    &self.warnings
+  }
+
+  /// Resolves a module specifier imported from `referrer` using this import
+  /// map, following
+  /// <https://html.spec.whatwg.org/multipage/webappapis.html#resolve-a-module-specifier>.
+  pub fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &Url,
+  ) -> Result<ModuleSpecifier, ImportMapError> {
+    let as_url = parse_url_like_import_specifier(specifier, referrer);
+    let normalized_specifier = match &as_url {
+      Some(url) => url.as_str(),
+      None => specifier,
+    };
+
+    for (scope_prefix, scope_imports) in &self.scopes {
+      if scope_prefix == referrer.as_str()
+        || (scope_prefix.ends_with('/')
+          && referrer.as_str().starts_with(scope_prefix.as_str()))
+      {
+        if let Some(url) = resolve_imports_match(
+          scope_imports,
+          normalized_specifier,
+          as_url.as_ref(),
+        )? {
+          return Ok(url);
+        }
+      }
+    }
+
+    if let Some(url) = resolve_imports_match(
+      &self.imports,
+      normalized_specifier,
+      as_url.as_ref(),
+    )? {
+      return Ok(url);
+    }
+
+    match as_url {
+      Some(url) => Ok(url),
+      None => Err(ImportMapError::UnmappedBareSpecifier(
+        specifier.to_string(),
+        Some(referrer.to_string()),
+      )),
+    }
+  }
+}
+
+/// <https://html.spec.whatwg.org/multipage/webappapis.html#resolving-a-url-like-module-specifier>
+fn parse_url_like_import_specifier(specifier: &str, base: &Url) -> Option<Url> {
+  if specifier.starts_with('/')
+    || specifier.starts_with("./")
+    || specifier.starts_with("../")
+  {
+    return base.join(specifier).ok();
+  }
+
+  Url::parse(specifier).ok()
+}
+
+fn is_special(url: &Url) -> bool {
+  matches!(
+    url.scheme(),
+    "ftp" | "file" | "http" | "https" | "ws" | "wss"
+  )
+}
+
+/// <https://html.spec.whatwg.org/multipage/webappapis.html#sorting-and-normalizing-a-module-specifier-map>
+fn sort_and_normalize_specifier_map(
+  map: serde_json::Map<String, serde_json::Value>,
+  base_url: &Url,
+  warnings: &mut Vec<String>,
+) -> SpecifierMap {
+  let mut normalized: SpecifierMap = vec![];
+
+  for (specifier_key, value) in map {
+    if specifier_key.is_empty() {
+      warnings.push("Invalid empty string specifier.".to_string());
+      continue;
+    }
+    let normalized_key =
+      match parse_url_like_import_specifier(&specifier_key, base_url) {
+        Some(url) => url.to_string(),
+        None => specifier_key.clone(),
+      };
+
+    let address = match value {
+      serde_json::Value::String(address) => address,
+      // An explicit `null` blocks the specifier.
+      serde_json::Value::Null => {
+        normalized.push((normalized_key, None));
+        continue;
+      }
+      value => {
+        warnings.push(format!(
+          "Invalid address {value} for the specifier key \"{specifier_key}\". Addresses must be strings."
+        ));
+        normalized.push((normalized_key, None));
+        continue;
+      }
+    };
+
+    let Some(address_url) =
+      parse_url_like_import_specifier(&address, base_url)
+    else {
+      warnings.push(format!(
+        "Invalid address \"{address}\" for the specifier key \"{specifier_key}\"."
+      ));
+      normalized.push((normalized_key, None));
+      continue;
+    };
+
+    if specifier_key.ends_with('/') && !address_url.as_str().ends_with('/') {
+      warnings.push(format!(
+        "Invalid address \"{address_url}\" for package specifier \"{specifier_key}\". Package address targets must end with \"/\"."
+      ));
+      normalized.push((normalized_key, None));
+      continue;
+    }
+
+    normalized.push((normalized_key, Some(address_url)));
+  }
+
+  // Later entries win over earlier ones normalizing to the same key.
+  normalized.reverse();
+  normalized.sort_by(|(a, _), (b, _)| b.cmp(a));
+  normalized.dedup_by(|(a, _), (b, _)| a == b);
+  normalized
+}
+
+/// <https://html.spec.whatwg.org/multipage/webappapis.html#sorting-and-normalizing-scopes>
+fn sort_and_normalize_scopes(
+  map: serde_json::Map<String, serde_json::Value>,
+  base_url: &Url,
+  warnings: &mut Vec<String>,
+) -> Result<Vec<(String, SpecifierMap)>, ImportMapError> {
+  let mut normalized = vec![];
+
+  for (scope_prefix, value) in map {
+    let serde_json::Value::Object(imports) = value else {
+      return Err(ImportMapError::InvalidImportMap(format!(
+        "the value for the \"{scope_prefix}\" scope prefix needs to be a JSON object"
+      )));
+    };
+
+    let Ok(scope_prefix_url) = base_url.join(&scope_prefix) else {
+      warnings.push(format!(
+        "Invalid scope \"{scope_prefix}\" (parsed against base URL \"{base_url}\")."
+      ));
+      continue;
+    };
+
+    normalized.push((
+      scope_prefix_url.to_string(),
+      sort_and_normalize_specifier_map(imports, base_url, warnings),
+    ));
+  }
+
+  normalized.reverse();
+  normalized.sort_by(|(a, _), (b, _)| b.cmp(a));
+  normalized.dedup_by(|(a, _), (b, _)| a == b);
+  Ok(normalized)
+}
+
+/// <https://html.spec.whatwg.org/multipage/webappapis.html#normalizing-a-module-integrity-map>
+fn normalize_integrity_map(
+  map: serde_json::Map<String, serde_json::Value>,
+  base_url: &Url,
+  warnings: &mut Vec<String>,
+) -> HashMap<String, String> {
+  let mut normalized = HashMap::new();
+
+  for (key, value) in map {
+    let Some(url) = parse_url_like_import_specifier(&key, base_url) else {
+      warnings.push(format!(
+        "Invalid integrity key \"{key}\". Keys must be valid URLs or start with /, ./ or ../."
+      ));
+      continue;
+    };
+    let serde_json::Value::String(metadata) = value else {
+      warnings.push(format!(
+        "Invalid integrity metadata {value} for \"{key}\". Metadata must be a string."
+      ));
+      continue;
+    };
+    normalized.insert(url.to_string(), metadata);
+  }
+
+  normalized
+}
+
+/// <https://html.spec.whatwg.org/multipage/webappapis.html#resolving-an-imports-match>
+fn resolve_imports_match(
+  specifier_map: &SpecifierMap,
+  normalized_specifier: &str,
+  as_url: Option<&Url>,
+) -> Result<Option<Url>, ImportMapError> {
+  for (specifier_key, resolution_result) in specifier_map {
+    if specifier_key == normalized_specifier {
+      return match resolution_result {
+        Some(url) => Ok(Some(url.clone())),
+        None => Err(ImportMapError::BlockedByNullEntry(
+          normalized_specifier.to_string(),
+        )),
+      };
+    }
+
+    if specifier_key.ends_with('/')
+      && normalized_specifier.starts_with(specifier_key.as_str())
+      && as_url.map(is_special).unwrap_or(true)
+    {
+      let Some(resolution_result) = resolution_result else {
+        return Err(ImportMapError::BlockedByNullEntry(
+          normalized_specifier.to_string(),
+        ));
+      };
+
+      let after_prefix = &normalized_specifier[specifier_key.len()..];
+      let Ok(url) = resolution_result.join(after_prefix) else {
+        return Err(ImportMapError::InvalidAfterPrefix {
+          specifier: normalized_specifier.to_string(),
+          after_prefix: after_prefix.to_string(),
+          prefix: specifier_key.clone(),
+          address: resolution_result.to_string(),
+        });
+      };
+
+      if !url.as_str().starts_with(resolution_result.as_str()) {
+        return Err(ImportMapError::BacktracksAbovePrefix {
+          specifier: normalized_specifier.to_string(),
+          prefix: specifier_key.clone(),
+        });
+      }
+
+      return Ok(Some(url));
+    }
+  }
+
+  Ok(None)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -497,4 +925,190 @@ mod tests {
     let expected = resolve_url("http://deno.land/x/mod.ts").unwrap();
     assert_eq!(actual, expected);
   }
+
+  #[test]
+  fn test_import_map_resolve() {
+    let base_url =
+      resolve_url("https://example.com/app/import_map.json").unwrap();
+    let import_map = ImportMap::parse(
+      r#"{
+        "imports": {
+          "moment": "/node_modules/moment/src/moment.js",
+          "moment/": "/node_modules/moment/src/",
+          "lodash": "https://cdn.example/lodash.js",
+          "./lib/": "./lib-v2/",
+          "https://example.com/blocked.js": null
+        },
+        "scopes": {
+          "/app/legacy/": {
+            "lodash": "https://cdn.example/lodash-v3.js"
+          }
+        }
+      }"#,
+      &base_url,
+    )
+    .unwrap();
+    assert!(import_map.warnings().is_empty());
+
+    let tests = vec![
+      (
+        "moment",
+        "https://example.com/app/main.js",
+        "https://example.com/node_modules/moment/src/moment.js",
+      ),
+      (
+        "moment/locale/fr.js",
+        "https://example.com/app/main.js",
+        "https://example.com/node_modules/moment/src/locale/fr.js",
+      ),
+      (
+        "lodash",
+        "https://example.com/app/main.js",
+        "https://cdn.example/lodash.js",
+      ),
+      (
+        "lodash",
+        "https://example.com/app/legacy/main.js",
+        "https://cdn.example/lodash-v3.js",
+      ),
+      (
+        "./util.js",
+        "https://example.com/app/lib/main.js",
+        "https://example.com/app/lib-v2/util.js",
+      ),
+      (
+        "./other.js",
+        "https://example.com/app/main.js",
+        "https://example.com/app/other.js",
+      ),
+    ];
+
+    for (specifier, referrer, expected) in tests {
+      let referrer = resolve_url(referrer).unwrap();
+      let actual = import_map.resolve(specifier, &referrer).unwrap();
+      assert_eq!(actual.as_str(), expected, "{specifier} from {referrer}");
+    }
+  }
+
+  #[test]
+  fn test_import_map_resolve_error() {
+    let base_url = resolve_url("https://example.com/import_map.json").unwrap();
+    let import_map = ImportMap::parse(
+      r#"{
+        "imports": {
+          "pkg/": "/pkg/",
+          "blocked": null,
+          "blocked/": null
+        }
+      }"#,
+      &base_url,
+    )
+    .unwrap();
+    let referrer = resolve_url("https://example.com/main.js").unwrap();
+
+    let tests = vec![
+      (
+        "blocked",
+        ImportMapError::BlockedByNullEntry("blocked".to_string()),
+      ),
+      (
+        "blocked/a.js",
+        ImportMapError::BlockedByNullEntry("blocked/a.js".to_string()),
+      ),
+      (
+        "pkg/../../a.js",
+        ImportMapError::BacktracksAbovePrefix {
+          specifier: "pkg/../../a.js".to_string(),
+          prefix: "pkg/".to_string(),
+        },
+      ),
+      (
+        "unmapped",
+        ImportMapError::UnmappedBareSpecifier(
+          "unmapped".to_string(),
+          Some(referrer.to_string()),
+        ),
+      ),
+    ];
+
+    for (specifier, expected_err) in tests {
+      let err = import_map.resolve(specifier, &referrer).unwrap_err();
+      assert_eq!(err, expected_err, "{specifier}");
+    }
+  }
+
+  #[test]
+  fn test_import_map_parse() {
+    let base_url = resolve_url("https://example.com/import_map.json").unwrap();
+
+    assert!(matches!(
+      ImportMap::parse("[]", &base_url),
+      Err(ImportMapError::InvalidImportMap(_))
+    ));
+    assert!(matches!(
+      ImportMap::parse(r#"{ "imports": [] }"#, &base_url),
+      Err(ImportMapError::InvalidImportMap(_))
+    ));
+    assert!(matches!(
+      ImportMap::parse(r#"{ "scopes": { "/a/": 1 } }"#, &base_url),
+      Err(ImportMapError::InvalidImportMap(_))
+    ));
+    assert!(matches!(
+      ImportMap::parse("{", &base_url),
+      Err(ImportMapError::InvalidImportMap(_))
+    ));
+
+    // Invalid entries are ignored with a warning.
+    let import_map = ImportMap::parse(
+      r#"{
+        "imports": {
+          "": "/empty.js",
+          "a": 1,
+          "b/": "/b.js",
+          "c": "bare"
+        },
+        "other": {}
+      }"#,
+      &base_url,
+    )
+    .unwrap();
+    assert_eq!(import_map.warnings().len(), 5);
+    let referrer = resolve_url("https://example.com/main.js").unwrap();
+    for specifier in ["a", "b/x.js", "c"] {
+      assert_eq!(
+        import_map.resolve(specifier, &referrer).unwrap_err(),
+        ImportMapError::BlockedByNullEntry(specifier.to_string())
+      );
+    }
+  }
+
+  #[test]
+  fn test_import_map_integrity() {
+    let base_url = resolve_url("https://example.com/import_map.json").unwrap();
+    let import_map = ImportMap::parse(
+      r#"{
+        "imports": { "lib": "/lib.js" },
+        "integrity": {
+          "./lib.js": "sha256-abc",
+          "https://cdn.example/x.js": "sha256-def",
+          "bare": "sha256-ghi",
+          "/invalid.js": 1
+        }
+      }"#,
+      &base_url,
+    )
+    .unwrap();
+    assert_eq!(import_map.warnings().len(), 2);
+    let lib = resolve_url("https://example.com/lib.js").unwrap();
+    assert_eq!(import_map.integrity(&lib), Some("sha256-abc"));
+    let cdn = resolve_url("https://cdn.example/x.js").unwrap();
+    assert_eq!(import_map.integrity(&cdn), Some("sha256-def"));
+    let invalid = resolve_url("https://example.com/invalid.js").unwrap();
+    assert_eq!(import_map.integrity(&invalid), None);
+
+    assert!(matches!(
+      ImportMap::parse(r#"{ "integrity": [] }"#, &base_url),
+      Err(ImportMapError::InvalidImportMap(_))
+    ));
+  }
 }
@@ -0,0 +1,511 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use super::code_cache;
+use super::import_attributes;
+use crate::error::custom_error;
+use crate::error::generic_error;
+use crate::module_specifier::ModuleSpecifier;
+use crate::modules::ModuleCodeCache;
+use crate::modules::ModuleGraph;
+use crate::modules::ModuleLoader;
+use crate::modules::ModuleSource;
+use crate::modules::ModuleSourceCode;
+use crate::modules::ModuleSourceFuture;
+use crate::modules::ModuleType;
+use crate::modules::ResolutionKind;
+use crate::resolve_import;
+use crate::runtime::JsRuntime;
+use crate::SourceMapGetter;
+use anyhow::Context;
+use anyhow::Error;
+use futures::future::FutureExt;
+use serde::Deserialize;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::pin::Pin;
+
+// An archive is the magic bytes, the format version and the length of the
+// header as a little-endian u32 each, the JSON header, then the data of the
+// modules. The header locates each piece of data relative to the end of the
+// header.
+const MAGIC: &[u8; 8] = b"DENOMODA";
+const VERSION: u32 = 1;
+const PREAMBLE_LEN: usize = MAGIC.len() + 8;
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ArchiveHeader {
+  main_module: Option<String>,
+  modules: BTreeMap<String, ArchivedModule>,
+  /// The URLs each redirected specifier was redirected through, ending with
+  /// the specifier of a module.
+  redirects: BTreeMap<String, Vec<String>>,
+  /// The resolved specifier of each import, by referrer.
+  resolutions: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ArchivedModule {
+  module_type: ModuleType,
+  code: Range<usize>,
+  source_map: Option<Range<usize>>,
+  code_cache: Option<Range<usize>>,
+}
+
+/// A module to add to a [`ModuleArchiveWriter`].
+#[derive(Clone, Debug)]
+pub struct ModuleArchiveEntry {
+  pub specifier: ModuleSpecifier,
+  pub module_type: ModuleType,
+  pub code: Vec<u8>,
+  /// The raw source map of the module.
+  pub source_map: Option<Vec<u8>>,
+  /// Code cache data, in the format given to
+  /// [`ModuleLoader::code_cache_ready`].
+  pub code_cache: Option<Vec<u8>>,
+}
+
+/// Builds a module archive, a single file holding a resolved module graph that
+/// an [`ArchiveModuleLoader`] can serve.
+///
+/// ```rust,ignore
+/// let main_id = runtime.load_main_module(&main_specifier, None).await?;
+/// let writer = ModuleArchiveWriter::from_runtime(&mut runtime).await?;
+/// writer.write(std::fs::File::create("app.archive")?)?;
+/// ```
+#[derive(Default)]
+pub struct ModuleArchiveWriter {
+  header: ArchiveHeader,
+  data: Vec<u8>,
+}
+
+impl ModuleArchiveWriter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Archives the modules loaded in the main realm of `runtime`, with their
+  /// redirects, the resolution of their static imports, their source maps if
+  /// the runtime has a [`SourceMapGetter`], and a code cache for JavaScript
+  /// modules.
+  ///
+  /// Sources are taken from the module map. Those it doesn't keep, ie. of
+  /// modules from a snapshot or with a custom type, are loaded again with the
+  /// module loader of the runtime, which must still be able to serve them.
+  /// Modules provided by extensions and synthetic modules registered by the
+  /// embedder are left out.
+  pub async fn from_runtime(runtime: &mut JsRuntime) -> Result<Self, Error> {
+    let mut writer = Self::new();
+    let module_map_rc = runtime.module_map();
+    let loader = module_map_rc.borrow().loader.clone();
+    let source_map_getter =
+      runtime.inner.state.borrow().source_map_getter.clone();
+
+    // The specifier of each module to archive, with its type and source if
+    // the module map has them, and the V8 code cache of JavaScript modules.
+    let mut modules = vec![];
+    {
+      let scope = &mut runtime.handle_scope();
+      let module_map = module_map_rc.borrow();
+      for id in module_map.registered_module_ids() {
+        let info = &module_map.info[id];
+        let module = v8::Local::new(scope, &module_map.handles[id]);
+        if info.name.as_str().starts_with("ext:")
+          || (module.is_synthetic_module()
+            && info.module_type == ModuleType::JavaScript)
+        {
+          continue;
+        }
+        let specifier = ModuleSpecifier::parse(info.name.as_str())?;
+        if info.main {
+          writer.set_main_module(&specifier);
+        }
+
+        let mut v8_code_cache = None;
+        if module.is_source_text_module() {
+          let unbound_module_script = module.get_unbound_module_script(scope);
+          if info.module_type == ModuleType::JavaScript {
+            v8_code_cache = unbound_module_script
+              .create_code_cache()
+              .map(|data| data.to_vec());
+          }
+          // Requests are in the order of the imports of the module.
+          let module_requests = module.get_module_requests();
+          for (i, request) in info.requests.iter().enumerate() {
+            let module_request = v8::Local::<v8::ModuleRequest>::try_from(
+              module_requests.get(scope, i).unwrap(),
+            )
+            .unwrap();
+            let import_specifier =
+              module_request.get_specifier().to_rust_string_lossy(scope);
+            writer
+              .header
+              .resolutions
+              .entry(specifier.to_string())
+              .or_default()
+              .insert(import_specifier, request.specifier.clone());
+          }
+        }
+        let source = module_map
+          .sources
+          .get(&id)
+          .map(ModuleSourceCode::as_bytes)
+          .or_else(|| {
+            let source = module_map.commonjs.sources.get(&specifier)?;
+            Some(source.as_bytes())
+          })
+          .map(|source| (info.module_type.clone(), source.to_vec()));
+        modules.push((specifier, source, v8_code_cache));
+      }
+
+      let graph = ModuleGraph::new(scope, &module_map);
+      for module in graph.modules {
+        for alias in module.aliases {
+          writer
+            .header
+            .redirects
+            .insert(alias.specifier, alias.redirect_chain);
+        }
+      }
+    }
+
+    for (specifier, source, v8_code_cache) in modules {
+      let (module_type, code) = match source {
+        Some(source) => source,
+        None => load_source(&*loader, &specifier).await?,
+      };
+      let code_cache = v8_code_cache.map(|v8_code_cache| {
+        // The source is hashed as it's compiled, with `with` clauses
+        // rewritten.
+        let source = String::from_utf8_lossy(&code);
+        let source = import_attributes::rewrite_with_clauses(&source)
+          .map(Cow::Owned)
+          .unwrap_or(source);
+        let source_hash = code_cache::source_hash(source.as_bytes());
+        code_cache::encode(source_hash, &v8_code_cache)
+      });
+      let source_map = source_map_getter
+        .as_ref()
+        .and_then(|getter| getter.get_source_map(specifier.as_str()));
+      writer.add_module(ModuleArchiveEntry {
+        specifier,
+        module_type,
+        code,
+        source_map,
+        code_cache,
+      });
+    }
+
+    Ok(writer)
+  }
+
+  pub fn set_main_module(&mut self, specifier: &ModuleSpecifier) {
+    self.header.main_module = Some(specifier.to_string());
+  }
+
+  /// Adds a module, replacing any module with the same specifier.
+  pub fn add_module(&mut self, entry: ModuleArchiveEntry) {
+    let mut push = |data: &[u8]| {
+      let start = self.data.len();
+      self.data.extend_from_slice(data);
+      start..self.data.len()
+    };
+    let module = ArchivedModule {
+      module_type: entry.module_type,
+      code: push(&entry.code),
+      source_map: entry.source_map.as_deref().map(&mut push),
+      code_cache: entry.code_cache.as_deref().map(&mut push),
+    };
+    self.header.modules.insert(entry.specifier.into(), module);
+  }
+
+  /// Records that `specifier` is redirected through `redirect_chain`, the
+  /// last URL of which is the specifier of a module.
+  pub fn add_redirect(
+    &mut self,
+    specifier: &ModuleSpecifier,
+    redirect_chain: &[ModuleSpecifier],
+  ) {
+    self.header.redirects.insert(
+      specifier.to_string(),
+      redirect_chain.iter().map(ToString::to_string).collect(),
+    );
+  }
+
+  /// Records that `specifier` imported from `referrer` resolves to
+  /// `resolved`.
+  pub fn add_resolution(
+    &mut self,
+    referrer: &ModuleSpecifier,
+    specifier: &str,
+    resolved: &ModuleSpecifier,
+  ) {
+    self
+      .header
+      .resolutions
+      .entry(referrer.to_string())
+      .or_default()
+      .insert(specifier.to_string(), resolved.to_string());
+  }
+
+  pub fn write(&self, mut writer: impl io::Write) -> io::Result<()> {
+    let header = serde_json::to_vec(&self.header)?;
+    let header_len = u32::try_from(header.len()).map_err(|_| {
+      io::Error::new(io::ErrorKind::InvalidInput, "Archive header too large")
+    })?;
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&header_len.to_le_bytes())?;
+    writer.write_all(&header)?;
+    writer.write_all(&self.data)?;
+    writer.flush()
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = vec![];
+    self.write(&mut bytes).unwrap();
+    bytes
+  }
+}
+
+/// Loads the source of a module that the module map doesn't keep.
+async fn load_source(
+  loader: &dyn ModuleLoader,
+  specifier: &ModuleSpecifier,
+) -> Result<(ModuleType, Vec<u8>), Error> {
+  let module_source = loader
+    .load(specifier, None, false)
+    .await
+    .with_context(|| format!("Failed to archive module \"{specifier}\""))?;
+  Ok((
+    module_source.module_type,
+    module_source.code.as_bytes().to_vec(),
+  ))
+}
+
+/// The bytes of an archive, either mapped from a file or in memory.
+enum ArchiveData {
+  Mapped(memmap2::Mmap),
+  Owned(Box<[u8]>),
+}
+
+impl std::ops::Deref for ArchiveData {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      Self::Mapped(mapped) => mapped,
+      Self::Owned(bytes) => bytes,
+    }
+  }
+}
+
+/// Module loader serving the modules of an archive written by a
+/// [`ModuleArchiveWriter`], without touching the file system once it's
+/// opened.
+///
+/// Imports are resolved as recorded in the archive, and other specifiers,
+/// eg. those of dynamic imports, relative to their referrer. The loader also
+/// serves the archived source maps as a [`SourceMapGetter`]:
+///
+/// ```rust,ignore
+/// let loader = Rc::new(ArchiveModuleLoader::open("app.archive")?);
+/// let main_module = loader.main_module().unwrap();
+/// let mut runtime = JsRuntime::new(RuntimeOptions {
+///   module_loader: Some(loader.clone()),
+///   source_map_getter: Some(Box::new(loader)),
+///   ..Default::default()
+/// });
+/// let main_id = runtime.load_main_module(&main_module, None).await?;
+/// ```
+pub struct ArchiveModuleLoader {
+  header: ArchiveHeader,
+  data: ArchiveData,
+  data_offset: usize,
+}
+
+impl ArchiveModuleLoader {
+  /// Opens an archive file. The file is memory-mapped, so that only the parts
+  /// of it that are used are read, and must not be modified while the loader
+  /// is alive. Module sources are copied out of the mapping when they are
+  /// loaded, since a [`ModuleSource`] owns its code.
+  pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+    let path = path.as_ref();
+    let file = std::fs::File::open(path)
+      .with_context(|| format!("Failed to open module archive {path:?}"))?;
+    let data = if file.metadata()?.len() == 0 {
+      ArchiveData::Owned(Box::default())
+    } else {
+      // SAFETY: The file is documented to stay unmodified while the loader
+      // is alive.
+      ArchiveData::Mapped(unsafe { memmap2::Mmap::map(&file)? })
+    };
+    Self::from_data(data)
+      .with_context(|| format!("Failed to open module archive {path:?}"))
+  }
+
+  pub fn from_bytes(bytes: impl Into<Box<[u8]>>) -> Result<Self, Error> {
+    Self::from_data(ArchiveData::Owned(bytes.into()))
+  }
+
+  fn from_data(data: ArchiveData) -> Result<Self, Error> {
+    let invalid = || generic_error("Invalid module archive.");
+    if data.len() < PREAMBLE_LEN || &data[..MAGIC.len()] != MAGIC {
+      return Err(invalid());
+    }
+    let version = u32::from_le_bytes(data[8..12].try_into().unwrap());
+    if version != VERSION {
+      return Err(generic_error(format!(
+        "Unsupported module archive version {version}, expected {VERSION}."
+      )));
+    }
+    let header_len =
+      u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+    let data_offset = PREAMBLE_LEN + header_len;
+    let header_bytes =
+      data.get(PREAMBLE_LEN..data_offset).ok_or_else(invalid)?;
+    let header: ArchiveHeader =
+      serde_json::from_slice(header_bytes).map_err(|_| invalid())?;
+
+    let data_len = data.len() - data_offset;
+    let in_bounds =
+      |range: &Range<usize>| range.start <= range.end && range.end <= data_len;
+    for module in header.modules.values() {
+      let ranges = [Some(&module.code), module.source_map.as_ref()]
+        .into_iter()
+        .chain([module.code_cache.as_ref()])
+        .flatten();
+      for range in ranges {
+        if !in_bounds(range) {
+          return Err(invalid());
+        }
+      }
+    }
+
+    Ok(Self {
+      header,
+      data,
+      data_offset,
+    })
+  }
+
+  /// The main module of the runtime the archive was written from, if any.
+  pub fn main_module(&self) -> Option<ModuleSpecifier> {
+    let main_module = self.header.main_module.as_ref()?;
+    ModuleSpecifier::parse(main_module).ok()
+  }
+
+  /// The specifiers of the archived modules.
+  pub fn specifiers(&self) -> impl Iterator<Item = &str> {
+    self.header.modules.keys().map(String::as_str)
+  }
+
+  fn bytes(&self, range: &Range<usize>) -> &[u8] {
+    &self.data[self.data_offset + range.start..self.data_offset + range.end]
+  }
+
+  /// The redirect chain of `specifier`, and the module it leads to.
+  fn get(&self, specifier: &str) -> Option<(&[String], &ArchivedModule)> {
+    let (redirect_chain, found): (&[String], &str) =
+      match self.header.redirects.get(specifier) {
+        Some(redirect_chain) => (redirect_chain, redirect_chain.last()?),
+        None => (&[], specifier),
+      };
+    let module = self.header.modules.get(found)?;
+    Some((redirect_chain, module))
+  }
+
+  fn load_module(
+    &self,
+    module_specifier: &ModuleSpecifier,
+  ) -> Result<ModuleSource, Error> {
+    let Some((redirect_chain, module)) = self.get(module_specifier.as_str())
+    else {
+      return Err(custom_error(
+        "NotFound",
+        format!("Cannot find module \"{module_specifier}\" in the archive."),
+      ));
+    };
+    let bytes = self.bytes(&module.code);
+    let code = match module.module_type {
+      ModuleType::JavaScript | ModuleType::Json | ModuleType::CommonJs => {
+        let code = std::str::from_utf8(bytes).map_err(|_| {
+          generic_error(format!(
+            "Module source for \"{module_specifier}\" is not valid UTF-8."
+          ))
+        })?;
+        ModuleSourceCode::String(code.to_string().into())
+      }
+      _ => ModuleSourceCode::Bytes(bytes.to_vec().into()),
+    };
+    let redirect_chain = redirect_chain
+      .iter()
+      .map(|url| ModuleSpecifier::parse(url))
+      .collect::<Result<Vec<_>, _>>()?;
+    let mut module_source = ModuleSource::new_with_redirect_chain(
+      module.module_type.clone(),
+      code,
+      module_specifier,
+      &redirect_chain,
+    );
+    module_source.code_cache = module
+      .code_cache
+      .as_ref()
+      .map(|range| ModuleCodeCache::Data(self.bytes(range).to_vec().into()));
+    Ok(module_source)
+  }
+}
+
+impl ModuleLoader for ArchiveModuleLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    _kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    if let Some(resolved) = self
+      .header
+      .resolutions
+      .get(referrer)
+      .and_then(|resolutions| resolutions.get(specifier))
+    {
+      return Ok(ModuleSpecifier::parse(resolved)?);
+    }
+    Ok(resolve_import(specifier, referrer)?)
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    _maybe_referrer: Option<&ModuleSpecifier>,
+    _is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    futures::future::ready(self.load_module(module_specifier)).boxed_local()
+  }
+}
+
+impl SourceMapGetter for ArchiveModuleLoader {
+  fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+    let (_, module) = self.get(file_name)?;
+    Some(self.bytes(module.source_map.as_ref()?).to_vec())
+  }
+
+  /// Only lines of modules without a source map are available, since the
+  /// original sources of the others aren't archived.
+  fn get_source_line(
+    &self,
+    file_name: &str,
+    line_number: usize,
+  ) -> Option<String> {
+    let (_, module) = self.get(file_name)?;
+    if module.source_map.is_some() {
+      return None;
+    }
+    let code = std::str::from_utf8(self.bytes(&module.code)).ok()?;
+    code.lines().nth(line_number).map(ToString::to_string)
+  }
+}
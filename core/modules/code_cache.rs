@@ -0,0 +1,94 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use sha2::Digest;
+use sha2::Sha256;
+
+// The V8 code cache is handed to module loaders with a header holding the
+// version of this format, the V8 cache version tag (which covers the V8
+// version and flags) and a hash of the module source, so that stale caches
+// are detected before V8 sees them. Code caches are persisted, so the hash
+// must be stable across builds.
+const FORMAT_VERSION: u32 = 1;
+const HEADER_LEN: usize = 16;
+
+/// The first 8 bytes of the sha256 of the source, as a little-endian u64.
+pub(crate) fn source_hash(source: &[u8]) -> u64 {
+  let digest = Sha256::digest(source);
+  u64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Prepends the header to code cache data produced by V8.
+pub(crate) fn encode(source_hash: u64, v8_data: &[u8]) -> Vec<u8> {
+  encode_with_tag(
+    v8::script_compiler::cached_data_version_tag(),
+    source_hash,
+    v8_data,
+  )
+}
+
+fn encode_with_tag(tag: u32, source_hash: u64, v8_data: &[u8]) -> Vec<u8> {
+  let mut data = Vec::with_capacity(HEADER_LEN + v8_data.len());
+  data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+  data.extend_from_slice(&tag.to_le_bytes());
+  data.extend_from_slice(&source_hash.to_le_bytes());
+  data.extend_from_slice(v8_data);
+  data
+}
+
+/// Returns the V8 code cache data, unless the cache was produced for another
+/// source or by an incompatible V8.
+pub(crate) fn decode(data: &[u8], source_hash: u64) -> Option<&[u8]> {
+  decode_with_tag(
+    v8::script_compiler::cached_data_version_tag(),
+    data,
+    source_hash,
+  )
+}
+
+fn decode_with_tag(tag: u32, data: &[u8], source_hash: u64) -> Option<&[u8]> {
+  if data.len() <= HEADER_LEN {
+    return None;
+  }
+  let (header, v8_data) = data.split_at(HEADER_LEN);
+  let data_version = u32::from_le_bytes(header[..4].try_into().unwrap());
+  let data_tag = u32::from_le_bytes(header[4..8].try_into().unwrap());
+  let data_source_hash = u64::from_le_bytes(header[8..].try_into().unwrap());
+  (data_version == FORMAT_VERSION
+    && data_tag == tag
+    && data_source_hash == source_hash)
+    .then_some(v8_data)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn roundtrip() {
+    let hash = source_hash(b"export const a = 1;");
+    let data = encode_with_tag(42, hash, b"cache");
+    assert_eq!(decode_with_tag(42, &data, hash), Some(&b"cache"[..]));
+  }
+
+  #[test]
+  fn stable_source_hash() {
+    // sha256("") starts with e3b0c44298fc1c14.
+    assert_eq!(source_hash(b""), 0x141c_fc98_42c4_b0e3);
+  }
+
+  #[test]
+  fn stale() {
+    let hash = source_hash(b"export const a = 1;");
+    let data = encode_with_tag(42, hash, b"cache");
+    // Different V8 version or flags.
+    assert_eq!(decode_with_tag(43, &data, hash), None);
+    // Different source.
+    let other_hash = source_hash(b"export const a = 2;");
+    assert_eq!(decode_with_tag(42, &data, other_hash), None);
+    // Truncated.
+    assert_eq!(decode_with_tag(42, &data[..HEADER_LEN], hash), None);
+    // Another format version.
+    let mut other_version = data.clone();
+    other_version[0] = 2;
+    assert_eq!(decode_with_tag(42, &other_version, hash), None);
+  }
+}
@@ -0,0 +1,609 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+//! CommonJS modules.
+//!
+//! Modules loaded with [`ModuleType::CommonJs`](crate::ModuleType::CommonJs)
+//! are registered as synthetic modules, so that they can be imported from ES
+//! modules. Their source is statically analyzed when they're loaded, to find
+//! the modules they `require()` with a string literal, which are loaded along
+//! with them through the module loader, and the names they export, which are
+//! exposed as named exports in addition to the `default` export holding
+//! `module.exports`.
+//!
+//! The source is run in a function wrapper, the first time the module is
+//! either required or evaluated as a synthetic module. `require()` is
+//! synchronous, so it can only return modules that are already loaded.
+use crate::module_specifier::ModuleSpecifier;
+use crate::modules::AssertedModuleType;
+use crate::modules::ModuleCode;
+use crate::modules::ModuleType;
+use crate::modules::ResolutionKind;
+use crate::runtime::script_origin;
+use crate::JsRealm;
+use std::collections::HashMap;
+
+/// The CommonJS modules of a realm.
+#[derive(Default)]
+pub(crate) struct CommonJsModules {
+  /// Sources of loaded modules that didn't run successfully yet.
+  pub sources: HashMap<ModuleSpecifier, ModuleCode>,
+  /// The `module` object of each module that ran, or is running.
+  pub cache: HashMap<ModuleSpecifier, v8::Global<v8::Object>>,
+}
+
+/// What the static analysis of a CommonJS module found.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct CommonJsAnalysis {
+  /// Names assigned to `exports` or `module.exports`, in source order.
+  pub exports: Vec<String>,
+  /// Specifiers passed as string literals to `require()`, in source order.
+  pub requires: Vec<String>,
+}
+
+/// Finds the exports and requires of a CommonJS module. This recognizes the
+/// usual forms, eg. `exports.a = ...`, `module.exports = { a, b: ... }` and
+/// `Object.defineProperty(exports, "a", ...)`, without evaluating anything.
+pub(crate) fn analyze(source: &str) -> CommonJsAnalysis {
+  fn add_export(analysis: &mut CommonJsAnalysis, name: &str) {
+    if name != "default" && !analysis.exports.iter().any(|e| e == name) {
+      analysis.exports.push(name.to_string());
+    }
+  }
+
+  let tokens = tokenize(source);
+  let mut analysis = CommonJsAnalysis::default();
+  let punct = |i: usize, c: char| tokens.get(i) == Some(&Token::Punct(c));
+  let ident = |i: usize, s: &str| tokens.get(i) == Some(&Token::Ident(s));
+  let string = |i: usize| match tokens.get(i) {
+    Some(Token::Str(s)) => Some(s.as_str()),
+    _ => None,
+  };
+  // Skips `exports` or `module.exports` at `i`.
+  let exports_target = |i: usize| {
+    if ident(i, "exports") {
+      Some(i + 1)
+    } else if ident(i, "module") && punct(i + 1, '.') && ident(i + 2, "exports")
+    {
+      Some(i + 3)
+    } else {
+      None
+    }
+  };
+  let assignment = |i: usize| punct(i, '=') && !punct(i + 1, '=');
+
+  for i in 0..tokens.len() {
+    if i > 0 && punct(i - 1, '.') {
+      continue;
+    }
+    if ident(i, "require") && punct(i + 1, '(') && punct(i + 3, ')') {
+      if let Some(specifier) = string(i + 2) {
+        analysis.requires.push(specifier.to_string());
+      }
+      continue;
+    }
+    if ident(i, "Object")
+      && punct(i + 1, '.')
+      && ident(i + 2, "defineProperty")
+      && punct(i + 3, '(')
+    {
+      if let Some(j) = exports_target(i + 4) {
+        if let (true, Some(name)) = (punct(j, ','), string(j + 1)) {
+          add_export(&mut analysis, name);
+        }
+      }
+      continue;
+    }
+    let Some(j) = exports_target(i) else {
+      continue;
+    };
+    if punct(j, '.') && assignment(j + 2) {
+      if let Some(Token::Ident(name)) = tokens.get(j + 1) {
+        add_export(&mut analysis, name);
+      }
+    } else if punct(j, '[') && punct(j + 2, ']') && assignment(j + 3) {
+      if let Some(name) = string(j + 1) {
+        add_export(&mut analysis, name);
+      }
+    } else if j == i + 3 && assignment(j) && punct(j + 1, '{') {
+      for name in object_literal_keys(&tokens[j + 2..]) {
+        add_export(&mut analysis, name);
+      }
+    }
+  }
+  analysis
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+  Ident(&'a str),
+  Str(String),
+  Punct(char),
+}
+
+/// Keys of the object literal starting with `tokens`, right after its `{`.
+fn object_literal_keys<'a>(tokens: &'a [Token]) -> Vec<&'a str> {
+  let mut keys = vec![];
+  let mut depth = 0;
+  let mut expect_key = true;
+  for (i, token) in tokens.iter().enumerate() {
+    let next = tokens.get(i + 1);
+    match token {
+      Token::Punct('{' | '[' | '(') => depth += 1,
+      Token::Punct('}' | ']' | ')') if depth == 0 => break,
+      Token::Punct('}' | ']' | ')') => depth -= 1,
+      Token::Punct(',') if depth == 0 => {
+        expect_key = true;
+        continue;
+      }
+      Token::Ident("get" | "set" | "async") if depth == 0 && expect_key => {
+        if let Some(Token::Ident(key)) = next {
+          keys.push(*key);
+          expect_key = false;
+          continue;
+        }
+      }
+      _ => {}
+    }
+    if depth == 0 && expect_key {
+      let key = match token {
+        Token::Ident(key) => Some(*key),
+        Token::Str(key) => Some(key.as_str()),
+        _ => None,
+      };
+      let is_key = matches!(next, Some(Token::Punct(',' | '}' | ':' | '(')));
+      if let (Some(key), true) = (key, is_key) {
+        keys.push(key);
+      }
+    }
+    if depth == 0 {
+      expect_key = false;
+    }
+  }
+  keys
+}
+
+const KEYWORDS_BEFORE_EXPRESSION: &[&str] = &[
+  "await",
+  "case",
+  "delete",
+  "do",
+  "else",
+  "in",
+  "instanceof",
+  "new",
+  "return",
+  "throw",
+  "typeof",
+  "void",
+  "yield",
+];
+
+/// Splits JavaScript source into identifiers, string literals and
+/// punctuators, skipping whitespace and comments. Template literals and
+/// regular expression literals are kept as placeholder identifiers, so that
+/// their contents aren't mistaken for code.
+fn tokenize(source: &str) -> Vec<Token> {
+  let bytes = source.as_bytes();
+  let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'$';
+  let mut tokens = vec![];
+  let mut i = 0;
+  while i < bytes.len() {
+    let b = bytes[i];
+    if b.is_ascii_whitespace() {
+      i += 1;
+    } else if is_ident(b) || b >= 0x80 {
+      let start = i;
+      while i < bytes.len() && (is_ident(bytes[i]) || bytes[i] >= 0x80) {
+        i += 1;
+      }
+      tokens.push(Token::Ident(&source[start..i]));
+    } else if b == b'"' || b == b'\'' {
+      let mut value = vec![];
+      i += 1;
+      while i < bytes.len() && bytes[i] != b && bytes[i] != b'\n' {
+        if bytes[i] == b'\\' {
+          i += 1;
+        }
+        if i < bytes.len() {
+          value.push(bytes[i]);
+        }
+        i += 1;
+      }
+      i += 1;
+      tokens.push(Token::Str(String::from_utf8_lossy(&value).into_owned()));
+    } else if b == b'`' {
+      i += 1;
+      while i < bytes.len() && bytes[i] != b'`' {
+        i += if bytes[i] == b'\\' { 2 } else { 1 };
+      }
+      i += 1;
+      tokens.push(Token::Ident("``"));
+    } else if source[i..].starts_with("//") {
+      while i < bytes.len() && bytes[i] != b'\n' {
+        i += 1;
+      }
+    } else if source[i..].starts_with("/*") {
+      i = source[i + 2..]
+        .find("*/")
+        .map_or(bytes.len(), |end| i + end + 4);
+    } else if b == b'/' && starts_expression(tokens.last()) {
+      let mut in_class = false;
+      i += 1;
+      while i < bytes.len() && bytes[i] != b'\n' {
+        match bytes[i] {
+          b'\\' => i += 1,
+          b'[' => in_class = true,
+          b']' => in_class = false,
+          b'/' if !in_class => break,
+          _ => {}
+        }
+        i += 1;
+      }
+      i += 1;
+      while i < bytes.len() && is_ident(bytes[i]) {
+        i += 1;
+      }
+      tokens.push(Token::Ident("//"));
+    } else {
+      tokens.push(Token::Punct(b as char));
+      i += 1;
+    }
+  }
+  tokens
+}
+
+/// Whether a `/` following `previous` starts a regular expression literal,
+/// rather than being a division.
+fn starts_expression(previous: Option<&Token>) -> bool {
+  match previous {
+    None => true,
+    Some(Token::Punct(c)) => !matches!(c, ')' | ']' | '}'),
+    Some(Token::Ident(ident)) => KEYWORDS_BEFORE_EXPRESSION.contains(ident),
+    Some(Token::Str(_)) => false,
+  }
+}
+
+/// The module request for a module required by a CommonJS module, so that it
+/// gets loaded with it. Required JSON files are loaded as JSON modules.
+pub(crate) fn require_request_type(
+  specifier: &ModuleSpecifier,
+) -> AssertedModuleType {
+  if specifier.path().ends_with(".json") {
+    AssertedModuleType::Json
+  } else {
+    AssertedModuleType::JavaScriptOrWasm
+  }
+}
+
+/// Returns `module.exports` of a loaded CommonJS module, running it first if
+/// it didn't run yet. Returns `None` with an exception thrown on failure.
+pub(crate) fn module_exports<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  specifier: &ModuleSpecifier,
+) -> Option<v8::Local<'s, v8::Value>> {
+  let module_map_rc = JsRealm::module_map_from(scope);
+  let cached = module_map_rc
+    .borrow()
+    .commonjs
+    .cache
+    .get(specifier)
+    .cloned();
+  if let Some(module) = cached {
+    // A module that is still running when it's required again, through a
+    // require cycle, returns its exports as they are so far.
+    let module = v8::Local::new(scope, module);
+    return get(scope, module, "exports");
+  }
+
+  let maybe_source = {
+    let mut module_map = module_map_rc.borrow_mut();
+    module_map.commonjs.sources.remove(specifier).map(|source| {
+      // Kept until the module runs successfully, so that requiring it again
+      // after it failed runs it again.
+      let (source, copy) = source.into_cheap_copy();
+      module_map.commonjs.sources.insert(specifier.clone(), copy);
+      source
+    })
+  };
+  let Some(source) = maybe_source else {
+    throw_error(
+      scope,
+      &format!("CommonJS module \"{specifier}\" isn't loaded."),
+    );
+    return None;
+  };
+  let exports = run(scope, specifier, source)?;
+  module_map_rc
+    .borrow_mut()
+    .commonjs
+    .sources
+    .remove(specifier);
+  Some(exports)
+}
+
+/// Runs a CommonJS module in its function wrapper and returns its
+/// `module.exports`.
+fn run<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  specifier: &ModuleSpecifier,
+  source: ModuleCode,
+) -> Option<v8::Local<'s, v8::Value>> {
+  let (filename, dirname) = match specifier.to_file_path() {
+    Ok(path) => (
+      path.display().to_string(),
+      path
+        .parent()
+        .map(|parent| parent.display().to_string())
+        .unwrap_or_default(),
+    ),
+    Err(_) => {
+      let dirname = specifier
+        .join(".")
+        .map(|url| url.as_str().trim_end_matches('/').to_string())
+        .unwrap_or_default();
+      (specifier.to_string(), dirname)
+    }
+  };
+
+  let module = v8::Object::new(scope);
+  let exports: v8::Local<v8::Value> = v8::Object::new(scope).into();
+  let id: v8::Local<v8::Value> =
+    v8::String::new(scope, specifier.as_str()).unwrap().into();
+  let filename: v8::Local<v8::Value> =
+    v8::String::new(scope, &filename).unwrap().into();
+  let dirname: v8::Local<v8::Value> =
+    v8::String::new(scope, &dirname).unwrap().into();
+  let loaded: v8::Local<v8::Value> = v8::Boolean::new(scope, false).into();
+  set(scope, module, "exports", exports);
+  set(scope, module, "id", id);
+  set(scope, module, "filename", filename);
+  set(scope, module, "loaded", loaded);
+  let require = require_function(scope, specifier);
+
+  let module_map_rc = JsRealm::module_map_from(scope);
+  let module_global = v8::Global::new(scope, module);
+  module_map_rc
+    .borrow_mut()
+    .commonjs
+    .cache
+    .insert(specifier.clone(), module_global);
+
+  let tc_scope = &mut v8::TryCatch::new(scope);
+  // The wrapper is on the first line, to keep line numbers intact.
+  let wrapped = format!(
+    "(function (exports, require, module, __filename, __dirname) {{{}\n}})",
+    source.as_str()
+  );
+  let wrapped = v8::String::new(tc_scope, &wrapped).unwrap();
+  let name = v8::String::new(tc_scope, specifier.as_str()).unwrap();
+  let origin = script_origin(tc_scope, name);
+  let result = v8::Script::compile(tc_scope, wrapped, Some(&origin))
+    .and_then(|script| script.run(tc_scope))
+    .and_then(|wrapper| v8::Local::<v8::Function>::try_from(wrapper).ok())
+    .and_then(|wrapper| {
+      wrapper.call(
+        tc_scope,
+        exports,
+        &[exports, require.into(), module.into(), filename, dirname],
+      )
+    });
+
+  if result.is_none() {
+    module_map_rc.borrow_mut().commonjs.cache.remove(specifier);
+    tc_scope.rethrow();
+    return None;
+  }
+  let loaded = v8::Boolean::new(tc_scope, true).into();
+  set(tc_scope, module, "loaded", loaded);
+  get(tc_scope, module, "exports")
+}
+
+/// Creates the `require()` function of a CommonJS module.
+fn require_function<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  specifier: &ModuleSpecifier,
+) -> v8::Local<'s, v8::Function> {
+  let referrer = v8::String::new(scope, specifier.as_str()).unwrap();
+  v8::Function::builder(require_callback)
+    .data(referrer.into())
+    .build(scope)
+    .unwrap()
+}
+
+pub(crate) fn require_callback(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut rv: v8::ReturnValue,
+) {
+  let referrer = args.data().to_rust_string_lossy(scope);
+  let specifier = args.get(0);
+  if !specifier.is_string() {
+    throw_error(scope, "The \"id\" argument must be a string.");
+    return;
+  }
+  let specifier = specifier.to_rust_string_lossy(scope);
+
+  let module_map_rc = JsRealm::module_map_from(scope);
+  let resolve_result = module_map_rc.borrow().resolve(
+    &specifier,
+    &referrer,
+    ResolutionKind::Import,
+  );
+  let resolved = match resolve_result {
+    Ok(resolved) => resolved,
+    Err(err) => {
+      throw_error(scope, &err.to_string());
+      return;
+    }
+  };
+
+  let is_commonjs = {
+    let module_map = module_map_rc.borrow();
+    module_map.commonjs.cache.contains_key(&resolved)
+      || module_map.commonjs.sources.contains_key(&resolved)
+  };
+  if is_commonjs {
+    if let Some(exports) = module_exports(scope, &resolved) {
+      rv.set(exports);
+    }
+    return;
+  }
+
+  // Other modules are returned as their namespace, or their default export
+  // for JSON modules.
+  let maybe_module = {
+    let module_map = module_map_rc.borrow();
+    let asserted_module_type = require_request_type(&resolved);
+    module_map
+      .get_id(resolved.as_str(), &asserted_module_type)
+      .map(|id| {
+        (
+          module_map.handles[id].clone(),
+          module_map.info[id].module_type.clone(),
+        )
+      })
+  };
+  let Some((module, module_type)) = maybe_module else {
+    throw_error(
+      scope,
+      &format!(
+        "Cannot find module \"{resolved}\" from \"{referrer}\". Only modules required with a string literal are loaded along with CommonJS modules; use import() to load other modules."
+      ),
+    );
+    return;
+  };
+  let module = v8::Local::new(scope, module);
+
+  // Synthetic modules, eg. JSON modules, can be evaluated synchronously. The
+  // module status reflects the outcome.
+  if module.is_synthetic_module() {
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    if module.get_status() == v8::ModuleStatus::Uninstantiated {
+      let _ = module.instantiate_module(tc_scope, no_dependencies_callback);
+    }
+    if module.get_status() == v8::ModuleStatus::Instantiated {
+      let _ = module.evaluate(tc_scope);
+    }
+  }
+  match module.get_status() {
+    v8::ModuleStatus::Evaluated => {}
+    v8::ModuleStatus::Errored => {
+      let exception = module.get_exception();
+      scope.throw_exception(exception);
+      return;
+    }
+    _ => {
+      throw_error(
+        scope,
+        &format!(
+          "Cannot require ES module \"{resolved}\" from \"{referrer}\" before it's evaluated; use import() instead."
+        ),
+      );
+      return;
+    }
+  }
+
+  let namespace = module.get_module_namespace();
+  if module_type == ModuleType::Json {
+    let namespace = v8::Local::<v8::Object>::try_from(namespace).unwrap();
+    if let Some(value) = get(scope, namespace, "default") {
+      rv.set(value);
+    }
+  } else {
+    rv.set(namespace);
+  }
+}
+
+// Synthetic modules have no dependencies to resolve.
+fn no_dependencies_callback<'s>(
+  _context: v8::Local<'s, v8::Context>,
+  _specifier: v8::Local<'s, v8::String>,
+  _import_assertions: v8::Local<'s, v8::FixedArray>,
+  _referrer: v8::Local<'s, v8::Module>,
+) -> Option<v8::Local<'s, v8::Module>> {
+  None
+}
+
+fn throw_error(scope: &mut v8::HandleScope, message: &str) {
+  let message = v8::String::new(scope, message).unwrap();
+  let exception = v8::Exception::error(scope, message);
+  scope.throw_exception(exception);
+}
+
+pub(crate) fn get<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  object: v8::Local<v8::Object>,
+  key: &str,
+) -> Option<v8::Local<'s, v8::Value>> {
+  let key = v8::String::new(scope, key).unwrap();
+  object.get(scope, key.into())
+}
+
+fn set(
+  scope: &mut v8::HandleScope,
+  object: v8::Local<v8::Object>,
+  key: &str,
+  value: v8::Local<v8::Value>,
+) {
+  let key = v8::String::new(scope, key).unwrap();
+  object.set(scope, key.into(), value);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn analyze_exports() {
+    let analysis = analyze(
+      r#"
+      "use strict";
+      exports.a = 1;
+      exports["b"] = 2;
+      module.exports.c = function () {};
+      Object.defineProperty(exports, "d", { get() { return 4; } });
+      Object.defineProperty(module.exports, '__esModule', { value: true });
+      exports.default = 5;
+      if (exports.a == 1) {}
+      foo.exports.e = 6;
+      "#,
+    );
+    assert_eq!(analysis.exports, ["a", "b", "c", "d", "__esModule"]);
+    assert!(analysis.requires.is_empty());
+  }
+
+  #[test]
+  fn analyze_object_literal() {
+    let analysis = analyze(
+      r#"
+      const a = require("./a");
+      module.exports = {
+        a,
+        b: require('./b.json'),
+        "c": [1, 2, { d: 3 }],
+        e() {},
+        get f() { return 1; },
+        ...a,
+      };
+      "#,
+    );
+    assert_eq!(analysis.exports, ["a", "b", "c", "e", "f"]);
+    assert_eq!(analysis.requires, ["./a", "./b.json"]);
+  }
+
+  #[test]
+  fn analyze_skips_comments_strings_and_regexes() {
+    let analysis = analyze(
+      r#"
+      // exports.a = require("./a");
+      /* exports.b = require("./b"); */
+      const s = "exports.c = 1; require('./c')";
+      const t = `exports.d = ${require}`;
+      const r = /exports.e = "require('./e')"/g;
+      const x = a / 2; exports.f = require("./f") / 2;
+      obj.require("./g");
+      "#,
+    );
+    assert_eq!(analysis.exports, ["f"]);
+    assert_eq!(analysis.requires, ["./f"]);
+  }
+}
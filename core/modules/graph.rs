@@ -0,0 +1,209 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use super::map::SymbolicModule;
+use crate::error::JsError;
+use crate::modules::AssertedModuleType;
+use crate::modules::ModuleId;
+use crate::modules::ModuleMap;
+use crate::modules::ModuleRequest;
+use crate::modules::ModuleType;
+use serde::Serialize;
+use serde::Serializer;
+
+/// A snapshot of the modules loaded in a realm, returned by
+/// [`JsRuntime::module_graph`](crate::JsRuntime::module_graph).
+///
+/// It serializes to JSON with camel-cased keys, for use by tooling.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleGraph {
+  /// The loaded modules, in the order they were created.
+  pub modules: Vec<ModuleGraphModule>,
+}
+
+impl ModuleGraph {
+  pub fn get(&self, id: ModuleId) -> Option<&ModuleGraphModule> {
+    self.modules.iter().find(|module| module.id == id)
+  }
+
+  pub fn get_by_specifier(
+    &self,
+    specifier: &str,
+  ) -> Option<&ModuleGraphModule> {
+    self.modules.iter().find(|module| {
+      module.specifier == specifier
+        || module
+          .aliases
+          .iter()
+          .any(|alias| alias.specifier == specifier)
+    })
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleGraphModule {
+  pub id: ModuleId,
+  /// The specifier the module was found at, after redirects.
+  pub specifier: String,
+  #[serde(serialize_with = "serialize_module_type")]
+  pub module_type: ModuleType,
+  pub main: bool,
+  /// The specifiers that were redirected to this module.
+  pub aliases: Vec<ModuleGraphAlias>,
+  /// The imports of the module, static ones first in source order, then
+  /// dynamic ones in the order they were first loaded.
+  pub dependencies: Vec<ModuleGraphDependency>,
+  pub status: ModuleStatus,
+  /// The exception thrown by the module or by one of its dependencies, if
+  /// `status` is [`ModuleStatus::Errored`].
+  pub error: Option<String>,
+  /// Size in bytes of the source the module was created from. Modules
+  /// injected by the embedder rather than loaded have a size of 0.
+  pub source_size: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleGraphAlias {
+  pub specifier: String,
+  /// The URLs `specifier` was redirected through, in order, ending with the
+  /// specifier of the module.
+  pub redirect_chain: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleGraphDependency {
+  /// The resolved specifier of the import.
+  pub specifier: String,
+  pub kind: ImportKind,
+  /// The `type` import attribute, if any.
+  pub attribute_type: Option<String>,
+  /// The module the import resolves to, if it's loaded.
+  pub id: Option<ModuleId>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportKind {
+  /// An `import` declaration or `export ... from` declaration.
+  Static,
+  /// An `import()` expression.
+  Dynamic,
+}
+
+/// The status of a module as reported by V8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ModuleStatus {
+  Uninstantiated,
+  Instantiating,
+  Instantiated,
+  Evaluating,
+  Evaluated,
+  Errored,
+}
+
+impl From<v8::ModuleStatus> for ModuleStatus {
+  fn from(status: v8::ModuleStatus) -> Self {
+    match status {
+      v8::ModuleStatus::Uninstantiated => Self::Uninstantiated,
+      v8::ModuleStatus::Instantiating => Self::Instantiating,
+      v8::ModuleStatus::Instantiated => Self::Instantiated,
+      v8::ModuleStatus::Evaluating => Self::Evaluating,
+      v8::ModuleStatus::Evaluated => Self::Evaluated,
+      v8::ModuleStatus::Errored => Self::Errored,
+    }
+  }
+}
+
+fn serialize_module_type<S: Serializer>(
+  module_type: &ModuleType,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  match module_type {
+    ModuleType::JavaScript => serializer.serialize_str("javascript"),
+    ModuleType::Json => serializer.serialize_str("json"),
+    ModuleType::Wasm => serializer.serialize_str("wasm"),
+    ModuleType::CommonJs => serializer.serialize_str("commonjs"),
+    ModuleType::Other(ty) => serializer.serialize_str(ty),
+  }
+}
+
+impl ModuleGraph {
+  pub(crate) fn new(
+    scope: &mut v8::HandleScope,
+    module_map: &ModuleMap,
+  ) -> Self {
+    let mut modules = vec![];
+    for id in module_map.registered_module_ids() {
+      let info = &module_map.info[id];
+      let module = v8::Local::new(scope, &module_map.handles[id]);
+      let status = module.get_status();
+      let error = (status == v8::ModuleStatus::Errored).then(|| {
+        let exception = module.get_exception();
+        JsError::from_v8_exception(scope, exception).exception_message
+      });
+
+      let dependency = |request: &ModuleRequest, kind| ModuleGraphDependency {
+        specifier: request.specifier.clone(),
+        kind,
+        attribute_type: match &request.asserted_module_type {
+          AssertedModuleType::JavaScriptOrWasm => None,
+          AssertedModuleType::Json => Some("json".to_string()),
+          AssertedModuleType::Other(ty) => Some(ty.to_string()),
+        },
+        id: module_map
+          .get_id(&request.specifier, &request.asserted_module_type),
+      };
+      let dependencies = info
+        .requests
+        .iter()
+        .map(|request| dependency(request, ImportKind::Static))
+        .chain(
+          info
+            .dynamic_requests
+            .iter()
+            .map(|request| dependency(request, ImportKind::Dynamic)),
+        )
+        .collect();
+
+      modules.push(ModuleGraphModule {
+        id,
+        specifier: info.name.as_str().to_string(),
+        module_type: info.module_type.clone(),
+        main: info.main,
+        aliases: vec![],
+        dependencies,
+        status: status.into(),
+        error,
+        source_size: info.source_size,
+      });
+    }
+
+    for (asserted_module_type, name, symbolic_module) in
+      module_map.collect_modules()
+    {
+      let SymbolicModule::Alias(_) = symbolic_module else {
+        continue;
+      };
+      let Some(id) = module_map.get_id(name, &asserted_module_type) else {
+        continue;
+      };
+      let mut redirect_chain =
+        module_map.redirect_chain(name.as_str(), &asserted_module_type);
+      redirect_chain.remove(0);
+      if let Some(module) = modules.iter_mut().find(|module| module.id == id) {
+        module.aliases.push(ModuleGraphAlias {
+          specifier: name.as_str().to_string(),
+          redirect_chain,
+        });
+      }
+    }
+    for module in &mut modules {
+      module.aliases.sort_by(|a, b| a.specifier.cmp(&b.specifier));
+    }
+
+    Self { modules }
+  }
+}
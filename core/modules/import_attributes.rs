@@ -0,0 +1,282 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+
+// The V8 version used here only parses the legacy `assert` form of import
+// attributes, so the `with` form is rewritten to it before modules are
+// compiled. Both forms carry the same attributes.
+//
+// A `with` keyword directly following a string literal and followed by `{`
+// can only be the attributes clause of an import or re-export declaration,
+// since `with` statements are not allowed in modules. The scanner below only
+// tokenizes enough of the source to find string literals outside of comments,
+// template literals and regular expressions.
+//
+// `assert` is two characters longer than `with`, so the blanks around `with`
+// make room for it. This keeps the columns of stack traces and source maps
+// right, unless there are less than two blanks on the line around `with`.
+
+const REGEX_PRECEDING_KEYWORDS: &[&str] = &[
+  "await",
+  "case",
+  "delete",
+  "do",
+  "else",
+  "in",
+  "instanceof",
+  "new",
+  "of",
+  "return",
+  "throw",
+  "typeof",
+  "void",
+  "yield",
+];
+
+/// Returns the source with the `with` attributes clauses of its static
+/// imports and re-exports rewritten to `assert`, or `None` if it has none.
+pub(crate) fn rewrite_with_clauses(source: &str) -> Option<String> {
+  if !source.contains("with") {
+    return None;
+  }
+  let bytes = source.as_bytes();
+  let mut clauses = vec![];
+  let mut i = 0;
+  let mut regex_allowed = true;
+  let mut after_string = false;
+  let mut brace_depth = 0usize;
+  // The brace depths at which template literal substitutions were opened.
+  let mut templates = vec![];
+
+  loop {
+    i = skip_trivia(bytes, i);
+    let Some(&c) = bytes.get(i) else {
+      break;
+    };
+    match c {
+      b'\'' | b'"' => {
+        i = skip_string(bytes, i);
+        regex_allowed = false;
+        after_string = true;
+        continue;
+      }
+      b'`' => {
+        i = skip_template_chunk(bytes, i + 1, &mut templates, &mut brace_depth);
+        regex_allowed = false;
+      }
+      b'}' if templates.last() == Some(&brace_depth) => {
+        templates.pop();
+        brace_depth -= 1;
+        i = skip_template_chunk(bytes, i + 1, &mut templates, &mut brace_depth);
+        regex_allowed = false;
+      }
+      b'/' if regex_allowed => {
+        i = skip_regex(bytes, i);
+        regex_allowed = false;
+      }
+      c if is_identifier_byte(c) => {
+        let start = i;
+        while i < bytes.len() && is_identifier_byte(bytes[i]) {
+          i += 1;
+        }
+        let word = &source[start..i];
+        if after_string
+          && word == "with"
+          && bytes.get(skip_trivia(bytes, i)) == Some(&b'{')
+        {
+          clauses.push(start);
+        }
+        regex_allowed = REGEX_PRECEDING_KEYWORDS.contains(&word);
+      }
+      _ => {
+        match c {
+          b'{' => brace_depth += 1,
+          b'}' => brace_depth = brace_depth.saturating_sub(1),
+          _ => {}
+        }
+        regex_allowed = !matches!(c, b')' | b']' | b'}');
+        i += 1;
+      }
+    }
+    after_string = false;
+  }
+
+  if clauses.is_empty() {
+    return None;
+  }
+  let mut rewritten = String::with_capacity(source.len() + clauses.len() * 2);
+  let mut last = 0;
+  for start in clauses {
+    let mut before = start;
+    let mut after = start + "with".len();
+    let mut removed = 0;
+    while removed < 2 && bytes.get(after).copied().map_or(false, is_blank) {
+      after += 1;
+      removed += 1;
+    }
+    while removed < 2 && before > last && is_blank(bytes[before - 1]) {
+      before -= 1;
+      removed += 1;
+    }
+    rewritten.push_str(&source[last..before]);
+    rewritten.push_str("assert");
+    last = after;
+  }
+  rewritten.push_str(&source[last..]);
+  Some(rewritten)
+}
+
+fn is_blank(c: u8) -> bool {
+  c == b' ' || c == b'\t'
+}
+
+fn is_identifier_byte(c: u8) -> bool {
+  c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80
+}
+
+/// Skips whitespace and comments.
+fn skip_trivia(bytes: &[u8], mut i: usize) -> usize {
+  while i < bytes.len() {
+    match (bytes[i], bytes.get(i + 1)) {
+      (b'/', Some(b'/')) => {
+        while i < bytes.len() && bytes[i] != b'\n' {
+          i += 1;
+        }
+      }
+      (b'/', Some(b'*')) => {
+        i += 2;
+        while i < bytes.len() && !bytes[i..].starts_with(b"*/") {
+          i += 1;
+        }
+        i = (i + 2).min(bytes.len());
+      }
+      (c, _) if c.is_ascii_whitespace() => i += 1,
+      _ => break,
+    }
+  }
+  i
+}
+
+/// Skips a string literal starting at `i`, returning the index after it.
+fn skip_string(bytes: &[u8], mut i: usize) -> usize {
+  let quote = bytes[i];
+  i += 1;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'\\' => i += 2,
+      c if c == quote || c == b'\n' => return i + 1,
+      _ => i += 1,
+    }
+  }
+  bytes.len()
+}
+
+/// Skips the characters of a template literal starting at `i`, up to its end
+/// or to the start of a substitution, which is then recorded in `templates`.
+fn skip_template_chunk(
+  bytes: &[u8],
+  mut i: usize,
+  templates: &mut Vec<usize>,
+  brace_depth: &mut usize,
+) -> usize {
+  while i < bytes.len() {
+    match bytes[i] {
+      b'\\' => i += 2,
+      b'`' => return i + 1,
+      b'$' if bytes.get(i + 1) == Some(&b'{') => {
+        *brace_depth += 1;
+        templates.push(*brace_depth);
+        return i + 2;
+      }
+      _ => i += 1,
+    }
+  }
+  bytes.len()
+}
+
+/// Skips a regular expression literal starting at `i`, returning the index
+/// after its closing slash. Its flags are skipped as an identifier.
+fn skip_regex(bytes: &[u8], mut i: usize) -> usize {
+  let mut in_class = false;
+  i += 1;
+  while i < bytes.len() {
+    match bytes[i] {
+      b'\\' => i += 2,
+      b'[' => {
+        in_class = true;
+        i += 1;
+      }
+      b']' => {
+        in_class = false;
+        i += 1;
+      }
+      b'/' if !in_class => return i + 1,
+      b'\n' => return i,
+      _ => i += 1,
+    }
+  }
+  bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::rewrite_with_clauses;
+
+  #[test]
+  fn test_rewrite_with_clauses() {
+    assert_eq!(
+      rewrite_with_clauses(
+        r#"import a from "./a.txt" with { type: "text" };
+export { b } from './b.json'with{type:"json"};
+import "./c.txt" /* attributes */ with
+  { type: "text" };"#
+      )
+      .unwrap(),
+      r#"import a from "./a.txt"assert{ type: "text" };
+export { b } from './b.json'assert{type:"json"};
+import "./c.txt" /* attributes */assert
+  { type: "text" };"#
+    );
+  }
+
+  #[test]
+  fn test_rewrite_with_clauses_keeps_columns() {
+    let source = r#"import a from "./a.txt" with  { type: "text" }; a();
+import b from "./b.txt"  with
+  { type: "text" }; b();"#;
+    let rewritten = rewrite_with_clauses(source).unwrap();
+    assert_eq!(
+      rewritten,
+      r#"import a from "./a.txt" assert{ type: "text" }; a();
+import b from "./b.txt"assert
+  { type: "text" }; b();"#
+    );
+    for (line, rewritten_line) in source.lines().zip(rewritten.lines()) {
+      assert_eq!(line.len(), rewritten_line.len());
+    }
+  }
+
+  #[test]
+  fn test_rewrite_with_clauses_ignores_other_tokens() {
+    for source in [
+      r#"import a from "./a.js";"#,
+      r#"// import a from "./a.txt" with { type: "text" };"#,
+      r#"/* "a" with { */"#,
+      r#"const s = `"a" with { ${"b"} with {`;"#,
+      r#"const s = `${"a"}` + "with {";"#,
+      r#"const r = /"/; const with_ = "a"; with_ {"#,
+      r#"const o = { with: "a" };"#,
+    ] {
+      assert_eq!(rewrite_with_clauses(source), None, "{source}");
+    }
+  }
+
+  #[test]
+  fn test_rewrite_with_clauses_after_template_and_regex() {
+    let source = r#"const t = `${`"`}`; const r = /["'`]/g;
+import a from "./a.txt" with { type: "text" };"#;
+    assert_eq!(
+      rewrite_with_clauses(source).unwrap(),
+      r#"const t = `${`"`}`; const r = /["'`]/g;
+import a from "./a.txt"assert{ type: "text" };"#
+    );
+  }
+}
@@ -0,0 +1,63 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use crate::modules::ModuleId;
+use crate::modules::ModuleType;
+use anyhow::Error;
+
+/// Callback that adds properties to the `import.meta` object of a module,
+/// set in [`RuntimeOptions::import_meta_callback`](crate::RuntimeOptions::import_meta_callback).
+///
+/// It's called when `import.meta` is first accessed in a module, after `url`,
+/// `main` and `resolve` are set. Properties added with [`ImportMeta::set`]
+/// override those.
+pub type ImportMetaCallback =
+  Box<dyn Fn(&mut v8::HandleScope, &ImportMetaModuleInfo, &mut ImportMeta)>;
+
+/// Computes the value of a lazy `import.meta` property, the first time it's
+/// read. An error is thrown to the reading code.
+pub type LazyImportMetaCb =
+  Box<dyn FnOnce(&mut v8::HandleScope) -> Result<v8::Global<v8::Value>, Error>>;
+
+/// The module whose `import.meta` is being populated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportMetaModuleInfo {
+  pub id: ModuleId,
+  pub specifier: String,
+  pub main: bool,
+  pub module_type: ModuleType,
+}
+
+pub(crate) enum ImportMetaProperty {
+  Value(v8::Global<v8::Value>),
+  Lazy(LazyImportMetaCb),
+}
+
+/// The properties an [`ImportMetaCallback`] adds to `import.meta`.
+#[derive(Default)]
+pub struct ImportMeta {
+  pub(crate) properties: Vec<(String, ImportMetaProperty)>,
+}
+
+impl ImportMeta {
+  pub fn set(&mut self, name: impl Into<String>, value: v8::Global<v8::Value>) {
+    self
+      .properties
+      .push((name.into(), ImportMetaProperty::Value(value)));
+  }
+
+  /// Adds a property whose value is computed by `get` the first time it's
+  /// read, and then kept.
+  ///
+  /// If the runtime is snapshotted before the property is read, the
+  /// [`ImportMetaCallback`] of the restored runtime is called again for the
+  /// module to get the property when it's read.
+  pub fn set_lazy(
+    &mut self,
+    name: impl Into<String>,
+    get: impl FnOnce(&mut v8::HandleScope) -> Result<v8::Global<v8::Value>, Error>
+      + 'static,
+  ) {
+    self
+      .properties
+      .push((name.into(), ImportMetaProperty::Lazy(Box::new(get))));
+  }
+}
@@ -0,0 +1,423 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use crate::module_specifier::ImportMap;
+use crate::module_specifier::ModuleSpecifier;
+use crate::modules::ModuleLoader;
+use crate::modules::ModuleResolveFuture;
+use crate::modules::ModuleSource;
+use crate::modules::ModuleSourceFuture;
+use crate::modules::ResolutionKind;
+use anyhow::bail;
+use anyhow::Error;
+use futures::future::FutureExt;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use sha2::Sha384;
+use sha2::Sha512;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+const LOCKFILE_VERSION: &str = "1";
+
+/// How an [`IntegrityModuleLoader`] uses its lockfile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegrityMode {
+  /// Modules in the lockfile must match their hash. Other modules are loaded
+  /// without being checked against it.
+  Verify,
+  /// The hashes of loaded modules are recorded in the lockfile, replacing the
+  /// ones that don't match.
+  Update,
+  /// Every module must be in the lockfile and match its hash.
+  Frozen,
+}
+
+/// The sha256 hashes of module sources, by module specifier.
+///
+/// Its JSON form is
+/// `{ "version": "1", "modules": { "<specifier>": "<hex sha256>" } }`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleLockfile {
+  modules: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LockfileJson {
+  version: String,
+  modules: BTreeMap<String, String>,
+}
+
+impl ModuleLockfile {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn parse(json: &str) -> Result<Self, Error> {
+    let lockfile: LockfileJson = serde_json::from_str(json)
+      .map_err(|err| anyhow::anyhow!("Invalid module lockfile: {err}"))?;
+    if lockfile.version != LOCKFILE_VERSION {
+      bail!(
+        "Unsupported module lockfile version \"{}\", expected \"{LOCKFILE_VERSION}\".",
+        lockfile.version
+      );
+    }
+    let modules = lockfile
+      .modules
+      .into_iter()
+      .map(|(specifier, hash)| (specifier, hash.to_ascii_lowercase()))
+      .collect();
+    Ok(Self { modules })
+  }
+
+  pub fn to_json_string(&self) -> String {
+    let lockfile = LockfileJson {
+      version: LOCKFILE_VERSION.to_string(),
+      modules: self.modules.clone(),
+    };
+    serde_json::to_string_pretty(&lockfile).unwrap()
+  }
+
+  /// The hex sha256 hash recorded for a module.
+  pub fn get(&self, specifier: &str) -> Option<&str> {
+    self.modules.get(specifier).map(String::as_str)
+  }
+
+  pub fn insert(&mut self, specifier: String, hash: String) {
+    self.modules.insert(specifier, hash.to_ascii_lowercase());
+  }
+
+  pub fn len(&self) -> usize {
+    self.modules.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.modules.is_empty()
+  }
+}
+
+/// Returned when the source of a module fails an integrity check of an
+/// [`IntegrityModuleLoader`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModuleIntegrityError {
+  /// The source doesn't match the hash recorded in the lockfile. Hashes are
+  /// hex sha256.
+  LockfileMismatch {
+    specifier: String,
+    expected: String,
+    actual: String,
+  },
+  /// The source doesn't match the integrity metadata of the import map.
+  /// `actual` is the metadata of the source for the strongest hash algorithm
+  /// of `expected`.
+  ImportMapMismatch {
+    specifier: String,
+    expected: String,
+    actual: String,
+  },
+  /// The module is not in a frozen lockfile.
+  NotInLockfile { specifier: String },
+  /// The import map has integrity metadata for the module without a sha256,
+  /// sha384 or sha512 hash.
+  UnsupportedMetadata { specifier: String, metadata: String },
+}
+
+impl std::error::Error for ModuleIntegrityError {}
+
+impl fmt::Display for ModuleIntegrityError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::LockfileMismatch {
+        specifier,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "Integrity check failed for module \"{specifier}\": the lockfile expects the sha256 hash {expected}, but the source hashes to {actual}."
+      ),
+      Self::ImportMapMismatch {
+        specifier,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "Integrity check failed for module \"{specifier}\": the import map expects \"{expected}\", but the source hashes to \"{actual}\"."
+      ),
+      Self::NotInLockfile { specifier } => write!(
+        f,
+        "Module \"{specifier}\" is not in the lockfile, which is frozen."
+      ),
+      Self::UnsupportedMetadata {
+        specifier,
+        metadata,
+      } => write!(
+        f,
+        "Unsupported integrity metadata \"{metadata}\" for module \"{specifier}\": only sha256, sha384 and sha512 hashes are supported."
+      ),
+    }
+  }
+}
+
+/// Module loader checking the sources loaded by another loader against a
+/// [`ModuleLockfile`] and, optionally, the integrity metadata of an
+/// [`ImportMap`].
+///
+/// Modules are identified by the specifier they were loaded with, before any
+/// redirect. A failed check fails the load of the module, and so the load of
+/// the module graph it's part of.
+///
+/// The lockfile is updated in place in [`IntegrityMode::Update`]; keep an
+/// `Rc` to the loader to read it back with [`IntegrityModuleLoader::lockfile`]
+/// once modules are loaded.
+pub struct IntegrityModuleLoader {
+  inner: Rc<dyn ModuleLoader>,
+  mode: IntegrityMode,
+  lockfile: Rc<RefCell<ModuleLockfile>>,
+  import_map: Option<Rc<ImportMap>>,
+}
+
+impl IntegrityModuleLoader {
+  pub fn new(
+    lockfile: ModuleLockfile,
+    mode: IntegrityMode,
+    inner: Rc<dyn ModuleLoader>,
+  ) -> Self {
+    Self {
+      inner,
+      mode,
+      lockfile: Rc::new(RefCell::new(lockfile)),
+      import_map: None,
+    }
+  }
+
+  /// Also check modules against the `"integrity"` entries of `import_map`,
+  /// in every mode.
+  pub fn with_import_map(mut self, import_map: ImportMap) -> Self {
+    self.import_map = Some(Rc::new(import_map));
+    self
+  }
+
+  pub fn mode(&self) -> IntegrityMode {
+    self.mode
+  }
+
+  pub fn lockfile(&self) -> ModuleLockfile {
+    self.lockfile.borrow().clone()
+  }
+}
+
+fn check_source(
+  mode: IntegrityMode,
+  lockfile: &RefCell<ModuleLockfile>,
+  import_map: Option<&ImportMap>,
+  module_specifier: &ModuleSpecifier,
+  module_source: &ModuleSource,
+) -> Result<(), ModuleIntegrityError> {
+  let specifier = module_specifier.as_str();
+  let digest: [u8; 32] = Sha256::digest(module_source.code.as_bytes()).into();
+
+  if let Some(metadata) =
+    import_map.and_then(|import_map| import_map.integrity(module_specifier))
+  {
+    check_metadata(specifier, metadata, module_source.code.as_bytes())?;
+  }
+
+  let actual = to_hex(&digest);
+  let mut lockfile = lockfile.borrow_mut();
+  match (mode, lockfile.get(specifier)) {
+    (IntegrityMode::Update, _) => {
+      lockfile.insert(specifier.to_string(), actual);
+    }
+    (_, Some(expected)) if expected != actual => {
+      return Err(ModuleIntegrityError::LockfileMismatch {
+        specifier: specifier.to_string(),
+        expected: expected.to_string(),
+        actual,
+      });
+    }
+    (IntegrityMode::Frozen, None) => {
+      return Err(ModuleIntegrityError::NotInLockfile {
+        specifier: specifier.to_string(),
+      });
+    }
+    _ => {}
+  }
+  Ok(())
+}
+
+/// The hash algorithms supported in subresource integrity metadata, from the
+/// weakest to the strongest.
+const SRI_ALGORITHMS: [&str; 3] = ["sha256", "sha384", "sha512"];
+
+fn sri_digest(algorithm: &str, source: &[u8]) -> Vec<u8> {
+  match algorithm {
+    "sha256" => Sha256::digest(source).to_vec(),
+    "sha384" => Sha384::digest(source).to_vec(),
+    "sha512" => Sha512::digest(source).to_vec(),
+    _ => unreachable!("unsupported algorithm {algorithm}"),
+  }
+}
+
+/// Checks a source against subresource integrity metadata, ie. whitespace
+/// separated `<algorithm>-<base64 hash>[?<options>]` items. As in the SRI
+/// spec, only the items of the strongest algorithm present are matched, any
+/// of which can match, and items of unsupported algorithms are ignored.
+fn check_metadata(
+  specifier: &str,
+  metadata: &str,
+  source: &[u8],
+) -> Result<(), ModuleIntegrityError> {
+  let hashes = metadata
+    .split_ascii_whitespace()
+    .filter_map(|item| {
+      let (algorithm, hash) = item.split('?').next()?.split_once('-')?;
+      let strength = SRI_ALGORITHMS.iter().position(|a| *a == algorithm)?;
+      Some((strength, hash))
+    })
+    .collect::<Vec<_>>();
+  let Some(strongest) = hashes.iter().map(|(strength, _)| *strength).max()
+  else {
+    if metadata.trim().is_empty() {
+      return Ok(());
+    }
+    return Err(ModuleIntegrityError::UnsupportedMetadata {
+      specifier: specifier.to_string(),
+      metadata: metadata.to_string(),
+    });
+  };
+  let algorithm = SRI_ALGORITHMS[strongest];
+  let digest = sri_digest(algorithm, source);
+  let matches = hashes
+    .iter()
+    .filter(|(strength, _)| *strength == strongest)
+    .any(|(_, hash)| base64::decode(hash).ok().as_deref() == Some(&digest[..]));
+  if !matches {
+    return Err(ModuleIntegrityError::ImportMapMismatch {
+      specifier: specifier.to_string(),
+      expected: metadata.to_string(),
+      actual: format!("{algorithm}-{}", base64::encode(&digest)),
+    });
+  }
+  Ok(())
+}
+
+impl ModuleLoader for IntegrityModuleLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    self.inner.resolve(specifier, referrer, kind)
+  }
+
+  fn resolve_async(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Pin<Box<ModuleResolveFuture>> {
+    self.inner.resolve_async(specifier, referrer, kind)
+  }
+
+  fn supports_async_resolution(&self) -> bool {
+    self.inner.supports_async_resolution()
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    let mode = self.mode;
+    let lockfile = self.lockfile.clone();
+    let import_map = self.import_map.clone();
+    let module_specifier = module_specifier.clone();
+    let fut = self
+      .inner
+      .load(&module_specifier, maybe_referrer, is_dyn_import);
+    async move {
+      let module_source = fut.await?;
+      check_source(
+        mode,
+        &lockfile,
+        import_map.as_deref(),
+        &module_specifier,
+        &module_source,
+      )?;
+      Ok(module_source)
+    }
+    .boxed_local()
+  }
+
+  fn prepare_load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<String>,
+    is_dyn_import: bool,
+  ) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
+    self
+      .inner
+      .prepare_load(module_specifier, maybe_referrer, is_dyn_import)
+  }
+
+  fn code_cache_ready(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    code_cache: &[u8],
+    rejected: bool,
+  ) {
+    self
+      .inner
+      .code_cache_ready(module_specifier, code_cache, rejected)
+  }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  let mut hex = String::with_capacity(bytes.len() * 2);
+  for byte in bytes {
+    write!(hex, "{byte:02x}").unwrap();
+  }
+  hex
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sri_metadata() {
+    const SHA256: &str = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+    const SHA384: &str =
+      "sha384-OLBgp1GsljhM2TJ+sbHjaiH9txEUvgdDTAzHv2P24donTt6/529l+9Ua0vFImLlb";
+    const SHA512: &str = "sha512-z4PhNX7vuL3xVChQ1m2AB9Yg5AULVxXcg/SpIdNs6c5H0NE8XYXysP+DGNKHfuwvY7kxvUdBeoGlODJ6+SfaPg==";
+    for metadata in [
+      SHA256.to_string(),
+      SHA384.to_string(),
+      SHA512.to_string(),
+      format!("sha256-bad {SHA256}?x"),
+      format!("md5-abc {SHA256}"),
+      // Only the strongest algorithm is matched.
+      format!("sha256-bad {SHA384}"),
+      String::new(),
+    ] {
+      check_metadata("file:///a.js", &metadata, b"").unwrap();
+    }
+    for metadata in ["sha256-bad".to_string(), format!("{SHA256} sha512-bad")] {
+      assert!(matches!(
+        check_metadata("file:///a.js", &metadata, b""),
+        Err(ModuleIntegrityError::ImportMapMismatch { .. })
+      ));
+    }
+    assert!(matches!(
+      check_metadata("file:///a.js", "sha1-abc", b""),
+      Err(ModuleIntegrityError::UnsupportedMetadata { .. })
+    ));
+  }
+}
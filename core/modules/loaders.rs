@@ -1,10 +1,14 @@
 // Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use crate::error::custom_error;
 use crate::error::generic_error;
 use crate::error::AnyError;
 use crate::extensions::ExtensionFileSource;
+use crate::module_specifier::ImportMap;
 use crate::module_specifier::ModuleSpecifier;
 use crate::modules::ModuleCode;
+use crate::modules::ModuleResolveFuture;
 use crate::modules::ModuleSource;
+use crate::modules::ModuleSourceCode;
 use crate::modules::ModuleSourceFuture;
 use crate::modules::ModuleType;
 use crate::modules::ResolutionKind;
@@ -17,8 +21,12 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::future::Future;
+use std::path::Path;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 pub trait ModuleLoader {
   /// Returns an absolute URL.
@@ -38,6 +46,42 @@ pub trait ModuleLoader {
     kind: ResolutionKind,
   ) -> Result<ModuleSpecifier, Error>;
 
+  /// Asynchronous variant of [`ModuleLoader::resolve`], for resolutions that
+  /// need async work, eg. reading a package manifest.
+  ///
+  /// It's only used by loaders that opt in with
+  /// [`ModuleLoader::supports_async_resolution`]. Module loads then await it
+  /// when [`ModuleLoader::resolve`] fails to resolve their root module, or an
+  /// import of a module, which is registered once all its imports are
+  /// resolved. The resolutions of imports are then used instead of calling
+  /// [`ModuleLoader::resolve`] again when the module graph is instantiated.
+  /// Imports of modules created from code passed to
+  /// [`JsRuntime::load_main_module`](crate::JsRuntime::load_main_module) or
+  /// [`JsRuntime::load_side_module`](crate::JsRuntime::load_side_module) are
+  /// only resolved with [`ModuleLoader::resolve`].
+  ///
+  /// It's not required to implement this method. By default it calls
+  /// [`ModuleLoader::resolve`].
+  fn resolve_async(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Pin<Box<ModuleResolveFuture>> {
+    futures::future::ready(self.resolve(specifier, referrer, kind))
+      .boxed_local()
+  }
+
+  /// Whether module loads fall back to [`ModuleLoader::resolve_async`] for
+  /// the specifiers that [`ModuleLoader::resolve`] fails to resolve. If not,
+  /// the errors of [`ModuleLoader::resolve`] fail the loads.
+  ///
+  /// It's not required to implement this method. By default it returns
+  /// `false`.
+  fn supports_async_resolution(&self) -> bool {
+    false
+  }
+
   /// Given ModuleSpecifier, load its source code.
   ///
   /// `is_dyn_import` can be used to check permissions or deny
@@ -65,6 +109,24 @@ pub trait ModuleLoader {
   ) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
     async { Ok(()) }.boxed_local()
   }
+
+  /// Called with a fresh V8 code cache for a module after it has been
+  /// evaluated, so that it can be persisted and passed back in
+  /// [`ModuleSource::code_cache`] when the module is loaded again.
+  ///
+  /// This is only called for modules that were loaded with a
+  /// [`ModuleCodeCache`](crate::ModuleCodeCache) that is either missing or
+  /// could not be used. In the latter case `rejected` is `true`, and the stale
+  /// code cache should be replaced.
+  ///
+  /// It's not required to implement this method.
+  fn code_cache_ready(
+    &self,
+    _module_specifier: &ModuleSpecifier,
+    _code_cache: &[u8],
+    _rejected: bool,
+  ) {
+  }
 }
 
 /// Placeholder structure used when creating
@@ -205,7 +267,7 @@ impl Drop for ExtModuleLoader {
 ///
 /// Note that this loader will **block** event loop
 /// when loading file as it uses synchronous FS API
-/// from standard library.
+/// from standard library. Use [`AsyncFsModuleLoader`] to avoid that.
 pub struct FsModuleLoader;
 
 impl ModuleLoader for FsModuleLoader {
@@ -232,18 +294,14 @@ impl ModuleLoader for FsModuleLoader {
           "Provided module specifier \"{module_specifier}\" is not a file URL."
         ))
       })?;
-      let module_type = if let Some(extension) = path.extension() {
-        let ext = extension.to_string_lossy().to_lowercase();
-        if ext == "json" {
-          ModuleType::Json
-        } else {
-          ModuleType::JavaScript
+      let module_type = module_type_from_path(&path);
+
+      let code = match module_type {
+        ModuleType::Wasm => {
+          ModuleSourceCode::Bytes(std::fs::read(path)?.into())
         }
-      } else {
-        ModuleType::JavaScript
+        _ => ModuleSourceCode::String(std::fs::read_to_string(path)?.into()),
       };
-
-      let code = std::fs::read_to_string(path)?.into();
       let module = ModuleSource::new(module_type, code, module_specifier);
       Ok(module)
     }
@@ -251,3 +309,683 @@ impl ModuleLoader for FsModuleLoader {
     futures::future::ready(load(module_specifier)).boxed_local()
   }
 }
+
+fn module_type_from_path(path: &Path) -> ModuleType {
+  if let Some(extension) = path.extension() {
+    let ext = extension.to_string_lossy().to_lowercase();
+    if ext == "json" {
+      ModuleType::Json
+    } else if ext == "wasm" {
+      ModuleType::Wasm
+    } else {
+      ModuleType::JavaScript
+    }
+  } else {
+    ModuleType::JavaScript
+  }
+}
+
+/// Options for [`AsyncFsModuleLoader`].
+#[derive(Clone, Debug)]
+pub struct AsyncFsModuleLoaderOptions {
+  /// Extensions that are appended, in order, to the path of a module that
+  /// doesn't exist, eg. `"./mod"` can be loaded from `"./mod.js"`.
+  pub extensions: Vec<String>,
+  /// File names that are tried, in order, when the path of a module is a
+  /// directory, eg. `"./lib"` can be loaded from `"./lib/index.js"`.
+  pub index_files: Vec<String>,
+  /// Resolve symbolic links, so that a module is registered under its real
+  /// path and only evaluated once.
+  pub canonicalize: bool,
+  /// Keep the sources of loaded modules in memory, and only read them again
+  /// if their modification time or size changed.
+  pub cache: bool,
+}
+
+impl Default for AsyncFsModuleLoaderOptions {
+  fn default() -> Self {
+    Self {
+      extensions: vec!["js".to_string(), "mjs".to_string(), "json".to_string()],
+      index_files: vec![
+        "index.js".to_string(),
+        "index.mjs".to_string(),
+        "index.json".to_string(),
+      ],
+      canonicalize: true,
+      cache: true,
+    }
+  }
+}
+
+#[derive(Clone)]
+enum CachedCode {
+  String(Arc<str>),
+  Bytes(Arc<[u8]>),
+}
+
+impl CachedCode {
+  fn to_module_source_code(&self) -> ModuleSourceCode {
+    match self {
+      Self::String(code) => ModuleSourceCode::String(code.clone().into()),
+      Self::Bytes(code) => ModuleSourceCode::Bytes(code.clone().into()),
+    }
+  }
+}
+
+struct CachedModuleSource {
+  modified: SystemTime,
+  len: u64,
+  code: CachedCode,
+}
+
+/// File system module loader built on the async file system API of tokio,
+/// so that it doesn't block the event loop.
+///
+/// When the specified file doesn't exist, the configured extensions and index
+/// files are probed. If the module is found at another path than the
+/// specified one, because of probing or symbolic links, that path is reported
+/// as a redirect through [`ModuleSource::new_with_redirect_chain`].
+pub struct AsyncFsModuleLoader {
+  options: Rc<AsyncFsModuleLoaderOptions>,
+  cache: Rc<RefCell<HashMap<PathBuf, CachedModuleSource>>>,
+}
+
+impl AsyncFsModuleLoader {
+  pub fn new(options: AsyncFsModuleLoaderOptions) -> Self {
+    Self {
+      options: Rc::new(options),
+      cache: Default::default(),
+    }
+  }
+
+  /// Drop all cached module sources.
+  pub fn clear_cache(&self) {
+    self.cache.borrow_mut().clear();
+  }
+
+  async fn find_module_path(
+    options: &AsyncFsModuleLoaderOptions,
+    path: PathBuf,
+  ) -> Option<(PathBuf, std::fs::Metadata)> {
+    let maybe_metadata = tokio::fs::metadata(&path).await.ok();
+    if let Some(metadata) = &maybe_metadata {
+      if metadata.is_file() {
+        return Some((path, metadata.clone()));
+      }
+    }
+
+    for extension in &options.extensions {
+      let mut candidate = path.clone().into_os_string();
+      candidate.push(".");
+      candidate.push(extension);
+      let candidate = PathBuf::from(candidate);
+      if let Ok(metadata) = tokio::fs::metadata(&candidate).await {
+        if metadata.is_file() {
+          return Some((candidate, metadata));
+        }
+      }
+    }
+
+    if maybe_metadata.map(|m| m.is_dir()).unwrap_or(false) {
+      for index_file in &options.index_files {
+        let candidate = path.join(index_file);
+        if let Ok(metadata) = tokio::fs::metadata(&candidate).await {
+          if metadata.is_file() {
+            return Some((candidate, metadata));
+          }
+        }
+      }
+    }
+
+    None
+  }
+
+  async fn load_inner(
+    options: Rc<AsyncFsModuleLoaderOptions>,
+    cache: Rc<RefCell<HashMap<PathBuf, CachedModuleSource>>>,
+    module_specifier: ModuleSpecifier,
+  ) -> Result<ModuleSource, AnyError> {
+    let path = module_specifier.to_file_path().map_err(|_| {
+      generic_error(format!(
+        "Provided module specifier \"{module_specifier}\" is not a file URL."
+      ))
+    })?;
+    let Some((probed_path, metadata)) =
+      Self::find_module_path(&options, path).await
+    else {
+      return Err(custom_error(
+        "NotFound",
+        format!("Cannot find module \"{module_specifier}\"."),
+      ));
+    };
+    let path = if options.canonicalize {
+      tokio::fs::canonicalize(&probed_path).await?
+    } else {
+      probed_path.clone()
+    };
+    let module_type = module_type_from_path(&path);
+
+    let modified = metadata.modified()?;
+    let len = metadata.len();
+    let maybe_cached_code = if options.cache {
+      cache
+        .borrow()
+        .get(&path)
+        .filter(|cached| cached.modified == modified && cached.len == len)
+        .map(|cached| cached.code.clone())
+    } else {
+      None
+    };
+    let code = match maybe_cached_code {
+      Some(code) => code,
+      None => {
+        let code = match module_type {
+          ModuleType::Wasm => {
+            CachedCode::Bytes(tokio::fs::read(&path).await?.into())
+          }
+          _ => {
+            CachedCode::String(tokio::fs::read_to_string(&path).await?.into())
+          }
+        };
+        if options.cache {
+          cache.borrow_mut().insert(
+            path.clone(),
+            CachedModuleSource {
+              modified,
+              len,
+              code: code.clone(),
+            },
+          );
+        }
+        code
+      }
+    };
+    let code = code.to_module_source_code();
+
+    // Probing for the file and resolving symbolic links are reported as
+    // separate redirects.
+    let redirect_chain = [probed_path, path]
+      .iter()
+      .map(|path| {
+        ModuleSpecifier::from_file_path(path)
+          .map_err(|_| generic_error(format!("Invalid module path {path:?}.")))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(ModuleSource::new_with_redirect_chain(
+      module_type,
+      code,
+      &module_specifier,
+      &redirect_chain,
+    ))
+  }
+}
+
+impl Default for AsyncFsModuleLoader {
+  fn default() -> Self {
+    Self::new(Default::default())
+  }
+}
+
+impl ModuleLoader for AsyncFsModuleLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    _kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    Ok(resolve_import(specifier, referrer)?)
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    _maybe_referrer: Option<&ModuleSpecifier>,
+    _is_dynamic: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    Self::load_inner(
+      self.options.clone(),
+      self.cache.clone(),
+      module_specifier.clone(),
+    )
+    .boxed_local()
+  }
+}
+
+/// Module loader that resolves specifiers with an [`ImportMap`] before
+/// handing them to another loader.
+///
+/// The import map applies to every [`ResolutionKind`]. Referrers that are
+/// not URLs, like the `"."` used for the main module, are replaced by the
+/// base URL of the import map. Loading is left to the inner loader.
+pub struct ImportMapLoader {
+  import_map: ImportMap,
+  inner: Rc<dyn ModuleLoader>,
+}
+
+impl ImportMapLoader {
+  pub fn new(import_map: ImportMap, inner: Rc<dyn ModuleLoader>) -> Self {
+    Self { import_map, inner }
+  }
+
+  pub fn import_map(&self) -> &ImportMap {
+    &self.import_map
+  }
+}
+
+impl ModuleLoader for ImportMapLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    let referrer_url = ModuleSpecifier::parse(referrer)
+      .unwrap_or_else(|_| self.import_map.base_url().clone());
+    let resolved = self.import_map.resolve(specifier, &referrer_url)?;
+    self.inner.resolve(resolved.as_str(), referrer, kind)
+  }
+
+  fn resolve_async(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Pin<Box<ModuleResolveFuture>> {
+    let referrer_url = ModuleSpecifier::parse(referrer)
+      .unwrap_or_else(|_| self.import_map.base_url().clone());
+    match self.import_map.resolve(specifier, &referrer_url) {
+      Ok(resolved) => {
+        self.inner.resolve_async(resolved.as_str(), referrer, kind)
+      }
+      Err(err) => futures::future::err(err.into()).boxed_local(),
+    }
+  }
+
+  fn supports_async_resolution(&self) -> bool {
+    self.inner.supports_async_resolution()
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    self
+      .inner
+      .load(module_specifier, maybe_referrer, is_dyn_import)
+  }
+
+  fn prepare_load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<String>,
+    is_dyn_import: bool,
+  ) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
+    self
+      .inner
+      .prepare_load(module_specifier, maybe_referrer, is_dyn_import)
+  }
+
+  fn code_cache_ready(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    code_cache: &[u8],
+    rejected: bool,
+  ) {
+    self
+      .inner
+      .code_cache_ready(module_specifier, code_cache, rejected)
+  }
+}
+
+/// Module loader that dispatches to other loaders by the scheme of the
+/// module specifier, eg. `file:` modules to an [`AsyncFsModuleLoader`] and
+/// `data:` modules to a [`DataUrlModuleLoader`].
+///
+/// A specifier that isn't an absolute URL is resolved by the loader of its
+/// referrer's scheme, so that relative and bare imports are handled by the
+/// loader that loaded the importing module. Specifiers whose scheme has no
+/// loader, and the main module when it's not given as a URL, go to the
+/// fallback loader if there is one.
+#[derive(Default)]
+pub struct RoutingModuleLoader {
+  loaders: HashMap<String, Rc<dyn ModuleLoader>>,
+  fallback: Option<Rc<dyn ModuleLoader>>,
+}
+
+impl RoutingModuleLoader {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Routes the modules with the given scheme, eg. `"file"`, to `loader`.
+  pub fn with_scheme(
+    mut self,
+    scheme: &str,
+    loader: Rc<dyn ModuleLoader>,
+  ) -> Self {
+    let scheme = scheme.trim_end_matches(':').to_ascii_lowercase();
+    self.loaders.insert(scheme, loader);
+    self
+  }
+
+  /// Routes the modules whose scheme has no loader to `loader`.
+  pub fn with_fallback(mut self, loader: Rc<dyn ModuleLoader>) -> Self {
+    self.fallback = Some(loader);
+    self
+  }
+
+  fn loader_for_scheme(
+    &self,
+    scheme: Option<&str>,
+  ) -> Option<&dyn ModuleLoader> {
+    scheme
+      .and_then(|scheme| self.loaders.get(scheme))
+      .or(self.fallback.as_ref())
+      .map(|loader| &**loader)
+  }
+
+  /// The loader for the scheme of `specifier` if it's a URL, or else of
+  /// `referrer`.
+  fn loader_for_resolution(
+    &self,
+    specifier: &str,
+    referrer: &str,
+  ) -> Result<&dyn ModuleLoader, Error> {
+    let maybe_url = ModuleSpecifier::parse(specifier)
+      .or_else(|_| ModuleSpecifier::parse(referrer))
+      .ok();
+    let scheme = maybe_url.as_ref().map(|url| url.scheme());
+    self.loader_for_scheme(scheme).ok_or_else(|| {
+      generic_error(format!(
+        "No module loader for the \"{}:\" scheme, attempted to resolve \"{specifier}\" from \"{referrer}\".",
+        scheme.unwrap_or_default(),
+      ))
+    })
+  }
+
+  fn loader_for_specifier(
+    &self,
+    module_specifier: &ModuleSpecifier,
+  ) -> Result<&dyn ModuleLoader, Error> {
+    self
+      .loader_for_scheme(Some(module_specifier.scheme()))
+      .ok_or_else(|| {
+        generic_error(format!(
+          "No module loader for the \"{}:\" scheme, attempted to load \"{module_specifier}\".",
+          module_specifier.scheme(),
+        ))
+      })
+  }
+}
+
+impl ModuleLoader for RoutingModuleLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    self
+      .loader_for_resolution(specifier, referrer)?
+      .resolve(specifier, referrer, kind)
+  }
+
+  fn resolve_async(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Pin<Box<ModuleResolveFuture>> {
+    match self.loader_for_resolution(specifier, referrer) {
+      Ok(loader) => loader.resolve_async(specifier, referrer, kind),
+      Err(err) => futures::future::err(err).boxed_local(),
+    }
+  }
+
+  fn supports_async_resolution(&self) -> bool {
+    self
+      .loaders
+      .values()
+      .chain(&self.fallback)
+      .any(|loader| loader.supports_async_resolution())
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    match self.loader_for_specifier(module_specifier) {
+      Ok(loader) => {
+        loader.load(module_specifier, maybe_referrer, is_dyn_import)
+      }
+      Err(err) => futures::future::err(err).boxed_local(),
+    }
+  }
+
+  fn prepare_load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<String>,
+    is_dyn_import: bool,
+  ) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
+    match self.loader_for_specifier(module_specifier) {
+      Ok(loader) => {
+        loader.prepare_load(module_specifier, maybe_referrer, is_dyn_import)
+      }
+      Err(err) => futures::future::err(err).boxed_local(),
+    }
+  }
+
+  fn code_cache_ready(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    code_cache: &[u8],
+    rejected: bool,
+  ) {
+    if let Ok(loader) = self.loader_for_specifier(module_specifier) {
+      loader.code_cache_ready(module_specifier, code_cache, rejected)
+    }
+  }
+}
+
+/// Module loader for `data:` URLs (RFC 2397), whose data is either
+/// percent-encoded or base64-encoded.
+///
+/// The module type is determined by the media type: JavaScript for
+/// `text/javascript` and its legacy aliases, JSON for `application/json`,
+/// `text/json` and `+json` types, and Wasm for `application/wasm`. Text is
+/// decoded as UTF-8 whatever the `charset` parameter.
+pub struct DataUrlModuleLoader;
+
+impl DataUrlModuleLoader {
+  fn load_data_url(
+    module_specifier: &ModuleSpecifier,
+  ) -> Result<ModuleSource, Error> {
+    let invalid = |reason: &str| {
+      custom_error(
+        "TypeError",
+        format!("Invalid data URL \"{module_specifier}\": {reason}."),
+      )
+    };
+    if module_specifier.scheme() != "data" {
+      return Err(invalid("not a data: URL"));
+    }
+    let url = module_specifier.as_str();
+    let url = &url["data:".len()..];
+    // The fragment isn't part of the data.
+    let url = url.split_once('#').map(|(url, _)| url).unwrap_or(url);
+    let Some((media_type, data)) = url.split_once(',') else {
+      return Err(invalid("missing comma"));
+    };
+
+    let mut params = media_type.split(';').map(str::trim).collect::<Vec<_>>();
+    let is_base64 = params
+      .last()
+      .map(|param| param.eq_ignore_ascii_case("base64"))
+      .unwrap_or(false);
+    if is_base64 {
+      params.pop();
+    }
+    let essence = params[0].to_ascii_lowercase();
+
+    let data = percent_decode(data.as_bytes());
+    let data = if is_base64 {
+      // Like the forgiving-base64 decode of the Infra standard, ASCII
+      // whitespace is ignored and padding is optional.
+      let data = data
+        .into_iter()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect::<Vec<_>>();
+      base64::decode(data).map_err(|_| invalid("invalid base64"))?
+    } else {
+      data
+    };
+
+    let module_type = match essence.as_str() {
+      "text/javascript"
+      | "application/javascript"
+      | "application/x-javascript"
+      | "application/ecmascript"
+      | "text/ecmascript" => ModuleType::JavaScript,
+      "application/json" | "text/json" => ModuleType::Json,
+      essence if essence.ends_with("+json") => ModuleType::Json,
+      "application/wasm" => ModuleType::Wasm,
+      "" => return Err(invalid("missing media type")),
+      essence => {
+        return Err(invalid(&format!("unsupported media type \"{essence}\"")))
+      }
+    };
+    let code = match module_type {
+      ModuleType::Wasm => ModuleSourceCode::Bytes(data.into()),
+      _ => ModuleSourceCode::String(
+        String::from_utf8(data)
+          .map_err(|_| invalid("invalid UTF-8"))?
+          .into(),
+      ),
+    };
+    Ok(ModuleSource::new(module_type, code, module_specifier))
+  }
+}
+
+impl ModuleLoader for DataUrlModuleLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    _kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    Ok(resolve_import(specifier, referrer)?)
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    _maybe_referrer: Option<&ModuleSpecifier>,
+    _is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    futures::future::ready(Self::load_data_url(module_specifier)).boxed_local()
+  }
+}
+
+fn percent_decode(input: &[u8]) -> Vec<u8> {
+  fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|value| value as u8)
+  }
+
+  let mut output = Vec::with_capacity(input.len());
+  let mut i = 0;
+  while i < input.len() {
+    if input[i] == b'%' && i + 2 < input.len() {
+      if let (Some(high), Some(low)) =
+        (hex_value(input[i + 1]), hex_value(input[i + 2]))
+      {
+        output.push(high << 4 | low);
+        i += 3;
+        continue;
+      }
+    }
+    output.push(input[i]);
+    i += 1;
+  }
+  output
+}
+
+/// Module loader serving sources held in memory, eg. generated code or the
+/// modules of a test, under any URL scheme.
+///
+/// Sources are kept for the lifetime of the loader, so a module can be loaded
+/// again, eg. in another realm.
+#[derive(Default)]
+pub struct StaticModuleLoader {
+  modules: HashMap<ModuleSpecifier, (ModuleType, CachedCode)>,
+}
+
+impl StaticModuleLoader {
+  /// Creates a loader serving the given sources. The type of each module is
+  /// determined by the extension of its path, like for files.
+  pub fn new<S: Into<Arc<str>>>(
+    modules: impl IntoIterator<Item = (ModuleSpecifier, S)>,
+  ) -> Self {
+    let modules = modules
+      .into_iter()
+      .map(|(specifier, code)| {
+        let module_type = module_type_from_path(Path::new(specifier.path()));
+        (specifier, (module_type, CachedCode::String(code.into())))
+      })
+      .collect();
+    Self { modules }
+  }
+
+  /// Adds a module, or replaces the module with the same specifier.
+  pub fn insert(
+    &mut self,
+    specifier: ModuleSpecifier,
+    module_type: ModuleType,
+    code: ModuleSourceCode,
+  ) {
+    let code = match code {
+      ModuleSourceCode::String(code) => {
+        CachedCode::String(code.as_str().into())
+      }
+      ModuleSourceCode::Bytes(code) => {
+        CachedCode::Bytes(code.as_bytes().into())
+      }
+    };
+    self.modules.insert(specifier, (module_type, code));
+  }
+}
+
+impl ModuleLoader for StaticModuleLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    _kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    Ok(resolve_import(specifier, referrer)?)
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    _maybe_referrer: Option<&ModuleSpecifier>,
+    _is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    let result = match self.modules.get(module_specifier) {
+      Some((module_type, code)) => Ok(ModuleSource::new(
+        module_type.clone(),
+        code.to_module_source_code(),
+        module_specifier,
+      )),
+      None => Err(custom_error(
+        "NotFound",
+        format!("Cannot find module \"{module_specifier}\"."),
+      )),
+    };
+    futures::future::ready(result).boxed_local()
+  }
+}
@@ -2,35 +2,55 @@
 use crate::error::exception_to_err_result;
 use crate::error::generic_error;
 use crate::error::throw_type_error;
+use crate::error::to_v8_type_error;
 use crate::fast_string::FastString;
+use crate::module_specifier::ModuleSpecifier;
 use crate::modules::get_asserted_module_type_from_assertions;
 use crate::modules::parse_import_assertions;
 use crate::modules::validate_import_assertions;
 use crate::modules::ImportAssertionsKind;
+use crate::modules::LazyImportMetaCb;
 use crate::modules::ModuleCode;
+use crate::modules::ModuleCodeCache;
 use crate::modules::ModuleError;
 use crate::modules::ModuleId;
 use crate::modules::ModuleInfo;
 use crate::modules::ModuleLoadId;
+use crate::modules::ModuleLoadPhase;
+use crate::modules::ModuleLoadTracer;
 use crate::modules::ModuleLoader;
 use crate::modules::ModuleName;
 use crate::modules::ModuleRequest;
+use crate::modules::ModuleSourceCode;
 use crate::modules::ModuleType;
 use crate::modules::NoopModuleLoader;
 use crate::modules::PrepareLoadFuture;
 use crate::modules::RecursiveModuleLoad;
 use crate::modules::ResolutionKind;
+use crate::modules::SyntheticModule;
+use crate::runtime::CompiledWasmModuleStore;
 use crate::runtime::SnapshottedData;
 use crate::JsRealm;
+use crate::JsRuntime;
 use anyhow::Error;
 use futures::future::FutureExt;
 use futures::stream::FuturesUnordered;
 use futures::stream::StreamFuture;
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::pin::Pin;
 use std::rc::Rc;
-
+use std::time::Instant;
+
+use super::code_cache;
+use super::commonjs;
+use super::commonjs::CommonJsModules;
+use super::import_attributes;
+use super::trace;
+use super::wasm;
 use super::AssertedModuleType;
 
 pub const BOM_CHAR: &[u8] = &[0xef, 0xbb, 0xbf];
@@ -55,27 +75,205 @@ pub(crate) enum SymbolicModule {
   Mod(ModuleId),
 }
 
+/// Data of each module, indexed by [`ModuleId`]. Unloading a module leaves a
+/// hole, so that the ids of other modules stay valid.
+pub(crate) struct ModuleSlots<T>(Vec<Option<T>>);
+
+impl<T> ModuleSlots<T> {
+  /// The number of modules in the slots.
+  pub fn len(&self) -> usize {
+    self.0.iter().flatten().count()
+  }
+
+  /// The id the next module will get.
+  pub fn next_id(&self) -> ModuleId {
+    self.0.len()
+  }
+
+  pub fn get(&self, id: ModuleId) -> Option<&T> {
+    self.0.get(id)?.as_ref()
+  }
+
+  pub fn push(&mut self, value: T) {
+    self.0.push(Some(value));
+  }
+
+  pub fn remove(&mut self, id: ModuleId) -> Option<T> {
+    self.0.get_mut(id)?.take()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (ModuleId, &T)> {
+    self
+      .0
+      .iter()
+      .enumerate()
+      .filter_map(|(id, value)| Some((id, value.as_ref()?)))
+  }
+}
+
+impl<T> Default for ModuleSlots<T> {
+  fn default() -> Self {
+    Self(vec![])
+  }
+}
+
+impl<T> From<Vec<T>> for ModuleSlots<T> {
+  fn from(values: Vec<T>) -> Self {
+    Self(values.into_iter().map(Some).collect())
+  }
+}
+
+impl<T> std::ops::Index<ModuleId> for ModuleSlots<T> {
+  type Output = T;
+
+  fn index(&self, id: ModuleId) -> &T {
+    self.get(id).expect("module was unloaded")
+  }
+}
+
+impl<T> std::ops::IndexMut<ModuleId> for ModuleSlots<T> {
+  fn index_mut(&mut self, id: ModuleId) -> &mut T {
+    self.0[id].as_mut().expect("module was unloaded")
+  }
+}
+
 /// A collection of JS modules.
 pub(crate) struct ModuleMap {
   // Handling of specifiers and v8 objects
-  pub handles: Vec<v8::Global<v8::Module>>,
-  pub info: Vec<ModuleInfo>,
+  pub handles: ModuleSlots<v8::Global<v8::Module>>,
+  pub info: ModuleSlots<ModuleInfo>,
   pub(crate) by_name_js: HashMap<ModuleName, SymbolicModule>,
   pub(crate) by_name_json: HashMap<ModuleName, SymbolicModule>,
+  pub(crate) by_name_other:
+    HashMap<String, HashMap<ModuleName, SymbolicModule>>,
   pub(crate) next_load_id: ModuleLoadId,
 
   // Handling of futures for loading module sources
   pub loader: Rc<dyn ModuleLoader>,
+  // The promise resolver and the referrer of each pending `import()`.
   pub(crate) dynamic_import_map:
-    HashMap<ModuleLoadId, v8::Global<v8::PromiseResolver>>,
+    HashMap<ModuleLoadId, (v8::Global<v8::PromiseResolver>, String)>,
   pub(crate) preparing_dynamic_imports:
     FuturesUnordered<Pin<Box<PrepareLoadFuture>>>,
   pub(crate) pending_dynamic_imports:
     FuturesUnordered<StreamFuture<RecursiveModuleLoad>>,
 
-  // This store is used temporarily, to forward parsed JSON
-  // value from `new_json_module` to `json_module_evaluation_steps`
-  json_value_store: HashMap<v8::Global<v8::Module>, v8::Global<v8::Value>>,
+  // This store is used temporarily, to forward the default export value of
+  // JSON and custom modules, or what embedder-provided synthetic modules and
+  // CommonJS modules export, from `new_synthetic_module` to
+  // `synthetic_module_evaluation_steps`
+  synthetic_value_store: HashMap<v8::Global<v8::Module>, SyntheticModuleValue>,
+
+  // Compiled `WebAssembly.Module` objects of Wasm modules, exposed to their
+  // generated JS modules through `import.meta.wasmModule`.
+  pub(crate) wasm_module_store:
+    HashMap<ModuleId, v8::Global<v8::WasmModuleObject>>,
+
+  // Modules that should have a code cache produced once they are evaluated.
+  pending_code_caches: Vec<PendingCodeCache>,
+
+  // Hot module replacement: the `import.meta.hot.data` object of each module,
+  // kept across reloads, and the callbacks registered by each module instance.
+  pub(crate) hot_data: HashMap<ModuleName, v8::Global<v8::Object>>,
+  pub(crate) hot_callbacks: HashMap<ModuleId, HotCallbacks>,
+
+  pub(crate) commonjs: CommonJsModules,
+
+  // Lazy `import.meta` properties added by the `import.meta` callback, that
+  // weren't read yet, by module and property name.
+  pub(crate) import_meta_lazy: HashMap<(ModuleId, String), LazyImportMetaCb>,
+
+  pub(crate) tracer: Option<ModuleLoadTracer>,
+
+  // The imports of registered modules that were resolved with
+  // `ModuleLoader::resolve_async`, by referrer and specifier as written. Only
+  // used to instantiate the referrers.
+  pub(crate) async_resolutions: HashMap<(String, String), ModuleSpecifier>,
+
+  // The source of each JavaScript, JSON and Wasm module, as it was loaded,
+  // for `ModuleArchiveWriter`.
+  pub(crate) sources: HashMap<ModuleId, ModuleSourceCode>,
+}
+
+/// Callbacks registered with `import.meta.hot.accept()` and
+/// `import.meta.hot.dispose()` by a module instance.
+#[derive(Default)]
+pub(crate) struct HotCallbacks {
+  pub accept: Vec<v8::Global<v8::Function>>,
+  pub dispose: Vec<v8::Global<v8::Function>>,
+}
+
+/// A module removed from the module map by [`ModuleMap::hot_invalidate`].
+pub(crate) struct InvalidatedModule {
+  pub id: ModuleId,
+  pub name: String,
+  pub asserted_module_type: AssertedModuleType,
+  pub main: bool,
+  /// Whether no other invalidated module imports this one, meaning that it
+  /// has to be loaded on its own.
+  pub root: bool,
+  /// The aliases that resolved to this module, which are removed along with
+  /// it.
+  pub aliases: Vec<RemovedAlias>,
+}
+
+/// An alias removed from the module map along with the module it resolved to.
+pub(crate) struct RemovedAlias {
+  pub name: String,
+  pub target: String,
+}
+
+/// What a synthetic module exports once it's evaluated.
+enum SyntheticModuleValue {
+  /// The `default` export of a JSON or custom module.
+  Default(v8::Global<v8::Value>),
+  /// The exports of a module registered by the embedder.
+  Exports(SyntheticModule),
+  /// `module.exports` of a CommonJS module, as the `default` export, and the
+  /// properties of it found by static analysis.
+  CommonJs {
+    specifier: ModuleSpecifier,
+    export_names: Vec<String>,
+  },
+}
+
+/// An ES module compiled by [`ModuleMap::compile_module_from_js_source`],
+/// that can be registered once its imports are resolved.
+pub(crate) struct CompiledModule {
+  name: ModuleName,
+  module_type: ModuleType,
+  handle: v8::Global<v8::Module>,
+  main: bool,
+  kind: ResolutionKind,
+  // The specifier of each import as written, and its asserted type.
+  imports: Vec<(String, AssertedModuleType)>,
+  // The source as it was loaded, before `with` clauses were rewritten.
+  source: ModuleCode,
+  source_size: usize,
+  // The source hash of the module, and whether its code cache was rejected,
+  // if a code cache should be produced once it's evaluated.
+  pending_code_cache: Option<(u64, bool)>,
+}
+
+impl CompiledModule {
+  pub(crate) fn name(&self) -> &str {
+    self.name.as_str()
+  }
+
+  pub(crate) fn kind(&self) -> ResolutionKind {
+    self.kind
+  }
+
+  /// The specifiers of the imports, as written.
+  pub(crate) fn import_specifiers(&self) -> impl Iterator<Item = &str> {
+    self.imports.iter().map(|(specifier, _)| specifier.as_str())
+  }
+}
+
+struct PendingCodeCache {
+  id: ModuleId,
+  source_hash: u64,
+  rejected: bool,
 }
 
 impl ModuleMap {
@@ -83,16 +281,15 @@ impl ModuleMap {
     &self,
   ) -> Vec<(AssertedModuleType, &ModuleName, &SymbolicModule)> {
     let mut output = vec![];
-    for module_type in [
-      AssertedModuleType::JavaScriptOrWasm,
-      AssertedModuleType::Json,
+    for (module_type, by_name) in [
+      (AssertedModuleType::JavaScriptOrWasm, &self.by_name_js),
+      (AssertedModuleType::Json, &self.by_name_json),
     ] {
-      output.extend(
-        self
-          .by_name(module_type)
-          .iter()
-          .map(|x| (module_type, x.0, x.1)),
-      )
+      output.extend(by_name.iter().map(|x| (module_type.clone(), x.0, x.1)))
+    }
+    for (ty, by_name) in &self.by_name_other {
+      let module_type = AssertedModuleType::Other(ty.clone().into());
+      output.extend(by_name.iter().map(|x| (module_type.clone(), x.0, x.1)))
     }
     output
   }
@@ -104,7 +301,7 @@ impl ModuleMap {
   ) {
     let mut not_evaluated = vec![];
 
-    for (i, handle) in self.handles.iter().enumerate() {
+    for (i, handle) in self.handles.iter() {
       let module = v8::Local::new(scope, handle);
       if !matches!(module.get_status(), v8::ModuleStatus::Evaluated) {
         not_evaluated.push(self.info[i].name.as_str().to_string());
@@ -129,11 +326,20 @@ impl ModuleMap {
     let next_load_id = v8::Integer::new(scope, self.next_load_id);
     array.set_index(scope, 0, next_load_id.into());
 
+    // Modules are renumbered in the snapshot to fill the holes left by
+    // unloaded modules.
+    let ids = self
+      .info
+      .iter()
+      .enumerate()
+      .map(|(new_id, (id, _))| (id, new_id))
+      .collect::<HashMap<_, _>>();
+
     let info_arr = v8::Array::new(scope, self.info.len() as i32);
-    for (i, info) in self.info.iter().enumerate() {
-      let module_info_arr = v8::Array::new(scope, 5);
+    for (i, (_, info)) in self.info.iter().enumerate() {
+      let module_info_arr = v8::Array::new(scope, 7);
 
-      let id = v8::Integer::new(scope, info.id as i32);
+      let id = v8::Integer::new(scope, i as i32);
       module_info_arr.set_index(scope, 0, id.into());
 
       let main = v8::Boolean::new(scope, info.main);
@@ -142,29 +348,18 @@ impl ModuleMap {
       let name = info.name.v8(scope);
       module_info_arr.set_index(scope, 2, name.into());
 
-      let array_len = 2 * info.requests.len() as i32;
-      let requests_arr = v8::Array::new(scope, array_len);
-      for (i, request) in info.requests.iter().enumerate() {
-        let specifier = v8::String::new_from_one_byte(
-          scope,
-          request.specifier.as_bytes(),
-          v8::NewStringType::Normal,
-        )
-        .unwrap();
-        requests_arr.set_index(scope, 2 * i as u32, specifier.into());
-
-        let asserted_module_type =
-          v8::Integer::new(scope, request.asserted_module_type as i32);
-        requests_arr.set_index(
-          scope,
-          (2 * i) as u32 + 1,
-          asserted_module_type.into(),
-        );
-      }
+      let requests_arr = module_requests_to_v8(scope, &info.requests);
       module_info_arr.set_index(scope, 3, requests_arr.into());
 
-      let module_type = v8::Integer::new(scope, info.module_type as i32);
-      module_info_arr.set_index(scope, 4, module_type.into());
+      let module_type = module_type_to_v8(scope, &info.module_type);
+      module_info_arr.set_index(scope, 4, module_type);
+
+      let dynamic_requests_arr =
+        module_requests_to_v8(scope, &info.dynamic_requests);
+      module_info_arr.set_index(scope, 5, dynamic_requests_arr.into());
+
+      let source_size = v8::Number::new(scope, info.source_size as f64);
+      module_info_arr.set_index(scope, 6, source_size.into());
 
       info_arr.set_index(scope, i as u32, module_info_arr.into());
     }
@@ -179,8 +374,9 @@ impl ModuleMap {
         let specifier = name.v8(scope);
         arr.set_index(scope, 0, specifier.into());
 
-        let asserted_module_type = v8::Integer::new(scope, module_type as i32);
-        arr.set_index(scope, 1, asserted_module_type.into());
+        let asserted_module_type =
+          asserted_module_type_to_v8(scope, &module_type);
+        arr.set_index(scope, 1, asserted_module_type);
 
         let symbolic_module: v8::Local<v8::Value> = match module {
           SymbolicModule::Alias(alias) => {
@@ -193,7 +389,7 @@ impl ModuleMap {
             alias.into()
           }
           SymbolicModule::Mod(id) => {
-            let id = v8::Integer::new(scope, *id as i32);
+            let id = v8::Integer::new(scope, ids[id] as i32);
             id.into()
           }
         };
@@ -206,7 +402,11 @@ impl ModuleMap {
 
     let array_global = v8::Global::new(scope, array);
 
-    let handles = self.handles.clone();
+    let handles = self
+      .handles
+      .iter()
+      .map(|(_, handle)| handle.clone())
+      .collect();
     SnapshottedData {
       module_map_data: array_global,
       module_handles: handles,
@@ -262,64 +462,41 @@ impl ModuleMap {
           .to_rust_string_lossy(scope)
           .into();
 
-        let requests_arr: v8::Local<v8::Array> = module_info_arr
-          .get_index(scope, 3)
-          .unwrap()
-          .try_into()
-          .unwrap();
-        let len = (requests_arr.length() as usize) / 2;
-        let mut requests = Vec::with_capacity(len);
-        for i in 0..len {
-          let specifier = requests_arr
-            .get_index(scope, (2 * i) as u32)
-            .unwrap()
-            .to_rust_string_lossy(scope);
-          let asserted_module_type_no = requests_arr
-            .get_index(scope, (2 * i + 1) as u32)
-            .unwrap()
-            .to_integer(scope)
-            .unwrap()
-            .value();
-          let asserted_module_type = match asserted_module_type_no {
-            0 => AssertedModuleType::JavaScriptOrWasm,
-            1 => AssertedModuleType::Json,
-            _ => unreachable!(),
-          };
-          requests.push(ModuleRequest {
-            specifier,
-            asserted_module_type,
-          });
-        }
+        let requests_val = module_info_arr.get_index(scope, 3).unwrap();
+        let requests = module_requests_from_v8(scope, requests_val);
 
-        let module_type_no = module_info_arr
-          .get_index(scope, 4)
+        let module_type_val = module_info_arr.get_index(scope, 4).unwrap();
+        let module_type = module_type_from_v8(scope, module_type_val);
+
+        let dynamic_requests_val = module_info_arr.get_index(scope, 5).unwrap();
+        let dynamic_requests =
+          module_requests_from_v8(scope, dynamic_requests_val);
+
+        let source_size = module_info_arr
+          .get_index(scope, 6)
           .unwrap()
           .to_integer(scope)
           .unwrap()
-          .value();
-        let module_type = match module_type_no {
-          0 => ModuleType::JavaScript,
-          1 => ModuleType::Json,
-          _ => unreachable!(),
-        };
+          .value() as usize;
 
         let module_info = ModuleInfo {
           id,
           main,
           name,
           requests,
+          dynamic_requests,
           module_type,
+          source_size,
         };
         info.push(module_info);
       }
 
-      self.info = info;
+      self.info = info.into();
     }
 
-    self
-      .by_name_mut(AssertedModuleType::JavaScriptOrWasm)
-      .clear();
-    self.by_name_mut(AssertedModuleType::Json).clear();
+    self.by_name_js.clear();
+    self.by_name_json.clear();
+    self.by_name_other.clear();
 
     {
       let by_name_arr: v8::Local<v8::Array> =
@@ -335,17 +512,9 @@ impl ModuleMap {
 
         let specifier =
           arr.get_index(scope, 0).unwrap().to_rust_string_lossy(scope);
-        let asserted_module_type = match arr
-          .get_index(scope, 1)
-          .unwrap()
-          .to_integer(scope)
-          .unwrap()
-          .value()
-        {
-          0 => AssertedModuleType::JavaScriptOrWasm,
-          1 => AssertedModuleType::Json,
-          _ => unreachable!(),
-        };
+        let asserted_module_type_val = arr.get_index(scope, 1).unwrap();
+        let asserted_module_type =
+          asserted_module_type_from_v8(scope, asserted_module_type_val);
 
         let symbolic_module_val = arr.get_index(scope, 2).unwrap();
         let val = if symbolic_module_val.is_number() {
@@ -364,26 +533,36 @@ impl ModuleMap {
         };
 
         self
-          .by_name_mut(asserted_module_type)
+          .by_name_mut(&asserted_module_type)
           .insert(specifier.into(), val);
       }
     }
 
-    self.handles = snapshotted_data.module_handles;
+    self.handles = snapshotted_data.module_handles.into();
   }
 
   pub(crate) fn new(loader: Rc<dyn ModuleLoader>) -> ModuleMap {
     Self {
-      handles: vec![],
-      info: vec![],
+      handles: Default::default(),
+      info: Default::default(),
       by_name_js: HashMap::new(),
       by_name_json: HashMap::new(),
+      by_name_other: HashMap::new(),
       next_load_id: 1,
       loader,
       dynamic_import_map: HashMap::new(),
       preparing_dynamic_imports: FuturesUnordered::new(),
       pending_dynamic_imports: FuturesUnordered::new(),
-      json_value_store: HashMap::new(),
+      synthetic_value_store: HashMap::new(),
+      wasm_module_store: HashMap::new(),
+      pending_code_caches: vec![],
+      hot_data: HashMap::new(),
+      hot_callbacks: HashMap::new(),
+      commonjs: CommonJsModules::default(),
+      import_meta_lazy: HashMap::new(),
+      tracer: None,
+      async_resolutions: Default::default(),
+      sources: Default::default(),
     }
   }
 
@@ -392,9 +571,9 @@ impl ModuleMap {
   pub(crate) fn get_id(
     &self,
     name: impl AsRef<str>,
-    asserted_module_type: AssertedModuleType,
+    asserted_module_type: &AssertedModuleType,
   ) -> Option<ModuleId> {
-    let map = self.by_name(asserted_module_type);
+    let map = self.by_name(asserted_module_type)?;
     let first_symbolic_module = map.get(name.as_ref())?;
     let mut mod_name = match first_symbolic_module {
       SymbolicModule::Mod(mod_id) => return Some(*mod_id),
@@ -418,7 +597,6 @@ impl ModuleMap {
     name: ModuleName,
     source: ModuleCode,
   ) -> Result<ModuleId, ModuleError> {
-    let name_str = name.v8(scope);
     let source_str = v8::String::new_from_utf8(
       scope,
       strip_bom(source.as_bytes()),
@@ -437,26 +615,304 @@ impl ModuleMap {
         return Err(ModuleError::Exception(exception));
       }
     };
+    let value = v8::Global::new(tc_scope, parsed_json);
 
-    let export_names = [v8::String::new(tc_scope, "default").unwrap()];
-    let module = v8::Module::create_synthetic_module(
+    let id = self.new_synthetic_module(
+      tc_scope,
+      name,
+      ModuleType::Json,
+      SyntheticModuleValue::Default(value),
+      vec![],
+      source.as_bytes().len(),
+    );
+    self.sources.insert(id, ModuleSourceCode::String(source));
+    Ok(id)
+  }
+
+  /// Evaluate a module with a custom type using the callback registered for
+  /// that type, and register its result as the default export of a synthetic
+  /// module.
+  pub(crate) fn new_custom_module(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    name: ModuleName,
+    module_type: Cow<'static, str>,
+    source: ModuleSourceCode,
+  ) -> Result<ModuleId, ModuleError> {
+    let custom_module_types = JsRuntime::state_from(scope)
+      .borrow()
+      .custom_module_types
+      .clone();
+    let Some(evaluate) = custom_module_types.get(module_type.as_ref()) else {
+      return Err(ModuleError::Other(generic_error(format!(
+        "\"{module_type}\" is not a valid module type."
+      ))));
+    };
+
+    let source_size = source.as_bytes().len();
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    let value = match evaluate(tc_scope, &name, source) {
+      Ok(value) => value,
+      Err(err) => {
+        if tc_scope.has_caught() {
+          let exception = tc_scope.exception().unwrap();
+          let exception = v8::Global::new(tc_scope, exception);
+          return Err(ModuleError::Exception(exception));
+        }
+        return Err(ModuleError::Other(err));
+      }
+    };
+
+    Ok(self.new_synthetic_module(
       tc_scope,
+      name,
+      ModuleType::Other(module_type),
+      SyntheticModuleValue::Default(value),
+      vec![],
+      source_size,
+    ))
+  }
+
+  /// Register a synthetic module whose exports are computed by `module` when
+  /// it's evaluated.
+  pub(crate) fn new_embedder_synthetic_module(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    module: SyntheticModule,
+  ) -> Result<ModuleId, Error> {
+    let name = module.specifier.as_str();
+    if self
+      .get_id(name, &AssertedModuleType::JavaScriptOrWasm)
+      .is_some()
+    {
+      return Err(generic_error(format!(
+        "Module \"{name}\" is already registered."
+      )));
+    }
+    module.check_export_names()?;
+
+    let name = ModuleName::from(name.to_string());
+    Ok(self.new_synthetic_module(
+      scope,
+      name,
+      ModuleType::JavaScript,
+      SyntheticModuleValue::Exports(module),
+      vec![],
+      0,
+    ))
+  }
+
+  /// Register a synthetic module, or attach the evaluation callback of
+  /// `module` to the synthetic module restored from a snapshot under the same
+  /// specifier. Snapshots don't hold the callbacks of modules that weren't
+  /// evaluated before they were taken.
+  pub(crate) fn restore_embedder_synthetic_module(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    module: SyntheticModule,
+  ) -> Result<ModuleId, Error> {
+    let Some(id) = self.get_id(
+      module.specifier.as_str(),
+      &AssertedModuleType::JavaScriptOrWasm,
+    ) else {
+      return self.new_embedder_synthetic_module(scope, module);
+    };
+    let handle = self.handles[id].clone();
+    let local = v8::Local::new(scope, &handle);
+    if !local.is_synthetic_module() {
+      return Err(generic_error(format!(
+        "Module \"{}\" is already registered.",
+        module.specifier
+      )));
+    }
+    if matches!(
+      local.get_status(),
+      v8::ModuleStatus::Uninstantiated | v8::ModuleStatus::Instantiated
+    ) {
+      self
+        .synthetic_value_store
+        .insert(handle, SyntheticModuleValue::Exports(module));
+    }
+    Ok(id)
+  }
+
+  /// Register a CommonJS module. The modules it requires with a string literal
+  /// become its requests, so that they are loaded along with it.
+  pub(crate) fn new_commonjs_module(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    name: ModuleName,
+    source: ModuleCode,
+  ) -> Result<ModuleId, ModuleError> {
+    if !JsRuntime::state_from(scope).borrow().commonjs_modules {
+      return Err(ModuleError::Other(generic_error(format!(
+        "Cannot load CommonJS module \"{}\", because CommonJS modules aren't enabled.",
+        name.as_str()
+      ))));
+    }
+    let specifier = ModuleSpecifier::parse(name.as_str())
+      .map_err(|err| ModuleError::Other(err.into()))?;
+    let analysis = commonjs::analyze(source.as_str());
+
+    let mut requests: Vec<ModuleRequest> = vec![];
+    for required in analysis.requires {
+      // Specifiers that can't be resolved throw when they're required.
+      let Ok(resolved) =
+        self.resolve(&required, name.as_str(), ResolutionKind::Import)
+      else {
+        continue;
+      };
+      let request = ModuleRequest {
+        asserted_module_type: commonjs::require_request_type(&resolved),
+        specifier: resolved.to_string(),
+      };
+      if !requests.contains(&request) {
+        requests.push(request);
+      }
+    }
+
+    let mut export_names = vec!["default".to_string()];
+    export_names.extend(analysis.exports);
+    let source_size = source.as_bytes().len();
+    // A module loaded again, eg. by hot module replacement, runs again.
+    self.commonjs.cache.remove(&specifier);
+    self.commonjs.sources.insert(specifier.clone(), source);
+
+    Ok(self.new_synthetic_module(
+      scope,
+      name,
+      ModuleType::CommonJs,
+      SyntheticModuleValue::CommonJs {
+        specifier,
+        export_names,
+      },
+      requests,
+      source_size,
+    ))
+  }
+
+  /// Create a synthetic module with the exports described by `value`.
+  fn new_synthetic_module(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    name: ModuleName,
+    module_type: ModuleType,
+    value: SyntheticModuleValue,
+    requests: Vec<ModuleRequest>,
+    source_size: usize,
+  ) -> ModuleId {
+    let name_str = name.v8(scope);
+    let export_names = match &value {
+      SyntheticModuleValue::Default(_) => {
+        vec![v8::String::new(scope, "default").unwrap()]
+      }
+      SyntheticModuleValue::Exports(SyntheticModule {
+        export_names, ..
+      })
+      | SyntheticModuleValue::CommonJs { export_names, .. } => export_names
+        .iter()
+        .map(|export_name| v8::String::new(scope, export_name).unwrap())
+        .collect(),
+    };
+    let module = v8::Module::create_synthetic_module(
+      scope,
       name_str,
       &export_names,
-      json_module_evaluation_steps,
+      synthetic_module_evaluation_steps,
     );
 
-    let handle = v8::Global::<v8::Module>::new(tc_scope, module);
-    let value_handle = v8::Global::<v8::Value>::new(tc_scope, parsed_json);
-    self.json_value_store.insert(handle.clone(), value_handle);
+    let handle = v8::Global::<v8::Module>::new(scope, module);
+    self.synthetic_value_store.insert(handle.clone(), value);
+
+    self.create_module_info(
+      name,
+      module_type,
+      handle,
+      false,
+      requests,
+      source_size,
+    )
+  }
 
-    let id =
-      self.create_module_info(name, ModuleType::Json, handle, false, vec![]);
+  /// Compile a Wasm module and register the JS module that instantiates it.
+  ///
+  /// The imports of the Wasm module are resolved as ES imports of that JS
+  /// module and its exports are re-exported from it, so the module namespace
+  /// exposes the Wasm exports.
+  pub(crate) fn new_wasm_module(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    name: ModuleName,
+    source: ModuleSourceCode,
+    is_dynamic_import: bool,
+  ) -> Result<ModuleId, ModuleError> {
+    let bytes = source.as_bytes();
+    let compiled_wasm_module_store = JsRuntime::state_from(scope)
+      .borrow()
+      .compiled_wasm_module_store
+      .clone();
+
+    let tc_scope = &mut v8::TryCatch::new(scope);
+
+    // Reuse a module compiled by another isolate from the same bytes, if any.
+    let maybe_wasm_module =
+      compiled_wasm_module_store.as_ref().and_then(|store| {
+        store
+          .with_named(name.as_str(), |compiled| {
+            if compiled.get_wire_bytes_ref() == bytes {
+              v8::WasmModuleObject::from_compiled_module(tc_scope, compiled)
+            } else {
+              None
+            }
+          })
+          .flatten()
+      });
+    let wasm_module = match maybe_wasm_module {
+      Some(wasm_module) => wasm_module,
+      None => match v8::WasmModuleObject::compile(tc_scope, bytes) {
+        Some(wasm_module) => {
+          if let Some(store) = &compiled_wasm_module_store {
+            store.insert_named(
+              name.as_str().to_string(),
+              wasm_module.get_compiled_module(),
+            );
+          }
+          wasm_module
+        }
+        None => {
+          assert!(tc_scope.has_caught());
+          let exception = tc_scope.exception().unwrap();
+          let exception = v8::Global::new(tc_scope, exception);
+          return Err(ModuleError::Exception(exception));
+        }
+      },
+    };
+    let wasm_module = v8::Global::new(tc_scope, wasm_module);
+
+    let analysis =
+      wasm::analyze_wasm_module(bytes).map_err(ModuleError::Other)?;
+    let js_source = wasm::render_js_wasm_module(&analysis);
+
+    let id = self.new_module_from_js_source(
+      tc_scope,
+      false,
+      ModuleType::Wasm,
+      name,
+      js_source.into(),
+      is_dynamic_import,
+      None,
+    )?;
+    self.wasm_module_store.insert(id, wasm_module);
+    // The size and source of the Wasm binary rather than of the generated JS
+    // module.
+    self.info[id].source_size = bytes.len();
+    self.sources.insert(id, source);
 
     Ok(id)
   }
 
-  /// Create and compile an ES module.
+  /// Create and compile an ES module, consuming the given code cache if
+  /// any.
   pub(crate) fn new_es_module(
     &mut self,
     scope: &mut v8::HandleScope,
@@ -464,16 +920,108 @@ impl ModuleMap {
     name: ModuleName,
     source: ModuleCode,
     is_dynamic_import: bool,
+    code_cache: Option<ModuleCodeCache>,
+  ) -> Result<ModuleId, ModuleError> {
+    self.new_module_from_js_source(
+      scope,
+      main,
+      ModuleType::JavaScript,
+      name,
+      source,
+      is_dynamic_import,
+      code_cache,
+    )
+  }
+
+  /// Create and compile an ES module from JavaScript source, registering it
+  /// with the given module type.
+  #[allow(clippy::too_many_arguments)]
+  fn new_module_from_js_source(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    main: bool,
+    module_type: ModuleType,
+    name: ModuleName,
+    source: ModuleCode,
+    is_dynamic_import: bool,
+    code_cache: Option<ModuleCodeCache>,
   ) -> Result<ModuleId, ModuleError> {
+    let compiled = self.compile_module_from_js_source(
+      scope,
+      main,
+      module_type,
+      name,
+      source,
+      is_dynamic_import,
+      code_cache,
+    )?;
+    let resolved = self
+      .resolve_imports(&compiled)
+      .into_iter()
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(ModuleError::Other)?;
+    self.register_compiled_module(compiled, resolved)
+  }
+
+  /// Compile an ES module from JavaScript source, without registering it.
+  #[allow(clippy::too_many_arguments)]
+  pub(crate) fn compile_module_from_js_source(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    main: bool,
+    module_type: ModuleType,
+    name: ModuleName,
+    source: ModuleCode,
+    is_dynamic_import: bool,
+    code_cache: Option<ModuleCodeCache>,
+  ) -> Result<CompiledModule, ModuleError> {
+    let (original_source, source) = source.into_cheap_copy();
+    let source = match import_attributes::rewrite_with_clauses(source.as_str())
+    {
+      Some(rewritten) => rewritten.into(),
+      None => source,
+    };
     let name_str = name.v8(scope);
     let source_str = source.v8(scope);
 
     let origin = module_origin(scope, name_str);
-    let source = v8::script_compiler::Source::new(source_str, Some(&origin));
+
+    let source_hash = code_cache
+      .as_ref()
+      .map(|_| code_cache::source_hash(source.as_bytes()));
+    let mut code_cache_rejected = false;
+    let maybe_v8_code_cache = match (&code_cache, source_hash) {
+      (Some(ModuleCodeCache::Data(data)), Some(source_hash)) => {
+        let maybe_v8_code_cache =
+          code_cache::decode(data.as_bytes(), source_hash);
+        code_cache_rejected = maybe_v8_code_cache.is_none();
+        maybe_v8_code_cache
+      }
+      _ => None,
+    };
 
     let tc_scope = &mut v8::TryCatch::new(scope);
 
-    let maybe_module = v8::script_compiler::compile_module(tc_scope, source);
+    let maybe_module = match maybe_v8_code_cache {
+      Some(v8_code_cache) => {
+        let source = v8::script_compiler::Source::new_with_cached_data(
+          source_str,
+          Some(&origin),
+          v8::CachedData::new(v8_code_cache),
+        );
+        v8::script_compiler::compile_module2(
+          tc_scope,
+          source,
+          v8::script_compiler::CompileOptions::ConsumeCodeCache,
+          v8::script_compiler::NoCacheReason::NoReason,
+        )
+      }
+      None => {
+        let source =
+          v8::script_compiler::Source::new(source_str, Some(&origin));
+        v8::script_compiler::compile_module(tc_scope, source)
+      }
+    };
 
     if tc_scope.has_caught() {
       assert!(maybe_module.is_none());
@@ -484,7 +1032,7 @@ impl ModuleMap {
 
     let module = maybe_module.unwrap();
 
-    let mut requests: Vec<ModuleRequest> = vec![];
+    let mut imports = vec![];
     let module_requests = module.get_module_requests();
     for i in 0..module_requests.length() {
       let module_request = v8::Local::<v8::ModuleRequest>::try_from(
@@ -512,30 +1060,80 @@ impl ModuleMap {
         return Err(ModuleError::Exception(exception));
       }
 
-      let module_specifier = match self.loader.resolve(
-        &import_specifier,
-        name.as_ref(),
-        if is_dynamic_import {
-          ResolutionKind::DynamicImport
-        } else {
-          ResolutionKind::Import
-        },
-      ) {
-        Ok(s) => s,
-        Err(e) => return Err(ModuleError::Other(e)),
-      };
       let asserted_module_type =
         get_asserted_module_type_from_assertions(&assertions);
-      let request = ModuleRequest {
-        specifier: module_specifier.to_string(),
-        asserted_module_type,
-      };
-      requests.push(request);
+      imports.push((import_specifier, asserted_module_type));
     }
 
+    let handle = v8::Global::<v8::Module>::new(tc_scope, module);
+    // A fresh code cache is produced after evaluation, when more functions
+    // have been compiled, unless a valid one was consumed.
+    let pending_code_cache = match (source_hash, maybe_v8_code_cache) {
+      (Some(source_hash), None) => Some((source_hash, code_cache_rejected)),
+      _ => None,
+    };
+    Ok(CompiledModule {
+      name,
+      module_type,
+      handle,
+      main,
+      kind: if is_dynamic_import {
+        ResolutionKind::DynamicImport
+      } else {
+        ResolutionKind::Import
+      },
+      imports,
+      source: original_source,
+      source_size: source.as_bytes().len(),
+      pending_code_cache,
+    })
+  }
+
+  /// Resolve the imports of a compiled module with
+  /// [`ModuleLoader::resolve`].
+  pub(crate) fn resolve_imports(
+    &self,
+    compiled: &CompiledModule,
+  ) -> Vec<Result<ModuleSpecifier, Error>> {
+    compiled
+      .imports
+      .iter()
+      .map(|(specifier, _)| {
+        self.resolve(specifier, compiled.name.as_str(), compiled.kind)
+      })
+      .collect()
+  }
+
+  /// Register a compiled module, given the resolved specifier of each of its
+  /// imports.
+  pub(crate) fn register_compiled_module(
+    &mut self,
+    compiled: CompiledModule,
+    resolved: Vec<ModuleSpecifier>,
+  ) -> Result<ModuleId, ModuleError> {
+    let CompiledModule {
+      name,
+      module_type,
+      handle,
+      main,
+      imports,
+      source,
+      source_size,
+      pending_code_cache,
+      ..
+    } = compiled;
+    let requests = imports
+      .into_iter()
+      .zip(resolved)
+      .map(|((_, asserted_module_type), specifier)| ModuleRequest {
+        specifier: specifier.to_string(),
+        asserted_module_type,
+      })
+      .collect();
+
     if main {
-      let maybe_main_module = self.info.iter().find(|module| module.main);
-      if let Some(main_module) = maybe_main_module {
+      let maybe_main_module = self.info.iter().find(|(_, module)| module.main);
+      if let Some((_, main_module)) = maybe_main_module {
         return Err(ModuleError::Other(generic_error(
           format!("Trying to create \"main\" module ({:?}), when one already exists ({:?})",
           name.as_ref(),
@@ -544,18 +1142,63 @@ impl ModuleMap {
       }
     }
 
-    let handle = v8::Global::<v8::Module>::new(tc_scope, module);
     let id = self.create_module_info(
       name,
-      ModuleType::JavaScript,
+      module_type,
       handle,
       main,
       requests,
+      source_size,
     );
+    self.sources.insert(id, ModuleSourceCode::String(source));
+
+    if let Some((source_hash, rejected)) = pending_code_cache {
+      self.pending_code_caches.push(PendingCodeCache {
+        id,
+        source_hash,
+        rejected,
+      });
+    }
 
     Ok(id)
   }
 
+  /// Produce the code caches of the modules that have been evaluated since
+  /// they were compiled. Returns the name of each module, with the code cache
+  /// data and whether the code cache provided for it was rejected.
+  pub(crate) fn take_code_caches(
+    &mut self,
+    scope: &mut v8::HandleScope,
+  ) -> Vec<(ModuleName, Vec<u8>, bool)> {
+    let mut code_caches = vec![];
+    let handles = &self.handles;
+    let info = &self.info;
+    self.pending_code_caches.retain(|pending| {
+      let module = v8::Local::new(scope, &handles[pending.id]);
+      match module.get_status() {
+        v8::ModuleStatus::Evaluated => {
+          let unbound_module_script = module.get_unbound_module_script(scope);
+          if let Some(v8_code_cache) = unbound_module_script.create_code_cache()
+          {
+            code_caches.push((
+              info[pending.id].name.as_str().to_string().into(),
+              code_cache::encode(pending.source_hash, &v8_code_cache),
+              pending.rejected,
+            ));
+          }
+          false
+        }
+        v8::ModuleStatus::Errored => false,
+        _ => true,
+      }
+    });
+    code_caches
+  }
+
+  pub(crate) fn has_pending_code_caches(&self) -> bool {
+    !self.pending_code_caches.is_empty()
+  }
+
   pub(crate) fn instantiate_module(
     &mut self,
     scope: &mut v8::HandleScope,
@@ -572,10 +1215,12 @@ impl ModuleMap {
       return Err(v8::Global::new(tc_scope, module.get_exception()));
     }
 
+    let start = Instant::now();
     tc_scope.set_slot(self as *const _);
     let instantiate_result =
       module.instantiate_module(tc_scope, Self::module_resolve_callback);
     tc_scope.remove_slot::<*const Self>();
+    self.trace(ModuleLoadPhase::Instantiate, id, start);
     if instantiate_result.is_none() {
       let exception = tc_scope.exception().unwrap();
       return Err(v8::Global::new(tc_scope, exception));
@@ -638,15 +1283,20 @@ impl ModuleMap {
     referrer: &str,
     import_assertions: HashMap<String, String>,
   ) -> Option<v8::Local<'s, v8::Module>> {
-    let resolved_specifier = self
-      .loader
-      .resolve(specifier, referrer, ResolutionKind::Import)
-      .expect("Module should have been already resolved");
+    let resolved_specifier = match self
+      .async_resolutions
+      .get(&(referrer.to_string(), specifier.to_string()))
+    {
+      Some(resolved_specifier) => resolved_specifier.clone(),
+      None => self
+        .resolve(specifier, referrer, ResolutionKind::Import)
+        .expect("Module should have been already resolved"),
+    };
 
     let module_type =
       get_asserted_module_type_from_assertions(&import_assertions);
 
-    if let Some(id) = self.get_id(resolved_specifier.as_str(), module_type) {
+    if let Some(id) = self.get_id(resolved_specifier.as_str(), &module_type) {
       if let Some(handle) = self.get_handle(id) {
         return Some(v8::Local::new(scope, handle));
       }
@@ -655,8 +1305,39 @@ impl ModuleMap {
     None
   }
 
+  /// Resolves a specifier with the module loader, tracing the resolution.
+  pub(crate) fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    trace::resolve(
+      &*self.loader,
+      self.tracer.as_ref(),
+      specifier,
+      referrer,
+      kind,
+    )
+  }
+
+  /// Reports a phase of the given module that started at `start` and just
+  /// ended, if tracing.
+  pub(crate) fn trace(
+    &self,
+    phase: ModuleLoadPhase,
+    id: ModuleId,
+    start: Instant,
+  ) {
+    if let Some(tracer) = &self.tracer {
+      tracer.trace(phase, self.info[id].name.as_str(), None, start);
+    }
+  }
+
   pub(crate) fn clear(&mut self) {
-    *self = Self::new(self.loader.clone())
+    let tracer = self.tracer.take();
+    *self = Self::new(self.loader.clone());
+    self.tracer = tracer;
   }
 
   pub(crate) fn get_handle_by_name(
@@ -664,8 +1345,8 @@ impl ModuleMap {
     name: impl AsRef<str>,
   ) -> Option<v8::Global<v8::Module>> {
     let id = self
-      .get_id(name.as_ref(), AssertedModuleType::JavaScriptOrWasm)
-      .or_else(|| self.get_id(name.as_ref(), AssertedModuleType::Json))?;
+      .get_id(name.as_ref(), &AssertedModuleType::JavaScriptOrWasm)
+      .or_else(|| self.get_id(name.as_ref(), &AssertedModuleType::Json))?;
     self.get_handle(id)
   }
 
@@ -675,7 +1356,7 @@ impl ModuleMap {
     module_type: ModuleType,
     handle: v8::Global<v8::Module>,
   ) {
-    self.create_module_info(name, module_type, handle, false, vec![]);
+    self.create_module_info(name, module_type, handle, false, vec![], 0);
   }
 
   fn create_module_info(
@@ -685,11 +1366,12 @@ impl ModuleMap {
     handle: v8::Global<v8::Module>,
     main: bool,
     requests: Vec<ModuleRequest>,
+    source_size: usize,
   ) -> ModuleId {
-    let id = self.handles.len();
+    let id = self.handles.next_id();
     let (name1, name2) = name.into_cheap_copy();
     self
-      .by_name_mut(module_type.into())
+      .by_name_mut(&(&module_type).into())
       .insert(name1, SymbolicModule::Mod(id));
     self.handles.push(handle);
     self.info.push(ModuleInfo {
@@ -697,12 +1379,32 @@ impl ModuleMap {
       main,
       name: name2,
       requests,
+      dynamic_requests: vec![],
       module_type,
+      source_size,
     });
 
     id
   }
 
+  /// Record that the module `referrer` imported a module with `import()`.
+  /// `referrer` may also be a script rather than a module.
+  pub(crate) fn add_dynamic_request(
+    &mut self,
+    referrer: &str,
+    request: &ModuleRequest,
+  ) {
+    let Some(id) =
+      self.get_id(referrer, &AssertedModuleType::JavaScriptOrWasm)
+    else {
+      return;
+    };
+    let dynamic_requests = &mut self.info[id].dynamic_requests;
+    if !dynamic_requests.contains(request) {
+      dynamic_requests.push(request.clone());
+    }
+  }
+
   pub(crate) fn get_requested_modules(
     &self,
     id: ModuleId,
@@ -713,36 +1415,64 @@ impl ModuleMap {
   fn is_registered(
     &self,
     specifier: impl AsRef<str>,
-    asserted_module_type: AssertedModuleType,
+    asserted_module_type: &AssertedModuleType,
   ) -> bool {
     if let Some(id) = self.get_id(specifier.as_ref(), asserted_module_type) {
       let info = self.get_info_by_id(id).unwrap();
-      return asserted_module_type == info.module_type.into();
+      return *asserted_module_type == (&info.module_type).into();
     }
 
     false
   }
 
+  /// Returns the modules registered with the given type, if any module of a
+  /// custom type has been registered yet.
   pub(crate) fn by_name(
     &self,
-    asserted_module_type: AssertedModuleType,
-  ) -> &HashMap<ModuleName, SymbolicModule> {
+    asserted_module_type: &AssertedModuleType,
+  ) -> Option<&HashMap<ModuleName, SymbolicModule>> {
     match asserted_module_type {
-      AssertedModuleType::Json => &self.by_name_json,
-      AssertedModuleType::JavaScriptOrWasm => &self.by_name_js,
+      AssertedModuleType::Json => Some(&self.by_name_json),
+      AssertedModuleType::JavaScriptOrWasm => Some(&self.by_name_js),
+      AssertedModuleType::Other(ty) => self.by_name_other.get(ty.as_ref()),
     }
   }
 
   pub(crate) fn by_name_mut(
     &mut self,
-    asserted_module_type: AssertedModuleType,
+    asserted_module_type: &AssertedModuleType,
   ) -> &mut HashMap<ModuleName, SymbolicModule> {
     match asserted_module_type {
       AssertedModuleType::Json => &mut self.by_name_json,
       AssertedModuleType::JavaScriptOrWasm => &mut self.by_name_js,
+      AssertedModuleType::Other(ty) => {
+        self.by_name_other.entry(ty.to_string()).or_default()
+      }
     }
   }
 
+  /// The names a module name is redirected through, starting with `name`
+  /// and ending with the name of the module. Empty if `name` isn't an alias.
+  pub(crate) fn redirect_chain(
+    &self,
+    name: &str,
+    asserted_module_type: &AssertedModuleType,
+  ) -> Vec<String> {
+    let Some(by_name) = self.by_name(asserted_module_type) else {
+      return vec![];
+    };
+    let mut redirect_chain = vec![];
+    let mut next = name;
+    while let Some(SymbolicModule::Alias(target)) = by_name.get(next) {
+      redirect_chain.push(next.to_string());
+      next = target.as_str();
+    }
+    if !redirect_chain.is_empty() {
+      redirect_chain.push(next.to_string());
+    }
+    redirect_chain
+  }
+
   pub(crate) fn alias(
     &mut self,
     name: FastString,
@@ -751,7 +1481,7 @@ impl ModuleMap {
   ) {
     debug_assert_ne!(name, target);
     self
-      .by_name_mut(asserted_module_type)
+      .by_name_mut(&asserted_module_type)
       .insert(name, SymbolicModule::Alias(target));
   }
 
@@ -759,12 +1489,379 @@ impl ModuleMap {
   pub(crate) fn is_alias(
     &self,
     name: &str,
-    asserted_module_type: AssertedModuleType,
+    asserted_module_type: &AssertedModuleType,
   ) -> bool {
-    let cond = self.by_name(asserted_module_type).get(name);
+    let cond = self
+      .by_name(asserted_module_type)
+      .and_then(|by_name| by_name.get(name));
     matches!(cond, Some(SymbolicModule::Alias(_)))
   }
 
+  /// Returns the ids of the modules registered under a name, in the order
+  /// they were created. Modules replaced by hot module replacement aren't
+  /// registered anymore.
+  pub(crate) fn registered_module_ids(&self) -> Vec<ModuleId> {
+    let mut ids = self
+      .by_name_js
+      .values()
+      .chain(self.by_name_json.values())
+      .chain(self.by_name_other.values().flat_map(|map| map.values()))
+      .filter_map(|symbolic_module| match symbolic_module {
+        SymbolicModule::Mod(id) => Some(*id),
+        SymbolicModule::Alias(_) => None,
+      })
+      .collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids
+  }
+
+  /// Get the id of a module registered with any type.
+  fn get_id_of_any_type(&self, name: &str) -> Option<ModuleId> {
+    [
+      AssertedModuleType::JavaScriptOrWasm,
+      AssertedModuleType::Json,
+    ]
+    .into_iter()
+    .chain(
+      self
+        .by_name_other
+        .keys()
+        .map(|ty| AssertedModuleType::Other(ty.clone().into())),
+    )
+    .find_map(|ty| self.get_id(name, &ty))
+  }
+
+  /// Remove a module, and every module that statically imports it directly or
+  /// transitively, from the module map so that loading them again fetches and
+  /// compiles them anew. Their handles and infos are kept until
+  /// [`ModuleMap::hot_commit`] drops them, or [`ModuleMap::hot_restore`]
+  /// registers them again.
+  ///
+  /// The aliases resolving to them are removed as well, so that reloading
+  /// them registers the aliases of the new versions.
+  ///
+  /// Compiled Wasm modules are removed from `compiled_wasm_module_store`, so
+  /// that the new versions are compiled from their new bytes.
+  ///
+  /// Returns the invalidated modules, starting with the specified one.
+  pub(crate) fn hot_invalidate(
+    &mut self,
+    specifier: &str,
+    compiled_wasm_module_store: Option<&CompiledWasmModuleStore>,
+  ) -> Result<Vec<InvalidatedModule>, Error> {
+    let Some(id) = self.get_id_of_any_type(specifier) else {
+      return Err(generic_error(format!(
+        "Cannot reload module \"{specifier}\", because it isn't loaded."
+      )));
+    };
+
+    let mut importers: HashMap<ModuleId, Vec<ModuleId>> = HashMap::new();
+    for importer in self.registered_module_ids() {
+      for request in &self.info[importer].requests {
+        if let Some(imported) =
+          self.get_id(&request.specifier, &request.asserted_module_type)
+        {
+          importers.entry(imported).or_default().push(importer);
+        }
+      }
+    }
+
+    let mut ids = vec![id];
+    let mut queue = VecDeque::from([id]);
+    while let Some(id) = queue.pop_front() {
+      for importer in importers.get(&id).into_iter().flatten() {
+        if !ids.contains(importer) {
+          ids.push(*importer);
+          queue.push_back(*importer);
+        }
+      }
+    }
+
+    let mut invalidated = Vec::with_capacity(ids.len());
+    for id in &ids {
+      let info = &mut self.info[*id];
+      let root = !importers
+        .get(id)
+        .into_iter()
+        .flatten()
+        .any(|importer| ids.contains(importer));
+      invalidated.push(InvalidatedModule {
+        id: *id,
+        name: info.name.as_str().to_owned(),
+        asserted_module_type: (&info.module_type).into(),
+        main: info.main,
+        root,
+        aliases: vec![],
+      });
+      // Another module will become the main module.
+      info.main = false;
+      if let (ModuleType::Wasm, Some(store)) =
+        (&info.module_type, compiled_wasm_module_store)
+      {
+        store.remove_named(info.name.as_str());
+      }
+    }
+    let mut aliases = self.remove_aliases(&ids.iter().copied().collect());
+    for module in &mut invalidated {
+      module.aliases = aliases.remove(&module.id).unwrap_or_default();
+      self
+        .by_name_mut(&module.asserted_module_type)
+        .remove(module.name.as_str());
+    }
+    // The new versions resolve their imports again.
+    self.async_resolutions.retain(|(referrer, _), _| {
+      !invalidated.iter().any(|module| module.name == *referrer)
+    });
+    Ok(invalidated)
+  }
+
+  /// Drop the replaced versions of hot reloaded modules, once their new
+  /// versions were evaluated. `ids` maps the ids of the replaced modules to
+  /// the ids of their new versions.
+  ///
+  /// The aliases that resolved to a replaced module, and that loading the new
+  /// version didn't register again, now point to the new version.
+  pub(crate) fn hot_commit(
+    &mut self,
+    invalidated: &[InvalidatedModule],
+    ids: &HashMap<ModuleId, ModuleId>,
+  ) {
+    for module in invalidated {
+      if let Some(new_id) = ids.get(&module.id) {
+        let target = self.info[*new_id].name.as_str().to_owned();
+        for alias in &module.aliases {
+          let by_name = self.by_name_mut(&module.asserted_module_type);
+          if alias.name != target && !by_name.contains_key(alias.name.as_str())
+          {
+            by_name.insert(
+              alias.name.clone().into(),
+              SymbolicModule::Alias(target.clone().into()),
+            );
+          }
+        }
+      }
+      self.remove_module(module.id);
+    }
+  }
+
+  /// Remove a module from the module map, along with the modules it imports,
+  /// statically or dynamically, that no remaining module imports. Their
+  /// handles and infos are dropped, so that loading them again fetches and
+  /// compiles them anew, and V8 can collect them once nothing references
+  /// them from JS.
+  ///
+  /// Fails for the main module, and for a module that a remaining module
+  /// statically imports.
+  ///
+  /// Compiled Wasm modules are removed from `compiled_wasm_module_store` as
+  /// well.
+  ///
+  /// Returns the ids of the removed modules, starting with the specified one.
+  pub(crate) fn unload(
+    &mut self,
+    specifier: &str,
+    compiled_wasm_module_store: Option<&CompiledWasmModuleStore>,
+    evaluating: &[ModuleId],
+  ) -> Result<Vec<ModuleId>, Error> {
+    let Some(root) = self.get_id_of_any_type(specifier) else {
+      return Err(generic_error(format!(
+        "Cannot unload module \"{specifier}\", because it isn't loaded."
+      )));
+    };
+    if self.info[root].main {
+      return Err(generic_error(format!(
+        "Cannot unload module \"{specifier}\", because it's the main module."
+      )));
+    }
+
+    let mut static_importers: HashMap<ModuleId, Vec<ModuleId>> = HashMap::new();
+    let mut dynamic_importers: HashMap<ModuleId, Vec<ModuleId>> =
+      HashMap::new();
+    for importer in self.registered_module_ids() {
+      let info = &self.info[importer];
+      for (requests, importers) in [
+        (&info.requests, &mut static_importers),
+        (&info.dynamic_requests, &mut dynamic_importers),
+      ] {
+        for request in requests {
+          if let Some(imported) =
+            self.get_id(&request.specifier, &request.asserted_module_type)
+          {
+            importers.entry(imported).or_default().push(importer);
+          }
+        }
+      }
+    }
+
+    let mut ids = vec![root];
+    let mut queue = VecDeque::from([root]);
+    while let Some(id) = queue.pop_front() {
+      let info = &self.info[id];
+      for request in info.requests.iter().chain(&info.dynamic_requests) {
+        if let Some(imported) =
+          self.get_id(&request.specifier, &request.asserted_module_type)
+        {
+          if !ids.contains(&imported) {
+            ids.push(imported);
+            queue.push_back(imported);
+          }
+        }
+      }
+    }
+
+    // Keep the imported modules that a remaining module imports, and so the
+    // modules they import, until no more modules are kept.
+    loop {
+      let unloaded = ids.iter().copied().collect::<HashSet<_>>();
+      let len = ids.len();
+      ids.retain(|id| {
+        *id == root
+          || !(self.info[*id].main
+            || static_importers
+              .get(id)
+              .into_iter()
+              .chain(dynamic_importers.get(id))
+              .flatten()
+              .any(|importer| !unloaded.contains(importer)))
+      });
+      if ids.len() == len {
+        break;
+      }
+    }
+    if let Some(id) = ids
+      .iter()
+      .find(|id| evaluating.contains(id) || self.is_dynamically_importing(**id))
+    {
+      return Err(generic_error(format!(
+        "Cannot unload module \"{specifier}\", because \"{}\" is being loaded or evaluated.",
+        self.info[*id].name.as_str()
+      )));
+    }
+    if let Some(importer) = static_importers
+      .get(&root)
+      .into_iter()
+      .flatten()
+      .find(|importer| !ids.contains(importer))
+    {
+      return Err(generic_error(format!(
+        "Cannot unload module \"{specifier}\", because \"{}\" imports it.",
+        self.info[*importer].name.as_str()
+      )));
+    }
+
+    self.remove_aliases(&ids.iter().copied().collect());
+
+    let mut unloaded_names = HashSet::new();
+    for id in &ids {
+      let info = self.remove_module(*id);
+      self
+        .by_name_mut(&(&info.module_type).into())
+        .remove(info.name.as_str());
+      if let (ModuleType::Wasm, Some(store)) =
+        (&info.module_type, compiled_wasm_module_store)
+      {
+        store.remove_named(info.name.as_str());
+      }
+      self.hot_data.remove(info.name.as_str());
+      if let Ok(specifier) = ModuleSpecifier::parse(info.name.as_str()) {
+        self.commonjs.sources.remove(&specifier);
+        self.commonjs.cache.remove(&specifier);
+      }
+      unloaded_names.insert(info.name.as_str().to_string());
+    }
+    self
+      .async_resolutions
+      .retain(|(referrer, _), _| !unloaded_names.contains(referrer));
+
+    Ok(ids)
+  }
+
+  /// Register invalidated modules and their aliases again, in case reloading
+  /// them failed. The new versions that were loaded already are dropped:
+  /// those registered under the names of the invalidated modules, and those
+  /// in `ids`, which maps the ids of the invalidated modules to the ids of
+  /// their new versions.
+  pub(crate) fn hot_restore(
+    &mut self,
+    invalidated: &[InvalidatedModule],
+    ids: &HashMap<ModuleId, ModuleId>,
+  ) {
+    let mut new_ids = ids.values().copied().collect::<HashSet<_>>();
+    new_ids.extend(invalidated.iter().filter_map(|module| {
+      match self
+        .by_name(&module.asserted_module_type)?
+        .get(module.name.as_str())?
+      {
+        SymbolicModule::Mod(id) => Some(*id),
+        SymbolicModule::Alias(_) => None,
+      }
+    }));
+    for id in new_ids {
+      self.remove_module(id);
+    }
+    for module in invalidated {
+      self.info[module.id].main = module.main;
+      let by_name = self.by_name_mut(&module.asserted_module_type);
+      by_name
+        .insert(module.name.clone().into(), SymbolicModule::Mod(module.id));
+      for alias in &module.aliases {
+        by_name.insert(
+          alias.name.clone().into(),
+          SymbolicModule::Alias(alias.target.clone().into()),
+        );
+      }
+    }
+  }
+
+  /// Remove the aliases resolving to any of `ids`, returning them keyed by the
+  /// id they resolved to.
+  fn remove_aliases(
+    &mut self,
+    ids: &HashSet<ModuleId>,
+  ) -> HashMap<ModuleId, Vec<RemovedAlias>> {
+    let aliases = self
+      .collect_modules()
+      .into_iter()
+      .filter_map(|(asserted_module_type, name, symbolic_module)| {
+        let SymbolicModule::Alias(target) = symbolic_module else {
+          return None;
+        };
+        let id = self.get_id(name.as_str(), &asserted_module_type)?;
+        ids.contains(&id).then(|| {
+          let alias = RemovedAlias {
+            name: name.as_str().to_string(),
+            target: target.as_str().to_string(),
+          };
+          (asserted_module_type, id, alias)
+        })
+      })
+      .collect::<Vec<_>>();
+    let mut removed: HashMap<ModuleId, Vec<RemovedAlias>> = HashMap::new();
+    for (asserted_module_type, id, alias) in aliases {
+      self
+        .by_name_mut(&asserted_module_type)
+        .remove(alias.name.as_str());
+      removed.entry(id).or_default().push(alias);
+    }
+    removed
+  }
+
+  /// Drop the handle and info of a module, along with everything kept about
+  /// it by id. The module map entries for its name are left to the caller.
+  fn remove_module(&mut self, id: ModuleId) -> ModuleInfo {
+    let info = self.info.remove(id).unwrap();
+    let handle = self.handles.remove(id).unwrap();
+    self.synthetic_value_store.remove(&handle);
+    self.wasm_module_store.remove(&id);
+    self.sources.remove(&id);
+    self.hot_callbacks.remove(&id);
+    self.pending_code_caches.retain(|pending| pending.id != id);
+    self
+      .import_meta_lazy
+      .retain(|(module_id, _), _| *module_id != id);
+    info
+  }
+
   pub(crate) fn get_handle(
     &self,
     id: ModuleId,
@@ -776,11 +1873,8 @@ impl ModuleMap {
     &self,
     global: &v8::Global<v8::Module>,
   ) -> Option<&ModuleInfo> {
-    if let Some(id) = self.handles.iter().position(|module| module == global) {
-      return self.info.get(id);
-    }
-
-    None
+    let (id, _) = self.handles.iter().find(|(_, module)| *module == global)?;
+    self.info.get(id)
   }
 
   pub(crate) fn get_info_by_id(&self, id: ModuleId) -> Option<&ModuleInfo> {
@@ -791,7 +1885,7 @@ impl ModuleMap {
     module_map_rc: Rc<RefCell<ModuleMap>>,
     specifier: impl AsRef<str>,
   ) -> Result<RecursiveModuleLoad, Error> {
-    let load =
+    let mut load =
       RecursiveModuleLoad::main(specifier.as_ref(), module_map_rc.clone());
     load.prepare().await?;
     Ok(load)
@@ -801,7 +1895,7 @@ impl ModuleMap {
     module_map_rc: Rc<RefCell<ModuleMap>>,
     specifier: impl AsRef<str>,
   ) -> Result<RecursiveModuleLoad, Error> {
-    let load =
+    let mut load =
       RecursiveModuleLoad::side(specifier.as_ref(), module_map_rc.clone());
     load.prepare().await?;
     Ok(load)
@@ -818,30 +1912,35 @@ impl ModuleMap {
     let load = RecursiveModuleLoad::dynamic_import(
       specifier,
       referrer,
-      asserted_module_type,
+      asserted_module_type.clone(),
       module_map_rc.clone(),
     );
     module_map_rc
       .borrow_mut()
       .dynamic_import_map
-      .insert(load.id, resolver_handle);
+      .insert(load.id, (resolver_handle, referrer.to_string()));
 
-    let loader = module_map_rc.borrow().loader.clone();
-    let resolve_result =
-      loader.resolve(specifier, referrer, ResolutionKind::DynamicImport);
+    let resolve_result = module_map_rc.borrow().resolve(
+      specifier,
+      referrer,
+      ResolutionKind::DynamicImport,
+    );
+    // Specifiers that can't be resolved synchronously are resolved with
+    // `ModuleLoader::resolve_async()` when preparing the load, if the loader
+    // supports it.
     let fut = match resolve_result {
-      Ok(module_specifier) => {
+      Ok(module_specifier)
         if module_map_rc
           .borrow()
-          .is_registered(module_specifier, asserted_module_type)
-        {
-          async move { (load.id, Ok(load)) }.boxed_local()
-        } else {
-          async move { (load.id, load.prepare().await.map(|()| load)) }
-            .boxed_local()
-        }
+          .is_registered(&module_specifier, &asserted_module_type) =>
+      {
+        async move { (load.id, Ok(load)) }.boxed_local()
+      }
+      _ => {
+        let mut load = load;
+        async move { (load.id, load.prepare().await.map(|()| load)) }
+          .boxed_local()
       }
-      Err(error) => async move { (load.id, Err(error)) }.boxed_local(),
     };
     module_map_rc
       .borrow_mut()
@@ -849,6 +1948,23 @@ impl ModuleMap {
       .push(fut);
   }
 
+  /// Returns whether a module has a pending `import()`, or is being loaded by
+  /// one.
+  fn is_dynamically_importing(&self, id: ModuleId) -> bool {
+    self.dynamic_import_map.values().any(|(_, referrer)| {
+      self.get_id(referrer, &AssertedModuleType::JavaScriptOrWasm) == Some(id)
+    }) || self.pending_dynamic_imports.iter().any(|load| {
+      let Some(load) = load.get_ref() else {
+        return false;
+      };
+      load.root_module_id == Some(id)
+        || load.visited.iter().any(|request| {
+          self.get_id(&request.specifier, &request.asserted_module_type)
+            == Some(id)
+        })
+    })
+  }
+
   pub(crate) fn has_pending_dynamic_imports(&self) -> bool {
     !(self.preparing_dynamic_imports.is_empty()
       && self.pending_dynamic_imports.is_empty())
@@ -903,49 +2019,95 @@ impl ModuleMap {
     }
   }
 
-  fn get_stalled_top_level_await_message_for_module(
-    &self,
-    scope: &mut v8::HandleScope,
-    module_id: ModuleId,
-  ) -> Vec<v8::Global<v8::Message>> {
-    let module_handle = self.handles.get(module_id).unwrap();
-
-    let module = v8::Local::new(scope, module_handle);
-    let stalled = module.get_stalled_top_level_await_message(scope);
-    let mut messages = vec![];
-    for (_, message) in stalled {
-      messages.push(v8::Global::new(scope, message));
-    }
-    messages
-  }
-
+  /// Returns every module with a stalled top-level await, with the message
+  /// V8 reports for it, ordered by module id.
   pub(crate) fn find_stalled_top_level_await(
     &self,
     scope: &mut v8::HandleScope,
-  ) -> Vec<v8::Global<v8::Message>> {
-    // First check if that's root module
-    let root_module_id =
-      self.info.iter().filter(|m| m.main).map(|m| m.id).next();
-
-    if let Some(root_module_id) = root_module_id {
-      let messages = self
-        .get_stalled_top_level_await_message_for_module(scope, root_module_id);
-      if !messages.is_empty() {
-        return messages;
+  ) -> Vec<(ModuleId, v8::Global<v8::Message>)> {
+    let mut stalled = HashMap::new();
+    // V8 reports the stalled modules of the whole graph of a module, so
+    // checking the main module first usually finds all of them at once.
+    // Modules outside of its graph, eg. dynamically imported ones, are then
+    // found by checking the remaining modules.
+    let mut ids = self.registered_module_ids();
+    ids.sort_by_key(|id| !self.info[*id].main);
+    for id in ids {
+      if stalled.contains_key(&id) {
+        continue;
+      }
+      let module = v8::Local::new(scope, &self.handles[id]);
+      for (module, message) in module.get_stalled_top_level_await_message(scope)
+      {
+        let module = v8::Global::new(scope, module);
+        if let Some(info) = self.get_info(&module) {
+          stalled
+            .entry(info.id)
+            .or_insert_with(|| v8::Global::new(scope, message));
+        }
       }
     }
+    let mut stalled = stalled.into_iter().collect::<Vec<_>>();
+    stalled.sort_by_key(|(id, _)| *id);
+    stalled
+  }
 
-    // It wasn't a top module, so iterate over all modules and try to find
-    // any with stalled top level await
-    for module_id in 0..self.handles.len() {
-      let messages =
-        self.get_stalled_top_level_await_message_for_module(scope, module_id);
-      if !messages.is_empty() {
-        return messages;
+  /// Returns the specifiers of the modules importing each other, statically
+  /// or dynamically, from the main module to the given module. If the main
+  /// module doesn't import it, the chain starts from a module nothing
+  /// imports, eg. the root of a dynamic import or a side module.
+  pub(crate) fn import_chain(&self, id: ModuleId) -> Vec<String> {
+    let ids = self.registered_module_ids();
+    let imports = |id: ModuleId| {
+      let info = &self.info[id];
+      info
+        .requests
+        .iter()
+        .chain(info.dynamic_requests.iter())
+        .filter_map(|request| {
+          self.get_id(&request.specifier, &request.asserted_module_type)
+        })
+        .collect::<Vec<_>>()
+    };
+    let imported = ids
+      .iter()
+      .flat_map(|id| imports(*id))
+      .collect::<HashSet<_>>();
+    let mut roots = ids
+      .iter()
+      .copied()
+      .filter(|id| self.info[*id].main || !imported.contains(id))
+      .collect::<Vec<_>>();
+    roots.sort_by_key(|id| !self.info[*id].main);
+
+    for root in roots {
+      let mut parents = HashMap::from([(root, root)]);
+      let mut queue = VecDeque::from([root]);
+      while let Some(current) = queue.pop_front() {
+        if current == id {
+          let mut chain = vec![current];
+          let mut current = current;
+          while current != root {
+            current = parents[&current];
+            chain.push(current);
+          }
+          return chain
+            .into_iter()
+            .rev()
+            .map(|id| self.info[id].name.as_str().to_string())
+            .collect();
+        }
+        for next in imports(current) {
+          if let std::collections::hash_map::Entry::Vacant(entry) =
+            parents.entry(next)
+          {
+            entry.insert(current);
+            queue.push_back(next);
+          }
+        }
       }
     }
-
-    vec![]
+    vec![self.info[id].name.as_str().to_string()]
   }
 }
 
@@ -958,36 +2120,213 @@ impl Default for ModuleMap {
 // Clippy thinks the return value doesn't need to be an Option, it's unaware
 // of the mapping that MapFnFrom<F> does for ResolveModuleCallback.
 #[allow(clippy::unnecessary_wraps)]
-fn json_module_evaluation_steps<'a>(
+pub(crate) fn synthetic_module_evaluation_steps<'a>(
   context: v8::Local<'a, v8::Context>,
   module: v8::Local<v8::Module>,
 ) -> Option<v8::Local<'a, v8::Value>> {
   // SAFETY: `CallbackScope` can be safely constructed from `Local<Context>`
   let scope = &mut unsafe { v8::CallbackScope::new(context) };
-  let tc_scope = &mut v8::TryCatch::new(scope);
-  let module_map = JsRealm::module_map_from(tc_scope);
+  let module_map_rc = JsRealm::module_map_from(scope);
 
-  let handle = v8::Global::<v8::Module>::new(tc_scope, module);
-  let value_handle = module_map
+  let handle = v8::Global::<v8::Module>::new(scope, module);
+  let value = module_map_rc
     .borrow_mut()
-    .json_value_store
-    .remove(&handle)
-    .unwrap();
-  let value_local = v8::Local::new(tc_scope, value_handle);
+    .synthetic_value_store
+    .remove(&handle);
+  let exports = match value {
+    Some(SyntheticModuleValue::Default(value)) => {
+      vec![("default".to_string(), value)]
+    }
+    Some(SyntheticModuleValue::Exports(synthetic_module)) => {
+      match evaluate_embedder_synthetic_module(scope, synthetic_module) {
+        Ok(exports) => exports,
+        Err(exception) => {
+          let exception = v8::Local::new(scope, exception);
+          scope.throw_exception(exception);
+          return None;
+        }
+      }
+    }
+    Some(SyntheticModuleValue::CommonJs {
+      specifier,
+      export_names,
+    }) => {
+      let exports = commonjs::module_exports(scope, &specifier)?;
+      let exports_object = exports.to_object(scope);
+      let mut values =
+        vec![("default".to_string(), v8::Global::new(scope, exports))];
+      for export_name in export_names.into_iter().skip(1) {
+        let value = match exports_object {
+          Some(object) => commonjs::get(scope, object, &export_name)?,
+          None => v8::undefined(scope).into(),
+        };
+        values.push((export_name, v8::Global::new(scope, value)));
+      }
+      values
+    }
+    None => {
+      // A module restored from a snapshot, whose exports weren't provided
+      // again when the runtime was created.
+      let name = module_map_rc
+        .borrow()
+        .get_info(&handle)
+        .map(|info| info.name.as_str().to_string())
+        .unwrap_or_default();
+      throw_type_error(
+        scope,
+        format!("Synthetic module \"{name}\" has no evaluation callback."),
+      );
+      return None;
+    }
+  };
 
-  let name = v8::String::new(tc_scope, "default").unwrap();
-  // This should never fail
-  assert!(
-    module.set_synthetic_module_export(tc_scope, name, value_local)
-      == Some(true)
-  );
-  assert!(!tc_scope.has_caught());
+  for (export_name, value) in exports {
+    let export_name = v8::String::new(scope, &export_name).unwrap();
+    let value = v8::Local::new(scope, value);
+    module.set_synthetic_module_export(scope, export_name, value)?;
+  }
 
   // Since TLA is active we need to return a promise.
-  let resolver = v8::PromiseResolver::new(tc_scope).unwrap();
-  let undefined = v8::undefined(tc_scope);
-  resolver.resolve(tc_scope, undefined.into());
-  Some(resolver.get_promise(tc_scope).into())
+  let resolver = v8::PromiseResolver::new(scope).unwrap();
+  let undefined = v8::undefined(scope);
+  resolver.resolve(scope, undefined.into());
+  Some(resolver.get_promise(scope).into())
+}
+
+/// Run the evaluation callback of an embedder-provided synthetic module,
+/// returning the exception to throw if it fails.
+fn evaluate_embedder_synthetic_module(
+  scope: &mut v8::HandleScope,
+  module: SyntheticModule,
+) -> Result<Vec<(String, v8::Global<v8::Value>)>, v8::Global<v8::Value>> {
+  let tc_scope = &mut v8::TryCatch::new(scope);
+  let values = match (module.evaluate)(tc_scope) {
+    Ok(values) => values,
+    Err(err) => {
+      if let Some(exception) = tc_scope.exception() {
+        return Err(v8::Global::new(tc_scope, exception));
+      }
+      return Err(to_v8_type_error(tc_scope, err));
+    }
+  };
+  if values.len() != module.export_names.len() {
+    let err = generic_error(format!(
+      "Synthetic module \"{}\" has {} exports, but its evaluation callback returned {} values.",
+      module.specifier,
+      module.export_names.len(),
+      values.len()
+    ));
+    return Err(to_v8_type_error(tc_scope, err));
+  }
+  Ok(module.export_names.into_iter().zip(values).collect())
+}
+
+fn module_requests_to_v8<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  requests: &[ModuleRequest],
+) -> v8::Local<'s, v8::Array> {
+  let array_len = 2 * requests.len() as i32;
+  let requests_arr = v8::Array::new(scope, array_len);
+  for (i, request) in requests.iter().enumerate() {
+    let specifier = v8::String::new_from_one_byte(
+      scope,
+      request.specifier.as_bytes(),
+      v8::NewStringType::Normal,
+    )
+    .unwrap();
+    requests_arr.set_index(scope, 2 * i as u32, specifier.into());
+
+    let asserted_module_type =
+      asserted_module_type_to_v8(scope, &request.asserted_module_type);
+    requests_arr.set_index(scope, (2 * i) as u32 + 1, asserted_module_type);
+  }
+  requests_arr
+}
+
+fn module_requests_from_v8(
+  scope: &mut v8::HandleScope,
+  requests_val: v8::Local<v8::Value>,
+) -> Vec<ModuleRequest> {
+  let requests_arr: v8::Local<v8::Array> = requests_val.try_into().unwrap();
+  let len = (requests_arr.length() as usize) / 2;
+  let mut requests = Vec::with_capacity(len);
+  for i in 0..len {
+    let specifier = requests_arr
+      .get_index(scope, (2 * i) as u32)
+      .unwrap()
+      .to_rust_string_lossy(scope);
+    let asserted_module_type_val =
+      requests_arr.get_index(scope, (2 * i + 1) as u32).unwrap();
+    let asserted_module_type =
+      asserted_module_type_from_v8(scope, asserted_module_type_val);
+    requests.push(ModuleRequest {
+      specifier,
+      asserted_module_type,
+    });
+  }
+  requests
+}
+
+// Built-in module types are stored in snapshots as integers, custom module
+// types as their name.
+fn module_type_to_v8<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  module_type: &ModuleType,
+) -> v8::Local<'s, v8::Value> {
+  let no = match module_type {
+    ModuleType::JavaScript => 0,
+    ModuleType::Json => 1,
+    ModuleType::Wasm => 2,
+    ModuleType::CommonJs => 3,
+    ModuleType::Other(ty) => {
+      return v8::String::new(scope, ty).unwrap().into();
+    }
+  };
+  v8::Integer::new(scope, no).into()
+}
+
+fn module_type_from_v8(
+  scope: &mut v8::HandleScope,
+  value: v8::Local<v8::Value>,
+) -> ModuleType {
+  if value.is_string() {
+    return ModuleType::Other(value.to_rust_string_lossy(scope).into());
+  }
+  match value.to_integer(scope).unwrap().value() {
+    0 => ModuleType::JavaScript,
+    1 => ModuleType::Json,
+    2 => ModuleType::Wasm,
+    3 => ModuleType::CommonJs,
+    _ => unreachable!(),
+  }
+}
+
+fn asserted_module_type_to_v8<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  asserted_module_type: &AssertedModuleType,
+) -> v8::Local<'s, v8::Value> {
+  let no = match asserted_module_type {
+    AssertedModuleType::JavaScriptOrWasm => 0,
+    AssertedModuleType::Json => 1,
+    AssertedModuleType::Other(ty) => {
+      return v8::String::new(scope, ty).unwrap().into();
+    }
+  };
+  v8::Integer::new(scope, no).into()
+}
+
+fn asserted_module_type_from_v8(
+  scope: &mut v8::HandleScope,
+  value: v8::Local<v8::Value>,
+) -> AssertedModuleType {
+  if value.is_string() {
+    return AssertedModuleType::Other(value.to_rust_string_lossy(scope).into());
+  }
+  match value.to_integer(scope).unwrap().value() {
+    0 => AssertedModuleType::JavaScriptOrWasm,
+    1 => AssertedModuleType::Json,
+    _ => unreachable!(),
+  }
 }
 
 pub fn module_origin<'a>(
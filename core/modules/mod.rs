@@ -3,6 +3,7 @@ use crate::error::generic_error;
 use crate::fast_string::FastString;
 use crate::module_specifier::ModuleSpecifier;
 use crate::resolve_url;
+use crate::JsRuntime;
 use anyhow::Error;
 use futures::future::FutureExt;
 use futures::stream::FuturesUnordered;
@@ -11,6 +12,7 @@ use futures::stream::TryStreamExt;
 use log::debug;
 use serde::Deserialize;
 use serde::Serialize;
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -18,38 +20,165 @@ use std::collections::VecDeque;
 use std::future::Future;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Instant;
 
+mod archive;
+mod code_cache;
+mod commonjs;
+mod graph;
+mod import_attributes;
+mod import_meta;
+mod integrity;
 mod loaders;
 mod map;
+mod policy;
+mod synthetic;
+mod trace;
+mod wasm;
 
 #[cfg(test)]
 mod tests;
 
+pub use archive::ArchiveModuleLoader;
+pub use archive::ModuleArchiveEntry;
+pub use archive::ModuleArchiveWriter;
+pub(crate) use commonjs::require_callback as commonjs_require;
+pub use graph::ImportKind;
+pub use graph::ModuleGraph;
+pub use graph::ModuleGraphAlias;
+pub use graph::ModuleGraphDependency;
+pub use graph::ModuleGraphModule;
+pub use graph::ModuleStatus;
+pub use import_meta::ImportMeta;
+pub use import_meta::ImportMetaCallback;
+pub use import_meta::ImportMetaModuleInfo;
+pub(crate) use import_meta::ImportMetaProperty;
+pub use import_meta::LazyImportMetaCb;
+pub use integrity::IntegrityMode;
+pub use integrity::IntegrityModuleLoader;
+pub use integrity::ModuleIntegrityError;
+pub use integrity::ModuleLockfile;
+pub use loaders::AsyncFsModuleLoader;
+pub use loaders::AsyncFsModuleLoaderOptions;
+pub use loaders::DataUrlModuleLoader;
 pub(crate) use loaders::ExtModuleLoader;
 pub use loaders::ExtModuleLoaderCb;
 pub use loaders::FsModuleLoader;
+pub use loaders::ImportMapLoader;
 pub use loaders::ModuleLoader;
 pub use loaders::NoopModuleLoader;
+pub use loaders::RoutingModuleLoader;
+pub use loaders::StaticModuleLoader;
+pub(crate) use map::synthetic_module_evaluation_steps;
+use map::CompiledModule;
+pub(crate) use map::InvalidatedModule;
 pub(crate) use map::ModuleMap;
 #[cfg(test)]
 pub(crate) use map::SymbolicModule;
+pub use policy::ModuleLoadPolicy;
+pub use policy::ModuleLoadPolicyError;
+pub use policy::ModuleLoadRules;
+pub use policy::ModuleLoadRulesOverride;
+pub use policy::PolicyModuleLoader;
+pub(crate) use synthetic::check_synthetic_modules;
+pub use synthetic::SyntheticModule;
+pub use synthetic::SyntheticModuleEvaluationCb;
+pub use trace::ChromeTraceExporter;
+pub use trace::ModuleLoadEvent;
+pub use trace::ModuleLoadPhase;
+pub use trace::ModuleLoadTraceCallback;
+pub(crate) use trace::ModuleLoadTracer;
+#[cfg(test)]
+pub(crate) use wasm::ADD_WASM;
 
 pub type ModuleId = usize;
 pub(crate) type ModuleLoadId = i32;
 pub type ModuleCode = FastString;
 pub type ModuleName = FastString;
 
-const SUPPORTED_TYPE_ASSERTIONS: &[&str] = &["json"];
+/// Callback that evaluates a module with a custom type, ie. one imported with
+/// a `type` import attribute that is registered in
+/// [`RuntimeOptions::custom_module_types`](crate::RuntimeOptions::custom_module_types).
+///
+/// It receives the name and source of the loaded module and returns the value
+/// of the `default` export of the synthetic module created for it.
+pub type CustomModuleEvaluationCb = Box<
+  dyn Fn(
+    &mut v8::HandleScope,
+    &ModuleName,
+    ModuleSourceCode,
+  ) -> Result<v8::Global<v8::Value>, Error>,
+>;
+
+/// Custom module types of a runtime, keyed by the value of the `type` import
+/// attribute.
+pub(crate) type CustomModuleTypes = HashMap<String, CustomModuleEvaluationCb>;
+
+/// Adds the custom module types that are available in every runtime, unless
+/// the embedder provided its own callbacks for them:
+/// - `"text"`, whose default export is the source as a string,
+/// - `"bytes"`, whose default export is the source as a `Uint8Array`.
+pub(crate) fn add_builtin_custom_module_types(
+  custom_module_types: &mut CustomModuleTypes,
+) {
+  custom_module_types
+    .entry("text".to_string())
+    .or_insert_with(|| Box::new(text_module_evaluation));
+  custom_module_types
+    .entry("bytes".to_string())
+    .or_insert_with(|| Box::new(bytes_module_evaluation));
+}
+
+fn text_module_evaluation(
+  scope: &mut v8::HandleScope,
+  name: &ModuleName,
+  source: ModuleSourceCode,
+) -> Result<v8::Global<v8::Value>, Error> {
+  let code = source.try_into_string(name.as_str())?;
+  let value = v8::String::new_from_utf8(
+    scope,
+    code.as_bytes(),
+    v8::NewStringType::Normal,
+  )
+  .ok_or_else(|| {
+    generic_error(format!(
+      "Module source for \"{}\" is too large.",
+      name.as_str()
+    ))
+  })?;
+  Ok(v8::Global::new(scope, v8::Local::<v8::Value>::from(value)))
+}
+
+fn bytes_module_evaluation(
+  scope: &mut v8::HandleScope,
+  _name: &ModuleName,
+  source: ModuleSourceCode,
+) -> Result<v8::Global<v8::Value>, Error> {
+  let bytes = source.as_bytes().to_vec().into_boxed_slice();
+  let len = bytes.len();
+  let backing_store =
+    v8::ArrayBuffer::new_backing_store_from_boxed_slice(bytes).make_shared();
+  let buffer = v8::ArrayBuffer::with_backing_store(scope, &backing_store);
+  let value = v8::Uint8Array::new(scope, buffer, 0, len).unwrap();
+  Ok(v8::Global::new(scope, v8::Local::<v8::Value>::from(value)))
+}
 
 /// Throws V8 exception if assertions are invalid
 pub(crate) fn validate_import_assertions(
   scope: &mut v8::HandleScope,
   assertions: &HashMap<String, String>,
 ) {
+  let state_rc = JsRuntime::state_from(scope);
+  let state = state_rc.borrow();
   for (key, value) in assertions {
-    if key == "type" && !SUPPORTED_TYPE_ASSERTIONS.contains(&value.as_str()) {
+    if key == "type"
+      && value != "json"
+      && !state.custom_module_types.contains_key(value)
+    {
+      drop(state);
       let message = v8::String::new(
         scope,
         &format!("\"{value}\" is not a valid module type."),
@@ -107,16 +236,11 @@ pub(crate) fn parse_import_assertions(
 pub(crate) fn get_asserted_module_type_from_assertions(
   assertions: &HashMap<String, String>,
 ) -> AssertedModuleType {
-  assertions
-    .get("type")
-    .map(|ty| {
-      if ty == "json" {
-        AssertedModuleType::Json
-      } else {
-        AssertedModuleType::JavaScriptOrWasm
-      }
-    })
-    .unwrap_or(AssertedModuleType::JavaScriptOrWasm)
+  match assertions.get("type").map(String::as_str) {
+    None => AssertedModuleType::JavaScriptOrWasm,
+    Some("json") => AssertedModuleType::Json,
+    Some(ty) => AssertedModuleType::Other(Cow::Owned(ty.to_string())),
+  }
 }
 
 /// A type of module to be executed.
@@ -125,11 +249,29 @@ pub(crate) fn get_asserted_module_type_from_assertions(
 /// how to interpret the module; it is only used to validate
 /// the module against an import assertion (if one is present
 /// in the import statement).
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
-#[repr(u32)]
+///
+/// `Wasm` modules are compiled from the binary source and linked
+/// against their imports, which are resolved as regular ES imports.
+///
+/// `CommonJs` modules are run in a function wrapper providing `require()`,
+/// `module` and `exports`, and can be imported like JavaScript modules. Their
+/// `module.exports` is the `default` export, and the names found to be
+/// assigned to it are named exports. They can only be loaded if
+/// [`RuntimeOptions::commonjs_modules`](crate::RuntimeOptions::commonjs_modules)
+/// is set, which also describes what `require()` can return.
+///
+/// `Other` modules have a custom type, registered in
+/// [`RuntimeOptions::custom_module_types`](crate::RuntimeOptions::custom_module_types)
+/// and requested with a `type` import attribute. Any loaded source can be
+/// imported with a custom type; loaders only need to return this variant for
+/// sources that can't be imported otherwise.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum ModuleType {
   JavaScript,
   Json,
+  Wasm,
+  CommonJs,
+  Other(Cow<'static, str>),
 }
 
 impl std::fmt::Display for ModuleType {
@@ -137,10 +279,149 @@ impl std::fmt::Display for ModuleType {
     match self {
       Self::JavaScript => write!(f, "JavaScript"),
       Self::Json => write!(f, "JSON"),
+      Self::Wasm => write!(f, "Wasm"),
+      Self::CommonJs => write!(f, "CommonJS"),
+      Self::Other(ty) => write!(f, "{ty}"),
     }
   }
 }
 
+/// Binary module source, eg. the contents of a `.wasm` file.
+///
+/// Like [`FastString`], this allows loaders to hand over static, owned or
+/// shared data without an extra copy.
+#[derive(Clone)]
+pub enum ModuleCodeBytes {
+  /// Created from static data.
+  Static(&'static [u8]),
+
+  /// An owned chunk of data.
+  Boxed(Box<[u8]>),
+
+  /// Shared data, eg. from a cache that outlives the load.
+  Arc(Arc<[u8]>),
+}
+
+impl ModuleCodeBytes {
+  pub fn as_bytes(&self) -> &[u8] {
+    match self {
+      Self::Static(b) => b,
+      Self::Boxed(b) => b,
+      Self::Arc(b) => b,
+    }
+  }
+
+  pub fn to_vec(&self) -> Vec<u8> {
+    self.as_bytes().to_vec()
+  }
+}
+
+impl std::fmt::Debug for ModuleCodeBytes {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "ModuleCodeBytes({} bytes)", self.as_bytes().len())
+  }
+}
+
+impl From<&'static [u8]> for ModuleCodeBytes {
+  fn from(value: &'static [u8]) -> Self {
+    Self::Static(value)
+  }
+}
+
+impl From<Vec<u8>> for ModuleCodeBytes {
+  fn from(value: Vec<u8>) -> Self {
+    Self::Boxed(value.into_boxed_slice())
+  }
+}
+
+impl From<Box<[u8]>> for ModuleCodeBytes {
+  fn from(value: Box<[u8]>) -> Self {
+    Self::Boxed(value)
+  }
+}
+
+impl From<Arc<[u8]>> for ModuleCodeBytes {
+  fn from(value: Arc<[u8]>) -> Self {
+    Self::Arc(value)
+  }
+}
+
+/// The source of a module, either as text (JavaScript, JSON) or as raw bytes
+/// (Wasm).
+#[derive(Debug)]
+pub enum ModuleSourceCode {
+  String(ModuleCode),
+  Bytes(ModuleCodeBytes),
+}
+
+impl ModuleSourceCode {
+  pub fn as_bytes(&self) -> &[u8] {
+    match self {
+      Self::String(s) => s.as_bytes(),
+      Self::Bytes(b) => b.as_bytes(),
+    }
+  }
+
+  /// Returns the source as text, failing if it was provided as bytes that are
+  /// not valid UTF-8.
+  pub(crate) fn try_into_string(
+    self,
+    specifier: &str,
+  ) -> Result<ModuleCode, Error> {
+    match self {
+      Self::String(s) => Ok(s),
+      Self::Bytes(b) => {
+        String::from_utf8(b.to_vec()).map(Into::into).map_err(|_| {
+          generic_error(format!(
+            "Module source for \"{specifier}\" is not valid UTF-8."
+          ))
+        })
+      }
+    }
+  }
+}
+
+impl Default for ModuleSourceCode {
+  fn default() -> Self {
+    Self::String(Default::default())
+  }
+}
+
+impl From<ModuleCode> for ModuleSourceCode {
+  fn from(value: ModuleCode) -> Self {
+    Self::String(value)
+  }
+}
+
+impl From<String> for ModuleSourceCode {
+  fn from(value: String) -> Self {
+    Self::String(value.into())
+  }
+}
+
+impl From<&'static str> for ModuleSourceCode {
+  fn from(value: &'static str) -> Self {
+    Self::String(ModuleCode::from_static(value))
+  }
+}
+
+impl From<ModuleCodeBytes> for ModuleSourceCode {
+  fn from(value: ModuleCodeBytes) -> Self {
+    Self::Bytes(value)
+  }
+}
+
+/// V8 code cache of a JavaScript module, see [`ModuleSource::code_cache`].
+#[derive(Debug)]
+pub enum ModuleCodeCache {
+  /// The loader has no code cache for this module yet, and wants one to be
+  /// produced through [`ModuleLoader::code_cache_ready`].
+  Missing,
+  /// Code cache data previously passed to
+  /// [`ModuleLoader::code_cache_ready`] for this module.
+  Data(ModuleCodeBytes),
+}
+
 /// EsModule source code that will be loaded into V8.
 ///
 /// Users can implement `Into<ModuleInfo>` for different file types that
@@ -151,34 +432,40 @@ impl std::fmt::Display for ModuleType {
 /// Eg. Both "`https://example.com/a.ts`" and
 /// "`https://example.com/b.ts`" may point to "`https://example.com/c.ts`"
 /// By keeping track of specified and found URL we can alias modules and avoid
-/// recompiling the same code 3 times.
-// TODO(bartlomieju): I have a strong opinion we should store all redirects
-// that happened; not only first and final target. It would simplify a lot
-// of things throughout the codebase otherwise we may end up requesting
-// intermediate redirects from file loader.
+/// recompiling the same code 3 times. If the module was redirected more than
+/// once, every intermediate URL is aliased too, so that none of them is
+/// requested from the loader again.
 // NOTE: This should _not_ be made #[derive(Clone)] unless we take some precautions to avoid excessive string copying.
 #[derive(Debug)]
 pub struct ModuleSource {
-  pub code: ModuleCode,
+  pub code: ModuleSourceCode,
   pub module_type: ModuleType,
+  /// V8 code cache for JavaScript modules. If [`None`], no code cache is
+  /// consumed or produced for this module.
+  pub code_cache: Option<ModuleCodeCache>,
   module_url_specified: ModuleName,
   /// If the module was found somewhere other than the specified address, this will be [`Some`].
   module_url_found: Option<ModuleName>,
+  /// The URLs the specified URL was redirected through before reaching
+  /// `module_url_found`, in order.
+  module_url_redirects: Vec<ModuleName>,
 }
 
 impl ModuleSource {
   /// Create a [`ModuleSource`] without a redirect.
   pub fn new(
     module_type: impl Into<ModuleType>,
-    code: ModuleCode,
+    code: impl Into<ModuleSourceCode>,
     specifier: &ModuleSpecifier,
   ) -> Self {
     let module_url_specified = specifier.as_ref().to_owned().into();
     Self {
-      code,
+      code: code.into(),
       module_type: module_type.into(),
+      code_cache: None,
       module_url_specified,
       module_url_found: None,
+      module_url_redirects: vec![],
     }
   }
 
@@ -186,7 +473,7 @@ impl ModuleSource {
   /// specifier, the code behaves the same was as `ModuleSource::new`.
   pub fn new_with_redirect(
     module_type: impl Into<ModuleType>,
-    code: ModuleCode,
+    code: impl Into<ModuleSourceCode>,
     specifier: &ModuleSpecifier,
     specifier_found: &ModuleSpecifier,
   ) -> Self {
@@ -197,20 +484,67 @@ impl ModuleSource {
     };
     let module_url_specified = specifier.as_ref().to_owned().into();
     Self {
-      code,
+      code: code.into(),
       module_type: module_type.into(),
+      code_cache: None,
       module_url_specified,
       module_url_found,
+      module_url_redirects: vec![],
+    }
+  }
+
+  /// Create a [`ModuleSource`] for a module that was redirected, possibly more
+  /// than once. `redirect_chain` lists the URLs the `specifier` was redirected
+  /// to, in order, the last one being where the module was found. If it's
+  /// empty, the code behaves the same way as `ModuleSource::new`.
+  pub fn new_with_redirect_chain(
+    module_type: impl Into<ModuleType>,
+    code: impl Into<ModuleSourceCode>,
+    specifier: &ModuleSpecifier,
+    redirect_chain: &[ModuleSpecifier],
+  ) -> Self {
+    let mut hops: Vec<&ModuleSpecifier> =
+      Vec::with_capacity(redirect_chain.len());
+    for hop in redirect_chain {
+      if hops.last().copied().unwrap_or(specifier) != hop {
+        hops.push(hop);
+      }
     }
+    let Some(specifier_found) = hops.pop() else {
+      return Self::new(module_type, code, specifier);
+    };
+    let mut module_source =
+      Self::new_with_redirect(module_type, code, specifier, specifier_found);
+    module_source.module_url_redirects = hops
+      .into_iter()
+      .map(|hop| hop.as_ref().to_owned().into())
+      .collect();
+    module_source
+  }
+
+  /// The URLs the module was redirected through, starting with the specified
+  /// URL and ending with the URL it was found at. Empty if the module wasn't
+  /// redirected.
+  pub fn redirect_chain(&self) -> Vec<&str> {
+    let Some(module_url_found) = &self.module_url_found else {
+      return vec![];
+    };
+    std::iter::once(&self.module_url_specified)
+      .chain(&self.module_url_redirects)
+      .chain(std::iter::once(module_url_found))
+      .map(|url| url.as_str())
+      .collect()
   }
 
   #[cfg(test)]
   pub fn for_test(code: &'static str, file: impl AsRef<str>) -> Self {
     Self {
-      code: ModuleCode::from_static(code),
+      code: ModuleSourceCode::String(ModuleCode::from_static(code)),
       module_type: ModuleType::JavaScript,
+      code_cache: None,
       module_url_specified: file.as_ref().to_owned().into(),
       module_url_found: None,
+      module_url_redirects: vec![],
     }
   }
 
@@ -229,10 +563,12 @@ impl ModuleSource {
       Some(found.into())
     };
     Self {
-      code: ModuleCode::from_static(code),
+      code: ModuleSourceCode::String(ModuleCode::from_static(code)),
       module_type: ModuleType::JavaScript,
+      code_cache: None,
       module_url_specified: specified.into(),
       module_url_found: found,
+      module_url_redirects: vec![],
     }
   }
 }
@@ -240,11 +576,13 @@ impl ModuleSource {
 pub(crate) type PrepareLoadFuture =
   dyn Future<Output = (ModuleLoadId, Result<RecursiveModuleLoad, Error>)>;
 pub type ModuleSourceFuture = dyn Future<Output = Result<ModuleSource, Error>>;
+pub type ModuleResolveFuture =
+  dyn Future<Output = Result<ModuleSpecifier, Error>>;
 
 type ModuleLoadFuture =
   dyn Future<Output = Result<(ModuleRequest, ModuleSource), Error>>;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ResolutionKind {
   /// This kind is used in only one situation: when a module is loaded via
   /// `JsRuntime::load_main_module` and is the top-level module, ie. the one
@@ -293,6 +631,26 @@ pub(crate) struct RecursiveModuleLoad {
   // The loader is copied from `module_map_rc`, but its reference is cloned
   // ahead of time to avoid already-borrowed errors.
   loader: Rc<dyn ModuleLoader>,
+  tracer: Option<ModuleLoadTracer>,
+  // The referrer of each module being loaded, when tracing.
+  referrers: HashMap<String, String>,
+  // The root specifier, once it's resolved by `prepare()`.
+  root_specifier: Option<ModuleSpecifier>,
+  // Compiled modules waiting for `ModuleLoader::resolve_async` to resolve
+  // some of their imports. Once it does, a fake load event for the module is
+  // passed to `register_and_recurse()`, which registers it.
+  deferred: HashMap<ModuleRequest, DeferredModule>,
+}
+
+struct DeferredModule {
+  compiled: CompiledModule,
+  // The imports resolved by `ModuleLoader::resolve`.
+  resolved: Vec<Option<ModuleSpecifier>>,
+  // The other imports, by specifier as written, once they're resolved by
+  // `ModuleLoader::resolve_async`.
+  async_resolved: Rc<RefCell<HashMap<String, ModuleSpecifier>>>,
+  asserted_module_type: AssertedModuleType,
+  compile_start: Instant,
 }
 
 impl RecursiveModuleLoad {
@@ -335,8 +693,9 @@ impl RecursiveModuleLoad {
       id
     };
     let loader = module_map_rc.borrow().loader.clone();
+    let tracer = module_map_rc.borrow().tracer.clone();
     let asserted_module_type = match init {
-      LoadInit::DynamicImport(_, _, module_type) => module_type,
+      LoadInit::DynamicImport(_, _, ref module_type) => module_type.clone(),
       _ => AssertedModuleType::JavaScriptOrWasm,
     };
     let mut load = Self {
@@ -348,6 +707,10 @@ impl RecursiveModuleLoad {
       state: LoadState::Init,
       module_map_rc: module_map_rc.clone(),
       loader,
+      tracer,
+      referrers: HashMap::new(),
+      root_specifier: None,
+      deferred: HashMap::new(),
       pending: FuturesUnordered::new(),
       visited: HashSet::new(),
     };
@@ -356,7 +719,7 @@ impl RecursiveModuleLoad {
     if let Ok(root_specifier) = load.resolve_root() {
       if let Some(module_id) = module_map_rc
         .borrow()
-        .get_id(root_specifier, asserted_module_type)
+        .get_id(root_specifier, &asserted_module_type)
       {
         load.root_module_id = Some(module_id);
         load.root_asserted_module_type = Some(asserted_module_type);
@@ -365,59 +728,91 @@ impl RecursiveModuleLoad {
             .borrow()
             .get_info_by_id(module_id)
             .unwrap()
-            .module_type,
+            .module_type
+            .clone(),
         );
       }
     }
     load
   }
 
-  fn resolve_root(&self) -> Result<ModuleSpecifier, Error> {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    trace::resolve(
+      &*self.loader,
+      self.tracer.as_ref(),
+      specifier,
+      referrer,
+      kind,
+    )
+  }
+
+  /// The root specifier as written, what it's resolved against, and the
+  /// kind of its resolution.
+  fn root_request(&self) -> (&str, &str, ResolutionKind) {
     match self.init {
       LoadInit::Main(ref specifier) => {
-        self
-          .loader
-          .resolve(specifier, ".", ResolutionKind::MainModule)
+        (specifier, ".", ResolutionKind::MainModule)
       }
-      LoadInit::Side(ref specifier) => {
-        self.loader.resolve(specifier, ".", ResolutionKind::Import)
+      LoadInit::Side(ref specifier) => (specifier, ".", ResolutionKind::Import),
+      LoadInit::DynamicImport(ref specifier, ref referrer, _) => {
+        (specifier, referrer, ResolutionKind::DynamicImport)
       }
-      LoadInit::DynamicImport(ref specifier, ref referrer, _) => self
-        .loader
-        .resolve(specifier, referrer, ResolutionKind::DynamicImport),
     }
   }
 
-  async fn prepare(&self) -> Result<(), Error> {
-    let (module_specifier, maybe_referrer) = match self.init {
-      LoadInit::Main(ref specifier) => {
-        let spec =
-          self
-            .loader
-            .resolve(specifier, ".", ResolutionKind::MainModule)?;
-        (spec, None)
-      }
-      LoadInit::Side(ref specifier) => {
-        let spec =
-          self
-            .loader
-            .resolve(specifier, ".", ResolutionKind::Import)?;
-        (spec, None)
-      }
-      LoadInit::DynamicImport(ref specifier, ref referrer, _) => {
-        let spec = self.loader.resolve(
-          specifier,
-          referrer,
-          ResolutionKind::DynamicImport,
-        )?;
-        (spec, Some(referrer.to_string()))
+  fn resolve_root(&self) -> Result<ModuleSpecifier, Error> {
+    if let Some(root_specifier) = &self.root_specifier {
+      return Ok(root_specifier.clone());
+    }
+    let (specifier, referrer, kind) = self.root_request();
+    self.resolve(specifier, referrer, kind)
+  }
+
+  async fn prepare(&mut self) -> Result<(), Error> {
+    let module_specifier = match self.resolve_root() {
+      Ok(module_specifier) => module_specifier,
+      Err(_) if self.loader.supports_async_resolution() => {
+        let (specifier, referrer, kind) = self.root_request();
+        trace::resolve_async(
+          self.loader.clone(),
+          self.tracer.clone(),
+          specifier.to_string(),
+          referrer.to_string(),
+          kind,
+        )
+        .await?
       }
+      Err(err) => return Err(err),
+    };
+    self.root_specifier = Some(module_specifier.clone());
+    let maybe_referrer = match self.init {
+      LoadInit::DynamicImport(_, ref referrer, _) => Some(referrer.to_string()),
+      _ => None,
     };
 
-    self
+    let start = Instant::now();
+    let result = self
       .loader
-      .prepare_load(&module_specifier, maybe_referrer, self.is_dynamic_import())
-      .await
+      .prepare_load(
+        &module_specifier,
+        maybe_referrer.clone(),
+        self.is_dynamic_import(),
+      )
+      .await;
+    if let Some(tracer) = &self.tracer {
+      tracer.trace(
+        ModuleLoadPhase::PrepareLoad,
+        module_specifier.as_str(),
+        maybe_referrer.as_deref(),
+        start,
+      );
+    }
+    result
   }
 
   fn is_currently_loading_main_module(&self) -> bool {
@@ -436,60 +831,13 @@ impl RecursiveModuleLoad {
     module_request: &ModuleRequest,
     module_source: ModuleSource,
   ) -> Result<(), ModuleError> {
-    let expected_asserted_module_type = module_source.module_type.into();
-    let module_url_found = module_source.module_url_found;
-    let module_url_specified = module_source.module_url_specified;
-
-    if module_request.asserted_module_type != expected_asserted_module_type {
-      return Err(ModuleError::Other(generic_error(format!(
-        "Expected a \"{}\" module but loaded a \"{}\" module.",
-        module_request.asserted_module_type, module_source.module_type,
-      ))));
-    }
-
-    // Register the module in the module map unless it's already there. If the
-    // specified URL and the "true" URL are different, register the alias.
-    let module_url_found = if let Some(module_url_found) = module_url_found {
-      let (module_url_found1, module_url_found2) =
-        module_url_found.into_cheap_copy();
-      self.module_map_rc.borrow_mut().alias(
-        module_url_specified,
-        expected_asserted_module_type,
-        module_url_found1,
-      );
-      module_url_found2
-    } else {
-      module_url_specified
+    let registered = match self.deferred.remove(module_request) {
+      Some(deferred) => Some(self.register_deferred(module_request, deferred)?),
+      None => self.register(scope, module_request, module_source)?,
     };
-
-    let maybe_module_id = self
-      .module_map_rc
-      .borrow()
-      .get_id(&module_url_found, expected_asserted_module_type);
-    let module_id = match maybe_module_id {
-      Some(id) => {
-        debug!(
-          "Already-registered module fetched again: {:?}",
-          module_url_found
-        );
-        id
-      }
-      None => match module_source.module_type {
-        ModuleType::JavaScript => {
-          self.module_map_rc.borrow_mut().new_es_module(
-            scope,
-            self.is_currently_loading_main_module(),
-            module_url_found,
-            module_source.code,
-            self.is_dynamic_import(),
-          )?
-        }
-        ModuleType::Json => self.module_map_rc.borrow_mut().new_json_module(
-          scope,
-          module_url_found,
-          module_source.code,
-        )?,
-      },
+    // The module is deferred until its imports are resolved.
+    let Some((module_id, expected_asserted_module_type)) = registered else {
+      return Ok(());
     };
 
     // Recurse the module's imports. There are two cases for each import:
@@ -512,11 +860,20 @@ impl RecursiveModuleLoad {
         .get_requested_modules(module_id)
         .unwrap()
         .clone();
+      let redirect_chain = self.module_map_rc.borrow().redirect_chain(
+        &module_request.specifier,
+        &module_request.asserted_module_type,
+      );
+      let redirect_chain = redirect_chain
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+      let redirects = describe_redirect_chain(&redirect_chain);
       for module_request in imports {
         if !self.visited.contains(&module_request) {
           if let Some(module_id) = self.module_map_rc.borrow().get_id(
             module_request.specifier.as_str(),
-            module_request.asserted_module_type,
+            &module_request.asserted_module_type,
           ) {
             already_registered.push_back((module_id, module_request.clone()));
           } else {
@@ -526,11 +883,34 @@ impl RecursiveModuleLoad {
             let referrer = referrer.clone();
             let loader = self.loader.clone();
             let is_dynamic_import = self.is_dynamic_import();
+            let tracer = self.tracer.clone();
+            let redirects = redirects.clone();
+            if tracer.is_some() {
+              self
+                .referrers
+                .insert(module_request.specifier.clone(), referrer.to_string());
+            }
             let fut = async move {
+              let start = Instant::now();
               let load_result = loader
                 .load(&specifier, Some(&referrer), is_dynamic_import)
                 .await;
-              load_result.map(|s| (request, s))
+              if let Some(tracer) = tracer {
+                tracer.trace(
+                  ModuleLoadPhase::Load,
+                  specifier.as_str(),
+                  Some(referrer.as_str()),
+                  start,
+                );
+              }
+              load_result.map(|s| (request, s)).map_err(|err| {
+                if redirects.is_empty() {
+                  return err;
+                }
+                let message =
+                  format!("{err} Imported by \"{referrer}\".{redirects}");
+                err.context(message)
+              })
             };
             self.pending.push(fut.boxed_local());
           }
@@ -541,8 +921,14 @@ impl RecursiveModuleLoad {
 
     // Update `self.state` however applicable.
     if self.state == LoadState::LoadingRoot {
+      if let LoadInit::DynamicImport(_, referrer, _) = &self.init {
+        self
+          .module_map_rc
+          .borrow_mut()
+          .add_dynamic_request(referrer, module_request);
+      }
       self.root_module_id = Some(module_id);
-      self.root_asserted_module_type = Some(module_source.module_type.into());
+      self.root_asserted_module_type = Some(expected_asserted_module_type);
       self.state = LoadState::LoadingImports;
     }
     if self.pending.is_empty() {
@@ -551,6 +937,312 @@ impl RecursiveModuleLoad {
 
     Ok(())
   }
+
+  /// Registers a loaded module, returning its id and asserted type, unless
+  /// it's deferred until some of its imports are resolved with
+  /// [`ModuleLoader::resolve_async`].
+  ///
+  /// If the module was redirected, the redirects are appended to the errors
+  /// it fails with, including the exceptions thrown while compiling it.
+  fn register(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    module_request: &ModuleRequest,
+    module_source: ModuleSource,
+  ) -> Result<Option<(ModuleId, AssertedModuleType)>, ModuleError> {
+    let redirects = describe_redirect_chain(&module_source.redirect_chain());
+    let result = self.register_source(scope, module_request, module_source);
+    if redirects.is_empty() {
+      return result;
+    }
+    result.map_err(|err| match err {
+      ModuleError::Exception(exception) => {
+        let exception = v8::Local::new(scope, exception);
+        if let Ok(exception) = v8::Local::<v8::Object>::try_from(exception) {
+          let key = v8::String::new(scope, "message").unwrap();
+          if let Some(message) = exception.get(scope, key.into()) {
+            let message =
+              format!("{}{redirects}", message.to_rust_string_lossy(scope));
+            let message = v8::String::new(scope, &message).unwrap();
+            exception.set(scope, key.into(), message.into());
+          }
+        }
+        ModuleError::Exception(v8::Global::new(scope, exception))
+      }
+      ModuleError::Other(err) => {
+        let message = format!("{err}{redirects}");
+        ModuleError::Other(err.context(message))
+      }
+    })
+  }
+
+  fn register_source(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    module_request: &ModuleRequest,
+    module_source: ModuleSource,
+  ) -> Result<Option<(ModuleId, AssertedModuleType)>, ModuleError> {
+    let module_url_found = module_source.module_url_found;
+    let module_url_specified = module_source.module_url_specified;
+    let module_url_redirects = module_source.module_url_redirects;
+
+    if !module_request
+      .asserted_module_type
+      .accepts(&module_source.module_type)
+    {
+      return Err(ModuleError::Other(generic_error(format!(
+        "Expected a \"{}\" module but loaded a \"{}\" module.",
+        module_request.asserted_module_type, module_source.module_type,
+      ))));
+    }
+    // A custom module type is determined by the import attribute rather than
+    // by the loaded source.
+    let module_type = match &module_request.asserted_module_type {
+      AssertedModuleType::Other(ty) => ModuleType::Other(ty.clone()),
+      _ => module_source.module_type,
+    };
+    let expected_asserted_module_type = AssertedModuleType::from(&module_type);
+
+    // Register the module in the module map unless it's already there. If the
+    // specified URL and the "true" URL are different, register an alias for
+    // each redirect.
+    let module_url_found = if let Some(module_url_found) = module_url_found {
+      let mut module_map = self.module_map_rc.borrow_mut();
+      let mut from = module_url_specified;
+      for to in module_url_redirects {
+        let (to1, to2) = to.into_cheap_copy();
+        module_map.alias(from, expected_asserted_module_type.clone(), to1);
+        from = to2;
+      }
+      let (module_url_found1, module_url_found2) =
+        module_url_found.into_cheap_copy();
+      module_map.alias(
+        from,
+        expected_asserted_module_type.clone(),
+        module_url_found1,
+      );
+      module_url_found2
+    } else {
+      module_url_specified
+    };
+
+    let maybe_module_id = self
+      .module_map_rc
+      .borrow()
+      .get_id(&module_url_found, &expected_asserted_module_type);
+    let compile_start = Instant::now();
+    let traced_specifier = self
+      .tracer
+      .is_some()
+      .then(|| module_url_found.as_str().to_string());
+    let module_id = match maybe_module_id {
+      Some(id) => {
+        debug!(
+          "Already-registered module fetched again: {:?}",
+          module_url_found
+        );
+        id
+      }
+      None => match module_type {
+        ModuleType::JavaScript => {
+          let code = module_source
+            .code
+            .try_into_string(module_url_found.as_str())
+            .map_err(ModuleError::Other)?;
+          let mut module_map = self.module_map_rc.borrow_mut();
+          let compiled = module_map.compile_module_from_js_source(
+            scope,
+            self.is_currently_loading_main_module(),
+            ModuleType::JavaScript,
+            module_url_found,
+            code,
+            self.is_dynamic_import(),
+            module_source.code_cache,
+          )?;
+          let resolved = module_map.resolve_imports(&compiled);
+          drop(module_map);
+          if resolved.iter().any(Result::is_err)
+            && self.loader.supports_async_resolution()
+          {
+            self.defer(
+              module_request,
+              DeferredModule {
+                compiled,
+                resolved: resolved.into_iter().map(Result::ok).collect(),
+                async_resolved: Default::default(),
+                asserted_module_type: expected_asserted_module_type,
+                compile_start,
+              },
+            );
+            return Ok(None);
+          }
+          let resolved = resolved
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ModuleError::Other)?;
+          self
+            .module_map_rc
+            .borrow_mut()
+            .register_compiled_module(compiled, resolved)?
+        }
+        ModuleType::Json => {
+          let code = module_source
+            .code
+            .try_into_string(module_url_found.as_str())
+            .map_err(ModuleError::Other)?;
+          self.module_map_rc.borrow_mut().new_json_module(
+            scope,
+            module_url_found,
+            code,
+          )?
+        }
+        ModuleType::Wasm => self.module_map_rc.borrow_mut().new_wasm_module(
+          scope,
+          module_url_found,
+          module_source.code,
+          self.is_dynamic_import(),
+        )?,
+        ModuleType::CommonJs => {
+          let code = module_source
+            .code
+            .try_into_string(module_url_found.as_str())
+            .map_err(ModuleError::Other)?;
+          self.module_map_rc.borrow_mut().new_commonjs_module(
+            scope,
+            module_url_found,
+            code,
+          )?
+        }
+        ModuleType::Other(ty) => self
+          .module_map_rc
+          .borrow_mut()
+          .new_custom_module(scope, module_url_found, ty, module_source.code)?,
+      },
+    };
+    if let (Some(specifier), None) = (traced_specifier, maybe_module_id) {
+      self.trace_compile(module_request, &specifier, compile_start);
+    }
+    Ok(Some((module_id, expected_asserted_module_type)))
+  }
+
+  fn trace_compile(
+    &self,
+    module_request: &ModuleRequest,
+    specifier: &str,
+    start: Instant,
+  ) {
+    let Some(tracer) = &self.tracer else {
+      return;
+    };
+    let referrer = match &self.init {
+      LoadInit::DynamicImport(_, referrer, _)
+        if self.state == LoadState::LoadingRoot =>
+      {
+        Some(referrer.as_str())
+      }
+      _ => self
+        .referrers
+        .get(&module_request.specifier)
+        .map(String::as_str),
+    };
+    tracer.trace(ModuleLoadPhase::Compile, specifier, referrer, start);
+  }
+
+  /// Resolves the imports of a compiled module that [`ModuleLoader::resolve`]
+  /// failed to resolve with [`ModuleLoader::resolve_async`], then passes a
+  /// fake load event for it to `register_and_recurse()`.
+  fn defer(
+    &mut self,
+    module_request: &ModuleRequest,
+    deferred: DeferredModule,
+  ) {
+    let referrer = deferred.compiled.name().to_string();
+    let kind = deferred.compiled.kind();
+    let unresolved = deferred
+      .compiled
+      .import_specifiers()
+      .zip(&deferred.resolved)
+      .filter(|(_, resolved)| resolved.is_none())
+      .map(|(specifier, _)| specifier.to_string())
+      .collect::<Vec<_>>();
+    let async_resolved = deferred.async_resolved.clone();
+    let loader = self.loader.clone();
+    let tracer = self.tracer.clone();
+    let request = module_request.clone();
+    // The code will be discarded, since the module is compiled already.
+    let module_source = ModuleSource::new(
+      ModuleType::JavaScript,
+      ModuleSourceCode::default(),
+      &ModuleSpecifier::parse(&referrer).unwrap(),
+    );
+    self.deferred.insert(module_request.clone(), deferred);
+    let fut = async move {
+      let resolved =
+        futures::future::try_join_all(unresolved.iter().map(|specifier| {
+          trace::resolve_async(
+            loader.clone(),
+            tracer.clone(),
+            specifier.clone(),
+            referrer.clone(),
+            kind,
+          )
+        }))
+        .await?;
+      async_resolved
+        .borrow_mut()
+        .extend(unresolved.into_iter().zip(resolved));
+      Ok((request, module_source))
+    };
+    self.pending.push(fut.boxed_local());
+  }
+
+  /// Registers a deferred module once its imports are resolved.
+  fn register_deferred(
+    &mut self,
+    module_request: &ModuleRequest,
+    deferred: DeferredModule,
+  ) -> Result<(ModuleId, AssertedModuleType), ModuleError> {
+    let DeferredModule {
+      compiled,
+      resolved,
+      async_resolved,
+      asserted_module_type,
+      compile_start,
+    } = deferred;
+    let name = compiled.name().to_string();
+    // Another load may have registered the module in the meantime.
+    let maybe_module_id = self
+      .module_map_rc
+      .borrow()
+      .get_id(&name, &asserted_module_type);
+    if let Some(module_id) = maybe_module_id {
+      return Ok((module_id, asserted_module_type));
+    }
+    let async_resolved = async_resolved.take();
+    let resolved = compiled
+      .import_specifiers()
+      .zip(resolved)
+      .map(|(specifier, resolved)| {
+        resolved
+          .or_else(|| async_resolved.get(specifier).cloned())
+          .ok_or_else(|| {
+            ModuleError::Other(generic_error(format!(
+              "Import \"{specifier}\" of \"{name}\" was not resolved."
+            )))
+          })
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let mut module_map = self.module_map_rc.borrow_mut();
+    let module_id = module_map.register_compiled_module(compiled, resolved)?;
+    module_map.async_resolutions.extend(
+      async_resolved
+        .into_iter()
+        .map(|(specifier, resolved)| ((name.clone(), specifier), resolved)),
+    );
+    drop(module_map);
+    self.trace_compile(module_request, &name, compile_start);
+    Ok((module_id, asserted_module_type))
+  }
 }
 
 impl Stream for RecursiveModuleLoad {
@@ -577,8 +1269,9 @@ impl Stream for RecursiveModuleLoad {
           // like the bottom of `RecursiveModuleLoad::register_and_recurse()`.
           // But the module map cannot be borrowed here. Instead fake a load
           // event so it gets passed to that function and recursed eventually.
-          let asserted_module_type = inner.root_asserted_module_type.unwrap();
-          let module_type = inner.root_module_type.unwrap();
+          let asserted_module_type =
+            inner.root_asserted_module_type.clone().unwrap();
+          let module_type = inner.root_module_type.clone().unwrap();
           let module_request = ModuleRequest {
             specifier: module_specifier.to_string(),
             asserted_module_type,
@@ -587,7 +1280,7 @@ impl Stream for RecursiveModuleLoad {
           // module map.
           let module_source = ModuleSource::new(
             module_type,
-            Default::default(),
+            ModuleSourceCode::default(),
             &module_specifier,
           );
           futures::future::ok((module_request, module_source)).boxed()
@@ -599,7 +1292,9 @@ impl Stream for RecursiveModuleLoad {
             _ => None,
           };
           let asserted_module_type = match inner.init {
-            LoadInit::DynamicImport(_, _, module_type) => module_type,
+            LoadInit::DynamicImport(_, _, ref module_type) => {
+              module_type.clone()
+            }
             _ => AssertedModuleType::JavaScriptOrWasm,
           };
           let module_request = ModuleRequest {
@@ -608,7 +1303,9 @@ impl Stream for RecursiveModuleLoad {
           };
           let loader = inner.loader.clone();
           let is_dynamic_import = inner.is_dynamic_import();
+          let tracer = inner.tracer.clone();
           async move {
+            let start = Instant::now();
             let result = loader
               .load(
                 &module_specifier,
@@ -616,6 +1313,14 @@ impl Stream for RecursiveModuleLoad {
                 is_dynamic_import,
               )
               .await;
+            if let Some(tracer) = tracer {
+              tracer.trace(
+                ModuleLoadPhase::Load,
+                module_specifier.as_str(),
+                maybe_referrer.as_ref().map(ModuleSpecifier::as_str),
+                start,
+              );
+            }
             result.map(|s| (module_request, s))
           }
           .boxed_local()
@@ -636,18 +1341,34 @@ impl Stream for RecursiveModuleLoad {
   }
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
-#[repr(u32)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub(crate) enum AssertedModuleType {
   JavaScriptOrWasm,
   Json,
+  Other(Cow<'static, str>),
 }
 
-impl From<ModuleType> for AssertedModuleType {
-  fn from(module_type: ModuleType) -> AssertedModuleType {
+impl AssertedModuleType {
+  /// Whether a module loaded with the given type can satisfy this request.
+  /// A custom type can be requested for any source, since it only determines
+  /// how the source is evaluated.
+  fn accepts(&self, module_type: &ModuleType) -> bool {
+    match (self, module_type) {
+      (Self::Other(expected), ModuleType::Other(found)) => expected == found,
+      (Self::Other(_), _) => true,
+      (expected, found) => *expected == AssertedModuleType::from(found),
+    }
+  }
+}
+
+impl From<&ModuleType> for AssertedModuleType {
+  fn from(module_type: &ModuleType) -> AssertedModuleType {
     match module_type {
-      ModuleType::JavaScript => AssertedModuleType::JavaScriptOrWasm,
+      ModuleType::JavaScript | ModuleType::Wasm | ModuleType::CommonJs => {
+        AssertedModuleType::JavaScriptOrWasm
+      }
       ModuleType::Json => AssertedModuleType::Json,
+      ModuleType::Other(ty) => AssertedModuleType::Other(ty.clone()),
     }
   }
 }
@@ -657,14 +1378,30 @@ impl std::fmt::Display for AssertedModuleType {
     match self {
       Self::JavaScriptOrWasm => write!(f, "JavaScriptOrWasm"),
       Self::Json => write!(f, "JSON"),
+      Self::Other(ty) => write!(f, "{ty}"),
     }
   }
 }
 
+/// Describes the redirects a module went through for error messages, or
+/// returns an empty string if it wasn't redirected.
+fn describe_redirect_chain(redirect_chain: &[&str]) -> String {
+  if redirect_chain.is_empty() {
+    return String::new();
+  }
+  let chain = redirect_chain
+    .iter()
+    .map(|url| format!("\"{url}\""))
+    .collect::<Vec<_>>()
+    .join(" -> ");
+  format!(" Redirects: {chain}.")
+}
+
 /// Describes a request for a module as parsed from the source code.
 /// Usually executable (`JavaScriptOrWasm`) is used, except when an
-/// import assertions explicitly constrains an import to JSON, in
-/// which case this will have a `AssertedModuleType::Json`.
+/// import assertions explicitly constrains an import to JSON or to a
+/// custom module type, in which case this will have a
+/// `AssertedModuleType::Json` or `AssertedModuleType::Other`.
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub(crate) struct ModuleRequest {
   pub specifier: String,
@@ -679,7 +1416,11 @@ pub(crate) struct ModuleInfo {
   pub main: bool,
   pub name: ModuleName,
   pub requests: Vec<ModuleRequest>,
+  /// Modules that were imported with `import()` from this module.
+  pub dynamic_requests: Vec<ModuleRequest>,
   pub module_type: ModuleType,
+  /// Size in bytes of the source the module was created from.
+  pub source_size: usize,
 }
 
 #[derive(Debug)]
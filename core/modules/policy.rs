@@ -0,0 +1,369 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use crate::module_specifier::ModuleSpecifier;
+use crate::modules::ModuleLoader;
+use crate::modules::ModuleResolveFuture;
+use crate::modules::ModuleSource;
+use crate::modules::ModuleSourceFuture;
+use crate::modules::ResolutionKind;
+use anyhow::Error;
+use futures::future::FutureExt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+/// What a module is allowed to import, see [`ModuleLoadPolicy`].
+#[derive(Clone, Debug)]
+pub struct ModuleLoadRules {
+  /// The URL schemes modules can be imported from, eg. `"file"` or
+  /// `"https"`. Any scheme is allowed if `None`.
+  pub allowed_schemes: Option<Vec<String>>,
+  /// The hosts modules can be imported from. `"*.example.com"` allows the
+  /// subdomains of `example.com`. Any host is allowed if `None`. URLs without
+  /// a host, eg. `data:` URLs, are only checked against `allowed_schemes`.
+  pub allowed_hosts: Option<Vec<String>>,
+  /// The URL paths modules can be imported from, eg. `"/srv/app/"` for
+  /// `file:///srv/app/main.js`. Any path is allowed if `None`. URLs without a
+  /// hierarchical path, eg. `data:` URLs, are only checked against
+  /// `allowed_schemes`.
+  pub allowed_path_prefixes: Option<Vec<String>>,
+  /// Whether `import()` can be used.
+  pub allow_dynamic_import: bool,
+  /// The maximum size of the source of a module, in bytes.
+  pub max_source_size: Option<usize>,
+}
+
+impl Default for ModuleLoadRules {
+  fn default() -> Self {
+    Self {
+      allowed_schemes: None,
+      allowed_hosts: None,
+      allowed_path_prefixes: None,
+      allow_dynamic_import: true,
+      max_source_size: None,
+    }
+  }
+}
+
+/// Rules replacing [`ModuleLoadPolicy::rules`] for some imports.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleLoadRulesOverride {
+  /// Only apply to imports from modules whose specifier starts with this.
+  pub referrer_prefix: Option<String>,
+  /// Only apply to these kinds of imports.
+  pub kinds: Option<Vec<ResolutionKind>>,
+  pub rules: ModuleLoadRules,
+}
+
+impl ModuleLoadRulesOverride {
+  fn matches(&self, referrer: Option<&str>, kind: ResolutionKind) -> bool {
+    let referrer_matches = match &self.referrer_prefix {
+      Some(prefix) => referrer.map_or(false, |r| r.starts_with(prefix)),
+      None => true,
+    };
+    let kind_matches = match &self.kinds {
+      Some(kinds) => kinds.contains(&kind),
+      None => true,
+    };
+    referrer_matches && kind_matches
+  }
+}
+
+/// Restrictions on the modules a runtime loads, enforced by
+/// [`PolicyModuleLoader`].
+#[derive(Clone, Debug, Default)]
+pub struct ModuleLoadPolicy {
+  /// The rules for imports that no override matches.
+  pub rules: ModuleLoadRules,
+  /// Rules for imports from some referrers, or of some kinds. The first
+  /// matching override applies.
+  pub overrides: Vec<ModuleLoadRulesOverride>,
+  /// The maximum number of imports between the main module, or a module
+  /// loaded without a referrer, and any module it imports.
+  pub max_depth: Option<usize>,
+  /// The maximum number of modules that can be loaded.
+  pub max_modules: Option<usize>,
+}
+
+impl ModuleLoadPolicy {
+  /// The rules for an import of the given kind from `referrer`.
+  pub fn rules_for(
+    &self,
+    referrer: Option<&str>,
+    kind: ResolutionKind,
+  ) -> &ModuleLoadRules {
+    self
+      .overrides
+      .iter()
+      .find(|rules_override| rules_override.matches(referrer, kind))
+      .map(|rules_override| &rules_override.rules)
+      .unwrap_or(&self.rules)
+  }
+
+  fn check_url(
+    &self,
+    url: &ModuleSpecifier,
+    referrer: Option<&str>,
+    kind: ResolutionKind,
+  ) -> Result<(), ModuleLoadPolicyError> {
+    let rules = self.rules_for(referrer, kind);
+    let deny = |reason: String| {
+      Err(ModuleLoadPolicyError {
+        specifier: url.to_string(),
+        referrer: referrer.map(ToString::to_string),
+        reason,
+      })
+    };
+
+    if kind == ResolutionKind::DynamicImport && !rules.allow_dynamic_import {
+      return deny("dynamic imports are not allowed".to_string());
+    }
+    if let Some(schemes) = &rules.allowed_schemes {
+      if !schemes
+        .iter()
+        .any(|scheme| scheme.trim_end_matches(':') == url.scheme())
+      {
+        return deny(format!(
+          "the \"{}:\" scheme is not allowed",
+          url.scheme()
+        ));
+      }
+    }
+    if let (Some(hosts), Some(host)) = (&rules.allowed_hosts, url.host_str()) {
+      let allowed =
+        hosts
+          .iter()
+          .any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host
+              .strip_suffix(domain)
+              .map_or(false, |subdomain| subdomain.ends_with('.')),
+            None => allowed.eq_ignore_ascii_case(host),
+          });
+      if !allowed {
+        return deny(format!("the host \"{host}\" is not allowed"));
+      }
+    }
+    if let Some(prefixes) = &rules.allowed_path_prefixes {
+      if !url.cannot_be_a_base()
+        && !prefixes.iter().any(|prefix| url.path().starts_with(prefix))
+      {
+        return deny(format!("the path \"{}\" is not allowed", url.path()));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Returned, and thrown in JS as a `ModuleLoadPolicyError`, when a module is
+/// denied by a [`ModuleLoadPolicy`]. The JS class extends `TypeError` and is
+/// available as `Deno.core.ModuleLoadPolicyError`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleLoadPolicyError {
+  pub specifier: String,
+  pub referrer: Option<String>,
+  pub reason: String,
+}
+
+impl std::error::Error for ModuleLoadPolicyError {}
+
+impl fmt::Display for ModuleLoadPolicyError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Module \"{}\" is not allowed", self.specifier)?;
+    if let Some(referrer) = &self.referrer {
+      write!(f, " to be imported from \"{referrer}\"")?;
+    }
+    write!(f, ": {}.", self.reason)
+  }
+}
+
+/// Module loader enforcing a [`ModuleLoadPolicy`] on the modules resolved and
+/// loaded by another loader.
+///
+/// Specifiers are checked once resolved, and the URLs a module was redirected
+/// to once it's loaded. The kind of a load is deduced from its arguments: a
+/// load without a referrer is for the main module, and every module loaded
+/// as part of an `import()` is a dynamic import, like for
+/// [`ModuleLoader::resolve`].
+pub struct PolicyModuleLoader {
+  policy: Rc<ModuleLoadPolicy>,
+  inner: Rc<dyn ModuleLoader>,
+  // The depth of each loaded module.
+  depths: RefCell<HashMap<ModuleSpecifier, usize>>,
+}
+
+impl PolicyModuleLoader {
+  pub fn new(policy: ModuleLoadPolicy, inner: Rc<dyn ModuleLoader>) -> Self {
+    Self {
+      policy: Rc::new(policy),
+      inner,
+      depths: Default::default(),
+    }
+  }
+
+  pub fn policy(&self) -> &ModuleLoadPolicy {
+    &self.policy
+  }
+
+  fn check_limits(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+  ) -> Result<(), ModuleLoadPolicyError> {
+    let mut depths = self.depths.borrow_mut();
+    let deny = |reason: String| {
+      Err(ModuleLoadPolicyError {
+        specifier: module_specifier.to_string(),
+        referrer: maybe_referrer.map(ToString::to_string),
+        reason,
+      })
+    };
+    // Referrers loaded by another loader, eg. extension modules, are treated
+    // like main modules.
+    let depth = maybe_referrer
+      .map(|referrer| depths.get(referrer).copied().unwrap_or(0) + 1)
+      .unwrap_or(0);
+    if let Some(max_depth) = self.policy.max_depth {
+      if depth > max_depth {
+        return deny(format!(
+          "the maximum import depth of {max_depth} was reached"
+        ));
+      }
+    }
+    if !depths.contains_key(module_specifier) {
+      if let Some(max_modules) = self.policy.max_modules {
+        if depths.len() >= max_modules {
+          return deny(format!(
+            "the maximum number of {max_modules} modules was reached"
+          ));
+        }
+      }
+    }
+    let entry = depths.entry(module_specifier.clone()).or_insert(depth);
+    *entry = (*entry).min(depth);
+    Ok(())
+  }
+}
+
+fn load_kind(
+  maybe_referrer: Option<&ModuleSpecifier>,
+  is_dyn_import: bool,
+) -> ResolutionKind {
+  if is_dyn_import {
+    ResolutionKind::DynamicImport
+  } else if maybe_referrer.is_none() {
+    ResolutionKind::MainModule
+  } else {
+    ResolutionKind::Import
+  }
+}
+
+fn check_source(
+  policy: &ModuleLoadPolicy,
+  module_source: &ModuleSource,
+  maybe_referrer: Option<&str>,
+  kind: ResolutionKind,
+) -> Result<(), ModuleLoadPolicyError> {
+  for url in module_source.redirect_chain().into_iter().skip(1) {
+    if let Ok(url) = ModuleSpecifier::parse(url) {
+      policy.check_url(&url, maybe_referrer, kind)?;
+    }
+  }
+  let rules = policy.rules_for(maybe_referrer, kind);
+  let size = module_source.code.as_bytes().len();
+  if let Some(max_source_size) = rules.max_source_size {
+    if size > max_source_size {
+      return Err(ModuleLoadPolicyError {
+        specifier: module_source.module_url_specified.as_str().to_string(),
+        referrer: maybe_referrer.map(ToString::to_string),
+        reason: format!(
+          "its source of {size} bytes exceeds the maximum of {max_source_size} bytes"
+        ),
+      });
+    }
+  }
+  Ok(())
+}
+
+impl ModuleLoader for PolicyModuleLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    let resolved = self.inner.resolve(specifier, referrer, kind)?;
+    // Modules loaded without a referrer are resolved against ".".
+    let maybe_referrer = (referrer != ".").then_some(referrer);
+    self.policy.check_url(&resolved, maybe_referrer, kind)?;
+    Ok(resolved)
+  }
+
+  fn resolve_async(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    kind: ResolutionKind,
+  ) -> Pin<Box<ModuleResolveFuture>> {
+    let policy = self.policy.clone();
+    let referrer = referrer.to_string();
+    let fut = self.inner.resolve_async(specifier, &referrer, kind);
+    async move {
+      let resolved = fut.await?;
+      let maybe_referrer = (referrer != ".").then_some(referrer.as_str());
+      policy.check_url(&resolved, maybe_referrer, kind)?;
+      Ok(resolved)
+    }
+    .boxed_local()
+  }
+
+  fn supports_async_resolution(&self) -> bool {
+    self.inner.supports_async_resolution()
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+    is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    if let Err(err) = self.check_limits(module_specifier, maybe_referrer) {
+      return futures::future::err(err.into()).boxed_local();
+    }
+    let policy = self.policy.clone();
+    let referrer = maybe_referrer.map(ToString::to_string);
+    let kind = load_kind(maybe_referrer, is_dyn_import);
+    let fut = self
+      .inner
+      .load(module_specifier, maybe_referrer, is_dyn_import);
+    async move {
+      let module_source = fut.await?;
+      check_source(&policy, &module_source, referrer.as_deref(), kind)?;
+      Ok(module_source)
+    }
+    .boxed_local()
+  }
+
+  fn prepare_load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    maybe_referrer: Option<String>,
+    is_dyn_import: bool,
+  ) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
+    self
+      .inner
+      .prepare_load(module_specifier, maybe_referrer, is_dyn_import)
+  }
+
+  fn code_cache_ready(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    code_cache: &[u8],
+    rejected: bool,
+  ) {
+    self
+      .inner
+      .code_cache_ready(module_specifier, code_cache, rejected)
+  }
+}
@@ -0,0 +1,123 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use crate::error::generic_error;
+use crate::error::type_error;
+use crate::module_specifier::ModuleSpecifier;
+use anyhow::Error;
+use serde::Serialize;
+use std::rc::Rc;
+
+/// Callback that computes the values of the exports of a [`SyntheticModule`]
+/// when the module is evaluated, in the order of its export names.
+pub type SyntheticModuleEvaluationCb =
+  Rc<dyn Fn(&mut v8::HandleScope) -> Result<Vec<v8::Global<v8::Value>>, Error>>;
+
+/// A module whose exports are supplied by the embedder rather than by a
+/// source file, eg. `internal:buildinfo` exporting `version` and `hash`.
+///
+/// Synthetic modules are registered with
+/// [`RuntimeOptions::synthetic_modules`](crate::RuntimeOptions::synthetic_modules)
+/// or [`JsRuntime::register_synthetic_module`](crate::JsRuntime::register_synthetic_module).
+/// They are then imported like any other module: the module loader resolves
+/// specifiers to them, but is never asked to load them.
+#[derive(Clone)]
+pub struct SyntheticModule {
+  pub(crate) specifier: ModuleSpecifier,
+  pub(crate) export_names: Vec<String>,
+  pub(crate) evaluate: SyntheticModuleEvaluationCb,
+}
+
+impl SyntheticModule {
+  /// Creates a synthetic module with the given export names. `evaluate` is
+  /// called once, when the module is evaluated, and returns one value per
+  /// export name.
+  pub fn new(
+    specifier: ModuleSpecifier,
+    export_names: Vec<String>,
+    evaluate: impl Fn(&mut v8::HandleScope) -> Result<Vec<v8::Global<v8::Value>>, Error>
+      + 'static,
+  ) -> Self {
+    Self {
+      specifier,
+      export_names,
+      evaluate: Rc::new(evaluate),
+    }
+  }
+
+  /// Creates a synthetic module exporting the given values. Since the values
+  /// belong to an isolate, the module can only be registered in the runtime
+  /// they were created in.
+  pub fn from_globals(
+    specifier: ModuleSpecifier,
+    exports: Vec<(String, v8::Global<v8::Value>)>,
+  ) -> Self {
+    let (export_names, values): (Vec<_>, Vec<_>) = exports.into_iter().unzip();
+    Self::new(specifier, export_names, move |_| Ok(values.clone()))
+  }
+
+  /// Creates a synthetic module exporting the fields of `value`, which must
+  /// serialize to a struct or a map with string keys. The values are
+  /// converted with `serde_v8` when the module is evaluated.
+  pub fn from_serializable<T: Serialize>(
+    specifier: ModuleSpecifier,
+    value: &T,
+  ) -> Result<Self, Error> {
+    let serde_json::Value::Object(fields) = serde_json::to_value(value)?
+    else {
+      return Err(type_error(format!(
+        "The exports of synthetic module \"{specifier}\" must serialize to a map."
+      )));
+    };
+    let (export_names, values): (Vec<_>, Vec<_>) = fields.into_iter().unzip();
+    Ok(Self::new(specifier, export_names, move |scope| {
+      values
+        .iter()
+        .map(|value| {
+          let value = serde_v8::to_v8(scope, value)?;
+          Ok(v8::Global::new(scope, value))
+        })
+        .collect()
+    }))
+  }
+
+  pub fn specifier(&self) -> &ModuleSpecifier {
+    &self.specifier
+  }
+
+  pub fn export_names(&self) -> &[String] {
+    &self.export_names
+  }
+
+  /// Fails if the module has the same export name twice.
+  pub(crate) fn check_export_names(&self) -> Result<(), Error> {
+    for (i, export_name) in self.export_names.iter().enumerate() {
+      if self.export_names[..i].contains(export_name) {
+        return Err(generic_error(format!(
+          "Synthetic module \"{}\" has a duplicate export \"{export_name}\".",
+          self.specifier
+        )));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Checks [`RuntimeOptions::synthetic_modules`](crate::RuntimeOptions::synthetic_modules)
+/// before the runtime is created: the specifiers of the modules, and the
+/// export names of each module, must be unique.
+pub(crate) fn check_synthetic_modules(
+  modules: &[SyntheticModule],
+) -> Result<(), Error> {
+  for (i, module) in modules.iter().enumerate() {
+    if modules[..i]
+      .iter()
+      .any(|other| other.specifier == module.specifier)
+    {
+      return Err(generic_error(format!(
+        "Synthetic module \"{}\" is given more than once.",
+        module.specifier
+      )));
+    }
+    module.check_export_names()?;
+  }
+  Ok(())
+}
@@ -1,14 +1,19 @@
 // Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
 use crate::ascii_str;
+use crate::error::JsError;
 use crate::resolve_import;
 use crate::runtime::JsRuntime;
 use crate::runtime::JsRuntimeForSnapshot;
+use crate::CompiledWasmModuleStore;
+use crate::ImportMap;
 use crate::RuntimeOptions;
 use crate::Snapshot;
+use crate::SourceMapGetter;
 use deno_ops::op;
 use futures::future::poll_fn;
 use futures::future::FutureExt;
 use parking_lot::Mutex;
+use std::cell::Cell;
 use std::fmt;
 use std::future::Future;
 use std::io;
@@ -254,17 +259,17 @@ fn test_recursive_load() {
   let modules = module_map_rc.borrow();
 
   assert_eq!(
-    modules.get_id("file:///a.js", AssertedModuleType::JavaScriptOrWasm),
+    modules.get_id("file:///a.js", &AssertedModuleType::JavaScriptOrWasm),
     Some(a_id)
   );
   let b_id = modules
-    .get_id("file:///b.js", AssertedModuleType::JavaScriptOrWasm)
+    .get_id("file:///b.js", &AssertedModuleType::JavaScriptOrWasm)
     .unwrap();
   let c_id = modules
-    .get_id("file:///c.js", AssertedModuleType::JavaScriptOrWasm)
+    .get_id("file:///c.js", &AssertedModuleType::JavaScriptOrWasm)
     .unwrap();
   let d_id = modules
-    .get_id("file:///d.js", AssertedModuleType::JavaScriptOrWasm)
+    .get_id("file:///d.js", &AssertedModuleType::JavaScriptOrWasm)
     .unwrap();
   assert_eq!(
     modules.get_requested_modules(a_id),
@@ -382,6 +387,7 @@ fn test_mods() {
       "#
         ),
         false,
+        None,
       )
       .unwrap();
 
@@ -402,6 +408,7 @@ fn test_mods() {
         ascii_str!("file:///b.js"),
         ascii_str!("export function b() { return 'b' }"),
         false,
+        None,
       )
       .unwrap();
     let imports = module_map.get_requested_modules(mod_b).unwrap();
@@ -493,6 +500,7 @@ fn test_json_module() {
         "#
         ),
         false,
+        None,
       )
       .unwrap();
 
@@ -527,565 +535,3077 @@ fn test_json_module() {
   futures::executor::block_on(receiver).unwrap().unwrap();
 }
 
-#[tokio::test]
-async fn dyn_import_err() {
-  #[derive(Clone, Default)]
-  struct DynImportErrLoader {
-    pub count: Arc<AtomicUsize>,
-  }
+#[test]
+fn test_wasm_module() {
+  struct ModsLoader;
 
-  impl ModuleLoader for DynImportErrLoader {
+  impl ModuleLoader for ModsLoader {
     fn resolve(
       &self,
       specifier: &str,
       referrer: &str,
       _kind: ResolutionKind,
     ) -> Result<ModuleSpecifier, Error> {
-      self.count.fetch_add(1, Ordering::Relaxed);
-      assert_eq!(specifier, "/foo.js");
-      assert_eq!(referrer, "file:///dyn_import2.js");
       let s = resolve_import(specifier, referrer).unwrap();
       Ok(s)
     }
 
     fn load(
       &self,
-      _module_specifier: &ModuleSpecifier,
+      module_specifier: &ModuleSpecifier,
       _maybe_referrer: Option<&ModuleSpecifier>,
       _is_dyn_import: bool,
     ) -> Pin<Box<ModuleSourceFuture>> {
-      async { Err(io::Error::from(io::ErrorKind::NotFound).into()) }.boxed()
+      let module_source = match module_specifier.as_str() {
+        "file:///main.js" => ModuleSource::new(
+          ModuleType::JavaScript,
+          ascii_str!(
+            r#"
+            import { exported_add } from "./add.wasm";
+            if (exported_add(2, 3) !== 5) throw Error();
+            "#
+          ),
+          module_specifier,
+        ),
+        "file:///add.wasm" => ModuleSource::new(
+          ModuleType::Wasm,
+          ModuleCodeBytes::Static(ADD_WASM),
+          module_specifier,
+        ),
+        "file:///lib.js" => ModuleSource::new(
+          ModuleType::JavaScript,
+          ascii_str!("export function add(a, b) { return a + b; }"),
+          module_specifier,
+        ),
+        _ => unreachable!(),
+      };
+      async move { Ok(module_source) }.boxed()
     }
   }
 
-  let loader = Rc::new(DynImportErrLoader::default());
-  let count = loader.count.clone();
-  let mut runtime = JsRuntime::new(RuntimeOptions {
-    module_loader: Some(loader),
-    ..Default::default()
-  });
+  let store = CompiledWasmModuleStore::default();
+  let main_specifier = resolve_url("file:///main.js").unwrap();
 
-  // Test an erroneous dynamic import where the specified module isn't found.
-  poll_fn(move |cx| {
-    runtime
-      .execute_script_static(
-        "file:///dyn_import2.js",
-        r#"
-      (async () => {
-        await import("/foo.js");
-      })();
-      "#,
-      )
+  // The second runtime reuses the module compiled by the first one.
+  for _ in 0..2 {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(Rc::new(ModsLoader)),
+      compiled_wasm_module_store: Some(store.clone()),
+      ..Default::default()
+    });
+
+    let main_id_fut = runtime
+      .load_main_module(&main_specifier, None)
+      .boxed_local();
+    let main_id = futures::executor::block_on(main_id_fut).unwrap();
+
+    let module_map = runtime.module_map();
+    let wasm_id = module_map
+      .borrow()
+      .get_id("file:///add.wasm", &AssertedModuleType::JavaScriptOrWasm)
       .unwrap();
+    assert_eq!(
+      module_map
+        .borrow()
+        .get_info_by_id(wasm_id)
+        .unwrap()
+        .module_type,
+      ModuleType::Wasm
+    );
 
-    // We should get an error here.
-    let result = runtime.poll_event_loop(cx, false);
-    if let Poll::Ready(Ok(_)) = result {
-      unreachable!();
-    }
-    assert_eq!(count.load(Ordering::Relaxed), 4);
-    Poll::Ready(())
-  })
-  .await;
-}
+    let receiver = runtime.mod_evaluate(main_id);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    futures::executor::block_on(receiver).unwrap().unwrap();
 
-#[derive(Clone, Default)]
-struct DynImportOkLoader {
-  pub prepare_load_count: Arc<AtomicUsize>,
-  pub resolve_count: Arc<AtomicUsize>,
-  pub load_count: Arc<AtomicUsize>,
+    assert!(store.with_named("file:///add.wasm", |_| ()).is_some());
+  }
+  // Only the first runtime compiled the module.
+  assert_eq!(store.named_inserts(), 1);
 }
 
-impl ModuleLoader for DynImportOkLoader {
-  fn resolve(
-    &self,
-    specifier: &str,
-    referrer: &str,
-    _kind: ResolutionKind,
-  ) -> Result<ModuleSpecifier, Error> {
-    let c = self.resolve_count.fetch_add(1, Ordering::Relaxed);
-    assert!(c < 7);
-    assert_eq!(specifier, "./b.js");
-    assert_eq!(referrer, "file:///dyn_import3.js");
-    let s = resolve_import(specifier, referrer).unwrap();
-    Ok(s)
-  }
+#[test]
+fn test_custom_module_types() {
+  struct ModsLoader;
 
-  fn load(
-    &self,
-    specifier: &ModuleSpecifier,
-    _maybe_referrer: Option<&ModuleSpecifier>,
-    _is_dyn_import: bool,
-  ) -> Pin<Box<ModuleSourceFuture>> {
-    self.load_count.fetch_add(1, Ordering::Relaxed);
-    let info =
-      ModuleSource::for_test("export function b() { return 'b' }", specifier);
-    async move { Ok(info) }.boxed()
-  }
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
 
-  fn prepare_load(
-    &self,
-    _module_specifier: &ModuleSpecifier,
-    _maybe_referrer: Option<String>,
-    _is_dyn_import: bool,
-  ) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
-    self.prepare_load_count.fetch_add(1, Ordering::Relaxed);
-    async { Ok(()) }.boxed_local()
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      let module_source = match module_specifier.as_str() {
+        "file:///main.js" => ModuleSource::new(
+          ModuleType::JavaScript,
+          ascii_str!(
+            r#"
+            import text from "./a.html" assert { type: "text" };
+            import bytes from "./a.html" assert { type: "bytes" };
+            import upper from "./a.html" assert { type: "upper" };
+            import withText from "./a.html" with { type: "text" };
+            if (text !== "<p>hi</p>") throw Error(text);
+            if (withText !== text) throw Error(withText);
+            if (!(bytes instanceof Uint8Array)) throw Error();
+            if (bytes.length !== 9 || bytes[0] !== 60) throw Error();
+            if (upper !== "<P>HI</P>") throw Error(upper);
+            const ns = await import("./a.html", { assert: { type: "text" } });
+            if (ns.default !== text) throw Error();
+            "#
+          ),
+          module_specifier,
+        ),
+        "file:///invalid.js" => ModuleSource::new(
+          ModuleType::JavaScript,
+          ascii_str!(r#"import a from "./a.html" assert { type: "foo" };"#),
+          module_specifier,
+        ),
+        "file:///a.html" => ModuleSource::new(
+          ModuleType::JavaScript,
+          ascii_str!("<p>hi</p>"),
+          module_specifier,
+        ),
+        _ => unreachable!(),
+      };
+      async move { Ok(module_source) }.boxed()
+    }
   }
-}
 
-#[tokio::test]
-async fn dyn_import_ok() {
-  let loader = Rc::new(DynImportOkLoader::default());
-  let prepare_load_count = loader.prepare_load_count.clone();
-  let resolve_count = loader.resolve_count.clone();
-  let load_count = loader.load_count.clone();
+  let mut custom_module_types = HashMap::new();
+  custom_module_types.insert(
+    "upper".to_string(),
+    Box::new(
+      |scope: &mut v8::HandleScope,
+       name: &ModuleName,
+       source: ModuleSourceCode| {
+        let code = source.try_into_string(name.as_str())?;
+        let value =
+          v8::String::new(scope, &code.as_str().to_uppercase()).unwrap();
+        Ok(v8::Global::new(scope, v8::Local::<v8::Value>::from(value)))
+      },
+    ) as CustomModuleEvaluationCb,
+  );
   let mut runtime = JsRuntime::new(RuntimeOptions {
-    module_loader: Some(loader),
+    module_loader: Some(Rc::new(ModsLoader)),
+    custom_module_types,
     ..Default::default()
   });
-  poll_fn(move |cx| {
-    // Dynamically import mod_b
-    runtime
-      .execute_script_static(
-        "file:///dyn_import3.js",
-        r#"
-        (async () => {
-          let mod = await import("./b.js");
-          if (mod.b() !== 'b') {
-            throw Error("bad1");
-          }
-          // And again!
-          mod = await import("./b.js");
-          if (mod.b() !== 'b') {
-            throw Error("bad2");
-          }
-        })();
-        "#,
-      )
-      .unwrap();
 
-    assert!(matches!(
-      runtime.poll_event_loop(cx, false),
-      Poll::Ready(Ok(_))
-    ));
-    assert_eq!(prepare_load_count.load(Ordering::Relaxed), 1);
-    assert_eq!(resolve_count.load(Ordering::Relaxed), 7);
-    assert_eq!(load_count.load(Ordering::Relaxed), 1);
-    assert!(matches!(
-      runtime.poll_event_loop(cx, false),
-      Poll::Ready(Ok(_))
-    ));
-    assert_eq!(resolve_count.load(Ordering::Relaxed), 7);
-    assert_eq!(load_count.load(Ordering::Relaxed), 1);
-    Poll::Ready(())
-  })
-  .await;
-}
+  let main_specifier = resolve_url("file:///main.js").unwrap();
+  let main_id_fut = runtime
+    .load_main_module(&main_specifier, None)
+    .boxed_local();
+  let main_id = futures::executor::block_on(main_id_fut).unwrap();
 
-#[tokio::test]
-async fn dyn_import_borrow_mut_error() {
-  // https://github.com/denoland/deno/issues/6054
-  let loader = Rc::new(DynImportOkLoader::default());
-  let prepare_load_count = loader.prepare_load_count.clone();
-  let mut runtime = JsRuntime::new(RuntimeOptions {
-    module_loader: Some(loader),
-    ..Default::default()
-  });
+  let module_map = runtime.module_map();
+  let text_id = module_map
+    .borrow()
+    .get_id(
+      "file:///a.html",
+      &AssertedModuleType::Other(Cow::Borrowed("text")),
+    )
+    .unwrap();
+  assert_eq!(
+    module_map
+      .borrow()
+      .get_info_by_id(text_id)
+      .unwrap()
+      .module_type,
+    ModuleType::Other(Cow::Borrowed("text"))
+  );
+  assert!(module_map
+    .borrow()
+    .get_id("file:///a.html", &AssertedModuleType::JavaScriptOrWasm)
+    .is_none());
 
-  poll_fn(move |cx| {
-    runtime
-      .execute_script_static(
-        "file:///dyn_import3.js",
-        r#"
-        (async () => {
-          let mod = await import("./b.js");
-          if (mod.b() !== 'b') {
-            throw Error("bad");
-          }
-        })();
-        "#,
-      )
-      .unwrap();
-    // First poll runs `prepare_load` hook.
-    let _ = runtime.poll_event_loop(cx, false);
-    assert_eq!(prepare_load_count.load(Ordering::Relaxed), 1);
-    // Second poll triggers error
-    let _ = runtime.poll_event_loop(cx, false);
-    Poll::Ready(())
-  })
-  .await;
+  let receiver = runtime.mod_evaluate(main_id);
+  futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+  futures::executor::block_on(receiver).unwrap().unwrap();
+
+  let invalid_specifier = resolve_url("file:///invalid.js").unwrap();
+  let err = futures::executor::block_on(
+    runtime.load_side_module(&invalid_specifier, None),
+  )
+  .unwrap_err();
+  assert!(err
+    .to_string()
+    .contains("\"foo\" is not a valid module type."));
 }
 
-// Regression test for https://github.com/denoland/deno/issues/3736.
 #[test]
-fn dyn_concurrent_circular_import() {
-  #[derive(Clone, Default)]
-  struct DynImportCircularLoader {
-    pub resolve_count: Arc<AtomicUsize>,
-    pub load_count: Arc<AtomicUsize>,
-  }
+fn test_import_map_loader() {
+  struct ModsLoader;
 
-  impl ModuleLoader for DynImportCircularLoader {
+  impl ModuleLoader for ModsLoader {
     fn resolve(
       &self,
       specifier: &str,
       referrer: &str,
       _kind: ResolutionKind,
     ) -> Result<ModuleSpecifier, Error> {
-      self.resolve_count.fetch_add(1, Ordering::Relaxed);
+      assert!(specifier.starts_with("file:///"));
       let s = resolve_import(specifier, referrer).unwrap();
       Ok(s)
     }
 
     fn load(
       &self,
-      specifier: &ModuleSpecifier,
+      module_specifier: &ModuleSpecifier,
       _maybe_referrer: Option<&ModuleSpecifier>,
       _is_dyn_import: bool,
     ) -> Pin<Box<ModuleSourceFuture>> {
-      self.load_count.fetch_add(1, Ordering::Relaxed);
-      let filename = PathBuf::from(specifier.to_string())
-        .file_name()
-        .unwrap()
-        .to_string_lossy()
-        .to_string();
-      let code = match filename.as_str() {
-        "a.js" => "import './b.js';",
-        "b.js" => "import './c.js';\nimport './a.js';",
-        "c.js" => "import './d.js';",
-        "d.js" => "// pass",
+      let code = match module_specifier.as_str() {
+        "file:///main.js" => ascii_str!(
+          r#"
+          import { a } from "lib";
+          if (a !== "a") throw Error();
+          const { b } = await import("dyn/b.js");
+          if (b !== "b") throw Error();
+          "#
+        ),
+        "file:///vendor/lib.js" => ascii_str!("export const a = 'a';"),
+        "file:///vendor/dyn/b.js" => ascii_str!("export const b = 'b';"),
         _ => unreachable!(),
       };
-      let info = ModuleSource::for_test(code, specifier);
-      async move { Ok(info) }.boxed()
+      let module_source =
+        ModuleSource::new(ModuleType::JavaScript, code, module_specifier);
+      async move { Ok(module_source) }.boxed()
     }
   }
 
-  let loader = Rc::new(DynImportCircularLoader::default());
+  let import_map = ImportMap::parse(
+    r#"{ "imports": { "lib": "./vendor/lib.js", "dyn/": "./vendor/dyn/" } }"#,
+    &resolve_url("file:///import_map.json").unwrap(),
+  )
+  .unwrap();
+  let loader = ImportMapLoader::new(import_map, Rc::new(ModsLoader));
   let mut runtime = JsRuntime::new(RuntimeOptions {
-    module_loader: Some(loader),
+    module_loader: Some(Rc::new(loader)),
     ..Default::default()
   });
 
-  runtime
-    .execute_script_static(
-      "file:///entry.js",
-      "import('./b.js');\nimport('./a.js');",
-    )
-    .unwrap();
+  let main_specifier = resolve_url("file:///main.js").unwrap();
+  let main_id_fut = runtime
+    .load_main_module(&main_specifier, None)
+    .boxed_local();
+  let main_id = futures::executor::block_on(main_id_fut).unwrap();
 
-  let result = futures::executor::block_on(runtime.run_event_loop(false));
-  assert!(result.is_ok());
+  let receiver = runtime.mod_evaluate(main_id);
+  futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+  futures::executor::block_on(receiver).unwrap().unwrap();
 }
 
 #[test]
-fn test_circular_load() {
+fn test_code_cache() {
+  struct ModsLoader {
+    code: &'static str,
+    code_cache: Rc<RefCell<Option<Vec<u8>>>>,
+    code_cache_ready_calls: Rc<RefCell<Vec<(String, bool)>>>,
+  }
+
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      assert_eq!(module_specifier.as_str(), "file:///main.js");
+      let mut module_source = ModuleSource::new(
+        ModuleType::JavaScript,
+        ModuleCode::from_static(self.code),
+        module_specifier,
+      );
+      module_source.code_cache = Some(match &*self.code_cache.borrow() {
+        Some(data) => ModuleCodeCache::Data(data.clone().into()),
+        None => ModuleCodeCache::Missing,
+      });
+      async move { Ok(module_source) }.boxed()
+    }
+
+    fn code_cache_ready(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      code_cache: &[u8],
+      rejected: bool,
+    ) {
+      self
+        .code_cache_ready_calls
+        .borrow_mut()
+        .push((module_specifier.to_string(), rejected));
+      *self.code_cache.borrow_mut() = Some(code_cache.to_vec());
+    }
+  }
+
+  let code_cache = Rc::new(RefCell::new(None));
+  let code_cache_ready_calls = Rc::new(RefCell::new(vec![]));
+  let main_specifier = resolve_url("file:///main.js").unwrap();
+
+  let run = |code: &'static str| {
+    let loader = ModsLoader {
+      code,
+      code_cache: code_cache.clone(),
+      code_cache_ready_calls: code_cache_ready_calls.clone(),
+    };
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(Rc::new(loader)),
+      ..Default::default()
+    });
+    let main_id_fut = runtime
+      .load_main_module(&main_specifier, None)
+      .boxed_local();
+    let main_id = futures::executor::block_on(main_id_fut).unwrap();
+    let receiver = runtime.mod_evaluate(main_id);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    futures::executor::block_on(receiver).unwrap().unwrap();
+    code_cache_ready_calls
+      .borrow_mut()
+      .drain(..)
+      .collect::<Vec<_>>()
+  };
+
+  // No code cache yet, one is produced.
+  assert_eq!(
+    run("export function f() { return 1; } f();"),
+    vec![("file:///main.js".to_string(), false)]
+  );
+  // The code cache is consumed.
+  assert_eq!(run("export function f() { return 1; } f();"), vec![]);
+  // The code cache doesn't match the source and is replaced.
+  assert_eq!(
+    run("export function f() { return 2; } f();"),
+    vec![("file:///main.js".to_string(), true)]
+  );
+  assert_eq!(run("export function f() { return 2; } f();"), vec![]);
+}
+
+#[tokio::test]
+async fn test_async_fs_module_loader() {
+  let dir = std::env::temp_dir().join(format!(
+    "deno_core_async_fs_module_loader_{}",
+    std::process::id()
+  ));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(dir.join("lib")).unwrap();
+  let dir = std::fs::canonicalize(&dir).unwrap();
+  std::fs::write(dir.join("mod.mjs"), "export const a = 1;").unwrap();
+  std::fs::write(dir.join("data.json"), "{}").unwrap();
+  std::fs::write(dir.join("lib/index.js"), "export const b = 2;").unwrap();
+
+  let url =
+    |path: &str| ModuleSpecifier::from_file_path(dir.join(path)).unwrap();
+  let loader = AsyncFsModuleLoader::default();
+  let load = |specifier: ModuleSpecifier| loader.load(&specifier, None, false);
+
+  let source = load(url("mod")).await.unwrap();
+  assert_eq!(source.module_type, ModuleType::JavaScript);
+  assert_eq!(source.code.as_bytes(), b"export const a = 1;");
+  assert_eq!(
+    source.module_url_found.as_ref().map(|s| s.as_str()),
+    Some(url("mod.mjs").as_str())
+  );
+
+  let source = load(url("data.json")).await.unwrap();
+  assert_eq!(source.module_type, ModuleType::Json);
+  assert!(source.module_url_found.is_none());
+
+  let source = load(url("lib")).await.unwrap();
+  assert_eq!(source.code.as_bytes(), b"export const b = 2;");
+  assert_eq!(
+    source.module_url_found.as_ref().map(|s| s.as_str()),
+    Some(url("lib/index.js").as_str())
+  );
+
+  let err = load(url("missing")).await.unwrap_err();
+  assert_eq!(crate::error::get_custom_error_class(&err), Some("NotFound"));
+
+  #[cfg(unix)]
+  {
+    std::os::unix::fs::symlink(dir.join("mod.mjs"), dir.join("link.js"))
+      .unwrap();
+    let source = load(url("link.js")).await.unwrap();
+    assert_eq!(
+      source.module_url_found.as_ref().map(|s| s.as_str()),
+      Some(url("mod.mjs").as_str())
+    );
+  }
+
+  // A changed file is read again rather than served from the cache.
+  std::fs::write(dir.join("mod.mjs"), "export const a = 10;").unwrap();
+  let source = load(url("mod.mjs")).await.unwrap();
+  assert_eq!(source.code.as_bytes(), b"export const a = 10;");
+
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_data_url_module_loader() {
+  let loader = DataUrlModuleLoader;
+  let load = |url: &str| {
+    let specifier = ModuleSpecifier::parse(url).unwrap();
+    loader.load(&specifier, None, false)
+  };
+
+  // "export default 1;"
+  let source = load("data:text/javascript;base64,ZXhwb3J0IGRlZmF1bHQgMTs=")
+    .await
+    .unwrap();
+  assert_eq!(source.module_type, ModuleType::JavaScript);
+  assert_eq!(source.code.as_bytes(), b"export default 1;");
+
+  let source = load("data:application/json;charset=utf-8,%7B%22a%22%3A1%7D")
+    .await
+    .unwrap();
+  assert_eq!(source.module_type, ModuleType::Json);
+  assert_eq!(source.code.as_bytes(), br#"{"a":1}"#);
+
+  // Whitespace and missing padding are allowed in base64.
+  let source = load("data:application/wasm;BASE64,AGFz bQEAAAA")
+    .await
+    .unwrap();
+  assert_eq!(source.module_type, ModuleType::Wasm);
+  assert_eq!(source.code.as_bytes(), b"\0asm\x01\0\0\0");
+
+  for (url, reason) in [
+    ("data:text/javascript", "missing comma"),
+    ("data:,export%20default%201;", "missing media type"),
+    (
+      "data:text/plain,hello",
+      "unsupported media type \"text/plain\"",
+    ),
+    ("data:text/javascript;base64,a", "invalid base64"),
+  ] {
+    let err = load(url).await.unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      format!("Invalid data URL \"{url}\": {reason}.")
+    );
+  }
+}
+
+#[tokio::test]
+async fn test_routing_module_loader() {
+  let url = |url: &str| ModuleSpecifier::parse(url).unwrap();
+  let static_loader = StaticModuleLoader::new([
+    (
+      url("memory:///main.js"),
+      r#"
+      import { a } from "./a.js";
+      import { b } from "data:text/javascript,export%20const%20b%20=%202;";
+      import data from "memory:///data.json" assert { type: "json" };
+      globalThis.result = [a, b, data.c];
+      "#,
+    ),
+    (url("memory:///a.js"), "export const a = 1;"),
+    (url("memory:///data.json"), r#"{ "c": 3 }"#),
+  ]);
+  let loader = RoutingModuleLoader::new()
+    .with_scheme("memory", Rc::new(static_loader))
+    .with_scheme("data:", Rc::new(DataUrlModuleLoader));
+
+  let err = loader
+    .resolve(
+      "https://example.com/mod.js",
+      "memory:///main.js",
+      ResolutionKind::Import,
+    )
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "No module loader for the \"https:\" scheme, attempted to resolve \"https://example.com/mod.js\" from \"memory:///main.js\"."
+  );
+  let err = loader
+    .load(&url("memory:///missing.js"), None, false)
+    .await
+    .unwrap_err();
+  assert_eq!(crate::error::get_custom_error_class(&err), Some("NotFound"));
+
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(loader)),
+    ..Default::default()
+  });
+  let main_id = runtime
+    .load_main_module(&url("memory:///main.js"), None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+  let result = runtime
+    .execute_script_static("check.js", "globalThis.result.join()")
+    .unwrap();
+  let scope = &mut runtime.handle_scope();
+  let result = v8::Local::new(scope, result);
+  assert_eq!(result.to_rust_string_lossy(scope), "1,2,3");
+}
+
+#[tokio::test]
+async fn test_policy_module_loader() {
+  let url = |url: &str| ModuleSpecifier::parse(url).unwrap();
+  let static_loader = StaticModuleLoader::new([
+    (
+      url("memory:///app/main.js"),
+      r#"
+      import "./untrusted/mod.js";
+      const results = [];
+      for (
+        const specifier of [
+          "./allowed.js",
+          "../secret.js",
+          "data:text/javascript,",
+          "./big.js",
+        ]
+      ) {
+        try {
+          await import(specifier);
+          results.push("ok");
+        } catch (e) {
+          if (!(e instanceof Deno.core.ModuleLoadPolicyError)) throw e;
+          if (!(e instanceof TypeError)) throw e;
+          results.push(e.name);
+        }
+      }
+      results.push(await globalThis.untrustedResult);
+      globalThis.results = results;
+      "#
+      .to_string(),
+    ),
+    (
+      url("memory:///app/untrusted/mod.js"),
+      r#"
+      globalThis.untrustedResult = import("../allowed.js")
+        .then(() => "ok", (e) => e.name);
+      "#
+      .to_string(),
+    ),
+    (url("memory:///app/allowed.js"), "export {};".to_string()),
+    (url("memory:///secret.js"), "export {};".to_string()),
+    (
+      url("memory:///app/big.js"),
+      format!("// {}", "x".repeat(1000)),
+    ),
+  ]);
+  let loader = PolicyModuleLoader::new(
+    ModuleLoadPolicy {
+      rules: ModuleLoadRules {
+        allowed_schemes: Some(vec!["memory".to_string()]),
+        allowed_path_prefixes: Some(vec!["/app/".to_string()]),
+        max_source_size: Some(1000),
+        ..Default::default()
+      },
+      overrides: vec![ModuleLoadRulesOverride {
+        referrer_prefix: Some("memory:///app/untrusted/".to_string()),
+        kinds: Some(vec![ResolutionKind::DynamicImport]),
+        rules: ModuleLoadRules {
+          allow_dynamic_import: false,
+          ..Default::default()
+        },
+      }],
+      ..Default::default()
+    },
+    Rc::new(static_loader),
+  );
+
+  let err = loader
+    .resolve(
+      "../secret.js",
+      "memory:///app/main.js",
+      ResolutionKind::Import,
+    )
+    .unwrap_err();
+  assert_eq!(
+    err.downcast_ref::<ModuleLoadPolicyError>(),
+    Some(&ModuleLoadPolicyError {
+      specifier: "memory:///secret.js".to_string(),
+      referrer: Some("memory:///app/main.js".to_string()),
+      reason: "the path \"/secret.js\" is not allowed".to_string(),
+    })
+  );
+
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(loader)),
+    ..Default::default()
+  });
+  let main_id = runtime
+    .load_main_module(&url("memory:///app/main.js"), None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+  let results = runtime
+    .execute_script_static("check.js", "globalThis.results.join()")
+    .unwrap();
+  let scope = &mut runtime.handle_scope();
+  let results = v8::Local::new(scope, results);
+  assert_eq!(
+    results.to_rust_string_lossy(scope),
+    "ok,ModuleLoadPolicyError,ModuleLoadPolicyError,ModuleLoadPolicyError,ModuleLoadPolicyError"
+  );
+}
+
+#[tokio::test]
+async fn test_policy_module_loader_limits() {
+  let url = |url: &str| ModuleSpecifier::parse(url).unwrap();
+  let static_loader = StaticModuleLoader::new(
+    ["a", "b", "c", "d"]
+      .map(|name| (url(&format!("memory:///{name}.js")), "export {};")),
+  );
+  let loader = PolicyModuleLoader::new(
+    ModuleLoadPolicy {
+      max_depth: Some(1),
+      max_modules: Some(3),
+      ..Default::default()
+    },
+    Rc::new(static_loader),
+  );
+  let load = |specifier: &str, referrer: Option<&str>| {
+    let referrer = referrer.map(url);
+    loader.load(&url(specifier), referrer.as_ref(), false)
+  };
+
+  load("memory:///a.js", None).await.unwrap();
+  load("memory:///b.js", Some("memory:///a.js"))
+    .await
+    .unwrap();
+  let err = load("memory:///c.js", Some("memory:///b.js"))
+    .await
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Module \"memory:///c.js\" is not allowed to be imported from \"memory:///b.js\": the maximum import depth of 1 was reached."
+  );
+  load("memory:///c.js", Some("memory:///a.js"))
+    .await
+    .unwrap();
+  let err = load("memory:///d.js", Some("memory:///a.js"))
+    .await
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Module \"memory:///d.js\" is not allowed to be imported from \"memory:///a.js\": the maximum number of 3 modules was reached."
+  );
+  // Modules already loaded don't count again.
+  load("memory:///b.js", Some("memory:///a.js"))
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_integrity_module_loader() {
+  let url = |url: &str| ModuleSpecifier::parse(url).unwrap();
+  let main = url("memory:///main.js");
+  let dep = url("memory:///dep.js");
+  let static_loader: Rc<dyn ModuleLoader> = Rc::new(StaticModuleLoader::new([
+    (main.clone(), "import \"./dep.js\";"),
+    (dep.clone(), "export {};"),
+  ]));
+  let load_main = |loader: Rc<IntegrityModuleLoader>| {
+    let main = main.clone();
+    async move {
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        module_loader: Some(loader),
+        ..Default::default()
+      });
+      runtime.load_main_module(&main, None).await
+    }
+  };
+
+  // Record the hashes of the module graph.
+  let loader = Rc::new(IntegrityModuleLoader::new(
+    ModuleLockfile::new(),
+    IntegrityMode::Update,
+    static_loader.clone(),
+  ));
+  load_main(loader.clone()).await.unwrap();
+  let lockfile = loader.lockfile();
+  assert_eq!(lockfile.len(), 2);
+  assert_eq!(
+    lockfile.get(dep.as_str()),
+    Some("2e29cd9a98755c46896f7a2d56524db2d6d96b248e36db46de14c30bf47c8d05")
+  );
+  let lockfile = ModuleLockfile::parse(&lockfile.to_json_string()).unwrap();
+
+  // Verify the graph against the recorded hashes.
+  let loader = Rc::new(IntegrityModuleLoader::new(
+    lockfile.clone(),
+    IntegrityMode::Frozen,
+    static_loader.clone(),
+  ));
+  load_main(loader).await.unwrap();
+
+  // A changed module fails the load of the graph.
+  let mut tampered = lockfile.clone();
+  tampered.insert(dep.to_string(), "00".repeat(32));
+  let loader = Rc::new(IntegrityModuleLoader::new(
+    tampered,
+    IntegrityMode::Verify,
+    static_loader.clone(),
+  ));
+  let err = load_main(loader).await.unwrap_err();
+  assert_eq!(
+    err.downcast_ref::<ModuleIntegrityError>(),
+    Some(&ModuleIntegrityError::LockfileMismatch {
+      specifier: dep.to_string(),
+      expected: "00".repeat(32),
+      actual: lockfile.get(dep.as_str()).unwrap().to_string(),
+    })
+  );
+
+  // Modules missing from the lockfile are only loaded if it's not frozen.
+  let mut partial = ModuleLockfile::new();
+  partial.insert(
+    main.to_string(),
+    lockfile.get(main.as_str()).unwrap().to_string(),
+  );
+  let loader = Rc::new(IntegrityModuleLoader::new(
+    partial.clone(),
+    IntegrityMode::Verify,
+    static_loader.clone(),
+  ));
+  load_main(loader).await.unwrap();
+  let loader = Rc::new(IntegrityModuleLoader::new(
+    partial,
+    IntegrityMode::Frozen,
+    static_loader.clone(),
+  ));
+  let err = load_main(loader).await.unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Module \"memory:///dep.js\" is not in the lockfile, which is frozen."
+  );
+
+  // The integrity metadata of the import map is checked too.
+  let import_map = ImportMap::parse(
+    r#"{ "integrity": { "/dep.js": "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=" } }"#,
+    &url("memory:///import_map.json"),
+  )
+  .unwrap();
+  let loader = Rc::new(
+    IntegrityModuleLoader::new(lockfile, IntegrityMode::Update, static_loader)
+      .with_import_map(import_map),
+  );
+  let err = load_main(loader).await.unwrap_err();
+  assert!(matches!(
+    err.downcast_ref::<ModuleIntegrityError>(),
+    Some(ModuleIntegrityError::ImportMapMismatch { specifier, .. })
+      if specifier == dep.as_str()
+  ));
+}
+
+#[tokio::test]
+async fn test_module_archive() {
+  let url = |url: &str| ModuleSpecifier::parse(url).unwrap();
+  let main = url("memory:///main.js");
+  let static_loader = StaticModuleLoader::new([
+    (
+      main.clone(),
+      r#"
+      import { value } from "lib";
+      import data from "./data.json" with { type: "json" };
+      globalThis.result = value + data.n;
+      "#,
+    ),
+    (url("memory:///dep.js"), "export const value = 1;"),
+    (url("memory:///data.json"), r#"{ "n": 2 }"#),
+  ]);
+  let import_map = ImportMap::parse(
+    r#"{ "imports": { "lib": "/dep.js" } }"#,
+    &url("memory:///import_map.json"),
+  )
+  .unwrap();
+  let loader = ImportMapLoader::new(import_map, Rc::new(static_loader));
+
+  async fn run_main(runtime: &mut JsRuntime, main: &ModuleSpecifier) -> i64 {
+    let main_id = runtime.load_main_module(main, None).await.unwrap();
+    let receiver = runtime.mod_evaluate(main_id);
+    runtime.run_event_loop(false).await.unwrap();
+    receiver.await.unwrap().unwrap();
+    let result = runtime
+      .execute_script_static("result.js", "globalThis.result")
+      .unwrap();
+    let scope = &mut runtime.handle_scope();
+    let result = v8::Local::new(scope, result);
+    result.integer_value(scope).unwrap()
+  }
+
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(loader)),
+    ..Default::default()
+  });
+  assert_eq!(run_main(&mut runtime, &main).await, 3);
+  let writer = ModuleArchiveWriter::from_runtime(&mut runtime)
+    .await
+    .unwrap();
+
+  let path = std::env::temp_dir().join(format!(
+    "deno_core_module_archive_{}.archive",
+    std::process::id()
+  ));
+  writer.write(std::fs::File::create(&path).unwrap()).unwrap();
+  let archive_loader = Rc::new(ArchiveModuleLoader::open(&path).unwrap());
+  assert_eq!(archive_loader.main_module(), Some(main.clone()));
+  assert_eq!(
+    archive_loader.specifiers().collect::<Vec<_>>(),
+    vec![
+      "memory:///data.json",
+      "memory:///dep.js",
+      "memory:///main.js"
+    ]
+  );
+  // The bare specifier is resolved as it was by the import map.
+  assert_eq!(
+    archive_loader
+      .resolve("lib", main.as_str(), ResolutionKind::Import)
+      .unwrap(),
+    url("memory:///dep.js")
+  );
+  let dep = archive_loader
+    .load(&url("memory:///dep.js"), None, false)
+    .await
+    .unwrap();
+  assert_eq!(dep.code.as_bytes(), b"export const value = 1;");
+  assert!(matches!(dep.code_cache, Some(ModuleCodeCache::Data(_))));
+  // The code cache of a module with `with` clauses is for its source as it's
+  // compiled.
+  let main_source = archive_loader.load(&main, None, false).await.unwrap();
+  let Some(ModuleCodeCache::Data(main_code_cache)) = &main_source.code_cache
+  else {
+    panic!("The main module has no code cache");
+  };
+  let compiled_source = super::import_attributes::rewrite_with_clauses(
+    std::str::from_utf8(main_source.code.as_bytes()).unwrap(),
+  )
+  .unwrap();
+  assert!(super::code_cache::decode(
+    main_code_cache.as_bytes(),
+    super::code_cache::source_hash(compiled_source.as_bytes())
+  )
+  .is_some());
+
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(archive_loader),
+    ..Default::default()
+  });
+  assert_eq!(run_main(&mut runtime, &main).await, 3);
+  // The file is mapped until the loader is dropped, and mapped files can't be
+  // removed on Windows.
+  drop(runtime);
+  std::fs::remove_file(&path).unwrap();
+
+  // Redirects are replayed.
+  let mut writer = ModuleArchiveWriter::new();
+  writer.add_module(ModuleArchiveEntry {
+    specifier: url("memory:///b.js"),
+    module_type: ModuleType::JavaScript,
+    code: b"export {};".to_vec(),
+    source_map: Some(b"{}".to_vec()),
+    code_cache: None,
+  });
+  writer.add_redirect(&url("memory:///a.js"), &[url("memory:///b.js")]);
+  let archive_loader =
+    ArchiveModuleLoader::from_bytes(writer.to_bytes()).unwrap();
+  let source = archive_loader
+    .load(&url("memory:///a.js"), None, false)
+    .await
+    .unwrap();
+  assert_eq!(
+    source.redirect_chain(),
+    vec!["memory:///a.js", "memory:///b.js"]
+  );
+  assert_eq!(
+    archive_loader.get_source_map("memory:///a.js"),
+    Some(b"{}".to_vec())
+  );
+  let err = archive_loader
+    .load(&url("memory:///c.js"), None, false)
+    .await
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Cannot find module \"memory:///c.js\" in the archive."
+  );
+
+  assert!(ArchiveModuleLoader::from_bytes(b"not an archive".to_vec()).is_err());
+}
+
+#[tokio::test]
+async fn test_module_graph() {
+  struct ModsLoader;
+
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      let found_specifier = match module_specifier.as_str() {
+        "file:///a" => resolve_url("file:///a.js").unwrap(),
+        _ => module_specifier.clone(),
+      };
+      let (module_type, code) = match found_specifier.as_str() {
+        "file:///main.js" => (
+          ModuleType::JavaScript,
+          ascii_str!(
+            r#"
+            import { a } from "./a";
+            import data from "./data.json" assert { type: "json" };
+            await import("./b.js");
+            await import("./throws.js").catch(() => {});
+            "#
+          ),
+        ),
+        "file:///a.js" => {
+          (ModuleType::JavaScript, ascii_str!("export const a = 1;"))
+        }
+        "file:///b.js" => (ModuleType::JavaScript, ascii_str!("")),
+        "file:///throws.js" => (
+          ModuleType::JavaScript,
+          ascii_str!("throw new Error('boom');"),
+        ),
+        "file:///data.json" => (ModuleType::Json, ascii_str!("{}")),
+        _ => unreachable!(),
+      };
+      let module_source = ModuleSource::new_with_redirect(
+        module_type,
+        code,
+        module_specifier,
+        &found_specifier,
+      );
+      async move { Ok(module_source) }.boxed()
+    }
+  }
+
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(ModsLoader)),
+    ..Default::default()
+  });
+  let main_specifier = resolve_url("file:///main.js").unwrap();
+  let main_id = runtime
+    .load_main_module(&main_specifier, None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+
+  let graph = runtime.module_graph();
+  assert_eq!(graph.modules.len(), 5);
+
+  let main = graph.get(main_id).unwrap();
+  assert!(main.main);
+  assert_eq!(main.status, ModuleStatus::Evaluated);
+  let dependencies = main
+    .dependencies
+    .iter()
+    .map(|d| (d.specifier.as_str(), d.kind, d.attribute_type.as_deref()))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    dependencies,
+    vec![
+      ("file:///a", ImportKind::Static, None),
+      ("file:///data.json", ImportKind::Static, Some("json")),
+      ("file:///b.js", ImportKind::Dynamic, None),
+      ("file:///throws.js", ImportKind::Dynamic, None),
+    ]
+  );
+
+  let a = graph.get_by_specifier("file:///a").unwrap();
+  assert_eq!(a.specifier, "file:///a.js");
+  assert_eq!(a.aliases.len(), 1);
+  assert_eq!(a.aliases[0].specifier, "file:///a");
+  assert_eq!(main.dependencies[0].id, Some(a.id));
+  assert_eq!(a.source_size, "export const a = 1;".len());
+
+  let data = graph.get_by_specifier("file:///data.json").unwrap();
+  assert_eq!(data.module_type, ModuleType::Json);
+
+  let throws = graph.get_by_specifier("file:///throws.js").unwrap();
+  assert_eq!(throws.status, ModuleStatus::Errored);
+  assert_eq!(throws.error.as_deref(), Some("Uncaught Error: boom"));
+
+  let json = serde_json::to_value(&graph).unwrap();
+  assert_eq!(
+    json["modules"][a.id],
+    serde_json::json!({
+      "id": a.id,
+      "specifier": "file:///a.js",
+      "moduleType": "javascript",
+      "main": false,
+      "aliases": [{
+        "specifier": "file:///a",
+        "redirectChain": ["file:///a.js"],
+      }],
+      "dependencies": [],
+      "status": "evaluated",
+      "error": null,
+      "sourceSize": 19,
+    })
+  );
+}
+
+#[tokio::test]
+async fn test_hot_reload_module() {
+  struct ModsLoader {
+    dep_code: Rc<RefCell<String>>,
+    dep_target: Rc<RefCell<&'static str>>,
+  }
+
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      let found_specifier = match module_specifier.as_str() {
+        "file:///dep" => resolve_url(*self.dep_target.borrow()).unwrap(),
+        _ => module_specifier.clone(),
+      };
+      let code: ModuleCode = match found_specifier.as_str() {
+        "file:///main.js" => ascii_str!(
+          r#"
+          import { value } from "./dep";
+          import "./other.js";
+          export { value };
+          globalThis.mainRuns = (globalThis.mainRuns ?? 0) + 1;
+          globalThis.mainValue = value;
+          globalThis.isMain = import.meta.main;
+          globalThis.disposeCount = import.meta.hot.data.count;
+          import.meta.hot.dispose((data) => {
+            data.count = (import.meta.hot.data.count ?? 0) + 1;
+          });
+          import.meta.hot.accept((ns) => {
+            globalThis.acceptedValue = ns.value;
+          });
+          "#
+        ),
+        "file:///other.js" => {
+          ascii_str!("globalThis.otherRuns = (globalThis.otherRuns ?? 0) + 1;")
+        }
+        "file:///dep.js" | "file:///dep2.js" => {
+          self.dep_code.borrow().clone().into()
+        }
+        _ => unreachable!(),
+      };
+      let module_source = ModuleSource::new_with_redirect(
+        ModuleType::JavaScript,
+        code,
+        module_specifier,
+        &found_specifier,
+      );
+      async move { Ok(module_source) }.boxed()
+    }
+  }
+
+  let dep_code = Rc::new(RefCell::new("export const value = 1;".to_string()));
+  let dep_target = Rc::new(RefCell::new("file:///dep.js"));
+  let loader = ModsLoader {
+    dep_code: dep_code.clone(),
+    dep_target: dep_target.clone(),
+  };
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(loader)),
+    hot_module_replacement: true,
+    ..Default::default()
+  });
+  let check = |runtime: &mut JsRuntime, expression: &'static str| {
+    let value = runtime
+      .execute_script_static("check.js", expression)
+      .unwrap();
+    let scope = &mut runtime.handle_scope();
+    let value = v8::Local::new(scope, value);
+    assert!(value.is_true(), "{expression}");
+  };
+
+  let main_specifier = resolve_url("file:///main.js").unwrap();
+  let main_id = runtime
+    .load_main_module(&main_specifier, None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+  check(
+    &mut runtime,
+    "mainRuns === 1 && mainValue === 1 && otherRuns === 1",
+  );
+  check(
+    &mut runtime,
+    "isMain === true && disposeCount === undefined",
+  );
+
+  let module_map_rc = runtime.module_map();
+  let js = AssertedModuleType::JavaScriptOrWasm;
+  let dep_id = module_map_rc.borrow().get_id("file:///dep", &js).unwrap();
+  let other_id = module_map_rc.borrow().get_id("file:///other.js", &js);
+
+  // The changed module and its importer are replaced, but not `other.js`.
+  *dep_code.borrow_mut() = "export const value = 2;".to_string();
+  let dep_specifier = resolve_url("file:///dep.js").unwrap();
+  let ids = runtime.hot_reload_module(&dep_specifier).await.unwrap();
+  assert_eq!(ids.len(), 2);
+  let new_main_id = ids[&main_id];
+  let new_dep_id = ids[&dep_id];
+  {
+    let module_map = module_map_rc.borrow();
+    assert_eq!(module_map.get_id("file:///main.js", &js), Some(new_main_id));
+    assert!(module_map.is_alias("file:///dep", &js));
+    assert_eq!(module_map.get_id("file:///dep", &js), Some(new_dep_id));
+    assert_eq!(module_map.get_id("file:///dep.js", &js), Some(new_dep_id));
+    assert_eq!(module_map.get_id("file:///other.js", &js), other_id);
+    assert!(module_map.get_info_by_id(new_main_id).unwrap().main);
+    // The replaced versions are dropped.
+    assert!(module_map.get_info_by_id(main_id).is_none());
+    assert!(module_map.get_handle(dep_id).is_none());
+  }
+  check(
+    &mut runtime,
+    "mainRuns === 2 && mainValue === 2 && otherRuns === 1",
+  );
+  check(&mut runtime, "isMain === true && disposeCount === 1");
+  check(&mut runtime, "acceptedValue === 2");
+
+  // If the new version can't be loaded, the current one is kept.
+  *dep_code.borrow_mut() = "export const value = ;".to_string();
+  assert!(runtime.hot_reload_module(&dep_specifier).await.is_err());
+  {
+    let module_map = module_map_rc.borrow();
+    assert_eq!(module_map.get_id("file:///main.js", &js), Some(new_main_id));
+    assert_eq!(module_map.get_id("file:///dep", &js), Some(new_dep_id));
+    assert!(module_map.get_info_by_id(new_main_id).unwrap().main);
+  }
+  check(&mut runtime, "mainRuns === 2 && mainValue === 2");
+
+  // If the new version fails to evaluate, the current one is put back.
+  *dep_code.borrow_mut() =
+    "export const value = 3; throw new Error('boom');".to_string();
+  assert!(runtime.hot_reload_module(&dep_specifier).await.is_err());
+  {
+    let module_map = module_map_rc.borrow();
+    assert_eq!(module_map.get_id("file:///main.js", &js), Some(new_main_id));
+    assert!(module_map.is_alias("file:///dep", &js));
+    assert_eq!(module_map.get_id("file:///dep", &js), Some(new_dep_id));
+    assert!(module_map.get_info_by_id(new_main_id).unwrap().main);
+    assert_eq!(module_map.registered_module_ids().len(), 3);
+  }
+  check(&mut runtime, "mainRuns === 2 && mainValue === 2");
+
+  // Aliases that the new versions don't register again point to them, even
+  // if they were found under another name.
+  module_map_rc.borrow_mut().alias(
+    ModuleName::from_static("file:///dep-alias"),
+    js.clone(),
+    ModuleName::from_static("file:///dep.js"),
+  );
+  *dep_target.borrow_mut() = "file:///dep2.js";
+  *dep_code.borrow_mut() = "export const value = 4;".to_string();
+  let ids = runtime.hot_reload_module(&dep_specifier).await.unwrap();
+  let dep2_id = ids[&new_dep_id];
+  {
+    let module_map = module_map_rc.borrow();
+    assert_eq!(module_map.get_id("file:///dep.js", &js), None);
+    assert_eq!(module_map.get_id("file:///dep2.js", &js), Some(dep2_id));
+    assert_eq!(module_map.get_id("file:///dep", &js), Some(dep2_id));
+    assert_eq!(module_map.get_id("file:///dep-alias", &js), Some(dep2_id));
+    assert!(module_map.get_info_by_id(new_dep_id).is_none());
+  }
+  check(&mut runtime, "mainRuns === 3 && mainValue === 4");
+
+  let err = runtime
+    .hot_reload_module(&resolve_url("file:///missing.js").unwrap())
+    .await
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Cannot reload module \"file:///missing.js\", because it isn't loaded."
+  );
+}
+
+#[tokio::test]
+async fn test_unload_module() {
+  struct PluginLoader {
+    loads: Rc<RefCell<Vec<String>>>,
+  }
+
+  impl ModuleLoader for PluginLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      Ok(resolve_import(specifier, referrer)?)
+    }
+
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      let found_specifier = match module_specifier.as_str() {
+        "file:///plugin" => resolve_url("file:///plugin.js").unwrap(),
+        _ => module_specifier.clone(),
+      };
+      let mut loads = self.loads.borrow_mut();
+      loads.push(found_specifier.to_string());
+      let version = loads
+        .iter()
+        .filter(|load| *load == found_specifier.as_str())
+        .count();
+      let code: ModuleCode = match found_specifier.as_str() {
+        "file:///main.js" => ascii_str!("import './shared.js';"),
+        "file:///plugin.js" => format!(
+          "import './helper.js'; import './shared.js'; export const version = {version};"
+        )
+        .into(),
+        "file:///helper.js" | "file:///shared.js" => ascii_str!("export {};"),
+        _ => unreachable!(),
+      };
+      let module_source = ModuleSource::new_with_redirect(
+        ModuleType::JavaScript,
+        code,
+        module_specifier,
+        &found_specifier,
+      );
+      async move { Ok(module_source) }.boxed()
+    }
+  }
+
+  let loads = Rc::new(RefCell::new(vec![]));
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(PluginLoader {
+      loads: loads.clone(),
+    })),
+    ..Default::default()
+  });
+  let main_specifier = resolve_url("file:///main.js").unwrap();
+  let main_id = runtime
+    .load_main_module(&main_specifier, None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+
+  async fn import_plugin(runtime: &mut JsRuntime) -> i64 {
+    runtime
+      .execute_script_static(
+        "import.js",
+        "import('file:///plugin').then((ns) => globalThis.version = ns.version);",
+      )
+      .unwrap();
+    runtime.run_event_loop(false).await.unwrap();
+    let version = runtime
+      .execute_script_static("version.js", "globalThis.version")
+      .unwrap();
+    let scope = &mut runtime.handle_scope();
+    let version = v8::Local::new(scope, version);
+    version.integer_value(scope).unwrap()
+  }
+
+  assert_eq!(import_plugin(&mut runtime).await, 1);
+  let module_map_rc = runtime.module_map();
+  let js = AssertedModuleType::JavaScriptOrWasm;
+  let plugin_id = module_map_rc.borrow().get_id("file:///plugin", &js);
+  let helper_id = module_map_rc.borrow().get_id("file:///helper.js", &js);
+  let shared_id = module_map_rc.borrow().get_id("file:///shared.js", &js);
+
+  // Statically imported modules that stay loaded can't be unloaded.
+  let err = runtime
+    .unload_module(&resolve_url("file:///shared.js").unwrap())
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Cannot unload module \"file:///shared.js\", because \"file:///main.js\" imports it."
+  );
+  let err = runtime.unload_module(&main_specifier).unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Cannot unload module \"file:///main.js\", because it's the main module."
+  );
+
+  // `shared.js` is still imported by the main module.
+  let plugin_specifier = resolve_url("file:///plugin.js").unwrap();
+  let ids = runtime.unload_module(&plugin_specifier).unwrap();
+  assert_eq!(ids, vec![plugin_id.unwrap(), helper_id.unwrap()]);
+  {
+    let module_map = module_map_rc.borrow();
+    assert_eq!(module_map.get_id("file:///plugin", &js), None);
+    assert_eq!(module_map.get_id("file:///plugin.js", &js), None);
+    assert_eq!(module_map.get_id("file:///helper.js", &js), None);
+    assert_eq!(module_map.get_id("file:///shared.js", &js), shared_id);
+    assert!(module_map.get_info_by_id(plugin_id.unwrap()).is_none());
+    assert!(module_map.get_handle(helper_id.unwrap()).is_none());
+  }
+
+  // Importing the plugin again fetches fresh code.
+  assert_eq!(import_plugin(&mut runtime).await, 2);
+  assert_eq!(
+    *loads.borrow(),
+    vec![
+      "file:///main.js",
+      "file:///shared.js",
+      "file:///plugin.js",
+      "file:///helper.js",
+      "file:///plugin.js",
+      "file:///helper.js",
+    ]
+  );
+
+  let err = runtime
+    .unload_module(&resolve_url("file:///missing.js").unwrap())
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Cannot unload module \"file:///missing.js\", because it isn't loaded."
+  );
+}
+
+#[tokio::test]
+async fn test_unload_module_in_flight() {
+  let url = |url: &str| ModuleSpecifier::parse(url).unwrap();
+  let loader = StaticModuleLoader::new([
+    (url("file:///main.js"), "export {};"),
+    (
+      url("file:///importer.js"),
+      "globalThis.importLate = () => import('./late.js');",
+    ),
+    (url("file:///late.js"), "export {};"),
+    (
+      url("file:///waiting.js"),
+      "await new Promise((resolve) => globalThis.release = resolve);",
+    ),
+  ]);
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(loader)),
+    ..Default::default()
+  });
+  let main_id = runtime
+    .load_main_module(&url("file:///main.js"), None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+  let importer_id = runtime
+    .load_side_module(&url("file:///importer.js"), None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(importer_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+
+  // A module with a pending `import()` can't be unloaded.
+  runtime
+    .execute_script_static("import.js", "importLate();")
+    .unwrap();
+  let err = runtime
+    .unload_module(&url("file:///importer.js"))
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Cannot unload module \"file:///importer.js\", because \"file:///importer.js\" is being loaded or evaluated."
+  );
+  runtime.run_event_loop(false).await.unwrap();
+  let late_id = runtime
+    .module_map()
+    .borrow()
+    .get_id("file:///late.js", &AssertedModuleType::JavaScriptOrWasm);
+  assert_eq!(
+    runtime.unload_module(&url("file:///importer.js")).unwrap(),
+    vec![importer_id, late_id.unwrap()]
+  );
+
+  // Neither can a module whose evaluation is pending.
+  runtime
+    .execute_script_static("import.js", "import('file:///waiting.js');")
+    .unwrap();
+  poll_fn(|cx| {
+    for _ in 0..3 {
+      let _ = runtime.poll_event_loop(cx, false);
+    }
+    Poll::Ready(())
+  })
+  .await;
+  let err = runtime
+    .unload_module(&url("file:///waiting.js"))
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Cannot unload module \"file:///waiting.js\", because \"file:///waiting.js\" is being loaded or evaluated."
+  );
+  runtime
+    .execute_script_static("release.js", "release();")
+    .unwrap();
+  runtime.run_event_loop(false).await.unwrap();
+  runtime.unload_module(&url("file:///waiting.js")).unwrap();
+}
+
+#[tokio::test]
+async fn test_async_resolve() {
+  // Resolves bare specifiers asynchronously, to the `index.js` of a package.
+  struct PackageLoader {
+    inner: StaticModuleLoader,
+    async_resolutions: Cell<usize>,
+  }
+
+  impl ModuleLoader for PackageLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      if !specifier.starts_with('.') && !specifier.contains(':') {
+        return Err(generic_error(
+          "Bare specifiers are resolved asynchronously",
+        ));
+      }
+      self.inner.resolve(specifier, referrer, kind)
+    }
+
+    fn resolve_async(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      kind: ResolutionKind,
+    ) -> Pin<Box<ModuleResolveFuture>> {
+      self.async_resolutions.set(self.async_resolutions.get() + 1);
+      let result = match self.resolve(specifier, referrer, kind) {
+        Ok(resolved) => Ok(resolved),
+        Err(_) if specifier == "missing" => {
+          Err(generic_error("Package \"missing\" not found"))
+        }
+        Err(_) => {
+          resolve_import(&format!("./{specifier}/index.js"), "memory:///pkgs/")
+            .map_err(Error::from)
+        }
+      };
+      async move {
+        tokio::task::yield_now().await;
+        result
+      }
+      .boxed_local()
+    }
+
+    fn supports_async_resolution(&self) -> bool {
+      true
+    }
+
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      maybe_referrer: Option<&ModuleSpecifier>,
+      is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      self
+        .inner
+        .load(module_specifier, maybe_referrer, is_dyn_import)
+    }
+  }
+
+  let url = |url: &str| ModuleSpecifier::parse(url).unwrap();
+  let loader = Rc::new(PackageLoader {
+    inner: StaticModuleLoader::new([
+      (
+        url("memory:///main.js"),
+        r#"
+        import { a } from "a";
+        import "./b.js";
+        const { c } = await import("c");
+        let error;
+        try {
+          await import("missing");
+        } catch (e) {
+          error = e.message;
+        }
+        globalThis.result = `${a},${c},${error},${await globalThis.denied}`;
+        "#,
+      ),
+      (
+        url("memory:///b.js"),
+        r#"
+        import "a";
+        globalThis.denied = import("a").then(
+          () => "allowed",
+          (e) => e.message,
+        );
+        "#,
+      ),
+      (
+        url("memory:///pkgs/a/index.js"),
+        "export { a } from \"./a.js\";",
+      ),
+      (url("memory:///pkgs/a/a.js"), "export const a = \"a\";"),
+      (url("memory:///pkgs/c/index.js"), "export const c = \"c\";"),
+    ]),
+    async_resolutions: Cell::new(0),
+  });
+  // Dynamic imports from "b.js" are denied, even of a module it imports
+  // statically.
+  let policy = ModuleLoadPolicy {
+    overrides: vec![ModuleLoadRulesOverride {
+      referrer_prefix: Some("memory:///b.js".to_string()),
+      kinds: Some(vec![ResolutionKind::DynamicImport]),
+      rules: ModuleLoadRules {
+        allow_dynamic_import: false,
+        ..Default::default()
+      },
+    }],
+    ..Default::default()
+  };
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(PolicyModuleLoader::new(
+      policy,
+      loader.clone(),
+    ))),
+    ..Default::default()
+  });
+  let main_id = runtime
+    .load_main_module(&url("memory:///main.js"), None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+
+  let result = runtime
+    .execute_script_static("check.js", "globalThis.result")
+    .unwrap();
+  {
+    let scope = &mut runtime.handle_scope();
+    let result = v8::Local::new(scope, result);
+    assert_eq!(
+      result.to_rust_string_lossy(scope),
+      "a,c,Package \"missing\" not found,Module \"memory:///pkgs/a/index.js\" \
+      is not allowed to be imported from \"memory:///b.js\": dynamic imports \
+      are not allowed."
+    );
+  }
+  // "a" is resolved once for each import, and the resolutions are reused
+  // when the module graph is instantiated.
+  assert_eq!(loader.async_resolutions.get(), 5);
+
+  let module_map = runtime.module_map();
+  let module_map = module_map.borrow();
+  let main_info = module_map.get_info_by_id(main_id).unwrap();
+  assert_eq!(main_info.requests[0].specifier, "memory:///pkgs/a/index.js");
+}
+
+#[tokio::test]
+async fn dyn_import_err() {
+  #[derive(Clone, Default)]
+  struct DynImportErrLoader {
+    pub count: Arc<AtomicUsize>,
+  }
+
+  impl ModuleLoader for DynImportErrLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      self.count.fetch_add(1, Ordering::Relaxed);
+      assert_eq!(specifier, "/foo.js");
+      assert_eq!(referrer, "file:///dyn_import2.js");
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      _module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      async { Err(io::Error::from(io::ErrorKind::NotFound).into()) }.boxed()
+    }
+  }
+
+  let loader = Rc::new(DynImportErrLoader::default());
+  let count = loader.count.clone();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(loader),
+    ..Default::default()
+  });
+
+  // Test an erroneous dynamic import where the specified module isn't found.
+  poll_fn(move |cx| {
+    runtime
+      .execute_script_static(
+        "file:///dyn_import2.js",
+        r#"
+      (async () => {
+        await import("/foo.js");
+      })();
+      "#,
+      )
+      .unwrap();
+
+    // We should get an error here.
+    let result = runtime.poll_event_loop(cx, false);
+    if let Poll::Ready(Ok(_)) = result {
+      unreachable!();
+    }
+    assert_eq!(count.load(Ordering::Relaxed), 4);
+    Poll::Ready(())
+  })
+  .await;
+}
+
+#[derive(Clone, Default)]
+struct DynImportOkLoader {
+  pub prepare_load_count: Arc<AtomicUsize>,
+  pub resolve_count: Arc<AtomicUsize>,
+  pub load_count: Arc<AtomicUsize>,
+}
+
+impl ModuleLoader for DynImportOkLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    _kind: ResolutionKind,
+  ) -> Result<ModuleSpecifier, Error> {
+    let c = self.resolve_count.fetch_add(1, Ordering::Relaxed);
+    assert!(c < 7);
+    assert_eq!(specifier, "./b.js");
+    assert_eq!(referrer, "file:///dyn_import3.js");
+    let s = resolve_import(specifier, referrer).unwrap();
+    Ok(s)
+  }
+
+  fn load(
+    &self,
+    specifier: &ModuleSpecifier,
+    _maybe_referrer: Option<&ModuleSpecifier>,
+    _is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    self.load_count.fetch_add(1, Ordering::Relaxed);
+    let info =
+      ModuleSource::for_test("export function b() { return 'b' }", specifier);
+    async move { Ok(info) }.boxed()
+  }
+
+  fn prepare_load(
+    &self,
+    _module_specifier: &ModuleSpecifier,
+    _maybe_referrer: Option<String>,
+    _is_dyn_import: bool,
+  ) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
+    self.prepare_load_count.fetch_add(1, Ordering::Relaxed);
+    async { Ok(()) }.boxed_local()
+  }
+}
+
+#[tokio::test]
+async fn dyn_import_ok() {
+  let loader = Rc::new(DynImportOkLoader::default());
+  let prepare_load_count = loader.prepare_load_count.clone();
+  let resolve_count = loader.resolve_count.clone();
+  let load_count = loader.load_count.clone();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(loader),
+    ..Default::default()
+  });
+  poll_fn(move |cx| {
+    // Dynamically import mod_b
+    runtime
+      .execute_script_static(
+        "file:///dyn_import3.js",
+        r#"
+        (async () => {
+          let mod = await import("./b.js");
+          if (mod.b() !== 'b') {
+            throw Error("bad1");
+          }
+          // And again!
+          mod = await import("./b.js");
+          if (mod.b() !== 'b') {
+            throw Error("bad2");
+          }
+        })();
+        "#,
+      )
+      .unwrap();
+
+    assert!(matches!(
+      runtime.poll_event_loop(cx, false),
+      Poll::Ready(Ok(_))
+    ));
+    assert_eq!(prepare_load_count.load(Ordering::Relaxed), 1);
+    assert_eq!(resolve_count.load(Ordering::Relaxed), 7);
+    assert_eq!(load_count.load(Ordering::Relaxed), 1);
+    assert!(matches!(
+      runtime.poll_event_loop(cx, false),
+      Poll::Ready(Ok(_))
+    ));
+    assert_eq!(resolve_count.load(Ordering::Relaxed), 7);
+    assert_eq!(load_count.load(Ordering::Relaxed), 1);
+    Poll::Ready(())
+  })
+  .await;
+}
+
+#[tokio::test]
+async fn dyn_import_borrow_mut_error() {
+  // https://github.com/denoland/deno/issues/6054
+  let loader = Rc::new(DynImportOkLoader::default());
+  let prepare_load_count = loader.prepare_load_count.clone();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(loader),
+    ..Default::default()
+  });
+
+  poll_fn(move |cx| {
+    runtime
+      .execute_script_static(
+        "file:///dyn_import3.js",
+        r#"
+        (async () => {
+          let mod = await import("./b.js");
+          if (mod.b() !== 'b') {
+            throw Error("bad");
+          }
+        })();
+        "#,
+      )
+      .unwrap();
+    // First poll runs `prepare_load` hook.
+    let _ = runtime.poll_event_loop(cx, false);
+    assert_eq!(prepare_load_count.load(Ordering::Relaxed), 1);
+    // Second poll triggers error
+    let _ = runtime.poll_event_loop(cx, false);
+    Poll::Ready(())
+  })
+  .await;
+}
+
+// Regression test for https://github.com/denoland/deno/issues/3736.
+#[test]
+fn dyn_concurrent_circular_import() {
+  #[derive(Clone, Default)]
+  struct DynImportCircularLoader {
+    pub resolve_count: Arc<AtomicUsize>,
+    pub load_count: Arc<AtomicUsize>,
+  }
+
+  impl ModuleLoader for DynImportCircularLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      self.resolve_count.fetch_add(1, Ordering::Relaxed);
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      self.load_count.fetch_add(1, Ordering::Relaxed);
+      let filename = PathBuf::from(specifier.to_string())
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+      let code = match filename.as_str() {
+        "a.js" => "import './b.js';",
+        "b.js" => "import './c.js';\nimport './a.js';",
+        "c.js" => "import './d.js';",
+        "d.js" => "// pass",
+        _ => unreachable!(),
+      };
+      let info = ModuleSource::for_test(code, specifier);
+      async move { Ok(info) }.boxed()
+    }
+  }
+
+  let loader = Rc::new(DynImportCircularLoader::default());
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(loader),
+    ..Default::default()
+  });
+
+  runtime
+    .execute_script_static(
+      "file:///entry.js",
+      "import('./b.js');\nimport('./a.js');",
+    )
+    .unwrap();
+
+  let result = futures::executor::block_on(runtime.run_event_loop(false));
+  assert!(result.is_ok());
+}
+
+#[test]
+fn test_circular_load() {
+  let loader = MockLoader::new();
+  let loads = loader.loads.clone();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(loader),
+    ..Default::default()
+  });
+
+  let fut = async move {
+    let spec = resolve_url("file:///circular1.js").unwrap();
+    let result = runtime.load_main_module(&spec, None).await;
+    assert!(result.is_ok());
+    let circular1_id = result.unwrap();
+    #[allow(clippy::let_underscore_future)]
+    let _ = runtime.mod_evaluate(circular1_id);
+    runtime.run_event_loop(false).await.unwrap();
+
+    let l = loads.lock();
+    assert_eq!(
+      l.to_vec(),
+      vec![
+        "file:///circular1.js",
+        "file:///circular2.js",
+        "file:///circular3.js"
+      ]
+    );
+
+    let module_map_rc = runtime.module_map();
+    let modules = module_map_rc.borrow();
+
+    assert_eq!(
+      modules.get_id(
+        "file:///circular1.js",
+        &AssertedModuleType::JavaScriptOrWasm
+      ),
+      Some(circular1_id)
+    );
+    let circular2_id = modules
+      .get_id(
+        "file:///circular2.js",
+        &AssertedModuleType::JavaScriptOrWasm,
+      )
+      .unwrap();
+
+    assert_eq!(
+      modules.get_requested_modules(circular1_id),
+      Some(&vec![ModuleRequest {
+        specifier: "file:///circular2.js".to_string(),
+        asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
+      }])
+    );
+
+    assert_eq!(
+      modules.get_requested_modules(circular2_id),
+      Some(&vec![ModuleRequest {
+        specifier: "file:///circular3.js".to_string(),
+        asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
+      }])
+    );
+
+    assert!(modules
+      .get_id(
+        "file:///circular3.js",
+        &AssertedModuleType::JavaScriptOrWasm
+      )
+      .is_some());
+    let circular3_id = modules
+      .get_id(
+        "file:///circular3.js",
+        &AssertedModuleType::JavaScriptOrWasm,
+      )
+      .unwrap();
+    assert_eq!(
+      modules.get_requested_modules(circular3_id),
+      Some(&vec![
+        ModuleRequest {
+          specifier: "file:///circular1.js".to_string(),
+          asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
+        },
+        ModuleRequest {
+          specifier: "file:///circular2.js".to_string(),
+          asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
+        }
+      ])
+    );
+  }
+  .boxed_local();
+
+  futures::executor::block_on(fut);
+}
+
+#[test]
+fn test_redirect_load() {
+  let loader = MockLoader::new();
+  let loads = loader.loads.clone();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(loader),
+    ..Default::default()
+  });
+
+  let fut = async move {
+    let spec = resolve_url("file:///redirect1.js").unwrap();
+    let result = runtime.load_main_module(&spec, None).await;
+    assert!(result.is_ok());
+    let redirect1_id = result.unwrap();
+    #[allow(clippy::let_underscore_future)]
+    let _ = runtime.mod_evaluate(redirect1_id);
+    runtime.run_event_loop(false).await.unwrap();
+    let l = loads.lock();
+    assert_eq!(
+      l.to_vec(),
+      vec![
+        "file:///redirect1.js",
+        "file:///redirect2.js",
+        "file:///dir/redirect3.js"
+      ]
+    );
+
+    let module_map_rc = runtime.module_map();
+    let modules = module_map_rc.borrow();
+
+    assert_eq!(
+      modules.get_id(
+        "file:///redirect1.js",
+        &AssertedModuleType::JavaScriptOrWasm
+      ),
+      Some(redirect1_id)
+    );
+
+    let redirect2_id = modules
+      .get_id(
+        "file:///dir/redirect2.js",
+        &AssertedModuleType::JavaScriptOrWasm,
+      )
+      .unwrap();
+    assert!(modules.is_alias(
+      "file:///redirect2.js",
+      &AssertedModuleType::JavaScriptOrWasm
+    ));
+    assert!(!modules.is_alias(
+      "file:///dir/redirect2.js",
+      &AssertedModuleType::JavaScriptOrWasm
+    ));
+    assert_eq!(
+      modules.get_id(
+        "file:///redirect2.js",
+        &AssertedModuleType::JavaScriptOrWasm
+      ),
+      Some(redirect2_id)
+    );
+
+    let redirect3_id = modules
+      .get_id(
+        "file:///redirect3.js",
+        &AssertedModuleType::JavaScriptOrWasm,
+      )
+      .unwrap();
+    assert!(modules.is_alias(
+      "file:///dir/redirect3.js",
+      &AssertedModuleType::JavaScriptOrWasm
+    ));
+    assert!(!modules.is_alias(
+      "file:///redirect3.js",
+      &AssertedModuleType::JavaScriptOrWasm
+    ));
+    assert_eq!(
+      modules.get_id(
+        "file:///dir/redirect3.js",
+        &AssertedModuleType::JavaScriptOrWasm
+      ),
+      Some(redirect3_id)
+    );
+  }
+  .boxed_local();
+
+  futures::executor::block_on(fut);
+}
+
+#[tokio::test]
+async fn test_redirect_chain_load() {
+  struct ModsLoader {
+    loads: Rc<RefCell<Vec<String>>>,
+  }
+
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      self.loads.borrow_mut().push(module_specifier.to_string());
+      if module_specifier.as_str() == "file:///missing.js" {
+        return async { Err(generic_error("Module not found.")) }.boxed();
+      }
+      let chain = |urls: &[&str]| {
+        urls
+          .iter()
+          .map(|url| resolve_url(url).unwrap())
+          .collect::<Vec<_>>()
+      };
+      let (redirect_chain, code) = match module_specifier.as_str() {
+        "file:///main.js" => (
+          vec![],
+          ascii_str!(
+            r#"
+            import { c } from "./a.js";
+            const { c: c2 } = await import("./b.js");
+            if (c !== c2) throw Error();
+            "#
+          ),
+        ),
+        "file:///a.js" => (
+          chain(&["file:///b.js", "file:///c.js"]),
+          ascii_str!("export const c = {};"),
+        ),
+        "file:///bad.js" => (
+          vec![],
+          ascii_str!(r#"import "./x.js" assert { type: "json" };"#),
+        ),
+        "file:///x.js" => (
+          chain(&["file:///y.js", "file:///z.js"]),
+          ascii_str!("export {};"),
+        ),
+        "file:///syntax.js" => (
+          chain(&["file:///syntax2.js"]),
+          ascii_str!("export const = 1;"),
+        ),
+        "file:///importer.js" => (
+          chain(&["file:///importer2.js"]),
+          ascii_str!(r#"import "./missing.js";"#),
+        ),
+        _ => unreachable!(),
+      };
+      let module_source = ModuleSource::new_with_redirect_chain(
+        ModuleType::JavaScript,
+        code,
+        module_specifier,
+        &redirect_chain,
+      );
+      async move { Ok(module_source) }.boxed()
+    }
+  }
+
+  let loads = Rc::new(RefCell::new(vec![]));
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(ModsLoader {
+      loads: loads.clone(),
+    })),
+    ..Default::default()
+  });
+  let main_specifier = resolve_url("file:///main.js").unwrap();
+  let main_id = runtime
+    .load_main_module(&main_specifier, None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+
+  // The intermediate redirect isn't requested again by the dynamic import.
+  assert_eq!(*loads.borrow(), vec!["file:///main.js", "file:///a.js"]);
+
+  {
+    let module_map_rc = runtime.module_map();
+    let module_map = module_map_rc.borrow();
+    let js = AssertedModuleType::JavaScriptOrWasm;
+    let c_id = module_map.get_id("file:///c.js", &js).unwrap();
+    assert!(module_map.is_alias("file:///a.js", &js));
+    assert!(module_map.is_alias("file:///b.js", &js));
+    assert_eq!(module_map.get_id("file:///a.js", &js), Some(c_id));
+    assert_eq!(module_map.get_id("file:///b.js", &js), Some(c_id));
+  }
+
+  let graph = runtime.module_graph();
+  let c = graph.get_by_specifier("file:///c.js").unwrap();
+  assert_eq!(
+    c.aliases,
+    vec![
+      ModuleGraphAlias {
+        specifier: "file:///a.js".to_string(),
+        redirect_chain: vec![
+          "file:///b.js".to_string(),
+          "file:///c.js".to_string()
+        ],
+      },
+      ModuleGraphAlias {
+        specifier: "file:///b.js".to_string(),
+        redirect_chain: vec!["file:///c.js".to_string()],
+      },
+    ]
+  );
+
+  let bad_specifier = resolve_url("file:///bad.js").unwrap();
+  let err = runtime
+    .load_side_module(&bad_specifier, None)
+    .await
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    r#"Expected a "JSON" module but loaded a "JavaScript" module. Redirects: "file:///x.js" -> "file:///y.js" -> "file:///z.js"."#
+  );
+
+  // Compile errors of a redirected module mention its redirects.
+  let syntax_specifier = resolve_url("file:///syntax.js").unwrap();
+  let err = runtime
+    .load_side_module(&syntax_specifier, None)
+    .await
+    .unwrap_err();
+  assert!(err.downcast_ref::<JsError>().is_some());
+  assert!(
+    err
+      .to_string()
+      .contains(r#"Redirects: "file:///syntax.js" -> "file:///syntax2.js"."#),
+    "{err}"
+  );
+
+  // Load failures of the imports of a redirected module mention the
+  // redirects of the importer.
+  let importer_specifier = resolve_url("file:///importer.js").unwrap();
+  let err = runtime
+    .load_side_module(&importer_specifier, None)
+    .await
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    r#"Module not found. Imported by "file:///importer.js". Redirects: "file:///importer.js" -> "file:///importer2.js"."#
+  );
+}
+
+#[tokio::test]
+async fn slow_never_ready_modules() {
+  let loader = MockLoader::new();
+  let loads = loader.loads.clone();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(loader),
+    ..Default::default()
+  });
+
+  poll_fn(move |cx| {
+    let spec = resolve_url("file:///main.js").unwrap();
+    let mut recursive_load =
+      runtime.load_main_module(&spec, None).boxed_local();
+
+    let result = recursive_load.poll_unpin(cx);
+    assert!(result.is_pending());
+
+    // TODO(ry) Arguably the first time we poll only the following modules
+    // should be loaded:
+    //      "file:///main.js",
+    //      "file:///never_ready.js",
+    //      "file:///slow.js"
+    // But due to current task notification in DelayedSourceCodeFuture they
+    // all get loaded in a single poll.
+
+    for _ in 0..10 {
+      let result = recursive_load.poll_unpin(cx);
+      assert!(result.is_pending());
+      let l = loads.lock();
+      assert_eq!(
+        l.to_vec(),
+        vec![
+          "file:///main.js",
+          "file:///never_ready.js",
+          "file:///slow.js",
+          "file:///a.js",
+          "file:///b.js",
+          "file:///c.js",
+          "file:///d.js"
+        ]
+      );
+    }
+    Poll::Ready(())
+  })
+  .await;
+}
+
+#[tokio::test]
+async fn loader_disappears_after_error() {
+  let loader = MockLoader::new();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(loader),
+    ..Default::default()
+  });
+
+  let spec = resolve_url("file:///bad_import.js").unwrap();
+  let result = runtime.load_main_module(&spec, None).await;
+  let err = result.unwrap_err();
+  assert_eq!(
+    err.downcast_ref::<MockError>().unwrap(),
+    &MockError::ResolveErr
+  );
+}
+
+#[test]
+fn recursive_load_main_with_code() {
+  const MAIN_WITH_CODE_SRC: FastString = ascii_str!(
+    r#"
+import { b } from "/b.js";
+import { c } from "/c.js";
+if (b() != 'b') throw Error();
+if (c() != 'c') throw Error();
+if (!import.meta.main) throw Error();
+if (import.meta.url != 'file:///main_with_code.js') throw Error();
+"#
+  );
+
   let loader = MockLoader::new();
   let loads = loader.loads.clone();
   let mut runtime = JsRuntime::new(RuntimeOptions {
     module_loader: Some(loader),
     ..Default::default()
   });
+  // In default resolution code should be empty.
+  // Instead we explicitly pass in our own code.
+  // The behavior should be very similar to /a.js.
+  let spec = resolve_url("file:///main_with_code.js").unwrap();
+  let main_id_fut = runtime
+    .load_main_module(&spec, Some(MAIN_WITH_CODE_SRC))
+    .boxed_local();
+  let main_id = futures::executor::block_on(main_id_fut).unwrap();
 
-  let fut = async move {
-    let spec = resolve_url("file:///circular1.js").unwrap();
-    let result = runtime.load_main_module(&spec, None).await;
-    assert!(result.is_ok());
-    let circular1_id = result.unwrap();
-    #[allow(clippy::let_underscore_future)]
-    let _ = runtime.mod_evaluate(circular1_id);
-    runtime.run_event_loop(false).await.unwrap();
+  #[allow(clippy::let_underscore_future)]
+  let _ = runtime.mod_evaluate(main_id);
+  futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
 
-    let l = loads.lock();
-    assert_eq!(
-      l.to_vec(),
-      vec![
-        "file:///circular1.js",
-        "file:///circular2.js",
-        "file:///circular3.js"
-      ]
-    );
+  let l = loads.lock();
+  assert_eq!(
+    l.to_vec(),
+    vec!["file:///b.js", "file:///c.js", "file:///d.js"]
+  );
 
-    let module_map_rc = runtime.module_map();
-    let modules = module_map_rc.borrow();
+  let module_map_rc = runtime.module_map();
+  let modules = module_map_rc.borrow();
 
-    assert_eq!(
-      modules
-        .get_id("file:///circular1.js", AssertedModuleType::JavaScriptOrWasm),
-      Some(circular1_id)
-    );
-    let circular2_id = modules
-      .get_id("file:///circular2.js", AssertedModuleType::JavaScriptOrWasm)
-      .unwrap();
+  assert_eq!(
+    modules.get_id(
+      "file:///main_with_code.js",
+      &AssertedModuleType::JavaScriptOrWasm
+    ),
+    Some(main_id)
+  );
+  let b_id = modules
+    .get_id("file:///b.js", &AssertedModuleType::JavaScriptOrWasm)
+    .unwrap();
+  let c_id = modules
+    .get_id("file:///c.js", &AssertedModuleType::JavaScriptOrWasm)
+    .unwrap();
+  let d_id = modules
+    .get_id("file:///d.js", &AssertedModuleType::JavaScriptOrWasm)
+    .unwrap();
 
-    assert_eq!(
-      modules.get_requested_modules(circular1_id),
-      Some(&vec![ModuleRequest {
-        specifier: "file:///circular2.js".to_string(),
+  assert_eq!(
+    modules.get_requested_modules(main_id),
+    Some(&vec![
+      ModuleRequest {
+        specifier: "file:///b.js".to_string(),
         asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
-      }])
-    );
-
-    assert_eq!(
-      modules.get_requested_modules(circular2_id),
-      Some(&vec![ModuleRequest {
-        specifier: "file:///circular3.js".to_string(),
+      },
+      ModuleRequest {
+        specifier: "file:///c.js".to_string(),
         asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
-      }])
-    );
+      }
+    ])
+  );
+  assert_eq!(
+    modules.get_requested_modules(b_id),
+    Some(&vec![ModuleRequest {
+      specifier: "file:///c.js".to_string(),
+      asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
+    }])
+  );
+  assert_eq!(
+    modules.get_requested_modules(c_id),
+    Some(&vec![ModuleRequest {
+      specifier: "file:///d.js".to_string(),
+      asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
+    }])
+  );
+  assert_eq!(modules.get_requested_modules(d_id), Some(&vec![]));
+}
 
-    assert!(modules
-      .get_id("file:///circular3.js", AssertedModuleType::JavaScriptOrWasm)
-      .is_some());
-    let circular3_id = modules
-      .get_id("file:///circular3.js", AssertedModuleType::JavaScriptOrWasm)
-      .unwrap();
-    assert_eq!(
-      modules.get_requested_modules(circular3_id),
-      Some(&vec![
-        ModuleRequest {
-          specifier: "file:///circular1.js".to_string(),
-          asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
-        },
-        ModuleRequest {
-          specifier: "file:///circular2.js".to_string(),
-          asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
-        }
-      ])
-    );
+#[tokio::test]
+async fn test_synthetic_module() {
+  struct ModsLoader;
+
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      _module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      unreachable!()
+    }
   }
-  .boxed_local();
 
-  futures::executor::block_on(fut);
+  #[derive(Serialize)]
+  struct BuildInfo {
+    version: &'static str,
+    hash: &'static str,
+  }
+
+  let buildinfo = SyntheticModule::from_serializable(
+    resolve_url("internal:buildinfo").unwrap(),
+    &BuildInfo {
+      version: "1.2.3",
+      hash: "abcdef",
+    },
+  )
+  .unwrap();
+  assert_eq!(buildinfo.export_names(), ["version", "hash"]);
+  let evaluations = Rc::new(Cell::new(0));
+  let evaluations_ = evaluations.clone();
+  let throws = SyntheticModule::new(
+    resolve_url("internal:throws").unwrap(),
+    vec!["value".to_string()],
+    move |_| {
+      evaluations_.set(evaluations_.get() + 1);
+      Err(generic_error("not available"))
+    },
+  );
+
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(ModsLoader)),
+    synthetic_modules: vec![buildinfo, throws],
+    ..Default::default()
+  });
+
+  let globals = {
+    let scope = &mut runtime.handle_scope();
+    let object: v8::Local<v8::Value> = v8::Object::new(scope).into();
+    let number: v8::Local<v8::Value> = v8::Number::new(scope, 42.0).into();
+    vec![
+      ("default".to_string(), v8::Global::new(scope, object)),
+      ("answer".to_string(), v8::Global::new(scope, number)),
+    ]
+  };
+  let globals_specifier = resolve_url("internal:globals").unwrap();
+  runtime
+    .register_synthetic_module(SyntheticModule::from_globals(
+      globals_specifier.clone(),
+      globals,
+    ))
+    .unwrap();
+  let err = runtime
+    .register_synthetic_module(SyntheticModule::from_globals(
+      globals_specifier,
+      vec![],
+    ))
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    r#"Module "internal:globals" is already registered."#
+  );
+
+  let main_specifier = resolve_url("file:///main.js").unwrap();
+  let main_id = runtime
+    .load_main_module(
+      &main_specifier,
+      Some(ascii_str!(
+        r#"
+        import { version, hash } from "internal:buildinfo";
+        import globals, { answer } from "internal:globals";
+        if (version !== "1.2.3" || hash !== "abcdef") throw new Error("a");
+        if (typeof globals !== "object" || answer !== 42) throw new Error("b");
+        const err = await import("internal:throws").catch((err) => err);
+        if (!(err instanceof TypeError) || err.message !== "not available") {
+          throw new Error("c");
+        }
+        "#
+      )),
+    )
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+  assert_eq!(evaluations.get(), 1);
+
+  let graph = runtime.module_graph();
+  let buildinfo = graph.get_by_specifier("internal:buildinfo").unwrap();
+  assert_eq!(buildinfo.module_type, ModuleType::JavaScript);
+  assert_eq!(buildinfo.status, ModuleStatus::Evaluated);
+  assert_eq!(buildinfo.source_size, 0);
 }
 
 #[test]
-fn test_redirect_load() {
-  let loader = MockLoader::new();
-  let loads = loader.loads.clone();
-  let mut runtime = JsRuntime::new(RuntimeOptions {
-    module_loader: Some(loader),
+#[should_panic(
+  expected = "Invalid synthetic modules in the runtime options: Synthetic module \"internal:buildinfo\" is given more than once."
+)]
+fn test_synthetic_module_duplicate_specifier() {
+  let buildinfo = || {
+    SyntheticModule::from_serializable(
+      resolve_url("internal:buildinfo").unwrap(),
+      &serde_json::json!({ "version": "1.2.3" }),
+    )
+    .unwrap()
+  };
+  JsRuntime::new(RuntimeOptions {
+    synthetic_modules: vec![buildinfo(), buildinfo()],
     ..Default::default()
   });
+}
 
-  let fut = async move {
-    let spec = resolve_url("file:///redirect1.js").unwrap();
-    let result = runtime.load_main_module(&spec, None).await;
-    assert!(result.is_ok());
-    let redirect1_id = result.unwrap();
-    #[allow(clippy::let_underscore_future)]
-    let _ = runtime.mod_evaluate(redirect1_id);
-    runtime.run_event_loop(false).await.unwrap();
-    let l = loads.lock();
-    assert_eq!(
-      l.to_vec(),
-      vec![
-        "file:///redirect1.js",
-        "file:///redirect2.js",
-        "file:///dir/redirect3.js"
-      ]
+#[test]
+fn synthetic_module_snapshot() {
+  fn buildinfo(version: &'static str) -> SyntheticModule {
+    SyntheticModule::new(
+      resolve_url("internal:buildinfo").unwrap(),
+      vec!["version".to_string()],
+      move |scope| {
+        let version: v8::Local<v8::Value> =
+          v8::String::new(scope, version).unwrap().into();
+        Ok(vec![v8::Global::new(scope, version)])
+      },
+    )
+  }
+
+  let snapshot = {
+    let runtime = JsRuntimeForSnapshot::new(
+      RuntimeOptions {
+        module_loader: Some(MockLoader::new()),
+        synthetic_modules: vec![buildinfo("1.0.0")],
+        ..Default::default()
+      },
+      Default::default(),
     );
+    runtime.snapshot()
+  };
 
-    let module_map_rc = runtime.module_map();
-    let modules = module_map_rc.borrow();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(MockLoader::new()),
+    startup_snapshot: Some(Snapshot::JustCreated(snapshot)),
+    synthetic_modules: vec![buildinfo("2.0.0")],
+    ..Default::default()
+  });
+  let spec = resolve_url("file:///main_with_code.js").unwrap();
+  let main_id_fut = runtime
+    .load_main_module(
+      &spec,
+      Some(ascii_str!(
+        r#"
+        import { version } from "internal:buildinfo";
+        if (version !== "2.0.0") throw new Error();
+        "#
+      )),
+    )
+    .boxed_local();
+  let main_id = futures::executor::block_on(main_id_fut).unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+  futures::executor::block_on(receiver).unwrap().unwrap();
+}
 
-    assert_eq!(
-      modules
-        .get_id("file:///redirect1.js", AssertedModuleType::JavaScriptOrWasm),
-      Some(redirect1_id)
-    );
+#[tokio::test]
+async fn test_commonjs_modules() {
+  struct ModsLoader {
+    loads: Rc<RefCell<Vec<String>>>,
+  }
 
-    let redirect2_id = modules
-      .get_id(
-        "file:///dir/redirect2.js",
-        AssertedModuleType::JavaScriptOrWasm,
-      )
-      .unwrap();
-    assert!(modules
-      .is_alias("file:///redirect2.js", AssertedModuleType::JavaScriptOrWasm));
-    assert!(!modules.is_alias(
-      "file:///dir/redirect2.js",
-      AssertedModuleType::JavaScriptOrWasm
-    ));
-    assert_eq!(
-      modules
-        .get_id("file:///redirect2.js", AssertedModuleType::JavaScriptOrWasm),
-      Some(redirect2_id)
-    );
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
 
-    let redirect3_id = modules
-      .get_id("file:///redirect3.js", AssertedModuleType::JavaScriptOrWasm)
-      .unwrap();
-    assert!(modules.is_alias(
-      "file:///dir/redirect3.js",
-      AssertedModuleType::JavaScriptOrWasm
-    ));
-    assert!(!modules
-      .is_alias("file:///redirect3.js", AssertedModuleType::JavaScriptOrWasm));
-    assert_eq!(
-      modules.get_id(
-        "file:///dir/redirect3.js",
-        AssertedModuleType::JavaScriptOrWasm
-      ),
-      Some(redirect3_id)
-    );
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      self.loads.borrow_mut().push(module_specifier.to_string());
+      let (module_type, code) = match module_specifier.as_str() {
+        "file:///main.js" => (
+          ModuleType::JavaScript,
+          ascii_str!(
+            r#"
+            import a, { fromB, later } from "./a.cjs";
+            import b from "./b.cjs";
+            if (a.a !== 1 || later !== 3) throw new Error("a");
+            if (fromB[0] !== 1 || fromB[1] !== undefined) throw new Error("b");
+            if (a.b !== b || b.b !== 2) throw new Error("c");
+            if (a.data.answer !== 42) throw new Error("d");
+            if (!a.dynamicError.startsWith('Cannot find module "file:///x.cjs"')) {
+              throw new Error("e");
+            }
+            if (a.id !== "file:///a.cjs" || a.loaded !== false) {
+              throw new Error("f");
+            }
+            "#
+          ),
+        ),
+        "file:///a.cjs" => (
+          ModuleType::CommonJs,
+          ascii_str!(
+            r#"
+            exports.a = 1;
+            const b = require("./b.cjs");
+            exports.later = 3;
+            module.exports.fromB = [b.seenA, b.seenLater];
+            exports.b = require("./b.cjs");
+            exports.data = require("./data.json");
+            try {
+              require("./" + "x.cjs");
+            } catch (err) {
+              exports.dynamicError = err.message;
+            }
+            exports.id = module.id;
+            exports.loaded = module.loaded;
+            "#
+          ),
+        ),
+        "file:///b.cjs" => (
+          ModuleType::CommonJs,
+          ascii_str!(
+            r#"
+            const a = require("./a.cjs");
+            exports.seenA = a.a;
+            exports.seenLater = a.later;
+            exports.b = 2;
+            "#
+          ),
+        ),
+        "file:///data.json" => {
+          (ModuleType::Json, ascii_str!(r#"{ "answer": 42 }"#))
+        }
+        _ => unreachable!(),
+      };
+      let module_source =
+        ModuleSource::new(module_type, code, module_specifier);
+      async move { Ok(module_source) }.boxed()
+    }
   }
-  .boxed_local();
 
-  futures::executor::block_on(fut);
-}
+  let main_specifier = resolve_url("file:///main.js").unwrap();
 
-#[tokio::test]
-async fn slow_never_ready_modules() {
-  let loader = MockLoader::new();
-  let loads = loader.loads.clone();
+  // CommonJS modules have to be enabled.
   let mut runtime = JsRuntime::new(RuntimeOptions {
-    module_loader: Some(loader),
+    module_loader: Some(Rc::new(ModsLoader {
+      loads: Default::default(),
+    })),
     ..Default::default()
   });
+  let err = runtime
+    .load_main_module(&main_specifier, None)
+    .await
+    .unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    r#"Cannot load CommonJS module "file:///a.cjs", because CommonJS modules aren't enabled."#
+  );
 
-  poll_fn(move |cx| {
-    let spec = resolve_url("file:///main.js").unwrap();
-    let mut recursive_load =
-      runtime.load_main_module(&spec, None).boxed_local();
-
-    let result = recursive_load.poll_unpin(cx);
-    assert!(result.is_pending());
+  let loads = Rc::new(RefCell::new(vec![]));
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(ModsLoader {
+      loads: loads.clone(),
+    })),
+    commonjs_modules: true,
+    ..Default::default()
+  });
+  let main_id = runtime
+    .load_main_module(&main_specifier, None)
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
 
-    // TODO(ry) Arguably the first time we poll only the following modules
-    // should be loaded:
-    //      "file:///main.js",
-    //      "file:///never_ready.js",
-    //      "file:///slow.js"
-    // But due to current task notification in DelayedSourceCodeFuture they
-    // all get loaded in a single poll.
+  let mut loads = loads.borrow().clone();
+  loads.sort();
+  assert_eq!(
+    loads,
+    [
+      "file:///a.cjs",
+      "file:///b.cjs",
+      "file:///data.json",
+      "file:///main.js"
+    ]
+  );
 
-    for _ in 0..10 {
-      let result = recursive_load.poll_unpin(cx);
-      assert!(result.is_pending());
-      let l = loads.lock();
-      assert_eq!(
-        l.to_vec(),
-        vec![
-          "file:///main.js",
-          "file:///never_ready.js",
-          "file:///slow.js",
-          "file:///a.js",
-          "file:///b.js",
-          "file:///c.js",
-          "file:///d.js"
-        ]
-      );
-    }
-    Poll::Ready(())
-  })
-  .await;
+  let graph = runtime.module_graph();
+  let a = graph.get_by_specifier("file:///a.cjs").unwrap();
+  assert_eq!(a.module_type, ModuleType::CommonJs);
+  let dependencies = a
+    .dependencies
+    .iter()
+    .map(|d| d.specifier.as_str())
+    .collect::<Vec<_>>();
+  assert_eq!(dependencies, ["file:///b.cjs", "file:///data.json"]);
 }
 
 #[tokio::test]
-async fn loader_disappears_after_error() {
+async fn test_import_meta_callback() {
   let loader = MockLoader::new();
+  let calls = Rc::new(RefCell::new(vec![]));
+  let lazy_calls = Rc::new(Cell::new(0));
+  let calls_ = calls.clone();
+  let lazy_calls_ = lazy_calls.clone();
   let mut runtime = JsRuntime::new(RuntimeOptions {
     module_loader: Some(loader),
+    import_meta_callback: Some(Box::new(move |scope, info, meta| {
+      calls_.borrow_mut().push(info.clone());
+      let filename: v8::Local<v8::Value> =
+        v8::String::new(scope, "/main.js").unwrap().into();
+      meta.set("filename", v8::Global::new(scope, filename));
+      let lazy_calls = lazy_calls_.clone();
+      meta.set_lazy("env", move |scope| {
+        lazy_calls.set(lazy_calls.get() + 1);
+        let env: v8::Local<v8::Value> = v8::Object::new(scope).into();
+        Ok(v8::Global::new(scope, env))
+      });
+      meta.set_lazy("secret", |_| Err(generic_error("Permission denied")));
+    })),
     ..Default::default()
   });
 
-  let spec = resolve_url("file:///bad_import.js").unwrap();
-  let result = runtime.load_main_module(&spec, None).await;
-  let err = result.unwrap_err();
+  let spec = resolve_url("file:///main.js").unwrap();
+  let main_id = runtime
+    .load_main_module(
+      &spec,
+      Some(ascii_str!(
+        r#"
+        if (import.meta.url !== "file:///main.js") throw new Error("a");
+        if (import.meta.filename !== "/main.js") throw new Error("b");
+        const env = import.meta.env;
+        if (typeof env !== "object" || import.meta.env !== env) {
+          throw new Error("c");
+        }
+        let err;
+        try {
+          import.meta.secret;
+        } catch (e) {
+          err = e;
+        }
+        if (err?.message !== "Permission denied") throw new Error("d");
+        "#
+      )),
+    )
+    .await
+    .unwrap();
+  let receiver = runtime.mod_evaluate(main_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+
+  assert_eq!(lazy_calls.get(), 1);
   assert_eq!(
-    err.downcast_ref::<MockError>().unwrap(),
-    &MockError::ResolveErr
+    calls.borrow()[0],
+    ImportMetaModuleInfo {
+      id: main_id,
+      specifier: "file:///main.js".to_string(),
+      main: true,
+      module_type: ModuleType::JavaScript,
+    }
   );
 }
 
 #[test]
-fn recursive_load_main_with_code() {
-  const MAIN_WITH_CODE_SRC: FastString = ascii_str!(
-    r#"
-import { b } from "/b.js";
-import { c } from "/c.js";
-if (b() != 'b') throw Error();
-if (c() != 'c') throw Error();
-if (!import.meta.main) throw Error();
-if (import.meta.url != 'file:///main_with_code.js') throw Error();
-"#
-  );
+fn import_meta_callback_snapshot() {
+  fn import_meta_callback(env: &'static str) -> ImportMetaCallback {
+    Box::new(move |_, info, meta| {
+      let specifier = info.specifier.clone();
+      meta.set_lazy("env", move |scope| {
+        let env: v8::Local<v8::Value> =
+          v8::String::new(scope, &format!("{env}:{specifier}"))
+            .unwrap()
+            .into();
+        Ok(v8::Global::new(scope, env))
+      });
+    })
+  }
+
+  let snapshot = {
+    let mut runtime = JsRuntimeForSnapshot::new(
+      RuntimeOptions {
+        module_loader: Some(MockLoader::new()),
+        import_meta_callback: Some(import_meta_callback("snapshot")),
+        ..Default::default()
+      },
+      Default::default(),
+    );
+    let spec = resolve_url("file:///a.js").unwrap();
+    let a_id_fut = runtime
+      .load_side_module(
+        &spec,
+        Some(ascii_str!("globalThis.metaA = import.meta;")),
+      )
+      .boxed_local();
+    let a_id = futures::executor::block_on(a_id_fut).unwrap();
+    #[allow(clippy::let_underscore_future)]
+    let _ = runtime.mod_evaluate(a_id);
+    let spec = resolve_url("file:///b.js").unwrap();
+    let b_id_fut = runtime
+      .load_side_module(
+        &spec,
+        Some(ascii_str!("globalThis.getMetaB = () => import.meta;")),
+      )
+      .boxed_local();
+    let b_id = futures::executor::block_on(b_id_fut).unwrap();
+    #[allow(clippy::let_underscore_future)]
+    let _ = runtime.mod_evaluate(b_id);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    runtime.snapshot()
+  };
 
-  let loader = MockLoader::new();
-  let loads = loader.loads.clone();
   let mut runtime = JsRuntime::new(RuntimeOptions {
-    module_loader: Some(loader),
+    startup_snapshot: Some(Snapshot::JustCreated(snapshot)),
+    import_meta_callback: Some(import_meta_callback("restored")),
     ..Default::default()
   });
-  // In default resolution code should be empty.
-  // Instead we explicitly pass in our own code.
-  // The behavior should be very similar to /a.js.
-  let spec = resolve_url("file:///main_with_code.js").unwrap();
-  let main_id_fut = runtime
-    .load_main_module(&spec, Some(MAIN_WITH_CODE_SRC))
-    .boxed_local();
-  let main_id = futures::executor::block_on(main_id_fut).unwrap();
+  runtime
+    .execute_script_static(
+      "check.js",
+      r#"
+      if (globalThis.metaA.env !== "restored:file:///a.js") throw new Error();
+      if (getMetaB().env !== "restored:file:///b.js") throw new Error();
+      "#,
+    )
+    .unwrap();
+}
 
-  #[allow(clippy::let_underscore_future)]
-  let _ = runtime.mod_evaluate(main_id);
-  futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+#[tokio::test]
+async fn test_import() {
+  struct ModsLoader {
+    resolutions: Rc<RefCell<Vec<(String, ResolutionKind)>>>,
+  }
 
-  let l = loads.lock();
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      self
+        .resolutions
+        .borrow_mut()
+        .push((specifier.to_string(), kind));
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      let (module_type, code) = match module_specifier.as_str() {
+        "file:///plugin.js" => (
+          ModuleType::JavaScript,
+          ascii_str!(
+            r#"
+            await Promise.resolve();
+            globalThis.evaluations = (globalThis.evaluations ?? 0) + 1;
+            export function name() { return "plugin"; }
+            "#
+          ),
+        ),
+        "file:///throws.js" => (
+          ModuleType::JavaScript,
+          ascii_str!("throw new Error('boom');"),
+        ),
+        "file:///data.json" => (ModuleType::Json, ascii_str!("[1, 2]")),
+        _ => unreachable!(),
+      };
+      let module_source =
+        ModuleSource::new(module_type, code, module_specifier);
+      async move { Ok(module_source) }.boxed()
+    }
+  }
+
+  let resolutions = Rc::new(RefCell::new(vec![]));
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(ModsLoader {
+      resolutions: resolutions.clone(),
+    })),
+    ..Default::default()
+  });
+
+  let namespace = runtime
+    .import("./plugin.js", "file:///main.js")
+    .await
+    .unwrap();
+  let namespace2 = runtime
+    .import("/plugin.js", "file:///a/b.js")
+    .await
+    .unwrap();
+  assert_eq!(namespace, namespace2);
+  {
+    let scope = &mut runtime.handle_scope();
+    let namespace = v8::Local::new(scope, namespace);
+    let key = v8::String::new(scope, "name").unwrap();
+    let name = namespace.get(scope, key.into()).unwrap();
+    let name = v8::Local::<v8::Function>::try_from(name).unwrap();
+    let undefined = v8::undefined(scope).into();
+    let result = name.call(scope, undefined, &[]).unwrap();
+    assert_eq!(result.to_rust_string_lossy(scope), "plugin");
+  }
+  let evaluations = runtime
+    .execute_script_static("check.js", "globalThis.evaluations")
+    .unwrap();
+  {
+    let scope = &mut runtime.handle_scope();
+    let evaluations = v8::Local::new(scope, evaluations);
+    assert_eq!(evaluations.integer_value(scope), Some(1));
+  }
   assert_eq!(
-    l.to_vec(),
-    vec!["file:///b.js", "file:///c.js", "file:///d.js"]
+    resolutions.borrow()[0],
+    ("./plugin.js".to_string(), ResolutionKind::DynamicImport)
   );
 
-  let module_map_rc = runtime.module_map();
-  let modules = module_map_rc.borrow();
+  let err = runtime
+    .import("./throws.js", "file:///main.js")
+    .await
+    .unwrap_err();
+  assert_eq!(
+    err.downcast::<JsError>().unwrap().exception_message,
+    "Uncaught Error: boom"
+  );
 
+  let err = runtime
+    .import_with_attributes(
+      "./data.json",
+      "file:///main.js",
+      &[("type", "css")],
+    )
+    .await
+    .unwrap_err();
   assert_eq!(
-    modules.get_id(
-      "file:///main_with_code.js",
-      AssertedModuleType::JavaScriptOrWasm
-    ),
-    Some(main_id)
+    err.to_string(),
+    r#"Uncaught TypeError: "css" is not a valid module type."#
   );
-  let b_id = modules
-    .get_id("file:///b.js", AssertedModuleType::JavaScriptOrWasm)
-    .unwrap();
-  let c_id = modules
-    .get_id("file:///c.js", AssertedModuleType::JavaScriptOrWasm)
-    .unwrap();
-  let d_id = modules
-    .get_id("file:///d.js", AssertedModuleType::JavaScriptOrWasm)
+  runtime
+    .import("./data.json", "file:///main.js")
+    .await
+    .unwrap_err();
+  let namespace = runtime
+    .import_with_attributes(
+      "./data.json",
+      "file:///main.js",
+      &[("type", "json")],
+    )
+    .await
     .unwrap();
+  let scope = &mut runtime.handle_scope();
+  let namespace = v8::Local::new(scope, namespace);
+  let key = v8::String::new(scope, "default").unwrap();
+  let data = namespace.get(scope, key.into()).unwrap();
+  assert!(data.is_array());
+}
+
+#[tokio::test]
+async fn test_stalled_top_level_await() {
+  struct ModsLoader;
+
+  impl ModuleLoader for ModsLoader {
+    fn resolve(
+      &self,
+      specifier: &str,
+      referrer: &str,
+      _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+      let s = resolve_import(specifier, referrer).unwrap();
+      Ok(s)
+    }
+
+    fn load(
+      &self,
+      module_specifier: &ModuleSpecifier,
+      _maybe_referrer: Option<&ModuleSpecifier>,
+      _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+      let code = match module_specifier.as_str() {
+        "file:///main.js" => ascii_str!("import './a.js'; import './c.js';"),
+        "file:///a.js" => ascii_str!("import './b.js';"),
+        "file:///b.js" => ascii_str!("await new Promise(() => {});"),
+        "file:///c.js" => ascii_str!("\nawait new Promise(() => {});"),
+        _ => unreachable!(),
+      };
+      let module_source =
+        ModuleSource::new(ModuleType::JavaScript, code, module_specifier);
+      async move { Ok(module_source) }.boxed()
+    }
+  }
 
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(Rc::new(ModsLoader)),
+    ..Default::default()
+  });
+  let specifier = resolve_import("./main.js", "file:///").unwrap();
+  let id = runtime.load_main_module(&specifier, None).await.unwrap();
+  #[allow(clippy::let_underscore_future)]
+  let _ = runtime.mod_evaluate(id);
+  let err = runtime.run_event_loop(false).await.unwrap_err();
+
+  let err = err.downcast::<crate::StalledTopLevelAwaitError>().unwrap();
+  let mut stalled = err
+    .stalled
+    .iter()
+    .map(|stalled| {
+      let call_site = stalled.call_site.as_ref().unwrap();
+      (
+        stalled.realm,
+        stalled.specifier.as_str(),
+        stalled.source_line.as_deref(),
+        call_site.line_number,
+        stalled.import_chain.join(" "),
+      )
+    })
+    .collect::<Vec<_>>();
+  stalled.sort();
   assert_eq!(
-    modules.get_requested_modules(main_id),
-    Some(&vec![
-      ModuleRequest {
-        specifier: "file:///b.js".to_string(),
-        asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
-      },
-      ModuleRequest {
-        specifier: "file:///c.js".to_string(),
-        asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
-      }
-    ])
+    stalled,
+    vec![
+      (
+        0,
+        "file:///b.js",
+        Some("await new Promise(() => {});"),
+        Some(1),
+        "file:///main.js file:///a.js file:///b.js".to_string()
+      ),
+      (
+        0,
+        "file:///c.js",
+        Some("await new Promise(() => {});"),
+        Some(2),
+        "file:///main.js file:///c.js".to_string()
+      ),
+    ]
   );
+  assert!(err.to_string().contains(
+    "\n    imported through file:///main.js -> file:///a.js -> file:///b.js"
+  ));
+}
+
+#[tokio::test]
+async fn test_module_load_trace() {
+  let exporter = ChromeTraceExporter::new();
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    module_loader: Some(MockLoader::new()),
+    module_load_trace_callback: Some(exporter.callback()),
+    ..Default::default()
+  });
+  let spec = resolve_url("file:///a.js").unwrap();
+  let a_id = runtime.load_main_module(&spec, None).await.unwrap();
+  let receiver = runtime.mod_evaluate(a_id);
+  runtime.run_event_loop(false).await.unwrap();
+  receiver.await.unwrap().unwrap();
+
+  let events = exporter.events();
+  assert!(events.iter().all(|event| !event.from_snapshot));
+  let phase = |phase| {
+    events
+      .iter()
+      .filter(|event| event.phase == phase)
+      .map(|event| (event.specifier.as_str(), event.referrer.as_deref()))
+      .collect::<Vec<_>>()
+  };
+  let mut loads = phase(ModuleLoadPhase::Load);
+  loads.sort();
   assert_eq!(
-    modules.get_requested_modules(b_id),
-    Some(&vec![ModuleRequest {
-      specifier: "file:///c.js".to_string(),
-      asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
-    }])
+    loads,
+    vec![
+      ("file:///a.js", None),
+      ("file:///b.js", Some("file:///a.js")),
+      ("file:///c.js", Some("file:///a.js")),
+      ("file:///d.js", Some("file:///c.js")),
+    ]
   );
+  let mut compiles = phase(ModuleLoadPhase::Compile);
+  compiles.sort();
+  assert_eq!(compiles, loads);
   assert_eq!(
-    modules.get_requested_modules(c_id),
-    Some(&vec![ModuleRequest {
-      specifier: "file:///d.js".to_string(),
-      asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
-    }])
+    phase(ModuleLoadPhase::PrepareLoad),
+    vec![("file:///a.js", None)]
   );
-  assert_eq!(modules.get_requested_modules(d_id), Some(&vec![]));
+  assert!(phase(ModuleLoadPhase::Resolve)
+    .contains(&("file:///d.js", Some("file:///c.js"))));
+  assert_eq!(
+    phase(ModuleLoadPhase::Instantiate),
+    vec![("file:///a.js", None)]
+  );
+  assert_eq!(
+    phase(ModuleLoadPhase::Evaluate),
+    vec![("file:///a.js", None)]
+  );
+
+  let json = exporter.to_json();
+  let trace_events = json["traceEvents"].as_array().unwrap();
+  assert!(trace_events.iter().any(|event| event["name"]
+    == "evaluate file:///a.js"
+    && event["ph"] == "X"));
 }
 
 #[test]
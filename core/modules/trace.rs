@@ -0,0 +1,303 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use crate::module_specifier::ModuleSpecifier;
+use crate::modules::ModuleLoader;
+use crate::modules::ModuleMap;
+use crate::modules::ResolutionKind;
+use anyhow::Error;
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Callback that receives the module loading and evaluation events of a
+/// runtime, set in
+/// [`RuntimeOptions::module_load_trace_callback`](crate::RuntimeOptions::module_load_trace_callback).
+pub type ModuleLoadTraceCallback = Box<dyn Fn(&ModuleLoadEvent)>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ModuleLoadPhase {
+  /// A call to [`ModuleLoader::resolve`] or
+  /// [`ModuleLoader::resolve_async`].
+  Resolve,
+  /// A call to [`ModuleLoader::prepare_load`], for the root of a module graph.
+  PrepareLoad,
+  /// A call to [`ModuleLoader::load`], until the source is available.
+  Load,
+  /// The creation of the module from its source, including the resolution
+  /// of its imports.
+  Compile,
+  /// The instantiation of a module graph, by its root module.
+  Instantiate,
+  /// The evaluation of a module graph, by its root module. For modules with
+  /// top-level await, it only covers the evaluation up to the first `await`.
+  Evaluate,
+}
+
+impl ModuleLoadPhase {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Resolve => "resolve",
+      Self::PrepareLoad => "prepare_load",
+      Self::Load => "load",
+      Self::Compile => "compile",
+      Self::Instantiate => "instantiate",
+      Self::Evaluate => "evaluate",
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleLoadEvent {
+  pub phase: ModuleLoadPhase,
+  /// The specifier of the module. For [`ModuleLoadPhase::Resolve`], it's the
+  /// resolved specifier, or the specifier as written if resolution failed.
+  pub specifier: String,
+  /// The module importing the module, if it's known.
+  pub referrer: Option<String>,
+  pub start: Instant,
+  pub duration: Duration,
+  /// Whether the module was restored from the startup snapshot.
+  pub from_snapshot: bool,
+}
+
+/// Reports the events of a module map to the trace callback.
+#[derive(Clone)]
+pub(crate) struct ModuleLoadTracer {
+  callback: Rc<ModuleLoadTraceCallback>,
+  // The specifiers of the modules restored from the snapshot, including the
+  // redirected ones.
+  snapshotted: Rc<HashSet<String>>,
+}
+
+impl ModuleLoadTracer {
+  /// Must be created before any module is added to `module_map` other than
+  /// the ones restored from the snapshot.
+  pub(crate) fn new(
+    callback: Rc<ModuleLoadTraceCallback>,
+    module_map: &ModuleMap,
+  ) -> Self {
+    let snapshotted = module_map
+      .collect_modules()
+      .into_iter()
+      .map(|(_, name, _)| name.as_str().to_string())
+      .collect();
+    Self {
+      callback,
+      snapshotted: Rc::new(snapshotted),
+    }
+  }
+
+  pub(crate) fn trace(
+    &self,
+    phase: ModuleLoadPhase,
+    specifier: &str,
+    referrer: Option<&str>,
+    start: Instant,
+  ) {
+    (self.callback)(&ModuleLoadEvent {
+      phase,
+      specifier: specifier.to_string(),
+      referrer: referrer.map(ToString::to_string),
+      start,
+      duration: start.elapsed(),
+      from_snapshot: self.snapshotted.contains(specifier),
+    });
+  }
+}
+
+/// Calls [`ModuleLoader::resolve`], tracing the call if there is a tracer.
+pub(crate) fn resolve(
+  loader: &dyn ModuleLoader,
+  tracer: Option<&ModuleLoadTracer>,
+  specifier: &str,
+  referrer: &str,
+  kind: ResolutionKind,
+) -> Result<ModuleSpecifier, Error> {
+  let start = Instant::now();
+  let result = loader.resolve(specifier, referrer, kind);
+  if let Some(tracer) = tracer {
+    trace_resolution(tracer, &result, specifier, referrer, start);
+  }
+  result
+}
+
+/// Calls [`ModuleLoader::resolve_async`], tracing the call if there is a
+/// tracer.
+pub(crate) async fn resolve_async(
+  loader: Rc<dyn ModuleLoader>,
+  tracer: Option<ModuleLoadTracer>,
+  specifier: String,
+  referrer: String,
+  kind: ResolutionKind,
+) -> Result<ModuleSpecifier, Error> {
+  let start = Instant::now();
+  let result = loader.resolve_async(&specifier, &referrer, kind).await;
+  if let Some(tracer) = &tracer {
+    trace_resolution(tracer, &result, &specifier, &referrer, start);
+  }
+  result
+}
+
+fn trace_resolution(
+  tracer: &ModuleLoadTracer,
+  result: &Result<ModuleSpecifier, Error>,
+  specifier: &str,
+  referrer: &str,
+  start: Instant,
+) {
+  let resolved = match result {
+    Ok(resolved) => resolved.as_str(),
+    Err(_) => specifier,
+  };
+  // Root modules are resolved against ".".
+  let referrer = (referrer != ".").then_some(referrer);
+  tracer.trace(ModuleLoadPhase::Resolve, resolved, referrer, start);
+}
+
+/// Collects module load events and writes them in the Chrome trace event
+/// format, which can be opened in `chrome://tracing` or Perfetto.
+///
+/// ```rust,ignore
+/// let exporter = ChromeTraceExporter::new();
+/// let mut runtime = JsRuntime::new(RuntimeOptions {
+///   module_load_trace_callback: Some(exporter.callback()),
+///   ..Default::default()
+/// });
+/// // Load and evaluate modules...
+/// exporter.write(std::fs::File::create("trace.json")?)?;
+/// ```
+#[derive(Clone)]
+pub struct ChromeTraceExporter {
+  origin: Instant,
+  events: Rc<RefCell<Vec<ModuleLoadEvent>>>,
+}
+
+impl Default for ChromeTraceExporter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ChromeTraceExporter {
+  /// Creates an exporter. Timestamps in the trace are relative to its
+  /// creation.
+  pub fn new() -> Self {
+    Self {
+      origin: Instant::now(),
+      events: Default::default(),
+    }
+  }
+
+  /// Returns a callback recording events in this exporter.
+  pub fn callback(&self) -> ModuleLoadTraceCallback {
+    let events = self.events.clone();
+    Box::new(move |event| events.borrow_mut().push(event.clone()))
+  }
+
+  pub fn events(&self) -> Vec<ModuleLoadEvent> {
+    self.events.borrow().clone()
+  }
+
+  /// Returns the recorded events as a trace event JSON object.
+  ///
+  /// Synchronous phases are complete events on a single thread, so that
+  /// resolutions nest in the compilations and instantiations they happen in.
+  /// Loads overlap each other, so they're async events instead.
+  pub fn to_json(&self) -> serde_json::Value {
+    let mut trace_events = vec![];
+    for (id, event) in self.events.borrow().iter().enumerate() {
+      let ts = event
+        .start
+        .saturating_duration_since(self.origin)
+        .as_micros();
+      let name = format!("{} {}", event.phase.as_str(), event.specifier);
+      let args = json!({
+        "specifier": event.specifier,
+        "referrer": event.referrer,
+        "fromSnapshot": event.from_snapshot,
+      });
+      match event.phase {
+        ModuleLoadPhase::PrepareLoad | ModuleLoadPhase::Load => {
+          let end = ts + event.duration.as_micros();
+          for (ph, ts) in [("b", ts), ("e", end)] {
+            trace_events.push(json!({
+              "name": name,
+              "cat": event.phase.as_str(),
+              "ph": ph,
+              "ts": ts as u64,
+              "pid": 1,
+              "tid": 1,
+              "id": id,
+              "args": args,
+            }));
+          }
+        }
+        _ => trace_events.push(json!({
+          "name": name,
+          "cat": event.phase.as_str(),
+          "ph": "X",
+          "ts": ts as u64,
+          "dur": event.duration.as_micros() as u64,
+          "pid": 1,
+          "tid": 1,
+          "args": args,
+        })),
+      }
+    }
+    json!({
+      "traceEvents": trace_events,
+      "displayTimeUnit": "ms",
+    })
+  }
+
+  pub fn write(&self, writer: impl io::Write) -> io::Result<()> {
+    serde_json::to_writer(writer, &self.to_json()).map_err(io::Error::from)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn chrome_trace_json() {
+    let exporter = ChromeTraceExporter::new();
+    let callback = exporter.callback();
+    let start = exporter.origin + Duration::from_micros(10);
+    for (phase, specifier, referrer) in [
+      (
+        ModuleLoadPhase::Load,
+        "file:///a.js",
+        Some("file:///main.js"),
+      ),
+      (ModuleLoadPhase::Compile, "file:///a.js", None),
+    ] {
+      callback(&ModuleLoadEvent {
+        phase,
+        specifier: specifier.to_string(),
+        referrer: referrer.map(ToString::to_string),
+        start,
+        duration: Duration::from_micros(5),
+        from_snapshot: false,
+      });
+    }
+
+    let json = exporter.to_json();
+    let events = json["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0]["ph"], "b");
+    assert_eq!(events[0]["ts"], 10);
+    assert_eq!(events[0]["name"], "load file:///a.js");
+    assert_eq!(events[0]["args"]["referrer"], "file:///main.js");
+    assert_eq!(events[1]["ph"], "e");
+    assert_eq!(events[1]["ts"], 15);
+    assert_eq!(events[1]["id"], events[0]["id"]);
+    assert_eq!(events[2]["ph"], "X");
+    assert_eq!(events[2]["dur"], 5);
+    assert_eq!(events[2]["args"]["referrer"], serde_json::Value::Null);
+    assert_eq!(events[2]["args"]["fromSnapshot"], false);
+  }
+}
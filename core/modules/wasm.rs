@@ -0,0 +1,276 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use crate::error::generic_error;
+use anyhow::Error;
+use std::fmt::Write;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+const IMPORT_SECTION_ID: u8 = 2;
+const EXPORT_SECTION_ID: u8 = 7;
+
+/// A single function, table, memory, global or tag imported by a Wasm module.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct WasmImport {
+  /// The module specifier, resolved as an ES import of the Wasm module.
+  pub module: String,
+  pub name: String,
+}
+
+/// Imports and exports of a Wasm module, as declared in its binary.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct WasmModuleAnalysis {
+  pub imports: Vec<WasmImport>,
+  pub exports: Vec<String>,
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(bytes: &'a [u8]) -> Self {
+    Self { bytes, pos: 0 }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.pos >= self.bytes.len()
+  }
+
+  fn byte(&mut self) -> Result<u8, Error> {
+    let b = *self
+      .bytes
+      .get(self.pos)
+      .ok_or_else(|| generic_error("Unexpected end of Wasm module."))?;
+    self.pos += 1;
+    Ok(b)
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+    let end = self
+      .pos
+      .checked_add(len)
+      .filter(|end| *end <= self.bytes.len())
+      .ok_or_else(|| generic_error("Unexpected end of Wasm module."))?;
+    let slice = &self.bytes[self.pos..end];
+    self.pos = end;
+    Ok(slice)
+  }
+
+  /// Reads an unsigned LEB128 integer.
+  fn leb_u64(&mut self) -> Result<u64, Error> {
+    let mut result = 0u64;
+    let mut shift = 0;
+    loop {
+      let b = self.byte()?;
+      if shift >= 64 {
+        return Err(generic_error("Invalid LEB128 integer in Wasm module."));
+      }
+      result |= ((b & 0x7f) as u64) << shift;
+      if b & 0x80 == 0 {
+        return Ok(result);
+      }
+      shift += 7;
+    }
+  }
+
+  fn leb_usize(&mut self) -> Result<usize, Error> {
+    usize::try_from(self.leb_u64()?)
+      .map_err(|_| generic_error("Invalid LEB128 integer in Wasm module."))
+  }
+
+  fn name(&mut self) -> Result<String, Error> {
+    let len = self.leb_usize()?;
+    let bytes = self.take(len)?;
+    String::from_utf8(bytes.to_vec())
+      .map_err(|_| generic_error("Invalid UTF-8 name in Wasm module."))
+  }
+
+  fn limits(&mut self) -> Result<(), Error> {
+    let flags = self.byte()?;
+    self.leb_u64()?;
+    if flags & 1 != 0 {
+      self.leb_u64()?;
+    }
+    Ok(())
+  }
+}
+
+/// Extracts the imports and exports of a Wasm module from its binary
+/// representation. Only the import and export sections are inspected; the
+/// module is expected to have been validated by V8 already.
+pub(crate) fn analyze_wasm_module(
+  bytes: &[u8],
+) -> Result<WasmModuleAnalysis, Error> {
+  let mut reader = Reader::new(bytes);
+  if reader.take(4)? != WASM_MAGIC {
+    return Err(generic_error("Invalid Wasm module: missing magic number."));
+  }
+  // Version
+  reader.take(4)?;
+
+  let mut analysis = WasmModuleAnalysis::default();
+  while !reader.is_empty() {
+    let section_id = reader.byte()?;
+    let section_len = reader.leb_usize()?;
+    let section = reader.take(section_len)?;
+    match section_id {
+      IMPORT_SECTION_ID => {
+        let mut section = Reader::new(section);
+        let count = section.leb_usize()?;
+        for _ in 0..count {
+          let module = section.name()?;
+          let name = section.name()?;
+          match section.byte()? {
+            // Function: type index
+            0x00 => {
+              section.leb_u64()?;
+            }
+            // Table: reference type and limits
+            0x01 => {
+              section.byte()?;
+              section.limits()?;
+            }
+            // Memory: limits
+            0x02 => section.limits()?,
+            // Global: value type and mutability
+            0x03 => {
+              section.take(2)?;
+            }
+            // Tag: attribute and type index
+            0x04 => {
+              section.byte()?;
+              section.leb_u64()?;
+            }
+            kind => {
+              return Err(generic_error(format!(
+                "Unknown import kind {kind} in Wasm module."
+              )))
+            }
+          }
+          analysis.imports.push(WasmImport { module, name });
+        }
+      }
+      EXPORT_SECTION_ID => {
+        let mut section = Reader::new(section);
+        let count = section.leb_usize()?;
+        for _ in 0..count {
+          let name = section.name()?;
+          // Export kind and index
+          section.byte()?;
+          section.leb_u64()?;
+          analysis.exports.push(name);
+        }
+      }
+      _ => {}
+    }
+  }
+
+  Ok(analysis)
+}
+
+/// Renders the JavaScript module that links a Wasm module into the module
+/// graph: each import module becomes an ES import, the compiled module (which
+/// is exposed on `import.meta.wasmModule` of this module only) is instantiated
+/// with them, and each export is re-exported under its own name.
+pub(crate) fn render_js_wasm_module(analysis: &WasmModuleAnalysis) -> String {
+  let mut src = String::new();
+
+  let mut import_modules: Vec<&str> = vec![];
+  for import in &analysis.imports {
+    if !import_modules.contains(&import.module.as_str()) {
+      import_modules.push(&import.module);
+    }
+  }
+
+  for (i, module) in import_modules.iter().enumerate() {
+    writeln!(src, "import * as import_{i} from {};", json_str(module)).unwrap();
+  }
+
+  src.push_str("const importsObject = {\n");
+  for (i, module) in import_modules.iter().enumerate() {
+    writeln!(src, "  {}: {{", json_str(module)).unwrap();
+    for import in analysis.imports.iter().filter(|i| &i.module == module) {
+      let name = json_str(&import.name);
+      writeln!(src, "    {name}: import_{i}[{name}],").unwrap();
+    }
+    src.push_str("  },\n");
+  }
+  src.push_str("};\n");
+  src.push_str(
+    "const instance = new WebAssembly.Instance(import.meta.wasmModule, importsObject);\n",
+  );
+
+  for (i, export) in analysis.exports.iter().enumerate() {
+    let name = json_str(export);
+    writeln!(src, "const export_{i} = instance.exports[{name}];").unwrap();
+    writeln!(src, "export {{ export_{i} as {name} }};").unwrap();
+  }
+
+  src
+}
+
+fn json_str(s: &str) -> String {
+  serde_json::to_string(s).unwrap()
+}
+
+// (module
+//   (import "./lib.js" "add" (func $add (param i32 i32) (result i32)))
+//   (func (export "exported_add") (param i32 i32) (result i32)
+//     local.get 0 local.get 1 call $add))
+#[cfg(test)]
+pub(crate) const ADD_WASM: &[u8] = &[
+  0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+  0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, // types
+  0x02, 0x10, 0x01, 0x08, b'.', b'/', b'l', b'i', b'b', b'.', b'j', b's', 0x03,
+  b'a', b'd', b'd', 0x00, 0x00, // imports
+  0x03, 0x02, 0x01, 0x00, // functions
+  0x07, 0x10, 0x01, 0x0c, b'e', b'x', b'p', b'o', b'r', b't', b'e', b'd', b'_',
+  b'a', b'd', b'd', 0x00, 0x01, // exports
+  0x0a, 0x0a, 0x01, 0x08, 0x00, 0x20, 0x00, 0x20, 0x01, 0x10, 0x00,
+  0x0b, // code
+];
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn analyze() {
+    let analysis = analyze_wasm_module(ADD_WASM).unwrap();
+    assert_eq!(
+      analysis,
+      WasmModuleAnalysis {
+        imports: vec![WasmImport {
+          module: "./lib.js".to_string(),
+          name: "add".to_string(),
+        }],
+        exports: vec!["exported_add".to_string()],
+      }
+    );
+  }
+
+  #[test]
+  fn analyze_invalid() {
+    assert!(analyze_wasm_module(b"\0asn\x01\0\0\0").is_err());
+    assert!(analyze_wasm_module(&ADD_WASM[..20]).is_err());
+  }
+
+  #[test]
+  fn render() {
+    let analysis = analyze_wasm_module(ADD_WASM).unwrap();
+    let src = render_js_wasm_module(&analysis);
+    assert_eq!(
+      src,
+      r#"import * as import_0 from "./lib.js";
+const importsObject = {
+  "./lib.js": {
+    "add": import_0["add"],
+  },
+};
+const instance = new WebAssembly.Instance(import.meta.wasmModule, importsObject);
+const export_0 = instance.exports["exported_add"];
+export { export_0 as "exported_add" };
+"#
+    );
+  }
+}
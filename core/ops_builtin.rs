@@ -48,6 +48,12 @@ crate::extension!(
     op_str_byte_length,
     ops_builtin_v8::op_ref_op,
     ops_builtin_v8::op_unref_op,
+    ops_builtin_v8::op_timer_queue,
+    ops_builtin_v8::op_timer_cancel,
+    ops_builtin_v8::op_timer_ref,
+    ops_builtin_v8::op_timer_unref,
+    ops_builtin_v8::op_virtual_clock_date_now,
+    ops_builtin_v8::op_virtual_clock_now,
     ops_builtin_v8::op_set_promise_reject_callback,
     ops_builtin_v8::op_run_microtasks,
     ops_builtin_v8::op_has_tick_scheduled,
@@ -1,5 +1,6 @@
 // Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
 use crate::error::custom_error;
+use crate::error::generic_error;
 use crate::error::is_instance_of_error;
 use crate::error::range_error;
 use crate::error::type_error;
@@ -13,6 +14,7 @@ use crate::JsBuffer;
 use crate::JsRealm;
 use crate::JsRuntime;
 use crate::ToJsBuffer;
+use crate::VirtualClock;
 use anyhow::Error;
 use deno_ops::op;
 use deno_ops::op2;
@@ -20,6 +22,8 @@ use serde::Deserialize;
 use serde::Serialize;
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::time::Duration;
+use std::time::SystemTime;
 use v8::ValueDeserializerHelper;
 use v8::ValueSerializerHelper;
 
@@ -35,6 +39,68 @@ pub fn op_unref_op(scope: &mut v8::HandleScope, promise_id: i32) {
   context_state.borrow_mut().unrefed_ops.insert(promise_id);
 }
 
+/// Queues a timer expiring after `timeout` milliseconds, from a task with
+/// the given timer nesting level.
+#[op2(core)]
+pub fn op_timer_queue(
+  scope: &mut v8::HandleScope,
+  nesting_level: u32,
+  timeout: u32,
+  repeat: bool,
+) -> u32 {
+  let clock = JsRuntime::state_from(scope).borrow().virtual_clock.clone();
+  let context_state = JsRealm::state_from_scope(scope);
+  let timeout = Duration::from_millis(timeout as u64);
+  let mut context_state = context_state.borrow_mut();
+  context_state
+    .timers
+    .queue(clock.as_ref(), timeout, repeat, nesting_level)
+}
+
+#[op2(core)]
+pub fn op_timer_cancel(scope: &mut v8::HandleScope, id: u32) {
+  let context_state = JsRealm::state_from_scope(scope);
+  context_state.borrow_mut().timers.cancel(id);
+}
+
+#[op2(core)]
+pub fn op_timer_ref(scope: &mut v8::HandleScope, id: u32) {
+  let context_state = JsRealm::state_from_scope(scope);
+  context_state.borrow_mut().timers.ref_timer(id);
+}
+
+#[op2(core)]
+pub fn op_timer_unref(scope: &mut v8::HandleScope, id: u32) {
+  let context_state = JsRealm::state_from_scope(scope);
+  context_state.borrow_mut().timers.unref_timer(id);
+}
+
+fn virtual_clock(scope: &mut v8::HandleScope) -> Result<VirtualClock, Error> {
+  JsRuntime::state_from(scope)
+    .borrow()
+    .virtual_clock
+    .clone()
+    .ok_or_else(|| generic_error("The runtime has no virtual clock"))
+}
+
+/// Backs `Date.now()` with a virtual clock.
+#[op2(core)]
+pub fn op_virtual_clock_date_now(
+  scope: &mut v8::HandleScope,
+) -> Result<f64, Error> {
+  let since_epoch = virtual_clock(scope)?
+    .now()
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .unwrap_or_default();
+  Ok(since_epoch.as_millis() as f64)
+}
+
+/// Backs `performance.now()` with a virtual clock.
+#[op2(core)]
+pub fn op_virtual_clock_now(scope: &mut v8::HandleScope) -> Result<f64, Error> {
+  Ok(virtual_clock(scope)?.elapsed().as_secs_f64() * 1000.0)
+}
+
 #[op2(core)]
 pub fn op_set_promise_reject_callback<'a>(
   scope: &mut v8::HandleScope<'a>,
@@ -1,18 +1,28 @@
 // Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
 
 use log::debug;
+use std::cell::RefCell;
 use std::fmt::Write;
 use std::option::Option;
 use std::os::raw::c_void;
+use std::rc::Rc;
 use v8::MapFnTo;
 
 use crate::error::is_instance_of_error;
 use crate::error::throw_type_error;
+use crate::error::to_v8_type_error;
 use crate::error::JsStackFrame;
+use crate::modules::commonjs_require;
 use crate::modules::get_asserted_module_type_from_assertions;
 use crate::modules::parse_import_assertions;
+use crate::modules::synthetic_module_evaluation_steps;
 use crate::modules::validate_import_assertions;
 use crate::modules::ImportAssertionsKind;
+use crate::modules::ImportMeta;
+use crate::modules::ImportMetaModuleInfo;
+use crate::modules::ImportMetaProperty;
+use crate::modules::LazyImportMetaCb;
+use crate::modules::ModuleId;
 use crate::modules::ModuleMap;
 use crate::modules::ResolutionKind;
 use crate::ops::OpCtx;
@@ -26,7 +36,7 @@ pub(crate) fn external_references(
 ) -> v8::ExternalReferences {
   // Overallocate a bit, it's better than having to resize the vector.
   let mut references =
-    Vec::with_capacity(4 + (ops.len() * 4) + additional_references.len());
+    Vec::with_capacity(9 + (ops.len() * 4) + additional_references.len());
 
   references.push(v8::ExternalReference {
     function: call_console.map_fn_to(),
@@ -34,12 +44,31 @@ pub(crate) fn external_references(
   references.push(v8::ExternalReference {
     function: import_meta_resolve.map_fn_to(),
   });
+  references.push(v8::ExternalReference {
+    function: import_meta_hot_accept.map_fn_to(),
+  });
+  references.push(v8::ExternalReference {
+    function: import_meta_hot_dispose.map_fn_to(),
+  });
   references.push(v8::ExternalReference {
     function: catch_dynamic_import_promise_error.map_fn_to(),
   });
   references.push(v8::ExternalReference {
     function: empty_fn.map_fn_to(),
   });
+  references.push(v8::ExternalReference {
+    function: commonjs_require.map_fn_to(),
+  });
+  references.push(v8::ExternalReference {
+    named_getter: import_meta_lazy_getter.map_fn_to(),
+  });
+  // Referenced by synthetic modules that weren't evaluated when a snapshot
+  // was taken.
+  let synthetic_module_evaluation_steps: v8::SyntheticModuleEvaluationSteps =
+    synthetic_module_evaluation_steps.map_fn_to();
+  references.push(v8::ExternalReference {
+    pointer: synthetic_module_evaluation_steps as *mut c_void,
+  });
 
   for ctx in ops {
     let ctx_ptr = ctx as *const OpCtx as _;
@@ -307,9 +336,11 @@ pub extern "C" fn host_initialize_import_meta_object_callback(
   let module_map = module_map_rc.borrow();
 
   let module_global = v8::Global::new(scope, module);
-  let info = module_map
-    .get_info(&module_global)
-    .expect("Module not found");
+  // Code of an unloaded module can still run, eg. through a function it
+  // exported, but its `import.meta` is left empty then.
+  let Some(info) = module_map.get_info(&module_global) else {
+    return;
+  };
 
   let url_key = v8::String::new_external_onebyte_static(scope, b"url").unwrap();
   let url_val = info.name.v8(scope);
@@ -326,6 +357,234 @@ pub extern "C" fn host_initialize_import_meta_object_callback(
   let resolve_key =
     v8::String::new_external_onebyte_static(scope, b"resolve").unwrap();
   meta.set(scope, resolve_key.into(), val.into());
+
+  // The JS module generated for a Wasm module instantiates the compiled
+  // `WebAssembly.Module` it gets from here.
+  if let Some(wasm_module) = module_map.wasm_module_store.get(&info.id) {
+    let wasm_module_key =
+      v8::String::new_external_onebyte_static(scope, b"wasmModule").unwrap();
+    let wasm_module = v8::Local::new(scope, wasm_module);
+    meta.create_data_property(
+      scope,
+      wasm_module_key.into(),
+      wasm_module.into(),
+    );
+  }
+
+  let id = info.id;
+  let name = info.name.as_str().to_owned();
+  drop(module_map);
+  let (hot_module_replacement, maybe_import_meta_callback) = {
+    let state = JsRuntime::state_from(scope);
+    let state = state.borrow();
+    (
+      state.hot_module_replacement,
+      state.import_meta_callback.clone(),
+    )
+  };
+  if hot_module_replacement {
+    let hot_key =
+      v8::String::new_external_onebyte_static(scope, b"hot").unwrap();
+    let hot = import_meta_hot(scope, &module_map_rc, id, name);
+    meta.create_data_property(scope, hot_key.into(), hot.into());
+  }
+
+  let Some(import_meta_callback) = maybe_import_meta_callback else {
+    return;
+  };
+  let info = import_meta_module_info(&module_map_rc.borrow(), id);
+  let mut import_meta = ImportMeta::default();
+  import_meta_callback(scope, &info, &mut import_meta);
+  for (name, property) in import_meta.properties {
+    let key = v8::String::new(scope, &name).unwrap();
+    match property {
+      ImportMetaProperty::Value(value) => {
+        let value = v8::Local::new(scope, value);
+        meta.create_data_property(scope, key.into(), value);
+      }
+      ImportMetaProperty::Lazy(get) => {
+        module_map_rc
+          .borrow_mut()
+          .import_meta_lazy
+          .insert((id, name), get);
+        // Snapshots keep the module id, so that the property can be computed
+        // once the runtime is restored.
+        let data = v8::Integer::new(scope, id as i32);
+        let configuration =
+          v8::AccessorConfiguration::new(import_meta_lazy_getter)
+            .data(data.into());
+        meta.set_accessor_with_configuration(scope, key.into(), configuration);
+      }
+    }
+  }
+}
+
+fn import_meta_module_info(
+  module_map: &ModuleMap,
+  id: ModuleId,
+) -> ImportMetaModuleInfo {
+  let info = &module_map.info[id];
+  ImportMetaModuleInfo {
+    id,
+    specifier: info.name.as_str().to_string(),
+    main: info.main,
+    module_type: info.module_type.clone(),
+  }
+}
+
+/// Computes a lazy property added by the `import.meta` callback, and replaces
+/// it with its value.
+fn import_meta_lazy_getter(
+  scope: &mut v8::HandleScope,
+  key: v8::Local<v8::Name>,
+  args: v8::PropertyCallbackArguments,
+  mut rv: v8::ReturnValue,
+) {
+  let id = args.data().integer_value(scope).unwrap() as ModuleId;
+  let name = key.to_rust_string_lossy(scope);
+  let module_map_rc = JsRealm::module_map_from(scope);
+  let maybe_get = module_map_rc
+    .borrow_mut()
+    .import_meta_lazy
+    .remove(&(id, name.clone()));
+  let maybe_get = maybe_get.or_else(|| {
+    import_meta_lazy_property_from_callback(scope, &module_map_rc, id, &name)
+  });
+  let Some(get) = maybe_get else {
+    return;
+  };
+
+  match get(scope) {
+    Ok(value) => {
+      let value = v8::Local::new(scope, value);
+      let meta = args.holder();
+      meta.delete(scope, key.into());
+      meta.create_data_property(scope, key, value);
+      rv.set(value);
+    }
+    Err(err) => {
+      let exception = to_v8_type_error(scope, err);
+      let exception = v8::Local::new(scope, exception);
+      scope.throw_exception(exception);
+    }
+  }
+}
+
+/// Gets a lazy `import.meta` property from the `import.meta` callback again,
+/// when it isn't pending, eg. because the module was restored from a snapshot
+/// or because computing it failed before.
+fn import_meta_lazy_property_from_callback(
+  scope: &mut v8::HandleScope,
+  module_map_rc: &Rc<RefCell<ModuleMap>>,
+  id: ModuleId,
+  name: &str,
+) -> Option<LazyImportMetaCb> {
+  let import_meta_callback = JsRuntime::state_from(scope)
+    .borrow()
+    .import_meta_callback
+    .clone()?;
+  // The module may have been unloaded since.
+  module_map_rc.borrow().get_info_by_id(id)?;
+  let info = import_meta_module_info(&module_map_rc.borrow(), id);
+  let mut import_meta = ImportMeta::default();
+  import_meta_callback(scope, &info, &mut import_meta);
+  import_meta.properties.into_iter().rev().find_map(
+    |(property_name, property)| match property {
+      ImportMetaProperty::Lazy(get) if property_name == name => Some(get),
+      _ => None,
+    },
+  )
+}
+
+/// Creates the `import.meta.hot` object of a module instance.
+fn import_meta_hot<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  module_map_rc: &Rc<RefCell<ModuleMap>>,
+  id: ModuleId,
+  name: String,
+) -> v8::Local<'s, v8::Object> {
+  let hot = v8::Object::new(scope);
+
+  // The data object is shared by all the instances of the module, and is
+  // passed to the dispose callbacks before the module is replaced.
+  let maybe_data = module_map_rc
+    .borrow()
+    .hot_data
+    .get(name.as_str())
+    .map(|data| v8::Local::new(scope, data));
+  let data = match maybe_data {
+    Some(data) => data,
+    None => {
+      let data = v8::Object::new(scope);
+      let data_global = v8::Global::new(scope, data);
+      module_map_rc
+        .borrow_mut()
+        .hot_data
+        .insert(name.into(), data_global);
+      data
+    }
+  };
+  let data_key =
+    v8::String::new_external_onebyte_static(scope, b"data").unwrap();
+  hot.create_data_property(scope, data_key.into(), data.into());
+
+  let id_val = v8::Number::new(scope, id as f64);
+  let builder =
+    v8::FunctionBuilder::new(import_meta_hot_accept).data(id_val.into());
+  let val = v8::FunctionBuilder::<v8::Function>::build(builder, scope).unwrap();
+  let accept_key =
+    v8::String::new_external_onebyte_static(scope, b"accept").unwrap();
+  hot.set(scope, accept_key.into(), val.into());
+
+  let builder =
+    v8::FunctionBuilder::new(import_meta_hot_dispose).data(id_val.into());
+  let val = v8::FunctionBuilder::<v8::Function>::build(builder, scope).unwrap();
+  let dispose_key =
+    v8::String::new_external_onebyte_static(scope, b"dispose").unwrap();
+  hot.set(scope, dispose_key.into(), val.into());
+
+  hot
+}
+
+fn import_meta_hot_accept(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _rv: v8::ReturnValue,
+) {
+  import_meta_hot_register(scope, args, true)
+}
+
+fn import_meta_hot_dispose(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _rv: v8::ReturnValue,
+) {
+  import_meta_hot_register(scope, args, false)
+}
+
+fn import_meta_hot_register(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  accept: bool,
+) {
+  // `import.meta.hot.accept()` without a callback only marks the module as
+  // able to be replaced, which all modules are.
+  if accept && args.length() == 0 {
+    return;
+  }
+  let Ok(callback) = v8::Local::<v8::Function>::try_from(args.get(0)) else {
+    return throw_type_error(scope, "Callback must be a function");
+  };
+  let id = args.data().integer_value(scope).unwrap() as ModuleId;
+  let callback = v8::Global::new(scope, callback);
+  let module_map_rc = JsRealm::module_map_from(scope);
+  let mut module_map = module_map_rc.borrow_mut();
+  let callbacks = module_map.hot_callbacks.entry(id).or_default();
+  if accept {
+    callbacks.accept.push(callback);
+  } else {
+    callbacks.dispose.push(callback);
+  }
 }
 
 fn import_meta_resolve(
@@ -0,0 +1,260 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::error::ExecutionTimeout;
+use crate::error::ExecutionTimeoutKind;
+use anyhow::Error;
+
+#[derive(Default)]
+struct WatchdogState {
+  // When the current run exceeds `max_run_time`.
+  run_deadline: Option<Instant>,
+  // When the current run would exceed `max_cpu_time` if it kept the CPU busy,
+  // and the CPU time of the runtime's thread at which it does.
+  cpu_deadline: Option<(Instant, Duration)>,
+  timed_out: Option<ExecutionTimeout>,
+  shutdown: bool,
+}
+
+#[derive(Default)]
+struct Watchdog {
+  state: Mutex<WatchdogState>,
+  condvar: Condvar,
+}
+
+/// Enforces [`crate::RuntimeOptions::max_run_time`] and
+/// [`crate::RuntimeOptions::max_cpu_time`], with a thread terminating the
+/// execution of runs that go past their deadline.
+///
+/// A run is a synchronous stretch of JavaScript execution: an
+/// `execute_script()` call, the evaluation of a module, or a single poll of the
+/// event loop.
+///
+/// The CPU time of a run is the CPU time its thread spent meanwhile, or its
+/// wall time on platforms without per-thread CPU clocks.
+pub(crate) struct ExecutionBudget {
+  max_run_time: Option<Duration>,
+  max_cpu_time: Option<Duration>,
+  cpu_time: Cell<Duration>,
+  // The start of the current run, in wall and CPU time, and how many runs
+  // it's nested in.
+  run: Cell<Option<(Instant, Duration, usize)>>,
+  watchdog: Arc<Watchdog>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl ExecutionBudget {
+  pub fn new(
+    isolate_handle: v8::IsolateHandle,
+    max_run_time: Option<Duration>,
+    max_cpu_time: Option<Duration>,
+  ) -> Self {
+    let watchdog = Arc::new(Watchdog::default());
+    let cpu_clock = ThreadCpuClock::current();
+    let thread = std::thread::spawn({
+      let watchdog = watchdog.clone();
+      move || {
+        let mut state = watchdog.state.lock().unwrap();
+        while !state.shutdown {
+          let deadline = state
+            .run_deadline
+            .into_iter()
+            .chain(state.cpu_deadline.map(|(deadline, _)| deadline))
+            .min();
+          let Some(deadline) = deadline else {
+            state = watchdog.condvar.wait(state).unwrap();
+            continue;
+          };
+          let now = Instant::now();
+          if now < deadline {
+            state = watchdog
+              .condvar
+              .wait_timeout(state, deadline - now)
+              .unwrap()
+              .0;
+            continue;
+          }
+
+          let kind = if state.run_deadline.map_or(false, |d| d <= now) {
+            ExecutionTimeoutKind::RunTime
+          } else {
+            let (_, cpu_limit) = state.cpu_deadline.unwrap();
+            // The run may have spent less CPU time than wall time, so check
+            // again once it could have spent the rest.
+            if let Some(cpu_time) = cpu_clock.and_then(ThreadCpuClock::now) {
+              if cpu_time < cpu_limit {
+                state.cpu_deadline =
+                  Some((now + (cpu_limit - cpu_time), cpu_limit));
+                continue;
+              }
+            }
+            ExecutionTimeoutKind::CpuTime
+          };
+          let limit = match kind {
+            ExecutionTimeoutKind::RunTime => max_run_time,
+            ExecutionTimeoutKind::CpuTime => max_cpu_time,
+          };
+          state.run_deadline = None;
+          state.cpu_deadline = None;
+          state.timed_out = Some(ExecutionTimeout {
+            kind,
+            limit: limit.unwrap(),
+          });
+          isolate_handle.terminate_execution();
+        }
+      }
+    });
+    Self {
+      max_run_time,
+      max_cpu_time,
+      cpu_time: Cell::new(Duration::ZERO),
+      run: Cell::new(None),
+      watchdog,
+      thread: Some(thread),
+    }
+  }
+
+  /// Gives back the whole CPU time budget.
+  pub fn reset(&self) {
+    self.cpu_time.set(Duration::ZERO);
+  }
+
+  /// Starts a run, unless the CPU time budget is spent. Nested runs count as
+  /// part of the outermost one.
+  pub fn enter(&self) -> Result<(), Error> {
+    if let Some((start, cpu_start, depth)) = self.run.get() {
+      self.run.set(Some((start, cpu_start, depth + 1)));
+      return Ok(());
+    }
+
+    let start = Instant::now();
+    let cpu_start = thread_cpu_time().unwrap_or_default();
+    let run_deadline = self.max_run_time.map(|limit| start + limit);
+    let mut cpu_deadline = None;
+    if let Some(limit) = self.max_cpu_time {
+      let remaining = limit.saturating_sub(self.cpu_time.get());
+      if remaining.is_zero() {
+        return Err(
+          ExecutionTimeout {
+            kind: ExecutionTimeoutKind::CpuTime,
+            limit,
+          }
+          .into(),
+        );
+      }
+      cpu_deadline = Some((start + remaining, cpu_start + remaining));
+    }
+
+    self.run.set(Some((start, cpu_start, 0)));
+    {
+      let mut state = self.watchdog.state.lock().unwrap();
+      state.run_deadline = run_deadline;
+      state.cpu_deadline = cpu_deadline;
+    }
+    self.watchdog.condvar.notify_one();
+    Ok(())
+  }
+
+  /// Ends a run, returning the limit it exceeded if the watchdog terminated
+  /// it. The termination is then cancelled, so that the isolate can run
+  /// JavaScript again.
+  pub fn exit(&self, isolate: &mut v8::Isolate) -> Option<ExecutionTimeout> {
+    let (start, cpu_start, depth) = self.run.get().expect("Not in a run");
+    if depth > 0 {
+      self.run.set(Some((start, cpu_start, depth - 1)));
+      return None;
+    }
+    self.run.set(None);
+
+    let timed_out = {
+      let mut state = self.watchdog.state.lock().unwrap();
+      state.run_deadline = None;
+      state.cpu_deadline = None;
+      state.timed_out.take()
+    };
+    let spent = thread_cpu_time()
+      .map_or_else(|| start.elapsed(), |now| now.saturating_sub(cpu_start));
+    self.cpu_time.set(self.cpu_time.get() + spent);
+    if timed_out.is_some() {
+      isolate.cancel_terminate_execution();
+    }
+    timed_out
+  }
+}
+
+impl Drop for ExecutionBudget {
+  fn drop(&mut self) {
+    self.watchdog.state.lock().unwrap().shutdown = true;
+    self.watchdog.condvar.notify_one();
+    if let Some(thread) = self.thread.take() {
+      thread.join().unwrap();
+    }
+  }
+}
+
+/// The CPU time of the current thread, where it can be measured.
+fn thread_cpu_time() -> Option<Duration> {
+  #[cfg(unix)]
+  {
+    clock_time(libc::CLOCK_THREAD_CPUTIME_ID)
+  }
+  #[cfg(not(unix))]
+  {
+    None
+  }
+}
+
+#[cfg(unix)]
+fn clock_time(clock: libc::clockid_t) -> Option<Duration> {
+  let mut time = libc::timespec {
+    tv_sec: 0,
+    tv_nsec: 0,
+  };
+  // SAFETY: `time` is a valid pointer to a `timespec`.
+  if unsafe { libc::clock_gettime(clock, &mut time) } != 0 {
+    return None;
+  }
+  Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+/// The CPU time clock of a thread, which other threads can read.
+#[derive(Clone, Copy)]
+struct ThreadCpuClock(
+  #[cfg(any(target_os = "linux", target_os = "android"))] libc::clockid_t,
+);
+
+impl ThreadCpuClock {
+  /// The clock of the current thread, on platforms that expose it.
+  fn current() -> Option<Self> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+      let mut clock = 0;
+      // SAFETY: `clock` is a valid pointer to a `clockid_t`.
+      let result = unsafe {
+        libc::pthread_getcpuclockid(libc::pthread_self(), &mut clock)
+      };
+      (result == 0).then_some(Self(clock))
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+      None
+    }
+  }
+
+  fn now(self) -> Option<Duration> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+      clock_time(self.0)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+      None
+    }
+  }
+}
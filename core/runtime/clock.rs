@@ -0,0 +1,101 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::Waker;
+use std::time::Duration;
+use std::time::SystemTime;
+use tokio::time::Instant;
+
+struct VirtualClockState {
+  start: SystemTime,
+  origin: Instant,
+  elapsed: Duration,
+  // Woken when the clock moves forward, so expired timers get to run.
+  waker: Option<Waker>,
+}
+
+/// A clock that only moves forward when told to, replacing wall-clock time
+/// for a runtime created with [`crate::RuntimeOptions::virtual_clock`].
+///
+/// It drives the runtime's timers, `Date.now()`, `new Date()` and
+/// `performance.now()`. Advance it with [`VirtualClock::advance`], or let the
+/// runtime do so with [`crate::JsRuntime::advance_time`] and
+/// [`crate::JsRuntime::run_until_idle`]. Together with V8's `--predictable`
+/// flag, which makes `Math.random()` deterministic, this gives reproducible
+/// runs. Note that primordials in a startup snapshot created without a virtual
+/// clock still read the system time.
+///
+/// Clones share the same time.
+#[derive(Clone)]
+pub struct VirtualClock(Rc<RefCell<VirtualClockState>>);
+
+impl VirtualClock {
+  /// Creates a clock whose `Date.now()` starts at `start`.
+  pub fn new(start: SystemTime) -> Self {
+    Self(Rc::new(RefCell::new(VirtualClockState {
+      start,
+      origin: Instant::now(),
+      elapsed: Duration::ZERO,
+      waker: None,
+    })))
+  }
+
+  /// The time the clock has moved forward since it was created, which is what
+  /// `performance.now()` returns.
+  pub fn elapsed(&self) -> Duration {
+    self.0.borrow().elapsed
+  }
+
+  /// The current time of the clock, which is what `Date.now()` returns.
+  pub fn now(&self) -> SystemTime {
+    let state = self.0.borrow();
+    state.start + state.elapsed
+  }
+
+  /// Moves the clock forward. Timers expiring in the meantime run the next
+  /// time the event loop is polled, all at once.
+  pub fn advance(&self, duration: Duration) {
+    let instant = self.instant() + duration;
+    self.advance_to(instant);
+  }
+
+  /// The current time of the clock, on the timeline of the runtime's timers.
+  pub(crate) fn instant(&self) -> Instant {
+    let state = self.0.borrow();
+    state.origin + state.elapsed
+  }
+
+  /// Moves the clock forward to `instant`, if it's later than the current
+  /// time.
+  pub(crate) fn advance_to(&self, instant: Instant) {
+    let waker = {
+      let mut state = self.0.borrow_mut();
+      if instant <= state.origin + state.elapsed {
+        return;
+      }
+      state.elapsed = instant - state.origin;
+      state.waker.clone()
+    };
+    if let Some(waker) = waker {
+      waker.wake();
+    }
+  }
+
+  /// Moves the clock back to its start time.
+  pub(crate) fn reset(&self) {
+    let mut state = self.0.borrow_mut();
+    state.origin = Instant::now();
+    state.elapsed = Duration::ZERO;
+  }
+
+  pub(crate) fn register_waker(&self, waker: &Waker) {
+    let mut state = self.0.borrow_mut();
+    if !state
+      .waker
+      .as_ref()
+      .map_or(false, |current| current.will_wake(waker))
+    {
+      state.waker = Some(waker.clone());
+    }
+  }
+}
@@ -0,0 +1,117 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+use crate::error::HeapLimitExceeded;
+use anyhow::Error;
+
+/// What happens to a runtime after its isolate reached the heap limit, see
+/// [`crate::RuntimeOptions::heap_limit_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapLimitPolicy {
+  /// Every later run of JavaScript fails with the same
+  /// [`HeapLimitExceeded`] error. Use this when the state of the runtime
+  /// can't be trusted after an execution was cut short.
+  Poison,
+  /// A full garbage collection runs and the heap limit goes back to its
+  /// initial value, after which the runtime can be used again.
+  Recover,
+}
+
+/// Implements a [`HeapLimitPolicy`] with a near heap limit callback, which
+/// terminates the execution and raises the limit just enough for it to unwind.
+pub(crate) struct HeapLimit {
+  policy: HeapLimitPolicy,
+  // The isolate the callback is registered on, which outlives it.
+  isolate: *mut v8::Isolate,
+  initial_limit: Cell<usize>,
+  exceeded: RefCell<Option<HeapLimitExceeded>>,
+  poisoned: RefCell<Option<HeapLimitExceeded>>,
+}
+
+impl HeapLimit {
+  pub fn install(
+    isolate: &mut v8::Isolate,
+    policy: HeapLimitPolicy,
+  ) -> Rc<Self> {
+    let heap_limit = Rc::new(Self {
+      policy,
+      isolate,
+      initial_limit: Cell::new(0),
+      exceeded: RefCell::new(None),
+      poisoned: RefCell::new(None),
+    });
+    isolate.add_near_heap_limit_callback(
+      near_heap_limit_callback,
+      Rc::as_ptr(&heap_limit) as *mut c_void,
+    );
+    heap_limit
+  }
+
+  /// Fails if the runtime was poisoned.
+  pub fn check(&self) -> Result<(), Error> {
+    match &*self.poisoned.borrow() {
+      Some(exceeded) => Err(exceeded.clone().into()),
+      None => Ok(()),
+    }
+  }
+
+  /// Ends a run, returning the error it failed with if it reached the heap
+  /// limit. The termination is then cancelled, and the policy applied.
+  pub fn exit(&self, isolate: &mut v8::Isolate) -> Option<HeapLimitExceeded> {
+    let exceeded = self.exceeded.borrow_mut().take()?;
+    isolate.cancel_terminate_execution();
+    match self.policy {
+      HeapLimitPolicy::Poison => {
+        *self.poisoned.borrow_mut() = Some(exceeded.clone());
+      }
+      HeapLimitPolicy::Recover => {
+        isolate.low_memory_notification();
+        // Removing the callback is the only way to restore the limit. V8 only
+        // calls the most recently added callback, and this one was called, so
+        // re-adding it doesn't put it in front of a callback registered with
+        // `JsRuntime::add_near_heap_limit_callback()`.
+        let data = self as *const Self as *mut c_void;
+        isolate.remove_near_heap_limit_callback(
+          near_heap_limit_callback,
+          self.initial_limit.get(),
+        );
+        isolate.add_near_heap_limit_callback(near_heap_limit_callback, data);
+      }
+    }
+    Some(exceeded)
+  }
+}
+
+extern "C" fn near_heap_limit_callback(
+  data: *mut c_void,
+  current_heap_limit: usize,
+  initial_heap_limit: usize,
+) -> usize {
+  // SAFETY: The data is a pointer to a `HeapLimit`, kept in
+  // `JsRuntime::allocations` so that it outlives the isolate, and the
+  // callback is called on the thread owning the isolate.
+  let heap_limit = unsafe { &*(data as *const HeapLimit) };
+  // SAFETY: The isolate is alive as long as its callbacks may be called.
+  let isolate = unsafe { &mut *heap_limit.isolate };
+
+  let mut exceeded = heap_limit.exceeded.borrow_mut();
+  if exceeded.is_none() {
+    let mut stats = v8::HeapStatistics::default();
+    isolate.get_heap_statistics(&mut stats);
+    *exceeded = Some(HeapLimitExceeded {
+      heap_size_limit: current_heap_limit,
+      total_heap_size: stats.total_heap_size(),
+      used_heap_size: stats.used_heap_size(),
+      external_memory: stats.external_memory(),
+      poisoned: heap_limit.policy == HeapLimitPolicy::Poison,
+    });
+    heap_limit.initial_limit.set(initial_heap_limit);
+    isolate.terminate_execution();
+  }
+  // Leave room for the terminated execution to unwind, and raise the limit
+  // again if that wasn't enough.
+  current_heap_limit + initial_heap_limit / 4
+}
@@ -5,6 +5,7 @@ use crate::joinset::JoinSet;
 use crate::modules::ModuleCode;
 use crate::modules::ModuleMap;
 use crate::ops::OpCtx;
+use crate::runtime::timers::Timers;
 use crate::runtime::JsRuntimeState;
 use crate::JsRuntime;
 use crate::OpId;
@@ -51,6 +52,7 @@ pub(crate) struct ContextState {
     VecDeque<(v8::Global<v8::Promise>, v8::Global<v8::Value>)>,
   pub(crate) unrefed_ops: HashSet<i32, BuildHasherDefault<IdentityHasher>>,
   pub(crate) pending_ops: JoinSet<(PromiseId, OpId, OpResult)>,
+  pub(crate) timers: Timers,
   // We don't explicitly re-read this prop but need the slice to live alongside
   // the context
   pub(crate) op_ctxs: Box<[OpCtx]>,
@@ -140,6 +142,14 @@ impl JsRealmInner {
     self.context_state.borrow().unrefed_ops.len()
   }
 
+  pub(crate) fn has_pending_refed_timers(&self) -> bool {
+    self.context_state.borrow().timers.has_pending_refed()
+  }
+
+  pub(crate) fn next_timer_deadline(&self) -> Option<tokio::time::Instant> {
+    self.context_state.borrow().timers.next_deadline()
+  }
+
   #[inline(always)]
   pub fn context(&self) -> &v8::Global<v8::Context> {
     &self.context
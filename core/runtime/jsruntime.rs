@@ -1,13 +1,19 @@
 // Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
 
 use super::bindings;
+use super::budget::ExecutionBudget;
+use super::heap_limit::HeapLimit;
 use super::jsrealm::JsRealmInner;
 use super::snapshot_util;
+use super::HeapLimitPolicy;
+use super::VirtualClock;
 use crate::error::exception_to_err_result;
 use crate::error::generic_error;
 use crate::error::to_v8_type_error;
 use crate::error::GetErrorClassFn;
 use crate::error::JsError;
+use crate::error::StalledTopLevelAwait;
+use crate::error::StalledTopLevelAwaitError;
 use crate::extensions::EventLoopMiddlewareFn;
 use crate::extensions::GlobalObjectMiddlewareFn;
 use crate::extensions::GlobalTemplateMiddlewareFn;
@@ -15,15 +21,28 @@ use crate::extensions::OpDecl;
 use crate::include_js_files;
 use crate::inspector::JsRuntimeInspector;
 use crate::module_specifier::ModuleSpecifier;
+use crate::modules::add_builtin_custom_module_types;
+use crate::modules::check_synthetic_modules;
+use crate::modules::get_asserted_module_type_from_assertions;
+use crate::modules::validate_import_assertions;
 use crate::modules::AssertedModuleType;
+use crate::modules::CustomModuleEvaluationCb;
+use crate::modules::CustomModuleTypes;
 use crate::modules::ExtModuleLoader;
 use crate::modules::ExtModuleLoaderCb;
+use crate::modules::ImportMetaCallback;
+use crate::modules::InvalidatedModule;
 use crate::modules::ModuleCode;
 use crate::modules::ModuleError;
+use crate::modules::ModuleGraph;
 use crate::modules::ModuleId;
 use crate::modules::ModuleLoadId;
+use crate::modules::ModuleLoadPhase;
+use crate::modules::ModuleLoadTraceCallback;
+use crate::modules::ModuleLoadTracer;
 use crate::modules::ModuleLoader;
 use crate::modules::ModuleMap;
+use crate::modules::SyntheticModule;
 use crate::ops::*;
 use crate::runtime::ContextState;
 use crate::runtime::JsRealm;
@@ -35,6 +54,7 @@ use crate::NoopModuleLoader;
 use crate::OpMiddlewareFn;
 use crate::OpResult;
 use crate::OpState;
+use crate::OpsTracker;
 use crate::V8_WRAPPER_OBJECT_INDEX;
 use crate::V8_WRAPPER_TYPE_INDEX;
 use anyhow::Context as AnyhowContext;
@@ -42,6 +62,7 @@ use anyhow::Error;
 use futures::channel::oneshot;
 use futures::future::poll_fn;
 use futures::stream::StreamExt;
+use futures::task::ArcWake;
 use once_cell::sync::Lazy;
 use smallvec::SmallVec;
 use std::any::Any;
@@ -60,6 +81,9 @@ use std::sync::Mutex;
 use std::sync::Once;
 use std::task::Context;
 use std::task::Poll;
+use std::task::Waker;
+use std::time::Duration;
+use std::time::Instant;
 
 const STATE_DATA_OFFSET: u32 = 0;
 
@@ -74,6 +98,7 @@ pub enum Snapshot {
 pub(crate) struct IsolateAllocations {
   pub(crate) near_heap_limit_callback_data:
     Option<(Box<RefCell<dyn Any>>, v8::NearHeapLimitCallback)>,
+  pub(crate) heap_limit: Option<Rc<HeapLimit>>,
 }
 
 /// ManuallyDrop<Rc<...>> is clone, but it returns a ManuallyDrop<Rc<...>> which is a massive
@@ -205,6 +230,10 @@ pub(crate) static BUILTIN_SOURCES: Lazy<Vec<ExtensionFileSource>> =
     )
   });
 
+/// Makes `Date` and `performance.now()` read the virtual clock.
+pub(crate) static VIRTUAL_CLOCK_SOURCE: Lazy<ExtensionFileSource> =
+  Lazy::new(|| include_js_files!(core "virtual_clock.js",).remove(0));
+
 /// A single execution context of JavaScript. Corresponds roughly to the "Web
 /// Worker" concept in the DOM.
 ////
@@ -216,6 +245,9 @@ pub struct JsRuntime {
   pub(crate) inner: InnerIsolateState,
   pub(crate) allocations: IsolateAllocations,
   extensions: Vec<Extension>,
+  // Applied to the module map of the main realm whenever it's created.
+  preserve_snapshotted_modules: Option<&'static [&'static str]>,
+  synthetic_modules: Vec<SyntheticModule>,
   event_loop_middlewares: Vec<Box<EventLoopMiddlewareFn>>,
   global_template_middlewares: Vec<Box<GlobalTemplateMiddlewareFn>>,
   global_object_middlewares: Vec<Box<GlobalObjectMiddlewareFn>>,
@@ -249,6 +281,7 @@ pub(crate) struct DynImportModEvaluate {
 }
 
 pub(crate) struct ModEvaluate {
+  module_id: ModuleId,
   pub(crate) promise: Option<v8::Global<v8::Promise>>,
   pub(crate) has_evaluated: bool,
   pub(crate) handled_promise_rejections: Vec<v8::Global<v8::Promise>>,
@@ -259,6 +292,13 @@ pub struct CrossIsolateStore<T>(Arc<Mutex<CrossIsolateStoreInner<T>>>);
 
 struct CrossIsolateStoreInner<T> {
   map: HashMap<u32, T>,
+  /// Values that are kept around to be shared by name, rather than taken
+  /// once by id.
+  named: HashMap<String, T>,
+  /// How many values were stored by name, to tell whether a value was shared
+  /// or created again.
+  #[cfg(test)]
+  named_inserts: usize,
   last_id: u32,
 }
 
@@ -275,12 +315,47 @@ impl<T> CrossIsolateStore<T> {
     let mut store = self.0.lock().unwrap();
     store.map.remove(&id)
   }
+
+  /// Stores a value under `name`, replacing any previous value with that name.
+  pub(crate) fn insert_named(&self, name: String, value: T) {
+    let mut store = self.0.lock().unwrap();
+    store.named.insert(name, value);
+    #[cfg(test)]
+    {
+      store.named_inserts += 1;
+    }
+  }
+
+  /// Removes the value stored under `name`, if any.
+  pub(crate) fn remove_named(&self, name: &str) -> Option<T> {
+    let mut store = self.0.lock().unwrap();
+    store.named.remove(name)
+  }
+
+  /// Calls `f` with the value stored under `name`, if any. The value stays in
+  /// the store.
+  pub(crate) fn with_named<R>(
+    &self,
+    name: &str,
+    f: impl FnOnce(&T) -> R,
+  ) -> Option<R> {
+    let store = self.0.lock().unwrap();
+    store.named.get(name).map(f)
+  }
+
+  #[cfg(test)]
+  pub(crate) fn named_inserts(&self) -> usize {
+    self.0.lock().unwrap().named_inserts
+  }
 }
 
 impl<T> Default for CrossIsolateStore<T> {
   fn default() -> Self {
     CrossIsolateStore(Arc::new(Mutex::new(CrossIsolateStoreInner {
       map: Default::default(),
+      named: Default::default(),
+      #[cfg(test)]
+      named_inserts: 0,
       last_id: 0,
     })))
   }
@@ -313,6 +388,13 @@ pub struct JsRuntimeState {
   pub(crate) op_state: Rc<RefCell<OpState>>,
   pub(crate) shared_array_buffer_store: Option<SharedArrayBufferStore>,
   pub(crate) compiled_wasm_module_store: Option<CompiledWasmModuleStore>,
+  pub(crate) custom_module_types: Rc<CustomModuleTypes>,
+  pub(crate) hot_module_replacement: bool,
+  pub(crate) commonjs_modules: bool,
+  pub(crate) import_meta_callback: Option<Rc<ImportMetaCallback>>,
+  pub(crate) module_load_trace_callback: Option<Rc<ModuleLoadTraceCallback>>,
+  pub(crate) virtual_clock: Option<VirtualClock>,
+  execution_budget: Option<Rc<ExecutionBudget>>,
   /// The error that was passed to an `op_dispatch_exception` call.
   /// It will be retrieved by `exception_to_err_result` and used as an error
   /// instead of any other exceptions.
@@ -431,8 +513,94 @@ pub struct RuntimeOptions {
   /// `WebAssembly.Module` objects, they should use the same
   /// [CompiledWasmModuleStore]. If no [CompiledWasmModuleStore] is specified,
   /// `WebAssembly.Module` objects cannot be serialized.
+  ///
+  /// Wasm modules loaded through the module graph are also kept in this
+  /// store, keyed by their specifier, so that isolates sharing the store only
+  /// compile each Wasm module once.
   pub compiled_wasm_module_store: Option<CompiledWasmModuleStore>,
 
+  /// Custom module types, keyed by the value of the `type` import attribute
+  /// that requests them, eg. `import tmpl from "./a.html" with { type: "text" }`
+  /// or, with the legacy syntax, `assert { type: "text" }`. Both forms are
+  /// handled identically. The V8 parser only accepts `assert`, so static
+  /// imports and re-exports using `with` are rewritten to it before they are
+  /// compiled; `import()` only reads the `assert` key of its options.
+  ///
+  /// Modules imported with such a type are loaded through the module loader
+  /// as usual, then the callback builds the default export of a synthetic
+  /// module from their source. The `"text"` and `"bytes"` types are always
+  /// available, unless overridden here; `"json"` can't be overridden.
+  pub custom_module_types: HashMap<String, CustomModuleEvaluationCb>,
+
+  /// Expose `import.meta.hot` to modules, so that they can migrate their
+  /// state when they are replaced by [`JsRuntime::hot_reload_module`].
+  pub hot_module_replacement: bool,
+
+  /// Allow loading modules of type
+  /// [`ModuleType::CommonJs`](crate::ModuleType::CommonJs). Module loaders
+  /// returning such modules fail to load them otherwise.
+  ///
+  /// `require()` is synchronous, so it only returns modules that are loaded
+  /// already. The modules a CommonJS module requires with a string literal,
+  /// eg. `require("./a.js")`, are loaded along with it; requiring any other
+  /// specifier, eg. a computed one, throws unless the module was loaded
+  /// otherwise. Requiring an ES module that isn't evaluated yet throws as
+  /// well; use `import()` for those.
+  pub commonjs_modules: bool,
+
+  /// Adds properties to `import.meta` of modules, eg. `filename` or
+  /// capabilities granted to each module. It's also called for modules
+  /// restored from a snapshot.
+  pub import_meta_callback: Option<ImportMetaCallback>,
+
+  /// Receives an event with timings for each module resolution, load,
+  /// compilation, instantiation and evaluation, in every realm. See
+  /// [`ChromeTraceExporter`](crate::ChromeTraceExporter) to open them in a
+  /// trace viewer.
+  pub module_load_trace_callback: Option<ModuleLoadTraceCallback>,
+
+  /// Synthetic modules to register in the main realm, whose exports are
+  /// supplied by the embedder.
+  ///
+  /// When the runtime is created from a snapshot, the synthetic modules it
+  /// holds that weren't evaluated yet get their evaluation callback from the
+  /// module with the same specifier here.
+  ///
+  /// Creating the runtime panics if a specifier is given twice, or a module
+  /// has the same export name twice.
+  pub synthetic_modules: Vec<SyntheticModule>,
+
+  /// Replaces wall-clock time with a virtual clock for timers, `Date` and
+  /// `performance.now()`, in every realm. See [`VirtualClock`].
+  pub virtual_clock: Option<VirtualClock>,
+
+  /// The longest a single synchronous run of JavaScript may take: an
+  /// [`JsRuntime::execute_script`] call, a module evaluation started by
+  /// [`JsRuntime::mod_evaluate`], or one poll of the event loop. Execution is
+  /// terminated once it's exceeded, and the run fails with an
+  /// [`ExecutionTimeout`](crate::error::ExecutionTimeout) error.
+  pub max_run_time: Option<Duration>,
+
+  /// The total CPU time the runtime's thread may spend running JavaScript,
+  /// summed over all runs as above. Time the event loop spends waiting for ops
+  /// or timers doesn't count, and on Linux neither does time the thread spends
+  /// blocked during a run. Other platforms count a run's wall time against the
+  /// budget while it's running, and on Windows afterwards too. Once it's
+  /// spent, the run in progress and every later one fail with an
+  /// [`ExecutionTimeout`](crate::error::ExecutionTimeout) error.
+  pub max_cpu_time: Option<Duration>,
+
+  /// Terminates runs of JavaScript that reach the heap limit of the isolate,
+  /// set through [`RuntimeOptions::create_params`], instead of letting V8
+  /// crash the process. They fail with a
+  /// [`HeapLimitExceeded`](crate::error::HeapLimitExceeded) error, and the
+  /// policy decides whether the runtime can be used afterwards.
+  ///
+  /// A callback registered with [`JsRuntime::add_near_heap_limit_callback`]
+  /// takes precedence over the policy until it's removed, also after the
+  /// policy recovered the runtime.
+  pub heap_limit_policy: Option<HeapLimitPolicy>,
+
   /// Start inspector instance to allow debuggers to connect.
   pub inspector: bool,
 
@@ -546,6 +714,9 @@ impl JsRuntime {
     will_snapshot: bool,
     maybe_load_callback: Option<ExtModuleLoaderCb>,
   ) -> JsRuntime {
+    if let Err(err) = check_synthetic_modules(&options.synthetic_modules) {
+      panic!("Invalid synthetic modules in the runtime options: {err}");
+    }
     let init_mode = InitMode::from_options(&options);
     let (op_state, ops) = Self::create_opstate(&mut options);
     let op_state = Rc::new(RefCell::new(op_state));
@@ -586,6 +757,10 @@ impl JsRuntime {
       // SAFETY: we just asserted that layout has non-0 size.
       unsafe { std::alloc::alloc(layout) as *mut _ };
 
+    let mut custom_module_types =
+      std::mem::take(&mut options.custom_module_types);
+    add_builtin_custom_module_types(&mut custom_module_types);
+
     let state_rc = Rc::new(RefCell::new(JsRuntimeState {
       pending_dyn_mod_evaluate: vec![],
       pending_mod_evaluate: None,
@@ -595,6 +770,15 @@ impl JsRuntime {
       source_map_cache: Default::default(),
       shared_array_buffer_store: options.shared_array_buffer_store,
       compiled_wasm_module_store: options.compiled_wasm_module_store,
+      custom_module_types: Rc::new(custom_module_types),
+      hot_module_replacement: options.hot_module_replacement,
+      commonjs_modules: options.commonjs_modules,
+      import_meta_callback: options.import_meta_callback.map(Rc::new),
+      module_load_trace_callback: options
+        .module_load_trace_callback
+        .map(Rc::new),
+      virtual_clock: options.virtual_clock,
+      execution_budget: None,
       op_state: op_state.clone(),
       dispatched_exception: None,
       // Some fields are initialized later after isolate is created
@@ -665,84 +849,18 @@ impl JsRuntime {
       bindings::wasm_async_resolve_promise_callback,
     );
 
-    let (main_context, snapshotted_data) = {
-      let scope = &mut v8::HandleScope::new(&mut isolate);
-
-      let context = create_context(
-        scope,
-        &global_template_middlewares,
-        &global_object_middlewares,
-      );
-
-      // Get module map data from the snapshot
-      let snapshotted_data = if init_mode == InitMode::FromSnapshot {
-        Some(snapshot_util::get_snapshotted_data(scope, context))
-      } else {
-        None
-      };
-
-      (v8::Global::new(scope, context), snapshotted_data)
-    };
-
     // SAFETY: this is first use of `isolate_ptr` so we are sure we're
     // not overwriting an existing pointer.
     isolate = unsafe {
       isolate_ptr.write(isolate);
       isolate_ptr.read()
     };
-
-    let mut context_scope: v8::HandleScope =
-      v8::HandleScope::with_context(&mut isolate, main_context.clone());
-    let scope = &mut context_scope;
-    let context = v8::Local::new(scope, main_context.clone());
-
-    bindings::initialize_context(
-      scope,
-      context,
-      &context_state.borrow().op_ctxs,
-      init_mode,
-    );
-
-    context.set_slot(scope, context_state.clone());
-
     op_state.borrow_mut().put(isolate_ptr);
-    let inspector = if options.inspector {
-      Some(JsRuntimeInspector::new(scope, context, options.is_main))
-    } else {
-      None
-    };
-
-    let loader = options
-      .module_loader
-      .unwrap_or_else(|| Rc::new(NoopModuleLoader));
-
-    let module_map_rc = Rc::new(RefCell::new(ModuleMap::new(loader)));
-    if let Some(snapshotted_data) = snapshotted_data {
-      let mut module_map = module_map_rc.borrow_mut();
-      module_map.update_with_snapshotted_data(scope, snapshotted_data);
-    }
-    context.set_slot(scope, module_map_rc.clone());
-
-    {
-      let main_realm = JsRealmInner::new(
-        context_state,
-        main_context,
-        module_map_rc,
-        state_rc.clone(),
-        true,
-      );
-      let mut state = state_rc.borrow_mut();
-      state.main_realm = Some(JsRealm::new(main_realm.clone()));
-      state.inspector = inspector;
-      state.known_realms.push(main_realm);
-    }
-    scope.set_data(
+    isolate.set_data(
       STATE_DATA_OFFSET,
       Rc::into_raw(state_rc.clone()) as *mut c_void,
     );
 
-    drop(context_scope);
-
     let mut js_runtime = JsRuntime {
       inner: InnerIsolateState {
         will_snapshot,
@@ -755,23 +873,52 @@ impl JsRuntime {
       global_template_middlewares,
       global_object_middlewares,
       extensions: options.extensions,
+      preserve_snapshotted_modules: options.preserve_snapshotted_modules,
+      synthetic_modules: options.synthetic_modules,
       is_main_runtime: options.is_main,
     };
 
+    let loader = options
+      .module_loader
+      .unwrap_or_else(|| Rc::new(NoopModuleLoader));
+    let main_realm = js_runtime.create_realm_inner(context_state, loader, true);
+    let inspector = if options.inspector {
+      let scope = &mut main_realm.handle_scope(js_runtime.v8_isolate());
+      let context = v8::Local::new(scope, main_realm.context());
+      Some(JsRuntimeInspector::new(scope, context, options.is_main))
+    } else {
+      None
+    };
+    {
+      let mut state = js_runtime.inner.state.borrow_mut();
+      state.main_realm = Some(JsRealm::new(main_realm.clone()));
+      state.inspector = inspector;
+      state.known_realms.push(main_realm);
+    }
+
     let realm = js_runtime.main_realm();
     // TODO(mmastrac): We should thread errors back out of the runtime
     js_runtime
       .init_extension_js(&realm, maybe_load_callback)
       .unwrap();
 
-    // If the user has requested that we rename modules
-    if let Some(preserve_snapshotted_modules) =
-      options.preserve_snapshotted_modules
-    {
-      js_runtime
-        .module_map()
-        .borrow_mut()
-        .clear_module_map(preserve_snapshotted_modules);
+    js_runtime
+      .init_main_module_map()
+      .unwrap_or_else(|err| panic!("{err:#}"));
+
+    // Extensions are trusted, so the limits only apply from here on.
+    if let Some(policy) = options.heap_limit_policy {
+      js_runtime.allocations.heap_limit =
+        Some(HeapLimit::install(js_runtime.v8_isolate(), policy));
+    }
+    if options.max_run_time.is_some() || options.max_cpu_time.is_some() {
+      let budget = ExecutionBudget::new(
+        js_runtime.v8_isolate().thread_safe_handle(),
+        options.max_run_time,
+        options.max_cpu_time,
+      );
+      js_runtime.inner.state.borrow_mut().execution_budget =
+        Some(Rc::new(budget));
     }
 
     js_runtime
@@ -824,81 +971,197 @@ impl JsRuntime {
     &mut self,
     options: CreateRealmOptions,
   ) -> Result<JsRealm, Error> {
-    let realm = {
-      let context_state = Rc::new(RefCell::new(ContextState::default()));
-      let op_ctxs: Box<[OpCtx]> = self
-        .main_realm()
-        .0
-        .state()
-        .borrow()
-        .op_ctxs
-        .iter()
-        .map(|op_ctx| {
-          OpCtx::new(
-            op_ctx.id,
-            context_state.clone(),
-            op_ctx.decl.clone(),
-            op_ctx.state.clone(),
-            op_ctx.runtime_state.clone(),
-          )
-        })
-        .collect();
-      context_state.borrow_mut().op_ctxs = op_ctxs;
-      context_state.borrow_mut().isolate = Some(self.v8_isolate() as _);
-
-      let raw_ptr = self.v8_isolate() as *mut v8::OwnedIsolate;
-      // SAFETY: Having the scope tied to self's lifetime makes it impossible to
-      // reference JsRuntimeState::op_ctxs while the scope is alive. Here we
-      // turn it into an unbound lifetime, which is sound because 1. it only
-      // lives until the end of this block, and 2. the HandleScope only has
-      // access to the isolate, and nothing else we're accessing from self does.
-      let isolate = unsafe { raw_ptr.as_mut() }.unwrap();
-      let scope = &mut v8::HandleScope::new(isolate);
-
-      let context = create_context(
-        scope,
-        &self.global_template_middlewares,
-        &self.global_object_middlewares,
-      );
-
-      let scope = &mut v8::ContextScope::new(scope, context);
-
-      let context = bindings::initialize_context(
-        scope,
-        context,
-        &context_state.borrow().op_ctxs,
-        self.init_mode,
-      );
-      context.set_slot(scope, context_state.clone());
-
-      let loader = options
-        .module_loader
-        .unwrap_or_else(|| Rc::new(NoopModuleLoader));
-      let module_map_rc = Rc::new(RefCell::new(ModuleMap::new(loader)));
-      // TODO(andreubotella): Should the module map be initialized with snapshotted data?
-      context.set_slot(scope, module_map_rc.clone());
-
-      let realm = JsRealmInner::new(
-        context_state,
-        v8::Global::new(scope, context),
-        module_map_rc,
-        self.inner.state.clone(),
-        false,
-      );
-      let mut state = self.inner.state.borrow_mut();
-      state.known_realms.push(realm.clone());
-      JsRealm::new(realm)
-    };
+    let context_state = self.new_context_state();
+    let loader = options
+      .module_loader
+      .unwrap_or_else(|| Rc::new(NoopModuleLoader));
+    let realm = self.create_realm_inner(context_state, loader, false);
+    self
+      .inner
+      .state
+      .borrow_mut()
+      .known_realms
+      .push(realm.clone());
+    let realm = JsRealm::new(realm);
 
     self.init_extension_js(&realm, None)?;
     Ok(realm)
   }
 
+  /// Creates the state of a new realm, with op contexts for the ops of the
+  /// main realm.
+  fn new_context_state(&mut self) -> Rc<RefCell<ContextState>> {
+    let context_state = Rc::new(RefCell::new(ContextState::default()));
+    let main_context_state = self.main_realm().0.state();
+    let main_context_state = main_context_state.borrow();
+    let op_ctxs: Box<[OpCtx]> = main_context_state
+      .op_ctxs
+      .iter()
+      .map(|op_ctx| {
+        OpCtx::new(
+          op_ctx.id,
+          context_state.clone(),
+          op_ctx.decl.clone(),
+          op_ctx.state.clone(),
+          op_ctx.runtime_state.clone(),
+        )
+      })
+      .collect();
+    context_state.borrow_mut().op_ctxs = op_ctxs;
+    context_state.borrow_mut().isolate = main_context_state.isolate;
+    context_state
+  }
+
+  /// Creates a realm with a new context and module map. The module map of the
+  /// main realm is populated from the startup snapshot, if any.
+  fn create_realm_inner(
+    &mut self,
+    context_state: Rc<RefCell<ContextState>>,
+    loader: Rc<dyn ModuleLoader>,
+    is_main_realm: bool,
+  ) -> JsRealmInner {
+    let scope = &mut v8::HandleScope::new(self.inner.v8_isolate.as_mut());
+    let context = create_context(
+      scope,
+      &self.global_template_middlewares,
+      &self.global_object_middlewares,
+    );
+    // TODO(andreubotella): Should the module map of other realms be initialized
+    // with snapshotted data?
+    let snapshotted_data =
+      if is_main_realm && self.init_mode == InitMode::FromSnapshot {
+        Some(snapshot_util::get_snapshotted_data(scope, context))
+      } else {
+        None
+      };
+    let scope = &mut v8::ContextScope::new(scope, context);
+    let context = bindings::initialize_context(
+      scope,
+      context,
+      &context_state.borrow().op_ctxs,
+      self.init_mode,
+    );
+    context.set_slot(scope, context_state.clone());
+
+    let module_map_rc = Rc::new(RefCell::new(ModuleMap::new(loader)));
+    if let Some(snapshotted_data) = snapshotted_data {
+      let mut module_map = module_map_rc.borrow_mut();
+      module_map.update_with_snapshotted_data(scope, snapshotted_data);
+    }
+    if let Some(callback) =
+      &self.inner.state.borrow().module_load_trace_callback
+    {
+      let mut module_map = module_map_rc.borrow_mut();
+      module_map.tracer =
+        Some(ModuleLoadTracer::new(callback.clone(), &module_map));
+    }
+    context.set_slot(scope, module_map_rc.clone());
+
+    JsRealmInner::new(
+      context_state,
+      v8::Global::new(scope, context),
+      module_map_rc,
+      self.inner.state.clone(),
+      is_main_realm,
+    )
+  }
+
+  /// Applies [`RuntimeOptions::preserve_snapshotted_modules`] and
+  /// [`RuntimeOptions::synthetic_modules`] to the module map of the main
+  /// realm.
+  fn init_main_module_map(&mut self) -> Result<(), Error> {
+    let module_map_rc = self.module_map();
+    if let Some(preserve_snapshotted_modules) =
+      self.preserve_snapshotted_modules
+    {
+      module_map_rc
+        .borrow_mut()
+        .clear_module_map(preserve_snapshotted_modules);
+    }
+    for synthetic_module in self.synthetic_modules.clone() {
+      let specifier = synthetic_module.specifier().clone();
+      module_map_rc
+        .borrow_mut()
+        .restore_embedder_synthetic_module(
+          &mut self.handle_scope(),
+          synthetic_module,
+        )
+        .with_context(|| {
+          format!("Failed to register synthetic module {specifier}")
+        })?;
+    }
+    Ok(())
+  }
+
   #[inline]
   pub fn handle_scope(&mut self) -> v8::HandleScope {
     self.main_realm().handle_scope(self.v8_isolate())
   }
 
+  /// Replaces every realm with a new main realm created from the startup
+  /// snapshot, dropping pending ops, timers and module evaluations, and clears
+  /// the resources and state in the `OpState`. The module map of the new realm
+  /// gets the synthetic and preserved modules of the [`RuntimeOptions`], and
+  /// the virtual clock, if any, is moved back to its start time. This is how a
+  /// [`JsRuntimePool`](crate::JsRuntimePool) makes a runtime as good as new.
+  pub(crate) fn reset_from_snapshot(&mut self) -> Result<(), Error> {
+    if self.init_mode != InitMode::FromSnapshot {
+      return Err(generic_error(
+        "Only a runtime created from a snapshot can be reset",
+      ));
+    }
+    if self.inner.state.borrow().inspector.is_some() {
+      return Err(generic_error("A runtime with an inspector can't be reset"));
+    }
+    if let Some(heap_limit) = &self.allocations.heap_limit {
+      heap_limit.check()?;
+    }
+
+    let loader = self.module_map().borrow().loader.clone();
+    let context_state = self.new_context_state();
+    let isolate_ptr = context_state.borrow().isolate.unwrap();
+
+    {
+      let mut state = self.inner.state.borrow_mut();
+      for realm in &state.known_realms {
+        let context_state = realm.state();
+        let mut context_state = context_state.borrow_mut();
+        // Dropping the tasks of pending ops cancels them.
+        std::mem::take(&mut context_state.pending_ops);
+        std::mem::take(&mut context_state.timers);
+      }
+      state.destroy_all_realms();
+      state.pending_mod_evaluate = None;
+      state.pending_dyn_mod_evaluate.clear();
+      state.dyn_module_evaluate_idle_counter = 0;
+      state.has_tick_scheduled = false;
+      state.dispatched_exception = None;
+      if let Some(budget) = &state.execution_budget {
+        budget.reset();
+      }
+      if let Some(clock) = &state.virtual_clock {
+        clock.reset();
+      }
+
+      let mut op_state = state.op_state.borrow_mut();
+      op_state.clear();
+      op_state.tracker = OpsTracker::new(context_state.borrow().op_ctxs.len());
+      op_state.last_fast_op_error = None;
+      op_state.put(isolate_ptr);
+    }
+
+    let realm = self.create_realm_inner(context_state, loader, true);
+    {
+      let mut state = self.inner.state.borrow_mut();
+      state.main_realm = Some(JsRealm::new(realm.clone()));
+      state.known_realms.push(realm);
+    }
+
+    let realm = self.main_realm();
+    self.init_extension_js(&realm, None)?;
+    self.init_main_module_map()
+  }
+
   /// Initializes JS of provided Extensions in the given realm.
   fn init_extension_js(
     &mut self,
@@ -925,7 +1188,15 @@ impl JsRuntime {
 
     let mut esm_entrypoints = vec![];
 
+    let virtual_clock = self.inner.state.borrow().virtual_clock.is_some();
     futures::executor::block_on(async {
+      if virtual_clock {
+        realm.execute_script(
+          self.v8_isolate(),
+          VIRTUAL_CLOCK_SOURCE.specifier,
+          VIRTUAL_CLOCK_SOURCE.load()?,
+        )?;
+      }
       if self.init_mode == InitMode::New {
         for file_source in &*BUILTIN_SOURCES {
           realm.execute_script(
@@ -967,7 +1238,7 @@ impl JsRuntime {
           self
             .module_map()
             .borrow()
-            .get_id(specifier, AssertedModuleType::JavaScriptOrWasm)
+            .get_id(specifier, &AssertedModuleType::JavaScriptOrWasm)
             .unwrap_or_else(|| {
               panic!("{} not present in the module map", specifier)
             })
@@ -992,6 +1263,14 @@ impl JsRuntime {
 
     self.extensions = extensions;
     self.module_map().borrow_mut().loader = loader;
+
+    if virtual_clock {
+      realm.execute_script(
+        self.v8_isolate(),
+        VIRTUAL_CLOCK_SOURCE.specifier,
+        VIRTUAL_CLOCK_SOURCE.load()?,
+      )?;
+    }
     Ok(())
   }
 
@@ -1174,9 +1453,36 @@ impl JsRuntime {
     name: &'static str,
     source_code: ModuleCode,
   ) -> Result<v8::Global<v8::Value>, Error> {
-    self
-      .main_realm()
-      .execute_script(self.v8_isolate(), name, source_code)
+    self.run_guarded(|runtime| {
+      runtime.main_realm().execute_script(
+        runtime.v8_isolate(),
+        name,
+        source_code,
+      )
+    })
+  }
+
+  fn run_guards(&self) -> RunGuards {
+    RunGuards {
+      budget: self.inner.state.borrow().execution_budget.clone(),
+      heap_limit: self.allocations.heap_limit.clone(),
+    }
+  }
+
+  /// Calls `f` as a run of JavaScript subject to the limits set in
+  /// [`RuntimeOptions`].
+  fn run_guarded<T>(
+    &mut self,
+    f: impl FnOnce(&mut Self) -> Result<T, Error>,
+  ) -> Result<T, Error> {
+    let guards = self.run_guards();
+    guards.enter()?;
+    let result = f(self);
+    match guards.exit(self.v8_isolate()) {
+      // A run that completed before its termination took effect succeeded.
+      Some(err) if result.is_err() => Err(err),
+      _ => result,
+    }
   }
 
   /// Executes traditional JavaScript code (traditional = not ES modules).
@@ -1240,6 +1546,28 @@ impl JsRuntime {
       .get_module_namespace(&mut self.handle_scope(), module_id)
   }
 
+  /// Returns a snapshot of the modules loaded in the main realm, with their
+  /// dependencies and evaluation status.
+  pub fn module_graph(&mut self) -> ModuleGraph {
+    let module_map_rc = self.module_map();
+    let module_map = module_map_rc.borrow();
+    ModuleGraph::new(&mut self.handle_scope(), &module_map)
+  }
+
+  /// Registers a synthetic module in the main realm, whose exports are
+  /// computed by `module` when it's evaluated. The module can then be
+  /// imported or loaded with [`JsRuntime::load_side_module`] like any other.
+  ///
+  /// Fails if a module is already registered under the same specifier.
+  pub fn register_synthetic_module(
+    &mut self,
+    module: SyntheticModule,
+  ) -> Result<ModuleId, Error> {
+    let module_map_rc = self.module_map();
+    let mut module_map = module_map_rc.borrow_mut();
+    module_map.new_embedder_synthetic_module(&mut self.handle_scope(), module)
+  }
+
   /// Registers a callback on the isolate when the memory limits are approached.
   /// Use this to prevent V8 from crashing the process when reaching the limit.
   ///
@@ -1374,6 +1702,115 @@ impl JsRuntime {
     poll_fn(|cx| self.poll_event_loop(cx, wait_for_inspector)).await
   }
 
+  /// The clock passed in [`RuntimeOptions::virtual_clock`], if any.
+  pub fn virtual_clock(&self) -> Option<VirtualClock> {
+    self.inner.state.borrow().virtual_clock.clone()
+  }
+
+  /// Moves the virtual clock forward by `duration`, stopping at the deadline
+  /// of each timer expiring in the meantime to run the event loop until it
+  /// has nothing left to do at that time.
+  ///
+  /// Pending async ops aren't waited for, but the ones that are ready get to
+  /// resolve at every step.
+  pub async fn advance_time(
+    &mut self,
+    duration: Duration,
+  ) -> Result<(), Error> {
+    let clock = self.expect_virtual_clock()?;
+    let target = clock.instant() + duration;
+    loop {
+      if let Some(result) = self.run_event_loop_until_stalled().await {
+        result?;
+      }
+      match self.next_timer_deadline() {
+        Some(deadline) if deadline <= target => clock.advance_to(deadline),
+        _ => break,
+      }
+    }
+    clock.advance_to(target);
+    Ok(())
+  }
+
+  /// Runs the event loop to completion like [`JsRuntime::run_event_loop`],
+  /// except that when only timers are left to wait for, the virtual clock
+  /// jumps to the deadline of the next one. Long timers don't make this take
+  /// any longer.
+  pub async fn run_until_idle(&mut self) -> Result<(), Error> {
+    let clock = self.expect_virtual_clock()?;
+    loop {
+      if let Some(result) = self.run_event_loop_until_stalled().await {
+        return result;
+      }
+      if self.event_loop_pending_state().is_waiting_only_for_timers() {
+        if let Some(deadline) = self.next_timer_deadline() {
+          clock.advance_to(deadline);
+          continue;
+        }
+      }
+      // Wait for the rest of the pending work to make progress.
+      let mut polled = false;
+      let result = poll_fn(|cx| {
+        if std::mem::replace(&mut polled, true) {
+          return Poll::Ready(None);
+        }
+        self.poll_event_loop(cx, false).map(Some)
+      })
+      .await;
+      if let Some(result) = result {
+        return result;
+      }
+    }
+  }
+
+  fn expect_virtual_clock(&self) -> Result<VirtualClock, Error> {
+    self
+      .virtual_clock()
+      .ok_or_else(|| generic_error("The runtime has no virtual clock"))
+  }
+
+  fn next_timer_deadline(&self) -> Option<tokio::time::Instant> {
+    let state = self.inner.state.borrow();
+    let deadlines = state.known_realms.iter();
+    deadlines
+      .filter_map(JsRealmInner::next_timer_deadline)
+      .min()
+  }
+
+  /// Polls the event loop for as long as it wakes itself up, and gives the
+  /// tasks of async ops a chance to run in between. Returns `None` once it's
+  /// left waiting for something else, such as a timer on the virtual clock.
+  async fn run_event_loop_until_stalled(
+    &mut self,
+  ) -> Option<Result<(), Error>> {
+    loop {
+      let mut woken = None;
+      let poll = poll_fn(|cx| {
+        let waker = Arc::new(FlaggingWaker {
+          woken: AtomicBool::new(false),
+          waker: cx.waker().clone(),
+        });
+        let poll = self.poll_event_loop(
+          &mut Context::from_waker(&futures::task::waker_ref(&waker)),
+          false,
+        );
+        woken = Some(waker);
+        Poll::Ready(poll)
+      })
+      .await;
+      if let Poll::Ready(result) = poll {
+        return Some(result);
+      }
+      let woken = woken.unwrap();
+      if !woken.woken.load(Ordering::Relaxed) {
+        tokio::task::yield_now().await;
+        if !woken.woken.load(Ordering::Relaxed) {
+          return None;
+        }
+      }
+    }
+  }
+
   /// Runs a single tick of event loop
   ///
   /// If `wait_for_inspector` is set to true event loop
@@ -1382,6 +1819,28 @@ impl JsRuntime {
     &mut self,
     cx: &mut Context,
     wait_for_inspector: bool,
+  ) -> Poll<Result<(), Error>> {
+    let mut pending = false;
+    let result = self.run_guarded(|runtime| {
+      match runtime.poll_event_loop_inner(cx, wait_for_inspector) {
+        Poll::Ready(result) => result,
+        Poll::Pending => {
+          pending = true;
+          Ok(())
+        }
+      }
+    });
+    if pending && result.is_ok() {
+      Poll::Pending
+    } else {
+      Poll::Ready(result)
+    }
+  }
+
+  fn poll_event_loop_inner(
+    &mut self,
+    cx: &mut Context,
+    wait_for_inspector: bool,
   ) -> Poll<Result<(), Error>> {
     let has_inspector: bool;
 
@@ -1445,6 +1904,8 @@ impl JsRuntime {
     // Top level module
     self.evaluate_pending_module();
 
+    self.produce_code_caches();
+
     let pending_state = self.event_loop_pending_state();
     if !pending_state.is_pending() && !maybe_scheduling {
       if has_inspector {
@@ -1490,6 +1951,7 @@ impl JsRuntime {
 
     if pending_state.has_pending_module_evaluation {
       if pending_state.has_pending_refed_ops
+        || pending_state.has_pending_refed_timers
         || pending_state.has_pending_dyn_imports
         || pending_state.has_pending_dyn_module_evaluation
         || pending_state.has_pending_background_tasks
@@ -1510,6 +1972,7 @@ impl JsRuntime {
 
     if pending_state.has_pending_dyn_module_evaluation {
       if pending_state.has_pending_refed_ops
+        || pending_state.has_pending_refed_timers
         || pending_state.has_pending_dyn_imports
         || pending_state.has_pending_background_tasks
         || pending_state.has_tick_scheduled
@@ -1537,7 +2000,7 @@ impl JsRuntime {
     Poll::Pending
   }
 
-  fn event_loop_pending_state(&mut self) -> EventLoopPendingState {
+  pub(crate) fn event_loop_pending_state(&mut self) -> EventLoopPendingState {
     let module_map = self.module_map();
     let mut scope = v8::HandleScope::new(self.inner.v8_isolate.as_mut());
     let x = EventLoopPendingState::new(
@@ -1553,23 +2016,46 @@ fn find_and_report_stalled_level_await_in_any_realm(
   v8_isolate: &mut v8::Isolate,
   known_realms: Vec<JsRealmInner>,
 ) -> Error {
-  for inner_realm in known_realms {
+  let mut stalled = vec![];
+  for (realm, inner_realm) in known_realms.into_iter().enumerate() {
     let scope = &mut inner_realm.handle_scope(v8_isolate);
     let module_map = inner_realm.module_map();
-    let messages = module_map.borrow().find_stalled_top_level_await(scope);
+    let modules = {
+      let module_map = module_map.borrow();
+      module_map
+        .find_stalled_top_level_await(scope)
+        .into_iter()
+        .map(|(id, message)| {
+          let specifier = module_map.info[id].name.as_str().to_string();
+          (specifier, message, module_map.import_chain(id))
+        })
+        .collect::<Vec<_>>()
+    };
 
-    if !messages.is_empty() {
-      // We are gonna print only a single message to provide a nice formatting
-      // with source line of offending promise shown. Once user fixed it, then
-      // they will get another error message for the next promise (but this
-      // situation is gonna be very rare, if ever happening).
-      let msg = v8::Local::new(scope, messages[0].clone());
-      let js_error = JsError::from_v8_message(scope, msg);
-      return js_error.into();
+    for (specifier, message, import_chain) in modules {
+      let message = v8::Local::new(scope, message);
+      // The source line is only looked up by `JsError` when there's a source
+      // map getter, otherwise take it from V8.
+      let v8_source_line = message
+        .get_source_line(scope)
+        .map(|line| line.to_rust_string_lossy(scope));
+      let js_error = JsError::from_v8_message(scope, message);
+      stalled.push(StalledTopLevelAwait {
+        realm,
+        specifier,
+        message: js_error.exception_message,
+        source_line: js_error.source_line.or(v8_source_line),
+        call_site: js_error.frames.into_iter().next(),
+        import_chain,
+      });
     }
   }
 
-  unreachable!("Expected at least one stalled top-level await");
+  assert!(
+    !stalled.is_empty(),
+    "Expected at least one stalled top-level await"
+  );
+  StalledTopLevelAwaitError { stalled }.into()
 }
 
 fn create_context<'a>(
@@ -1660,6 +2146,7 @@ impl JsRuntimeForSnapshot {
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub(crate) struct EventLoopPendingState {
   has_pending_refed_ops: bool,
+  has_pending_refed_timers: bool,
   has_pending_dyn_imports: bool,
   has_pending_dyn_module_evaluation: bool,
   has_pending_module_evaluation: bool,
@@ -1674,13 +2161,16 @@ impl EventLoopPendingState {
   ) -> EventLoopPendingState {
     let mut num_unrefed_ops = 0;
     let mut num_pending_ops = 0;
+    let mut has_pending_refed_timers = false;
     for realm in &state.known_realms {
       num_unrefed_ops += realm.num_unrefed_ops();
       num_pending_ops += realm.num_pending_ops();
+      has_pending_refed_timers |= realm.has_pending_refed_timers();
     }
 
     EventLoopPendingState {
       has_pending_refed_ops: num_pending_ops > num_unrefed_ops,
+      has_pending_refed_timers,
       has_pending_dyn_imports: module_map.has_pending_dynamic_imports(),
       has_pending_dyn_module_evaluation: !state
         .pending_dyn_mod_evaluate
@@ -1691,8 +2181,19 @@ impl EventLoopPendingState {
     }
   }
 
+  /// Whether refed timers are all the event loop is waiting for, as opposed to
+  /// work that makes progress on its own.
+  pub fn is_waiting_only_for_timers(&self) -> bool {
+    self.has_pending_refed_timers
+      && !self.has_pending_refed_ops
+      && !self.has_pending_dyn_imports
+      && !self.has_pending_background_tasks
+      && !self.has_tick_scheduled
+  }
+
   pub fn is_pending(&self) -> bool {
     self.has_pending_refed_ops
+      || self.has_pending_refed_timers
       || self.has_pending_dyn_imports
       || self.has_pending_dyn_module_evaluation
       || self.has_pending_module_evaluation
@@ -1701,6 +2202,47 @@ impl EventLoopPendingState {
   }
 }
 
+/// The limits applying to every run of JavaScript, see
+/// [`RuntimeOptions::max_run_time`], [`RuntimeOptions::max_cpu_time`] and
+/// [`RuntimeOptions::heap_limit_policy`].
+struct RunGuards {
+  budget: Option<Rc<ExecutionBudget>>,
+  heap_limit: Option<Rc<HeapLimit>>,
+}
+
+impl RunGuards {
+  fn enter(&self) -> Result<(), Error> {
+    if let Some(heap_limit) = &self.heap_limit {
+      heap_limit.check()?;
+    }
+    if let Some(budget) = &self.budget {
+      budget.enter()?;
+    }
+    Ok(())
+  }
+
+  /// Ends a run, returning the error to fail it with if its execution was
+  /// terminated for exceeding a limit.
+  fn exit(&self, isolate: &mut v8::Isolate) -> Option<Error> {
+    let timed_out = self.budget.as_ref().and_then(|b| b.exit(isolate));
+    let exceeded = self.heap_limit.as_ref().and_then(|h| h.exit(isolate));
+    exceeded.map(Error::from).or(timed_out.map(Error::from))
+  }
+}
+
+/// Forwards wake-ups to another waker, remembering that there was one.
+struct FlaggingWaker {
+  woken: AtomicBool,
+  waker: Waker,
+}
+
+impl ArcWake for FlaggingWaker {
+  fn wake_by_ref(arc_self: &Arc<Self>) {
+    arc_self.woken.store(true, Ordering::Relaxed);
+    arc_self.waker.wake_by_ref();
+  }
+}
+
 extern "C" fn near_heap_limit_callback<F>(
   data: *mut c_void,
   current_heap_limit: usize,
@@ -1778,7 +2320,13 @@ impl JsRuntime {
     let scope = &mut main_realm.handle_scope(&mut self.inner.v8_isolate);
     let tc_scope = &mut v8::TryCatch::new(scope);
     let module = v8::Local::new(tc_scope, &module_handle);
+    let start = Instant::now();
     let maybe_value = module.evaluate(tc_scope);
+    main_realm.0.module_map().borrow().trace(
+      ModuleLoadPhase::Evaluate,
+      id,
+      start,
+    );
 
     // Update status after evaluating.
     let status = module.get_status();
@@ -1837,6 +2385,7 @@ impl JsRuntime {
     let main_realm = self.main_realm();
     let state_rc = self.inner.state.clone();
     let module_map_rc = self.module_map();
+    let guards = self.run_guards();
     let scope = &mut self.handle_scope();
     let tc_scope = &mut v8::TryCatch::new(scope);
 
@@ -1866,6 +2415,13 @@ impl JsRuntime {
 
     let (sender, receiver) = oneshot::channel();
 
+    if let Err(err) = guards.enter() {
+      sender
+        .send(Err(err))
+        .expect("Failed to send module evaluation error.");
+      return receiver;
+    }
+
     // IMPORTANT: Top-level-await is enabled, which means that return value
     // of module evaluation is a promise.
     //
@@ -1889,6 +2445,7 @@ impl JsRuntime {
         "There is already pending top level module evaluation"
       );
       state.pending_mod_evaluate = Some(ModEvaluate {
+        module_id: id,
         promise: None,
         has_evaluated: false,
         handled_promise_rejections: vec![],
@@ -1896,7 +2453,11 @@ impl JsRuntime {
       });
     }
 
+    let start = Instant::now();
     let maybe_value = module.evaluate(tc_scope);
+    module_map_rc
+      .borrow()
+      .trace(ModuleLoadPhase::Evaluate, id, start);
     {
       let mut state = state_rc.borrow_mut();
       let pending_mod_evaluate = state.pending_mod_evaluate.as_mut().unwrap();
@@ -1908,23 +2469,12 @@ impl JsRuntime {
 
     let has_dispatched_exception =
       state_rc.borrow_mut().dispatched_exception.is_some();
-    if has_dispatched_exception {
-      // This will be overridden in `exception_to_err_result()`.
-      let exception = v8::undefined(tc_scope).into();
-      let pending_mod_evaluate = {
-        let mut state = state_rc.borrow_mut();
-        state.pending_mod_evaluate.take().unwrap()
-      };
-      pending_mod_evaluate
-        .sender
-        .send(exception_to_err_result(tc_scope, exception, false))
-        .expect("Failed to send module evaluation error.");
-    } else if let Some(value) = maybe_value {
+    if let (false, Some(value)) = (has_dispatched_exception, &maybe_value) {
       assert!(
         status == v8::ModuleStatus::Evaluated
           || status == v8::ModuleStatus::Errored
       );
-      let promise = v8::Local::<v8::Promise>::try_from(value)
+      let promise = v8::Local::<v8::Promise>::try_from(*value)
         .expect("Expected to get promise as module evaluation result");
       let promise_global = v8::Global::new(tc_scope, promise);
       let mut state = state_rc.borrow_mut();
@@ -1945,7 +2495,34 @@ impl JsRuntime {
       let promise_global = v8::Global::new(tc_scope, promise);
       state.pending_mod_evaluate.as_mut().unwrap().promise =
         Some(promise_global);
+      drop(state);
       tc_scope.perform_microtask_checkpoint();
+    }
+    // The microtask checkpoint is part of the run.
+    let interrupted = guards.exit(tc_scope);
+
+    if has_dispatched_exception {
+      // This will be overridden in `exception_to_err_result()`.
+      let exception = v8::undefined(tc_scope).into();
+      let pending_mod_evaluate = {
+        let mut state = state_rc.borrow_mut();
+        state.pending_mod_evaluate.take().unwrap()
+      };
+      pending_mod_evaluate
+        .sender
+        .send(exception_to_err_result(tc_scope, exception, false))
+        .expect("Failed to send module evaluation error.");
+    } else if let Some(err) = interrupted {
+      let pending_mod_evaluate = {
+        let mut state = state_rc.borrow_mut();
+        state.pending_mod_evaluate.take().unwrap()
+      };
+      pending_mod_evaluate
+        .sender
+        .send(Err(err))
+        .expect("Failed to send module evaluation error.");
+    } else if maybe_value.is_some() {
+      // The evaluation completes in `evaluate_pending_module()`.
     } else if tc_scope.has_terminated() || tc_scope.is_execution_terminating() {
       let pending_mod_evaluate = {
         let mut state = state_rc.borrow_mut();
@@ -1969,7 +2546,7 @@ impl JsRuntime {
     let module_map_rc = self.module_map();
     let scope = &mut self.handle_scope();
 
-    let resolver_handle = module_map_rc
+    let (resolver_handle, _) = module_map_rc
       .borrow_mut()
       .dynamic_import_map
       .remove(&id)
@@ -1990,7 +2567,7 @@ impl JsRuntime {
     let module_map_rc = self.module_map();
     let scope = &mut self.handle_scope();
 
-    let resolver_handle = module_map_rc
+    let (resolver_handle, _) = module_map_rc
       .borrow_mut()
       .dynamic_import_map
       .remove(&id)
@@ -2207,6 +2784,25 @@ impl JsRuntime {
     }
   }
 
+  /// Hand the code caches of freshly evaluated modules over to the module
+  /// loader.
+  fn produce_code_caches(&mut self) {
+    let module_map_rc = self.module_map();
+    if !module_map_rc.borrow().has_pending_code_caches() {
+      return;
+    }
+    let code_caches = {
+      let scope = &mut self.handle_scope();
+      module_map_rc.borrow_mut().take_code_caches(scope)
+    };
+    let loader = module_map_rc.borrow().loader.clone();
+    for (name, code_cache, rejected) in code_caches {
+      if let Ok(specifier) = ModuleSpecifier::parse(name.as_str()) {
+        loader.code_cache_ready(&specifier, &code_cache, rejected);
+      }
+    }
+  }
+
   // Returns true if some dynamic import was resolved.
   fn evaluate_dyn_imports(&mut self) -> bool {
     let pending = std::mem::take(
@@ -2277,7 +2873,7 @@ impl JsRuntime {
       // true for main module
       module_map_rc
         .borrow_mut()
-        .new_es_module(scope, true, specifier, code, false)
+        .new_es_module(scope, true, specifier, code, false, None)
         .map_err(|e| match e {
           ModuleError::Exception(exception) => {
             let exception = v8::Local::new(scope, exception);
@@ -2332,7 +2928,7 @@ impl JsRuntime {
       // false for side module (not main module)
       module_map_rc
         .borrow_mut()
-        .new_es_module(scope, false, specifier, code, false)
+        .new_es_module(scope, false, specifier, code, false, None)
         .map_err(|e| match e {
           ModuleError::Exception(exception) => {
             let exception = v8::Local::new(scope, exception);
@@ -2368,6 +2964,344 @@ impl JsRuntime {
     Ok(root_id)
   }
 
+  /// Imports a module like `import(specifier)` in the module at `referrer`
+  /// would, and returns its namespace object. The event loop is run until the
+  /// import settles.
+  ///
+  /// The specifier is resolved with [`ResolutionKind::DynamicImport`](crate::ResolutionKind::DynamicImport),
+  /// modules that are already loaded or being imported are reused, and an
+  /// error thrown while evaluating the module or its dependencies is
+  /// returned.
+  pub async fn import(
+    &mut self,
+    specifier: &str,
+    referrer: &str,
+  ) -> Result<v8::Global<v8::Object>, Error> {
+    self.import_with_attributes(specifier, referrer, &[]).await
+  }
+
+  /// Like [`JsRuntime::import`], with import attributes, eg.
+  /// `[("type", "json")]` for `import(specifier, { assert: { type: "json" } })`.
+  /// Unknown module types are rejected with a `TypeError`.
+  pub async fn import_with_attributes(
+    &mut self,
+    specifier: &str,
+    referrer: &str,
+    attributes: &[(&str, &str)],
+  ) -> Result<v8::Global<v8::Object>, Error> {
+    let module_map_rc = self.module_map();
+    let promise = {
+      let scope = &mut self.handle_scope();
+      let resolver = v8::PromiseResolver::new(scope).unwrap();
+      let promise = resolver.get_promise(scope);
+      let assertions = attributes
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+      let tc_scope = &mut v8::TryCatch::new(scope);
+      validate_import_assertions(tc_scope, &assertions);
+      if let Some(exception) = tc_scope.exception() {
+        resolver.reject(tc_scope, exception);
+      } else {
+        let asserted_module_type =
+          get_asserted_module_type_from_assertions(&assertions);
+        ModuleMap::load_dynamic_import(
+          module_map_rc,
+          specifier,
+          referrer,
+          asserted_module_type,
+          v8::Global::new(tc_scope, resolver),
+        );
+        JsRuntime::state_from(tc_scope)
+          .borrow_mut()
+          .notify_new_dynamic_import();
+      }
+      v8::Global::new(tc_scope, v8::Local::<v8::Value>::from(promise))
+    };
+
+    let namespace = self.resolve_value(promise).await?;
+    let scope = &mut self.handle_scope();
+    let namespace = v8::Local::new(scope, namespace);
+    let namespace = v8::Local::<v8::Object>::try_from(namespace)
+      .map_err(|err: v8::DataError| generic_error(err.to_string()))?;
+    Ok(v8::Global::new(scope, namespace))
+  }
+
+  /// Replaces a loaded module, and every module that statically imports it
+  /// directly or transitively, with new versions fetched from the module
+  /// loader. This is meant for hot module replacement during development.
+  ///
+  /// The new versions are loaded and instantiated first; if that fails, the
+  /// current versions are kept and the error is returned. Otherwise:
+  ///  - the callbacks registered by the current versions with
+  ///    `import.meta.hot.dispose()` are called with a new object, which the
+  ///    new versions get as `import.meta.hot.data`,
+  ///  - the new versions are evaluated,
+  ///  - the callbacks registered by the current versions with
+  ///    `import.meta.hot.accept()` are called with the namespace object of
+  ///    the new version.
+  ///
+  /// If a dispose callback throws or a new version fails to evaluate, the
+  /// current versions are registered again in place of the new ones and the
+  /// error is returned. Once the new versions are evaluated, the handles and
+  /// infos of the replaced modules are dropped.
+  ///
+  /// `import.meta.hot` is only available if
+  /// [`RuntimeOptions::hot_module_replacement`] is set.
+  ///
+  /// Modules that aren't JavaScript or Wasm, and that no other module
+  /// statically imports, are only removed from the module map, to be fetched
+  /// again by their next import.
+  ///
+  /// Returns the ids of the new versions, keyed by the ids of the replaced
+  /// modules.
+  pub async fn hot_reload_module(
+    &mut self,
+    specifier: &ModuleSpecifier,
+  ) -> Result<HashMap<ModuleId, ModuleId>, Error> {
+    let module_map_rc = self.module_map();
+    let compiled_wasm_module_store =
+      self.inner.state.borrow().compiled_wasm_module_store.clone();
+    let invalidated = module_map_rc.borrow_mut().hot_invalidate(
+      specifier.as_str(),
+      compiled_wasm_module_store.as_ref(),
+    )?;
+    let root_ids = match self.hot_load_modules(&invalidated).await {
+      Ok(root_ids) => root_ids,
+      Err(error) => {
+        module_map_rc
+          .borrow_mut()
+          .hot_restore(&invalidated, &HashMap::new());
+        return Err(error);
+      }
+    };
+
+    let mut ids = HashMap::new();
+    let mut previous_data = vec![];
+    let mut result = Ok(());
+    for module in &invalidated {
+      // The new version may have been found under another name, which one of
+      // the aliases of the current version then resolves to.
+      let maybe_id = std::iter::once(&module.name)
+        .chain(module.aliases.iter().map(|alias| &alias.name))
+        .find_map(|name| {
+          module_map_rc
+            .borrow()
+            .get_id(name, &module.asserted_module_type)
+        });
+      if let Some(id) = maybe_id {
+        ids.insert(module.id, id);
+        if module.main {
+          module_map_rc.borrow_mut().info[id].main = true;
+        }
+      }
+
+      let dispose = module_map_rc
+        .borrow()
+        .hot_callbacks
+        .get(&module.id)
+        .map(|callbacks| callbacks.dispose.clone())
+        .unwrap_or_default();
+      let data = {
+        let scope = &mut self.handle_scope();
+        let data = v8::Object::new(scope);
+        let data_global = v8::Global::new(scope, data);
+        previous_data.push(
+          module_map_rc
+            .borrow_mut()
+            .hot_data
+            .insert(module.name.clone().into(), data_global),
+        );
+        v8::Global::new(scope, v8::Local::<v8::Value>::from(data))
+      };
+      if result.is_ok() {
+        result = self.call_hot_callbacks(&dispose, &data);
+      }
+    }
+    for root_id in root_ids {
+      if result.is_ok() {
+        result = self.hot_evaluate_module(root_id).await;
+      }
+    }
+    if let Err(error) = result {
+      // Put the current versions back in place of the new ones.
+      let mut module_map = module_map_rc.borrow_mut();
+      module_map.hot_restore(&invalidated, &ids);
+      for (module, data) in invalidated.iter().zip(previous_data) {
+        match data {
+          Some(data) => {
+            module_map.hot_data.insert(module.name.clone().into(), data)
+          }
+          None => module_map.hot_data.remove(module.name.as_str()),
+        };
+      }
+      return Err(error);
+    }
+
+    let mut accept_callbacks = vec![];
+    {
+      let mut module_map = module_map_rc.borrow_mut();
+      for module in &invalidated {
+        if let (Some(id), Some(callbacks)) = (
+          ids.get(&module.id),
+          module_map.hot_callbacks.remove(&module.id),
+        ) {
+          accept_callbacks.push((*id, callbacks.accept));
+        }
+      }
+      module_map.hot_commit(&invalidated, &ids);
+    }
+
+    for (id, callbacks) in accept_callbacks {
+      if callbacks.is_empty() {
+        continue;
+      }
+      let namespace = self.get_module_namespace(id)?;
+      let namespace = {
+        let scope = &mut self.handle_scope();
+        let namespace = v8::Local::new(scope, namespace);
+        v8::Global::new(scope, v8::Local::<v8::Value>::from(namespace))
+      };
+      self.call_hot_callbacks(&callbacks, &namespace)?;
+    }
+
+    Ok(ids)
+  }
+
+  /// Unloads a module from the main realm, along with the modules it imports,
+  /// statically or with `import()`, that no other module imports. Their
+  /// module handles and infos are dropped, and a later import of any of them
+  /// fetches and evaluates it anew. V8 frees them once JS code no longer
+  /// references them, eg. through their namespace objects.
+  ///
+  /// This is meant for modules loaded with `import()` or
+  /// [`JsRuntime::load_side_module`], eg. plugins. It fails for the main
+  /// module, for a module that a module staying loaded imports statically,
+  /// and while any of the modules is being loaded by or has a pending
+  /// `import()`, or is being evaluated.
+  ///
+  /// Returns the ids of the unloaded modules, starting with the specified
+  /// one.
+  pub fn unload_module(
+    &mut self,
+    specifier: &ModuleSpecifier,
+  ) -> Result<Vec<ModuleId>, Error> {
+    let (compiled_wasm_module_store, evaluating) = {
+      let state = self.inner.state.borrow();
+      let evaluating = state
+        .pending_dyn_mod_evaluate
+        .iter()
+        .map(|evaluate| evaluate.module_id)
+        .chain(state.pending_mod_evaluate.as_ref().map(|e| e.module_id))
+        .collect::<Vec<_>>();
+      (state.compiled_wasm_module_store.clone(), evaluating)
+    };
+    self.module_map().borrow_mut().unload(
+      specifier.as_str(),
+      compiled_wasm_module_store.as_ref(),
+      &evaluating,
+    )
+  }
+
+  /// Loads and instantiates the invalidated modules that aren't imported by
+  /// other invalidated modules, which loads the others as well.
+  async fn hot_load_modules(
+    &mut self,
+    invalidated: &[InvalidatedModule],
+  ) -> Result<Vec<ModuleId>, Error> {
+    let module_map_rc = self.module_map();
+    let mut roots = invalidated
+      .iter()
+      .filter(|module| {
+        module.root
+          && module.asserted_module_type == AssertedModuleType::JavaScriptOrWasm
+      })
+      .collect::<Vec<_>>();
+    // Keep the order in which the modules were first loaded.
+    roots.sort_by_key(|module| module.id);
+
+    let mut root_ids = vec![];
+    for module in roots {
+      let mut load =
+        ModuleMap::load_side(module_map_rc.clone(), &module.name).await?;
+
+      while let Some(load_result) = load.next().await {
+        let (request, info) = load_result?;
+        let scope = &mut self.handle_scope();
+        load.register_and_recurse(scope, &request, info).map_err(
+          |e| match e {
+            ModuleError::Exception(exception) => {
+              let exception = v8::Local::new(scope, exception);
+              exception_to_err_result::<()>(scope, exception, false)
+                .unwrap_err()
+            }
+            ModuleError::Other(error) => error,
+          },
+        )?;
+      }
+
+      let root_id = load.root_module_id.expect("Root module should be loaded");
+      self.instantiate_module(root_id).map_err(|e| {
+        let scope = &mut self.handle_scope();
+        let exception = v8::Local::new(scope, e);
+        exception_to_err_result::<()>(scope, exception, false).unwrap_err()
+      })?;
+      root_ids.push(root_id);
+    }
+    Ok(root_ids)
+  }
+
+  async fn hot_evaluate_module(&mut self, id: ModuleId) -> Result<(), Error> {
+    let module_map_rc = self.module_map();
+    let promise = {
+      let scope = &mut self.handle_scope();
+      let tc_scope = &mut v8::TryCatch::new(scope);
+      let module = module_map_rc
+        .borrow()
+        .get_handle(id)
+        .map(|handle| v8::Local::new(tc_scope, handle))
+        .expect("ModuleInfo not found");
+      let start = Instant::now();
+      let maybe_value = module.evaluate(tc_scope);
+      module_map_rc
+        .borrow()
+        .trace(ModuleLoadPhase::Evaluate, id, start);
+      let Some(value) = maybe_value else {
+        let undefined = v8::undefined(tc_scope).into();
+        return exception_to_err_result(tc_scope, undefined, false);
+      };
+      // The rejection is returned from here, so it shouldn't be reported as
+      // unhandled as well.
+      let promise = v8::Local::<v8::Promise>::try_from(value)
+        .expect("Expected to get promise as module evaluation result");
+      let empty_fn = bindings::create_empty_fn(tc_scope).unwrap();
+      promise.catch(tc_scope, empty_fn);
+      v8::Global::new(tc_scope, v8::Local::<v8::Value>::from(promise))
+    };
+    self.resolve_value(promise).await?;
+    Ok(())
+  }
+
+  fn call_hot_callbacks(
+    &mut self,
+    callbacks: &[v8::Global<v8::Function>],
+    arg: &v8::Global<v8::Value>,
+  ) -> Result<(), Error> {
+    let scope = &mut self.handle_scope();
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    let arg = v8::Local::new(tc_scope, arg);
+    let undefined = v8::undefined(tc_scope).into();
+    for callback in callbacks {
+      let callback = v8::Local::new(tc_scope, callback);
+      if callback.call(tc_scope, undefined, &[arg]).is_none() {
+        let exception = tc_scope.exception().unwrap_or(undefined);
+        return exception_to_err_result(tc_scope, exception, false);
+      }
+    }
+    Ok(())
+  }
+
   fn check_promise_rejections(&mut self) -> Result<(), Error> {
     let state = self.inner.state.clone();
     let scope = &mut self.handle_scope();
@@ -2378,12 +3312,14 @@ impl JsRuntime {
     Ok(())
   }
 
-  // Polls pending ops and then runs `Deno.core.eventLoopTick` callback.
+  // Polls pending ops and expired timers and then runs
+  // `Deno.core.eventLoopTick` callback.
   fn do_js_event_loop_tick(&mut self, cx: &mut Context) -> Result<(), Error> {
     // Handle responses for each realm.
     let state = self.inner.state.clone();
     let isolate = &mut self.inner.v8_isolate;
     let realm_count = state.borrow().known_realms.len();
+    let virtual_clock = state.borrow().virtual_clock.clone();
     for realm_idx in 0..realm_count {
       let realm = state.borrow().known_realms.get(realm_idx).unwrap().clone();
       let context_state = realm.state();
@@ -2425,6 +3361,21 @@ impl JsRuntime {
         });
       }
 
+      // The ids of the expired timers, whose callbacks run after the next
+      // tick callbacks.
+      let expired_timers = context_state
+        .timers
+        .poll_expired(virtual_clock.as_ref(), cx);
+      if expired_timers.is_empty() {
+        args.push(v8::undefined(scope).into());
+      } else {
+        let expired_timers = expired_timers
+          .into_iter()
+          .map(|id| v8::Integer::new_from_unsigned(scope, id).into())
+          .collect::<Vec<_>>();
+        args.push(v8::Array::new_with_elements(scope, &expired_timers).into());
+      }
+
       let has_tick_scheduled =
         v8::Boolean::new(scope, self.inner.state.borrow().has_tick_scheduled);
       args.push(has_tick_scheduled.into());
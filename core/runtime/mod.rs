@@ -1,10 +1,15 @@
 // Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
 mod bindings;
+mod budget;
+mod clock;
+mod heap_limit;
 mod jsrealm;
 mod jsruntime;
 #[doc(hidden)]
 pub mod ops;
+mod pool;
 mod snapshot_util;
+mod timers;
 
 #[cfg(test)]
 mod tests;
@@ -12,6 +17,8 @@ mod tests;
 pub const V8_WRAPPER_TYPE_INDEX: i32 = 0;
 pub const V8_WRAPPER_OBJECT_INDEX: i32 = 1;
 
+pub use clock::VirtualClock;
+pub use heap_limit::HeapLimitPolicy;
 pub(crate) use jsrealm::ContextState;
 pub use jsrealm::JsRealm;
 pub use jsruntime::CompiledWasmModuleStore;
@@ -25,6 +32,10 @@ pub use jsruntime::RuntimeOptions;
 pub use jsruntime::RuntimeSnapshotOptions;
 pub use jsruntime::SharedArrayBufferStore;
 pub use jsruntime::Snapshot;
+pub use pool::JsRuntimePool;
+pub use pool::JsRuntimePoolOptions;
+pub use pool::PooledJsRuntime;
+pub use pool::ResetOpStateFn;
 pub use snapshot_util::create_snapshot;
 pub use snapshot_util::get_js_files;
 pub use snapshot_util::CreateSnapshotOptions;
@@ -0,0 +1,224 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::ops::DerefMut;
+
+use super::JsRuntime;
+use super::RuntimeOptions;
+use super::Snapshot;
+use crate::error::generic_error;
+use crate::OpState;
+use anyhow::Error;
+
+pub type ResetOpStateFn = dyn Fn(&mut OpState);
+
+pub struct JsRuntimePoolOptions {
+  /// The snapshot every runtime of the pool is created from, and reset to.
+  pub snapshot: Snapshot,
+  /// Creates the options of a runtime. Their `startup_snapshot` is replaced
+  /// with `snapshot`.
+  ///
+  /// A virtual clock is moved back to its start time when its runtime is
+  /// reset, so each runtime should get its own clock rather than a clone of
+  /// one shared with the embedder or the other runtimes.
+  pub create_options: Box<dyn Fn() -> RuntimeOptions>,
+  /// The number of runtimes created upfront and kept ready to be checked out.
+  /// With a size of 0, a runtime is created for each checkout and dropped
+  /// when it's checked in.
+  pub size: usize,
+  /// How many times a runtime is checked out before it's replaced with a new
+  /// one instead of being reset.
+  pub max_uses: usize,
+  /// Called with the `OpState` of a runtime after it was reset, to put back
+  /// the state its extensions set up when it was created. The `state`
+  /// functions of extensions only run when a runtime is created, so the state
+  /// they put in the `OpState` is gone after a reset unless this restores it.
+  pub reset_op_state: Option<Box<ResetOpStateFn>>,
+}
+
+/// A runtime checked out of a [`JsRuntimePool`], to give back with
+/// [`JsRuntimePool::checkin`].
+pub struct PooledJsRuntime {
+  runtime: JsRuntime,
+  uses: usize,
+}
+
+impl PooledJsRuntime {
+  /// How many times the runtime was checked out, this time included.
+  pub fn uses(&self) -> usize {
+    self.uses
+  }
+}
+
+impl Deref for PooledJsRuntime {
+  type Target = JsRuntime;
+
+  fn deref(&self) -> &Self::Target {
+    &self.runtime
+  }
+}
+
+impl DerefMut for PooledJsRuntime {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.runtime
+  }
+}
+
+/// A pool of runtimes created from a snapshot, for workloads that need a
+/// fresh runtime for each unit of work, such as a request, but can't afford
+/// to create one every time.
+///
+/// Runtimes checked back in are reset instead of being dropped: their realms
+/// are replaced with a new main realm created from the snapshot, their
+/// pending ops and timers are dropped, their virtual clock is moved back to
+/// its start time, and the resources and state in their `OpState` are
+/// cleared, see [`JsRuntimePoolOptions::reset_op_state`]. The pool then
+/// verifies that nothing was left behind, and replaces the runtimes that fail
+/// the check or reached [`JsRuntimePoolOptions::max_uses`] with new ones.
+/// Runtimes checked in while the pool is full are dropped without being
+/// reset.
+///
+/// Runtimes with an inspector can't be reset.
+pub struct JsRuntimePool {
+  snapshot: Box<[u8]>,
+  create_options: Box<dyn Fn() -> RuntimeOptions>,
+  size: usize,
+  max_uses: usize,
+  reset_op_state: Option<Box<ResetOpStateFn>>,
+  idle: VecDeque<PooledJsRuntime>,
+  // The own properties of the global object of a new runtime.
+  globals: Vec<String>,
+}
+
+impl JsRuntimePool {
+  pub fn new(options: JsRuntimePoolOptions) -> Self {
+    let snapshot = match options.snapshot {
+      Snapshot::Static(data) => data.into(),
+      Snapshot::JustCreated(data) => (*data).into(),
+      Snapshot::Boxed(data) => data,
+    };
+    let mut pool = Self {
+      snapshot,
+      create_options: options.create_options,
+      size: options.size,
+      max_uses: options.max_uses.max(1),
+      reset_op_state: options.reset_op_state,
+      idle: VecDeque::with_capacity(options.size),
+      globals: vec![],
+    };
+    // Runtimes are only reset when the pool keeps some.
+    if pool.size > 0 {
+      let mut runtime = pool.create_runtime();
+      pool.globals = global_names(&mut runtime);
+      pool.idle.push_back(runtime);
+      pool.fill();
+    }
+    pool
+  }
+
+  /// The number of runtimes ready to be checked out.
+  pub fn idle_count(&self) -> usize {
+    self.idle.len()
+  }
+
+  /// Takes a runtime out of the pool, or creates a new one if there's none
+  /// left.
+  pub fn checkout(&mut self) -> PooledJsRuntime {
+    let mut runtime = self
+      .idle
+      .pop_front()
+      .unwrap_or_else(|| self.create_runtime());
+    runtime.uses += 1;
+    runtime
+  }
+
+  /// Gives a runtime back to the pool, resetting it for its next use. It's
+  /// dropped instead if the pool is full or it reached
+  /// [`JsRuntimePoolOptions::max_uses`]. If it can't be reset, or the reset
+  /// left something behind, the runtime is dropped, the pool gets a new one,
+  /// and the error is returned.
+  pub fn checkin(&mut self, mut runtime: PooledJsRuntime) -> Result<(), Error> {
+    if runtime.uses >= self.max_uses || self.idle.len() >= self.size {
+      drop(runtime);
+      self.fill();
+      return Ok(());
+    }
+    if let Err(err) = self.reset(&mut runtime) {
+      drop(runtime);
+      self.fill();
+      return Err(err);
+    }
+    self.idle.push_back(runtime);
+    Ok(())
+  }
+
+  fn reset(&self, runtime: &mut PooledJsRuntime) -> Result<(), Error> {
+    runtime.reset_from_snapshot()?;
+    if let Some(reset_op_state) = &self.reset_op_state {
+      reset_op_state(&mut runtime.op_state().borrow_mut());
+    }
+
+    let resources = runtime
+      .op_state()
+      .borrow()
+      .resource_table
+      .names()
+      .map(|(_, name)| name.into_owned())
+      .collect::<Vec<_>>();
+    if !resources.is_empty() {
+      return Err(generic_error(format!(
+        "Resetting the runtime left resources behind: {}",
+        resources.join(", ")
+      )));
+    }
+    if runtime.event_loop_pending_state().is_pending() {
+      return Err(generic_error(
+        "Resetting the runtime left pending work behind",
+      ));
+    }
+    let globals = global_names(runtime);
+    if globals != self.globals {
+      return Err(generic_error(format!(
+        "Resetting the runtime didn't restore the global object, which has \
+        the properties {}",
+        globals.join(", ")
+      )));
+    }
+    Ok(())
+  }
+
+  fn fill(&mut self) {
+    while self.idle.len() < self.size {
+      let runtime = self.create_runtime();
+      self.idle.push_back(runtime);
+    }
+  }
+
+  fn create_runtime(&self) -> PooledJsRuntime {
+    let options = RuntimeOptions {
+      startup_snapshot: Some(Snapshot::Boxed(self.snapshot.clone())),
+      ..(self.create_options)()
+    };
+    PooledJsRuntime {
+      runtime: JsRuntime::new(options),
+      uses: 0,
+    }
+  }
+}
+
+fn global_names(runtime: &mut JsRuntime) -> Vec<String> {
+  let scope = &mut runtime.handle_scope();
+  let context = scope.get_current_context();
+  let global = context.global(scope);
+  let args = v8::GetPropertyNamesArgs {
+    property_filter: v8::PropertyFilter::SKIP_SYMBOLS,
+    ..Default::default()
+  };
+  let names = global.get_own_property_names(scope, args).unwrap();
+  (0..names.length())
+    .map(|i| {
+      let name = names.get_index(scope, i).unwrap();
+      name.to_rust_string_lossy(scope)
+    })
+    .collect()
+}
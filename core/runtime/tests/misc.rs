@@ -315,6 +315,99 @@ fn terminate_execution() {
   terminator_thread.join().unwrap();
 }
 
+#[tokio::test]
+async fn test_max_run_time() {
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    max_run_time: Some(Duration::from_millis(100)),
+    ..Default::default()
+  });
+
+  let err = runtime
+    .execute_script_static("infinite_loop.js", "for(;;) {}")
+    .unwrap_err();
+  assert_eq!(
+    err.downcast_ref::<ExecutionTimeout>(),
+    Some(&ExecutionTimeout {
+      kind: ExecutionTimeoutKind::RunTime,
+      limit: Duration::from_millis(100),
+    })
+  );
+
+  // The runtime is usable again, without cancelling the termination.
+  runtime
+    .execute_script_static("simple.js", "1 + 1")
+    .expect("execution should be possible again");
+
+  // Polls of the event loop are limited as well.
+  runtime
+    .execute_script_static(
+      "infinite_timer.js",
+      "Deno.core.setTimeout(() => { for(;;) {} })",
+    )
+    .unwrap();
+  let err = runtime.run_event_loop(false).await.unwrap_err();
+  assert!(err.is::<ExecutionTimeout>());
+
+  // So are module evaluations, including the microtasks they queue.
+  let specifier = ModuleSpecifier::parse("file:///main.js").unwrap();
+  let id = runtime
+    .load_main_module(
+      &specifier,
+      Some(ascii_str!("Promise.resolve().then(() => { for(;;) {} });")),
+    )
+    .await
+    .unwrap();
+  let err = runtime.mod_evaluate(id).await.unwrap().unwrap_err();
+  assert!(err.is::<ExecutionTimeout>());
+}
+
+#[test]
+fn test_max_cpu_time() {
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    max_cpu_time: Some(Duration::from_millis(200)),
+    ..Default::default()
+  });
+
+  runtime
+    .execute_script_static("simple.js", "1 + 1")
+    .expect("the budget isn't spent yet");
+  let err = runtime
+    .execute_script_static("infinite_loop.js", "for(;;) {}")
+    .unwrap_err();
+  let timeout = err.downcast_ref::<ExecutionTimeout>().unwrap();
+  assert_eq!(timeout.kind, ExecutionTimeoutKind::CpuTime);
+
+  // Once spent, later runs fail without running.
+  let err = runtime
+    .execute_script_static("simple.js", "1 + 1")
+    .unwrap_err();
+  assert!(err.is::<ExecutionTimeout>());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_max_cpu_time_excludes_blocking() {
+  #[op]
+  fn op_block() {
+    std::thread::sleep(Duration::from_millis(300));
+  }
+
+  deno_core::extension!(test_ext, ops = [op_block]);
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    extensions: vec![test_ext::init_ops()],
+    max_cpu_time: Some(Duration::from_millis(200)),
+    ..Default::default()
+  });
+
+  // Blocking the thread doesn't spend CPU time.
+  runtime
+    .execute_script_static("block.js", "Deno.core.ops.op_block()")
+    .unwrap();
+  runtime
+    .execute_script_static("block.js", "Deno.core.ops.op_block()")
+    .unwrap();
+}
+
 #[test]
 fn dangling_shared_isolate() {
   let v8_isolate_handle = {
@@ -542,6 +635,95 @@ fn test_heap_limit_cb_multiple() {
   assert!(callback_invoke_count_second.load(Ordering::SeqCst) > 0);
 }
 
+#[test]
+fn test_heap_limit_policy_recover() {
+  let create_params =
+    v8::Isolate::create_params().heap_limits(0, 10 * 1024 * 1024);
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    create_params: Some(create_params),
+    heap_limit_policy: Some(HeapLimitPolicy::Recover),
+    ..Default::default()
+  });
+
+  for _ in 0..2 {
+    let err = runtime
+      .execute_script_static(
+        "script name",
+        r#"{ let s = ""; while(true) { s += "Hello"; } }"#,
+      )
+      .expect_err("script should fail");
+    let exceeded = err.downcast::<HeapLimitExceeded>().unwrap();
+    assert!(!exceeded.poisoned);
+    assert!(exceeded.used_heap_size > 0);
+    assert!(exceeded.heap_size_limit >= 10 * 1024 * 1024);
+
+    runtime
+      .execute_script_static("simple.js", "1 + 1")
+      .expect("execution should be possible again");
+    // The limit was restored.
+    let mut stats = v8::HeapStatistics::default();
+    runtime.v8_isolate().get_heap_statistics(&mut stats);
+    assert!(stats.heap_size_limit() <= exceeded.heap_size_limit);
+  }
+
+  // A callback added after the policy recovered takes precedence over it.
+  let cb_handle = runtime.v8_isolate().thread_safe_handle();
+  let callback_invoke_count = Rc::new(AtomicUsize::new(0));
+  let inner_invoke_count = Rc::clone(&callback_invoke_count);
+  runtime.add_near_heap_limit_callback(move |current_limit, _initial_limit| {
+    inner_invoke_count.fetch_add(1, Ordering::SeqCst);
+    cb_handle.terminate_execution();
+    current_limit * 2
+  });
+  let err = runtime
+    .execute_script_static(
+      "script name",
+      r#"{ let s = ""; while(true) { s += "Hello"; } }"#,
+    )
+    .expect_err("script should fail");
+  assert_eq!(
+    "Uncaught Error: execution terminated",
+    err.downcast::<JsError>().unwrap().exception_message
+  );
+  assert!(callback_invoke_count.load(Ordering::SeqCst) > 0);
+  runtime.v8_isolate().cancel_terminate_execution();
+
+  // Once it's removed, the policy applies again.
+  runtime.remove_near_heap_limit_callback(10 * 1024 * 1024);
+  let err = runtime
+    .execute_script_static(
+      "script name",
+      r#"{ let s = ""; while(true) { s += "Hello"; } }"#,
+    )
+    .expect_err("script should fail");
+  assert!(err.is::<HeapLimitExceeded>());
+}
+
+#[test]
+fn test_heap_limit_policy_poison() {
+  let create_params =
+    v8::Isolate::create_params().heap_limits(0, 10 * 1024 * 1024);
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    create_params: Some(create_params),
+    heap_limit_policy: Some(HeapLimitPolicy::Poison),
+    ..Default::default()
+  });
+
+  let err = runtime
+    .execute_script_static(
+      "script name",
+      r#"let s = ""; while(true) { s += "Hello"; }"#,
+    )
+    .expect_err("script should fail");
+  let exceeded = err.downcast::<HeapLimitExceeded>().unwrap();
+  assert!(exceeded.poisoned);
+
+  let err = runtime
+    .execute_script_static("simple.js", "1 + 1")
+    .expect_err("the runtime should be poisoned");
+  assert_eq!(err.downcast::<HeapLimitExceeded>().unwrap(), exceeded);
+}
+
 #[tokio::test]
 async fn test_pump_message_loop() {
   let mut runtime = JsRuntime::new(RuntimeOptions::default());
@@ -668,6 +850,237 @@ async fn test_set_macrotask_callback_set_next_tick_callback() {
   runtime.run_event_loop(false).await.unwrap();
 }
 
+#[tokio::test]
+async fn test_timers() {
+  let mut runtime = JsRuntime::new(Default::default());
+  runtime
+    .execute_script_static(
+      "timers.js",
+      r#"
+      const results = [];
+      Deno.core.setTimeout((a, b) => results.push(`timeout ${a}${b}`), 50, "a", "b");
+      const cancelled = Deno.core.setTimeout(() => results.push("cancelled"), 1);
+      Deno.core.clearTimeout(cancelled);
+      Deno.core.setTimeout(() => {
+        results.push("zero");
+        Promise.resolve().then(() => results.push("microtask"));
+      });
+      let count = 0;
+      const interval = Deno.core.setInterval(() => {
+        results.push(`interval ${++count}`);
+        if (count === 3) {
+          Deno.core.clearInterval(interval);
+        }
+      }, 1);
+      // An unrefed timer doesn't keep the event loop alive.
+      const unrefed = Deno.core.setTimeout(() => results.push("unrefed"), 60000);
+      Deno.core.unrefTimer(unrefed);
+      globalThis.results = results;
+      "#,
+    )
+    .unwrap();
+  runtime.run_event_loop(false).await.unwrap();
+
+  let results = runtime
+    .execute_script_static("check.js", "globalThis.results.join()")
+    .unwrap();
+  let scope = &mut runtime.handle_scope();
+  let results = v8::Local::new(scope, results);
+  assert_eq!(
+    results.to_rust_string_lossy(scope),
+    "zero,microtask,interval 1,interval 2,interval 3,timeout ab"
+  );
+}
+
+#[tokio::test]
+async fn test_timer_exception() {
+  let clock = VirtualClock::new(std::time::SystemTime::now());
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    virtual_clock: Some(clock.clone()),
+    ..Default::default()
+  });
+  runtime
+    .execute_script_static(
+      "timers.js",
+      r#"
+      const results = [];
+      Deno.core.setTimeout(() => {
+        Promise.resolve().then(() => results.push("microtask"));
+        throw new Error("first");
+      }, 10);
+      Deno.core.setTimeout(() => results.push("second"), 10);
+      Deno.core.setMacrotaskCallback(() => {
+        results.push("macrotask");
+      });
+      globalThis.results = results;
+      "#,
+    )
+    .unwrap();
+
+  // Both timers expire in the same tick, and the exception thrown by the first
+  // one doesn't keep the rest of the tick from running.
+  clock.advance(Duration::from_millis(10));
+  let err = runtime.run_event_loop(false).await.unwrap_err();
+  assert_eq!(
+    err.downcast::<JsError>().unwrap().exception_message,
+    "Uncaught Error: first"
+  );
+  runtime.run_event_loop(false).await.unwrap();
+
+  let results = runtime
+    .execute_script_static("check.js", "globalThis.results.join()")
+    .unwrap();
+  let scope = &mut runtime.handle_scope();
+  let results = v8::Local::new(scope, results);
+  assert_eq!(
+    results.to_rust_string_lossy(scope),
+    "microtask,second,macrotask"
+  );
+}
+
+#[tokio::test]
+async fn test_nested_timers_are_clamped() {
+  let mut runtime = JsRuntime::new(Default::default());
+  runtime
+    .execute_script_static(
+      "nested_timers.js",
+      r#"
+      const start = Date.now();
+      let depth = 0;
+      function nest() {
+        if (++depth < 10) {
+          Deno.core.setTimeout(nest, 0);
+        } else {
+          globalThis.elapsed = Date.now() - start;
+        }
+      }
+      Deno.core.setTimeout(nest, 0);
+      "#,
+    )
+    .unwrap();
+  runtime.run_event_loop(false).await.unwrap();
+
+  // The timers set from the 6th nesting level on wait for 4ms.
+  let elapsed = runtime
+    .execute_script_static("check.js", "globalThis.elapsed")
+    .unwrap();
+  let scope = &mut runtime.handle_scope();
+  let elapsed = v8::Local::new(scope, elapsed);
+  assert!(elapsed.number_value(scope).unwrap() >= 16.0);
+}
+
+#[tokio::test]
+async fn test_virtual_clock_advance_time() {
+  let start = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+  let clock = VirtualClock::new(start);
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    virtual_clock: Some(clock.clone()),
+    ..Default::default()
+  });
+  runtime
+    .execute_script_static(
+      "virtual_clock.js",
+      r#"
+      const results = [];
+      const log = (name) => results.push(`${name}@${Date.now()}`);
+      Deno.core.setTimeout(() => log("a"), 100);
+      Deno.core.setTimeout(() => {
+        log("b");
+        Deno.core.setTimeout(() => log("c"), 50);
+      }, 200);
+      let count = 0;
+      const interval = Deno.core.setInterval(() => {
+        log("interval");
+        if (++count === 2) {
+          Deno.core.clearInterval(interval);
+        }
+      }, 120);
+      globalThis.results = results;
+      "#,
+    )
+    .unwrap();
+
+  runtime
+    .advance_time(Duration::from_millis(200))
+    .await
+    .unwrap();
+  assert_eq!(clock.elapsed(), Duration::from_millis(200));
+  runtime
+    .advance_time(Duration::from_millis(1000))
+    .await
+    .unwrap();
+  assert_eq!(clock.elapsed(), Duration::from_millis(1200));
+
+  let results = runtime
+    .execute_script_static("check.js", "globalThis.results.join()")
+    .unwrap();
+  {
+    let scope = &mut runtime.handle_scope();
+    let results = v8::Local::new(scope, results);
+    assert_eq!(
+      results.to_rust_string_lossy(scope),
+      "a@1000100,interval@1000120,b@1000200,interval@1000240,c@1000250"
+    );
+  }
+
+  // `new Date()` and primordials read the virtual clock too.
+  let dates = runtime
+    .execute_script_static(
+      "check.js",
+      r#"
+      const { DateNow } = globalThis.__bootstrap.primordials;
+      [
+        new Date().getTime(),
+        DateNow(),
+        new Date(0).getTime(),
+        Date.parse(Date()),
+        new Date() instanceof Date,
+      ].join()
+      "#,
+    )
+    .unwrap();
+  let scope = &mut runtime.handle_scope();
+  let dates = v8::Local::new(scope, dates);
+  assert_eq!(
+    dates.to_rust_string_lossy(scope),
+    "1001200,1001200,0,1001000,true"
+  );
+}
+
+#[tokio::test]
+async fn test_virtual_clock_run_until_idle() {
+  let clock = VirtualClock::new(std::time::SystemTime::now());
+  let mut runtime = JsRuntime::new(RuntimeOptions {
+    virtual_clock: Some(clock.clone()),
+    ..Default::default()
+  });
+  runtime
+    .execute_script_static(
+      "virtual_clock.js",
+      r#"
+      Deno.core.setTimeout(() => {
+        globalThis.fired = true;
+      }, 60000);
+      const unrefed = Deno.core.setTimeout(() => {}, 3600000);
+      Deno.core.unrefTimer(unrefed);
+      "#,
+    )
+    .unwrap();
+
+  let start = std::time::Instant::now();
+  runtime.run_until_idle().await.unwrap();
+  assert!(start.elapsed() < Duration::from_secs(10));
+  // The unrefed timer doesn't keep the event loop alive.
+  assert_eq!(clock.elapsed(), Duration::from_secs(60));
+
+  let fired = runtime
+    .execute_script_static("check.js", "globalThis.fired")
+    .unwrap();
+  let scope = &mut runtime.handle_scope();
+  let fired = v8::Local::new(scope, fired);
+  assert!(fired.is_true());
+}
+
 #[test]
 fn test_has_tick_scheduled() {
   use futures::task::ArcWake;
@@ -171,8 +171,9 @@ fn es_snapshot() {
       import {{ f{prev} }} from "file:///{prev}.js";
       export function f{i}() {{ return f{prev}() }}
       "#
-    )
-    .into();
+    );
+    let source_size = source_code.len();
+    let source_code = source_code.into();
 
     let id = if main {
       futures::executor::block_on(
@@ -199,7 +200,9 @@ fn es_snapshot() {
         specifier: format!("file:///{prev}.js"),
         asserted_module_type: AssertedModuleType::JavaScriptOrWasm,
       }],
+      dynamic_requests: vec![],
       module_type: ModuleType::JavaScript,
+      source_size,
     }
   }
 
@@ -209,10 +212,7 @@ fn es_snapshot() {
     assert_eq!(module_map.handles.len(), modules.len());
     assert_eq!(module_map.info.len(), modules.len());
     assert_eq!(
-      module_map.by_name(AssertedModuleType::Json).len()
-        + module_map
-          .by_name(AssertedModuleType::JavaScriptOrWasm)
-          .len(),
+      module_map.by_name_json.len() + module_map.by_name_js.len(),
       modules.len()
     );
 
@@ -222,10 +222,7 @@ fn es_snapshot() {
       assert!(module_map.handles.get(info.id).is_some());
       assert_eq!(module_map.info.get(info.id).unwrap(), info);
       assert_eq!(
-        module_map
-          .by_name(AssertedModuleType::JavaScriptOrWasm)
-          .get(&info.name)
-          .unwrap(),
+        module_map.by_name_js.get(&info.name).unwrap(),
         &SymbolicModule::Mod(info.id)
       );
     }
@@ -251,6 +248,7 @@ fn es_snapshot() {
   let specifier = crate::resolve_url("file:///0.js").unwrap();
   let source_code =
     ascii_str!(r#"export function f0() { return "hello world" }"#);
+  let source_size = source_code.as_bytes().len();
   let id = futures::executor::block_on(
     runtime.load_side_module(&specifier, Some(source_code)),
   )
@@ -266,7 +264,9 @@ fn es_snapshot() {
     main: false,
     name: specifier.into(),
     requests: vec![],
+    dynamic_requests: vec![],
     module_type: ModuleType::JavaScript,
+    source_size,
   });
 
   modules.extend((1..200).map(|i| create_module(&mut runtime, i, false)));
@@ -320,3 +320,138 @@ fn es_snapshot() {
     assert_eq!(str_, "hello world test");
   }
 }
+
+#[test]
+fn test_runtime_pool() {
+  let snapshot = {
+    let mut runtime =
+      JsRuntimeForSnapshot::new(Default::default(), Default::default());
+    runtime.execute_script_static("a.js", "a = 1 + 2").unwrap();
+    runtime.snapshot()
+  };
+
+  let mut pool = JsRuntimePool::new(JsRuntimePoolOptions {
+    snapshot: Snapshot::JustCreated(snapshot),
+    create_options: Box::new(Default::default),
+    size: 1,
+    max_uses: 2,
+    reset_op_state: Some(Box::new(|state| state.put(42u32))),
+  });
+  assert_eq!(pool.idle_count(), 1);
+
+  let mut runtime = pool.checkout();
+  assert_eq!(runtime.uses(), 1);
+  assert_eq!(pool.idle_count(), 0);
+  runtime
+    .execute_script_static(
+      "leak.js",
+      r#"
+      if (a != 3) throw Error('x');
+      a = 4;
+      globalThis.leaked = true;
+      Deno.core.setTimeout(() => {}, 100000);
+      "#,
+    )
+    .unwrap();
+  runtime
+    .op_state()
+    .borrow_mut()
+    .resource_table
+    .add(CancelHandle::new());
+  pool.checkin(runtime).unwrap();
+  assert_eq!(pool.idle_count(), 1);
+
+  // The runtime is reused, without anything left from its previous use.
+  let mut runtime = pool.checkout();
+  assert_eq!(runtime.uses(), 2);
+  runtime
+    .execute_script_static(
+      "check.js",
+      "if (a != 3 || globalThis.leaked) throw Error('x')",
+    )
+    .unwrap();
+  {
+    let op_state = runtime.op_state();
+    let op_state = op_state.borrow();
+    assert_eq!(op_state.resource_table.names().count(), 0);
+    assert_eq!(op_state.borrow::<u32>(), &42);
+  }
+  futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+
+  // Runtimes that were used `max_uses` times are replaced.
+  pool.checkin(runtime).unwrap();
+  assert_eq!(pool.idle_count(), 1);
+  let runtime = pool.checkout();
+  assert_eq!(runtime.uses(), 1);
+
+  // Runtimes checked in while the pool is full are dropped.
+  let other = pool.checkout();
+  pool.checkin(runtime).unwrap();
+  pool.checkin(other).unwrap();
+  assert_eq!(pool.idle_count(), 1);
+}
+
+#[test]
+fn test_runtime_pool_empty() {
+  let snapshot =
+    JsRuntimeForSnapshot::new(Default::default(), Default::default())
+      .snapshot();
+  let mut pool = JsRuntimePool::new(JsRuntimePoolOptions {
+    snapshot: Snapshot::JustCreated(snapshot),
+    create_options: Box::new(Default::default),
+    size: 0,
+    max_uses: 2,
+    reset_op_state: None,
+  });
+  assert_eq!(pool.idle_count(), 0);
+  let mut runtime = pool.checkout();
+  runtime.execute_script_static("a.js", "1 + 2").unwrap();
+  pool.checkin(runtime).unwrap();
+  assert_eq!(pool.idle_count(), 0);
+}
+
+#[test]
+fn test_runtime_pool_reset_options() {
+  let snapshot =
+    JsRuntimeForSnapshot::new(Default::default(), Default::default())
+      .snapshot();
+  let mut pool = JsRuntimePool::new(JsRuntimePoolOptions {
+    snapshot: Snapshot::JustCreated(snapshot),
+    create_options: Box::new(|| RuntimeOptions {
+      module_loader: Some(Rc::new(StaticModuleLoader::new([(
+        ModuleSpecifier::parse("file:///main.js").unwrap(),
+        r#"
+        import { version } from "internal:buildinfo";
+        if (version !== "1.0.0" || Date.now() !== 0) throw new Error();
+        "#,
+      )]))),
+      synthetic_modules: vec![SyntheticModule::from_serializable(
+        ModuleSpecifier::parse("internal:buildinfo").unwrap(),
+        &serde_json::json!({ "version": "1.0.0" }),
+      )
+      .unwrap()],
+      virtual_clock: Some(VirtualClock::new(std::time::SystemTime::UNIX_EPOCH)),
+      ..Default::default()
+    }),
+    size: 1,
+    max_uses: 3,
+    reset_op_state: None,
+  });
+
+  // The synthetic modules are registered again, and the clock is moved back,
+  // every time the runtime is reset.
+  for uses in 1..=3 {
+    let mut runtime = pool.checkout();
+    assert_eq!(runtime.uses(), uses);
+    let specifier = ModuleSpecifier::parse("file:///main.js").unwrap();
+    let id =
+      futures::executor::block_on(runtime.load_main_module(&specifier, None))
+        .unwrap();
+    let receiver = runtime.mod_evaluate(id);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    futures::executor::block_on(receiver).unwrap().unwrap();
+    let clock = runtime.virtual_clock().unwrap();
+    clock.advance(std::time::Duration::from_secs(1));
+    pool.checkin(runtime).unwrap();
+  }
+}
@@ -0,0 +1,203 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+use super::VirtualClock;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Waker;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+pub(crate) type TimerId = u32;
+
+/// Timers nested deeper than this are clamped to [`MIN_NESTED_TIMEOUT`], see
+/// <https://html.spec.whatwg.org/multipage/timers-and-user-prompts.html#timer-initialisation-steps>.
+const MAX_UNCLAMPED_NESTING_LEVEL: u32 = 5;
+const MIN_NESTED_TIMEOUT: Duration = Duration::from_millis(4);
+
+struct Timer {
+  deadline: Instant,
+  // The timeout of a repeating timer, which is queued again when it expires.
+  repeat: Option<Duration>,
+  // The timer nesting level of the task running the timer's callback.
+  nesting_level: u32,
+  refed: bool,
+}
+
+/// The timers of a realm, backing `Deno.core.setTimeout()` and
+/// `Deno.core.setInterval()`. Their callbacks are kept in JS, and called with
+/// the ids of the expired timers by `Deno.core.eventLoopTick()`.
+///
+/// Like ops, a timer keeps the event loop alive unless it's unrefed.
+///
+/// With a [`VirtualClock`], timers expire as the clock is advanced instead of
+/// as real time passes.
+#[derive(Default)]
+pub(crate) struct Timers {
+  next_id: TimerId,
+  timers: HashMap<TimerId, Timer>,
+  // The pending timers by deadline, then by id so that timers with the same
+  // deadline expire in the order they were queued.
+  queue: BTreeSet<(Instant, TimerId)>,
+  num_refed: usize,
+  // Expires with the first pending timer, unless there's a virtual clock.
+  sleep: Option<Pin<Box<Sleep>>>,
+  // Woken when a timer is queued to expire before the one `sleep` waits for.
+  waker: Option<Waker>,
+}
+
+fn now(clock: Option<&VirtualClock>) -> Instant {
+  clock.map_or_else(Instant::now, VirtualClock::instant)
+}
+
+fn clamp_timeout(timeout: Duration, nesting_level: u32) -> Duration {
+  if nesting_level > MAX_UNCLAMPED_NESTING_LEVEL {
+    timeout.max(MIN_NESTED_TIMEOUT)
+  } else {
+    timeout
+  }
+}
+
+impl Timers {
+  /// Queues a timer from a task with the given timer nesting level, which is
+  /// 0 outside of timer callbacks.
+  pub fn queue(
+    &mut self,
+    clock: Option<&VirtualClock>,
+    timeout: Duration,
+    repeat: bool,
+    nesting_level: u32,
+  ) -> TimerId {
+    let id = loop {
+      self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+      if !self.timers.contains_key(&self.next_id) {
+        break self.next_id;
+      }
+    };
+    let deadline = now(clock) + clamp_timeout(timeout, nesting_level);
+    self.timers.insert(
+      id,
+      Timer {
+        deadline,
+        repeat: repeat.then_some(timeout),
+        nesting_level: nesting_level.saturating_add(1),
+        refed: true,
+      },
+    );
+    self.queue.insert((deadline, id));
+    self.num_refed += 1;
+    let expires_first = self
+      .sleep
+      .as_ref()
+      .map_or(true, |sleep| deadline < sleep.deadline());
+    if expires_first {
+      if let Some(waker) = &self.waker {
+        waker.wake_by_ref();
+      }
+    }
+    id
+  }
+
+  pub fn cancel(&mut self, id: TimerId) {
+    if let Some(timer) = self.timers.remove(&id) {
+      self.queue.remove(&(timer.deadline, id));
+      if timer.refed {
+        self.num_refed -= 1;
+      }
+    }
+  }
+
+  pub fn ref_timer(&mut self, id: TimerId) {
+    if let Some(timer) = self.timers.get_mut(&id) {
+      if !timer.refed {
+        timer.refed = true;
+        self.num_refed += 1;
+      }
+    }
+  }
+
+  pub fn unref_timer(&mut self, id: TimerId) {
+    if let Some(timer) = self.timers.get_mut(&id) {
+      if timer.refed {
+        timer.refed = false;
+        self.num_refed -= 1;
+      }
+    }
+  }
+
+  pub fn has_pending_refed(&self) -> bool {
+    self.num_refed > 0
+  }
+
+  /// The deadline of the first pending timer, refed or not.
+  pub fn next_deadline(&self) -> Option<Instant> {
+    self.queue.first().map(|&(deadline, _)| deadline)
+  }
+
+  /// Returns the ids of the expired timers, in the order their callbacks
+  /// should run, and queues the repeating ones again. The waker of `cx` is
+  /// woken when the next timer expires.
+  pub fn poll_expired(
+    &mut self,
+    clock: Option<&VirtualClock>,
+    cx: &mut Context,
+  ) -> Vec<TimerId> {
+    if !self
+      .waker
+      .as_ref()
+      .map_or(false, |waker| waker.will_wake(cx.waker()))
+    {
+      self.waker = Some(cx.waker().clone());
+    }
+
+    let now = now(clock);
+    let mut expired = vec![];
+    while let Some(&(deadline, id)) = self.queue.first() {
+      if deadline > now {
+        break;
+      }
+      self.queue.pop_first();
+      expired.push(id);
+    }
+    for id in &expired {
+      let timer = self.timers.get_mut(id).unwrap();
+      match timer.repeat {
+        Some(timeout) => {
+          timer.deadline = now + clamp_timeout(timeout, timer.nesting_level);
+          timer.nesting_level = timer.nesting_level.saturating_add(1);
+          self.queue.insert((timer.deadline, *id));
+        }
+        None => {
+          if self.timers.remove(id).unwrap().refed {
+            self.num_refed -= 1;
+          }
+        }
+      }
+    }
+
+    match (self.queue.first(), clock) {
+      (Some(&(deadline, _)), Some(clock)) => {
+        self.sleep = None;
+        clock.register_waker(cx.waker());
+        if deadline <= clock.instant() {
+          cx.waker().wake_by_ref();
+        }
+      }
+      (Some(&(deadline, _)), None) => {
+        let sleep = self
+          .sleep
+          .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+        if sleep.deadline() != deadline {
+          sleep.as_mut().reset(deadline);
+        }
+        if sleep.as_mut().poll(cx).is_ready() {
+          cx.waker().wake_by_ref();
+        }
+      }
+      (None, _) => self.sleep = None,
+    }
+    expired
+  }
+}
@@ -0,0 +1,54 @@
+// Copyright 2018-2023 the Deno authors. All rights reserved. MIT license.
+
+// Makes `Date` and `performance.now()` read the runtime's virtual clock.
+//
+// This runs before the other builtin sources, so that primordials capture the
+// replaced `Date`, and again once the extensions are initialized, since they
+// may define `performance`.
+
+// deno-lint-ignore-file prefer-primordials
+
+"use strict";
+
+((window) => {
+  const { ops } = window.Deno.core;
+  const dateNow = () => ops.op_virtual_clock_date_now();
+  const kVirtualClock = Symbol.for("Deno.core.virtualClock");
+
+  if (!window.Date[kVirtualClock]) {
+    const OriginalDate = window.Date;
+    // `new Date()` and `Date()` without arguments read the virtual clock,
+    // everything else is left to the original constructor.
+    function Date(...args) {
+      if (new.target === undefined) {
+        return new OriginalDate(dateNow()).toString();
+      }
+      return Reflect.construct(
+        OriginalDate,
+        args.length === 0 ? [dateNow()] : args,
+        new.target,
+      );
+    }
+    // Copy `prototype`, `name`, `length` and the static methods.
+    for (const key of Reflect.ownKeys(OriginalDate)) {
+      Object.defineProperty(
+        Date,
+        key,
+        Object.getOwnPropertyDescriptor(OriginalDate, key),
+      );
+    }
+    Object.defineProperty(Date.prototype, "constructor", {
+      value: Date,
+      writable: true,
+      enumerable: false,
+      configurable: true,
+    });
+    Object.defineProperty(Date, kVirtualClock, { value: true });
+    Date.now = dateNow;
+    window.Date = Date;
+  }
+
+  if (window.performance) {
+    window.performance.now = () => ops.op_virtual_clock_now();
+  }
+})(globalThis);